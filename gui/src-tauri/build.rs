@@ -1,3 +1,45 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Trimmed output of a successful `git` command.
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|out| out.trim().to_string())
+        .filter(|out| !out.is_empty())
+}
+
 fn main() {
+    // Commit and build time for `get_version_info`; builds outside a git
+    // checkout report the commit as unknown
+    let git_hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    let built_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    println!("cargo:rustc-env=KIWI_GIT_HASH={}", git_hash);
+    // Only set when this script runs, i.e. when the commit or the script
+    // changes, so it is when the commit was first built rather than the
+    // latest incremental rebuild
+    println!("cargo:rustc-env=KIWI_BUILD_TIMESTAMP={}", built_at);
+
+    // Rerun when HEAD moves: a checkout or detached HEAD rewrites HEAD, a
+    // commit rewrites the branch's loose ref, and `git gc` moves refs into
+    // packed-refs. Paths that don't exist would rerun every build. Refs live
+    // in the repository's common folder, which differs in a worktree.
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        let common_dir = git(&["rev-parse", "--path-format=absolute", "--git-common-dir"]).unwrap_or(git_dir.clone());
+        let (git_dir, common_dir) = (Path::new(&git_dir), Path::new(&common_dir));
+        let mut watched = vec![git_dir.join("HEAD"), common_dir.join("packed-refs")];
+        if let Some(branch) = git(&["rev-parse", "--symbolic-full-name", "HEAD"]).filter(|r| r.starts_with("refs/")) {
+            watched.push(common_dir.join(branch));
+        }
+        for path in watched.iter().filter(|path| path.exists()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+
     tauri_build::build()
 }
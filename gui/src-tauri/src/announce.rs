@@ -0,0 +1,209 @@
+//! Spoken status messages for low-vision users. Announcements play on their
+//! own sink, so they never stop or replace the audio the user is listening
+//! to, and a new announcement cuts off the previous one. They are spoken by
+//! the local speech engine when there is one, which costs no API credits and
+//! still works when the server is unreachable, and by the server otherwise.
+//! During the `quiet_hours` setting's window, status announcements are
+//! skipped; they are the only sounds the app makes on its own, and the
+//! events behind them still reach the UI.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager, State};
+
+use crate::playback::Player;
+use crate::{client, offline, settings, synthesize_bytes, text, AppHandle, TTSRequest};
+
+const MAX_ANNOUNCEMENT_CHARS: usize = 200;
+
+/// Rendered announcements kept in memory, since the same few recur.
+const MAX_CACHED: usize = 32;
+
+/// Daily window, in local time, without status announcements. `start` and
+/// `end` are `HH:MM`; a window whose end is earlier than its start runs past
+/// midnight, and one that starts and ends at the same time is empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: String,
+    pub end: String,
+}
+
+impl QuietHours {
+    fn times(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| format!("Invalid quiet hours time '{}'; use HH:MM, e.g. 22:30", time))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.times().map(|_| ())
+    }
+
+    /// Whether `now` falls in the window; the end minute itself is outside it.
+    pub fn contains(&self, now: NaiveTime) -> bool {
+        let Ok((start, end)) = self.times() else {
+            return false;
+        };
+        if start <= end {
+            start <= now && now < end
+        } else {
+            now >= start || now < end
+        }
+    }
+}
+
+pub fn is_quiet_now(quiet_hours: Option<&QuietHours>) -> bool {
+    quiet_hours.is_some_and(|window| window.contains(chrono::Local::now().time()))
+}
+
+#[derive(Default)]
+pub struct Announcer {
+    player: Player,
+    rendered: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+fn render_locally(text: &str, language: &str) -> Result<Vec<u8>, String> {
+    let voice = offline::list_voices(language)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("The local speech engine has no {} voice", language))?;
+    let path = std::env::temp_dir().join(format!("kiwi-announcement-{}.wav", std::process::id()));
+    let rendered = offline::synthesize_to_file(text, &voice.name, &path)
+        .and_then(|_| std::fs::read(&path).map_err(|e| format!("Failed to read announcement audio: {}", e)));
+    let _ = std::fs::remove_file(&path);
+    rendered
+}
+
+async fn render(app: &AppHandle, text: &str) -> Result<Vec<u8>, String> {
+    let settings = settings::current(app);
+    let voice = settings.announcement_voice.unwrap_or(settings.default_voice);
+    let language = text::language_from_voice(&voice).unwrap_or(settings.default_language);
+
+    let (owned_text, owned_language) = (text.to_string(), language.clone());
+    let local = tauri::async_runtime::spawn_blocking(move || render_locally(&owned_text, &owned_language))
+        .await
+        .unwrap_or_else(|e| Err(format!("Local speech engine crashed: {}", e)));
+    if let Ok(bytes) = local {
+        return Ok(bytes);
+    }
+
+    let request = TTSRequest {
+        text: text.to_string(),
+        voice,
+        format: "MP3".to_string(),
+        language,
+        ..Default::default()
+    };
+    synthesize_bytes(app, &client::build(app), &request).await
+}
+
+async fn speak(app: &AppHandle, text: &str) -> Result<(), String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(());
+    }
+    if text.chars().count() > MAX_ANNOUNCEMENT_CHARS {
+        return Err(format!("Announcements are limited to {} characters", MAX_ANNOUNCEMENT_CHARS));
+    }
+
+    let announcer = app.state::<Announcer>();
+    let cached = announcer.rendered.lock().unwrap().get(text).cloned();
+    let bytes = match cached {
+        Some(bytes) => bytes,
+        None => {
+            let bytes = render(app, text).await?;
+            let mut rendered = announcer.rendered.lock().unwrap();
+            if rendered.len() >= MAX_CACHED {
+                rendered.clear();
+            }
+            rendered.insert(text.to_string(), bytes.clone());
+            bytes
+        }
+    };
+
+    announcer.player.set_device(settings::current(app).output_device);
+    announcer.player.play(app, bytes)
+}
+
+/// Speaks a status message in the background when `announce_status` is on
+/// and it isn't quiet hours.
+pub fn announce(app: &AppHandle, text: &str) {
+    let settings = settings::current(app);
+    if !settings.announce_status || is_quiet_now(settings.quiet_hours.as_ref()) {
+        return;
+    }
+    let (app, text) = (app.clone(), text.to_string());
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = speak(&app, &text).await {
+            println!("Announcement failed: {}", e);
+        }
+    });
+}
+
+/// Speaks `text` as an announcement, interrupting the previous one. Unlike
+/// the built-in status messages this works whether or not `announce_status`
+/// is on, and during quiet hours, so the frontend can offer a preview.
+#[command]
+pub async fn speak_announcement(app: AppHandle, text: String) -> Result<(), String> {
+    speak(&app, &text).await
+}
+
+#[command]
+pub fn stop_announcement(announcer: State<'_, Announcer>) {
+    announcer.player.stop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: &str) -> QuietHours {
+        QuietHours { start: start.to_string(), end: end.to_string() }
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let lunch = window("12:00", "13:30");
+        assert!(lunch.contains(at("12:00")));
+        assert!(lunch.contains(at("13:29")));
+        assert!(!lunch.contains(at("13:30")));
+        assert!(!lunch.contains(at("11:59")));
+    }
+
+    #[test]
+    fn quiet_hours_past_midnight() {
+        let night = window("22:30", "07:00");
+        assert!(night.contains(at("23:00")));
+        assert!(night.contains(at("00:00")));
+        assert!(night.contains(at("06:59")));
+        assert!(!night.contains(at("07:00")));
+        assert!(!night.contains(at("22:29")));
+    }
+
+    #[test]
+    fn quiet_hours_with_equal_ends_is_empty() {
+        let empty = window("09:00", "09:00");
+        assert!(!empty.contains(at("09:00")));
+        assert!(!empty.contains(at("21:00")));
+    }
+
+    #[test]
+    fn quiet_hours_rejects_bad_times() {
+        assert!(window(" 22:30 ", "07:00").validate().is_ok());
+        let err = window("10pm", "07:00").validate().unwrap_err();
+        assert_eq!(err, "Invalid quiet hours time '10pm'; use HH:MM, e.g. 22:30");
+        assert!(window("22:30", "24:00").validate().is_err());
+        // An unreadable window never silences anything
+        assert!(!window("late", "early").contains(at("03:00")));
+        assert!(!is_quiet_now(None));
+    }
+}
@@ -0,0 +1,2337 @@
+use std::fmt;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// Container of an audio buffer returned by the API server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Wav,
+    Mp3,
+    /// Opus in an Ogg container.
+    Ogg,
+    /// AAC in an MP4 container, as `.m4a`.
+    M4a,
+}
+
+impl Container {
+    /// The API format name that produces this container.
+    pub fn format(self) -> &'static str {
+        match self {
+            Container::Mp3 => "MP3",
+            Container::Wav => "LINEAR16",
+            Container::Ogg => "OGG_OPUS",
+            Container::M4a => "M4A",
+        }
+    }
+}
+
+/// Stream parameters that must match for two buffers to be stitched together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpec {
+    pub container: Container,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: Option<u16>,
+}
+
+impl fmt::Display for AudioSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let container = match self.container {
+            Container::Wav => "WAV",
+            Container::Mp3 => "MP3",
+            Container::Ogg => "Ogg Opus",
+            Container::M4a => "M4A AAC",
+        };
+        let channels = if self.channels == 1 { "mono".to_string() } else { format!("{} channels", self.channels) };
+        match self.bits_per_sample {
+            Some(bits) => write!(f, "{} {} Hz {} {}-bit", container, self.sample_rate, channels, bits),
+            None => write!(f, "{} {} Hz {}", container, self.sample_rate, channels),
+        }
+    }
+}
+
+/// PCM WAV file split into its format fields and raw sample data.
+#[derive(Debug, Clone)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub data: Vec<u8>,
+}
+
+impl Wav {
+    pub fn spec(&self) -> AudioSpec {
+        AudioSpec {
+            container: Container::Wav,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            bits_per_sample: Some(self.bits_per_sample),
+        }
+    }
+
+    pub fn block_align(&self) -> usize {
+        self.channels as usize * (self.bits_per_sample as usize / 8)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let block_align = self.block_align() as u16;
+        let byte_rate = self.sample_rate * block_align as u32;
+        let mut out = Vec::with_capacity(44 + self.data.len());
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + self.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+        out.extend_from_slice(b"fmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&self.channels.to_le_bytes());
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        out.extend_from_slice(&byte_rate.to_le_bytes());
+        out.extend_from_slice(&block_align.to_le_bytes());
+        out.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.data);
+        out
+    }
+}
+
+pub fn parse_wav(bytes: &[u8]) -> Result<Wav, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Not a WAV file".to_string());
+    }
+
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let body_start = offset + 8;
+        // Streamed WAVs may carry a placeholder size, so clamp to what we actually have
+        let body_end = body_start.saturating_add(size).min(bytes.len());
+        let body = &bytes[body_start..body_end];
+
+        match id {
+            b"fmt " => {
+                if body.len() < 16 {
+                    return Err("WAV format chunk is truncated".to_string());
+                }
+                let audio_format = u16::from_le_bytes([body[0], body[1]]);
+                // 0xFFFE is WAVE_FORMAT_EXTENSIBLE, which servers use for plain PCM too
+                if audio_format != 1 && audio_format != 0xFFFE {
+                    return Err(format!("Unsupported WAV encoding (format tag {})", audio_format));
+                }
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                let bits_per_sample = u16::from_le_bytes([body[14], body[15]]);
+                if channels == 0 || sample_rate == 0 || bits_per_sample == 0 || !bits_per_sample.is_multiple_of(8) {
+                    return Err("WAV format chunk has invalid parameters".to_string());
+                }
+                format = Some((channels, sample_rate, bits_per_sample));
+            }
+            b"data" => {
+                let (channels, sample_rate, bits_per_sample) =
+                    format.ok_or("WAV data chunk appears before the format chunk")?;
+                return Ok(Wav {
+                    sample_rate,
+                    channels,
+                    bits_per_sample,
+                    data: body.to_vec(),
+                });
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even number of bytes
+        offset = body_start.saturating_add(size + (size & 1));
+    }
+
+    Err("WAV file has no data chunk".to_string())
+}
+
+/// Header fields of a single MPEG audio Layer III frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mp3Header {
+    /// Raw version bits: 0 = MPEG 2.5, 2 = MPEG 2, 3 = MPEG 1.
+    pub version: u8,
+    pub bitrate_index: u8,
+    pub sample_rate_index: u8,
+    pub padding: bool,
+    pub protected: bool,
+    pub channel_mode: u8,
+}
+
+const MP3_BITRATES_V1: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MP3_BITRATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+impl Mp3Header {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = (bytes[1] >> 3) & 0x03;
+        let layer = (bytes[1] >> 1) & 0x03;
+        let bitrate_index = bytes[2] >> 4;
+        let sample_rate_index = (bytes[2] >> 2) & 0x03;
+        // Only Layer III with a fixed bitrate is produced by the TTS API
+        if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+        Some(Mp3Header {
+            version,
+            bitrate_index,
+            sample_rate_index,
+            padding: (bytes[2] >> 1) & 0x01 == 1,
+            protected: bytes[1] & 0x01 == 0,
+            channel_mode: bytes[3] >> 6,
+        })
+    }
+
+    /// Header of a `bitrate_kbps` stream at `sample_rate`, or `None` when
+    /// MP3 has no such sample rate or bitrate.
+    pub fn for_stream(sample_rate: u32, channels: u16, bitrate_kbps: u32) -> Option<Self> {
+        let (version, sample_rate_index) = [3, 2, 0].into_iter().find_map(|version| {
+            let header = |index| Mp3Header {
+                version,
+                bitrate_index: 1,
+                sample_rate_index: index,
+                padding: false,
+                protected: false,
+                channel_mode: 0,
+            };
+            (0..3).find(|&index| header(index).sample_rate() == sample_rate).map(|index| (version, index))
+        })?;
+        let bitrates = if version == 3 { &MP3_BITRATES_V1 } else { &MP3_BITRATES_V2 };
+        let bitrate_index = bitrates.iter().position(|&kbps| kbps == bitrate_kbps).filter(|&i| i > 0)?;
+        Some(Mp3Header {
+            version,
+            bitrate_index: bitrate_index as u8,
+            sample_rate_index,
+            padding: false,
+            protected: false,
+            channel_mode: if channels == 1 { 3 } else { 0 },
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; 4] {
+        [
+            0xFF,
+            0xE0 | (self.version << 3) | (1 << 1) | u8::from(!self.protected),
+            (self.bitrate_index << 4) | (self.sample_rate_index << 2) | (u8::from(self.padding) << 1),
+            self.channel_mode << 6,
+        ]
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        let base = [44100, 48000, 32000][self.sample_rate_index as usize];
+        match self.version {
+            3 => base,
+            2 => base / 2,
+            _ => base / 4,
+        }
+    }
+
+    pub fn bitrate_kbps(&self) -> u32 {
+        if self.version == 3 {
+            MP3_BITRATES_V1[self.bitrate_index as usize]
+        } else {
+            MP3_BITRATES_V2[self.bitrate_index as usize]
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        if self.channel_mode == 3 { 1 } else { 2 }
+    }
+
+    pub fn samples_per_frame(&self) -> u32 {
+        if self.version == 3 { 1152 } else { 576 }
+    }
+
+    pub fn frame_len(&self) -> usize {
+        let bytes_per_slot = self.samples_per_frame() / 8;
+        (bytes_per_slot * self.bitrate_kbps() * 1000 / self.sample_rate()) as usize + usize::from(self.padding)
+    }
+
+    fn side_info_len(&self) -> usize {
+        match (self.version == 3, self.channels() == 1) {
+            (true, true) => 17,
+            (true, false) => 32,
+            (false, true) => 9,
+            (false, false) => 17,
+        }
+    }
+
+    pub fn spec(&self) -> AudioSpec {
+        AudioSpec {
+            container: Container::Mp3,
+            sample_rate: self.sample_rate(),
+            channels: self.channels(),
+            bits_per_sample: None,
+        }
+    }
+}
+
+/// Location of one MP3 frame inside a buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Mp3Frame {
+    pub header: Mp3Header,
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Length of a leading ID3v2 tag, or zero when there is none.
+fn id3v2_len(bytes: &[u8]) -> usize {
+    if bytes.len() < 10 || &bytes[0..3] != b"ID3" {
+        return 0;
+    }
+    let size = bytes[6..10].iter().fold(0usize, |acc, b| (acc << 7) | (*b as usize & 0x7F));
+    let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+    (10 + size + footer).min(bytes.len())
+}
+
+/// Lists the audio frames of an MP3 buffer, skipping ID3 tags and any
+/// Xing/Info/VBRI header frame so the result can be spliced freely.
+pub fn mp3_frames(bytes: &[u8]) -> Vec<Mp3Frame> {
+    let mut frames = Vec::new();
+    let mut offset = id3v2_len(bytes);
+
+    while offset + 4 <= bytes.len() {
+        if &bytes[offset..offset + 3] == b"TAG" && bytes.len() - offset == 128 {
+            break;
+        }
+        match Mp3Header::parse(&bytes[offset..]) {
+            Some(header) if offset + header.frame_len() <= bytes.len() => {
+                let len = header.frame_len();
+                frames.push(Mp3Frame { header, offset, len });
+                offset += len;
+            }
+            // Resynchronise on garbage or a truncated trailing frame
+            _ => offset += 1,
+        }
+    }
+
+    if let Some(first) = frames.first() {
+        if is_info_frame(bytes, first) {
+            frames.remove(0);
+        }
+    }
+
+    frames
+}
+
+fn is_info_frame(bytes: &[u8], frame: &Mp3Frame) -> bool {
+    let frame_bytes = &bytes[frame.offset..frame.offset + frame.len];
+    let xing_at = 4 + if frame.header.protected { 2 } else { 0 } + frame.header.side_info_len();
+    let tag_at = |at: usize| frame_bytes.get(at..at + 4);
+    matches!(tag_at(xing_at), Some(b"Xing") | Some(b"Info")) || tag_at(36) == Some(b"VBRI")
+}
+
+/// A silent frame with the same stream parameters as `template`. All-zero side
+/// information means no Huffman data, which every decoder renders as silence.
+pub fn silent_mp3_frame(template: &Mp3Header) -> Vec<u8> {
+    let header = Mp3Header { padding: false, protected: false, ..*template };
+    let mut frame = vec![0u8; header.frame_len()];
+    frame[..4].copy_from_slice(&header.to_bytes());
+    frame
+}
+
+/// Determines the stream parameters of an encoded buffer.
+pub fn probe_spec(bytes: &[u8]) -> Result<AudioSpec, String> {
+    if bytes.starts_with(b"RIFF") {
+        return parse_wav(bytes).map(|wav| wav.spec());
+    }
+    if bytes.starts_with(b"OggS") {
+        return parse_opus_head(bytes).map(|head| head.spec());
+    }
+    if is_mp4(bytes) {
+        return parse_m4a(bytes).map(|info| info.spec);
+    }
+    mp3_frames(bytes)
+        .first()
+        .map(|frame| frame.header.spec())
+        .ok_or_else(|| "Unrecognized audio data (expected WAV, MP3, Ogg Opus or M4A)".to_string())
+}
+
+/// Decodes the first packet of MP3 or M4A audio, failing when there is none
+/// or it can't be decoded.
+fn decode_first_packet(bytes: &[u8]) -> Result<(), String> {
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::probe::Hint;
+
+    let extension = if is_mp4(bytes) { "m4a" } else { "mp3" };
+    let stream = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes.to_vec())), Default::default());
+    let mut format = symphonia::default::get_probe()
+        .format(Hint::new().with_extension(extension), stream, &Default::default(), &Default::default())
+        .map_err(|e| format!("Unsupported audio data: {}", e))?
+        .format;
+    let track = format.default_track().ok_or("Audio data has no audio track")?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Cannot decode audio: {}", e))?;
+    loop {
+        let packet = format.next_packet().map_err(|e| format!("No audio frames: {}", e))?;
+        if packet.track_id() == track_id {
+            return decoder.decode(&packet).map(|_| ()).map_err(|e| format!("The first frame doesn't decode: {}", e));
+        }
+    }
+}
+
+/// Checks that `bytes` is audio a player can start: not empty, with a valid
+/// header for its format and, for MP3 and M4A, a first frame that decodes.
+/// WAV only needs a sample. Opus can't be decoded here, so an Ogg stream
+/// only needs an `OpusHead` and a last page whose granule position is past
+/// the pre-skip. The rest isn't decoded, so this is quick even for long
+/// files.
+pub fn verify(bytes: &[u8]) -> Result<(), String> {
+    if bytes.is_empty() {
+        return Err("The file is empty".to_string());
+    }
+    let spec = probe_spec(bytes)?;
+    match spec.container {
+        Container::Wav => {
+            let wav = parse_wav(bytes)?;
+            if wav.data.len() < wav.block_align().max(1) {
+                return Err("The WAV file holds no samples".to_string());
+            }
+            Ok(())
+        }
+        Container::Ogg => match opus_samples(bytes) {
+            Some(samples) if samples > 0 => Ok(()),
+            _ => Err("The Ogg stream holds no complete audio".to_string()),
+        },
+        Container::Mp3 | Container::M4a => decode_first_packet(bytes),
+    }
+}
+
+/// Opus always decodes at 48 kHz, whatever rate the input had.
+const OPUS_SAMPLE_RATE: u32 = 48_000;
+
+/// The `OpusHead` packet opening an Ogg Opus stream.
+#[derive(Debug, Clone, Copy)]
+pub struct OpusHead {
+    pub channels: u16,
+    /// Samples at the start the decoder discards.
+    pub pre_skip: u16,
+}
+
+impl OpusHead {
+    pub fn spec(&self) -> AudioSpec {
+        AudioSpec {
+            container: Container::Ogg,
+            sample_rate: OPUS_SAMPLE_RATE,
+            channels: self.channels,
+            bits_per_sample: None,
+        }
+    }
+}
+
+/// Reads the `OpusHead` packet from the first page of an Ogg stream.
+pub fn parse_opus_head(bytes: &[u8]) -> Result<OpusHead, String> {
+    if !bytes.starts_with(b"OggS") || bytes.len() < 27 {
+        return Err("Not an Ogg stream".to_string());
+    }
+    let segments = bytes[26] as usize;
+    let packet = bytes.get(27 + segments..).unwrap_or_default();
+    if !packet.starts_with(b"OpusHead") || packet.len() < 19 {
+        return Err("Ogg stream does not contain Opus audio".to_string());
+    }
+    Ok(OpusHead {
+        channels: packet[9] as u16,
+        pre_skip: u16::from_le_bytes([packet[10], packet[11]]),
+    })
+}
+
+/// Decoded samples per channel in an Ogg Opus buffer, from the granule
+/// position of its last page. Chained streams add up.
+fn opus_samples(bytes: &[u8]) -> Option<u64> {
+    let mut total = 0;
+    let mut rest = bytes;
+    while rest.starts_with(b"OggS") {
+        let head = parse_opus_head(rest).ok()?;
+        // The next stream of a chain starts with a page flagged "beginning of stream"
+        let end = (4..rest.len().saturating_sub(5))
+            .find(|&i| rest[i..].starts_with(b"OggS") && rest[i + 5] & 0x02 != 0)
+            .unwrap_or(rest.len());
+        let stream = &rest[..end];
+        let last_page = (0..stream.len().saturating_sub(14)).rev().find(|&i| stream[i..].starts_with(b"OggS"))?;
+        let granule = u64::from_le_bytes(stream[last_page + 6..last_page + 14].try_into().ok()?);
+        total += granule.saturating_sub(head.pre_skip as u64);
+        rest = &rest[end..];
+    }
+    Some(total)
+}
+
+/// Concatenates encoded buffers of matching format, inserting
+/// `gaps_ms[i]` milliseconds of silence after part `i`.
+pub fn concat_audio(parts: &[Vec<u8>], gaps_ms: &[u64]) -> Result<Vec<u8>, String> {
+    concat_audio_with_progress(parts, gaps_ms, |_| {}).map(|joined| joined.bytes)
+}
+
+/// Output of `concat_audio_with_progress`.
+pub struct Concatenated {
+    pub bytes: Vec<u8>,
+    /// Where each part starts in the output, in milliseconds.
+    pub offsets_ms: Vec<u64>,
+    pub duration_ms: u64,
+}
+
+/// Like `concat_audio`, but also reports where each part lands and calls
+/// `on_part(i)` after part `i` is appended. Every part is checked against
+/// the first before any audio is copied, so a mismatch fails immediately.
+pub fn concat_audio_with_progress(
+    parts: &[Vec<u8>],
+    gaps_ms: &[u64],
+    mut on_part: impl FnMut(usize),
+) -> Result<Concatenated, String> {
+    let first = parts.first().ok_or("No audio parts to concatenate")?;
+    let gap_after = |i: usize| gaps_ms.get(i).copied().unwrap_or(0);
+
+    let expected = probe_spec(first).map_err(|e| format!("Part 1: {}", e))?;
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        let spec = probe_spec(part).map_err(|e| format!("Part {}: {}", i + 1, e))?;
+        if spec != expected {
+            return Err(format!("Part {} is {}, expected {}", i + 1, spec, expected));
+        }
+    }
+
+    let to_ms = |samples: u64| samples * 1000 / expected.sample_rate as u64;
+    let mut offsets_ms = Vec::with_capacity(parts.len());
+    let mut samples: u64 = 0;
+
+    // Ogg streams are joined into a chain, which players read back to back
+    if expected.container == Container::Ogg {
+        if gaps_ms.iter().any(|&gap| gap > 0) {
+            return Err("Silence between parts is not supported for Ogg Opus audio".to_string());
+        }
+        let mut output = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            offsets_ms.push(to_ms(samples));
+            samples += opus_samples(part).ok_or_else(|| format!("Part {} is not a complete Ogg Opus stream", i + 1))?;
+            output.extend_from_slice(part);
+            on_part(i);
+        }
+        return Ok(Concatenated {
+            bytes: output,
+            offsets_ms,
+            duration_ms: to_ms(samples),
+        });
+    }
+
+    // MP4 has no way to append frames, so M4A is decoded, joined and encoded again
+    if expected.container == Container::M4a {
+        let channels = expected.channels as usize;
+        let mut joined: Vec<i16> = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            offsets_ms.push(to_ms(samples));
+            joined.extend(decode_pcm(part).map_err(|e| format!("Part {}: {}", i + 1, e))?);
+            let silence = gap_after(i) * expected.sample_rate as u64 / 1000;
+            joined.resize(joined.len() + silence as usize * channels, 0);
+            samples = (joined.len() / channels) as u64;
+            on_part(i);
+        }
+        return Ok(Concatenated {
+            bytes: encode_m4a(&joined, expected, DEFAULT_AAC_BITRATE_KBPS)?,
+            offsets_ms,
+            duration_ms: to_ms(samples),
+        });
+    }
+
+    if first.starts_with(b"RIFF") {
+        let mut output: Option<Wav> = None;
+        for (i, part) in parts.iter().enumerate() {
+            let wav = parse_wav(part).map_err(|e| format!("Part {}: {}", i + 1, e))?;
+            let out = output.get_or_insert_with(|| Wav { data: Vec::new(), ..wav.clone() });
+            offsets_ms.push(to_ms(samples));
+            out.data.extend_from_slice(&wav.data);
+            let silence = wav_silence(out, gap_after(i));
+            out.data.extend_from_slice(&silence);
+            samples = (out.data.len() / out.block_align()) as u64;
+            on_part(i);
+        }
+        return Ok(Concatenated {
+            bytes: output.map(|wav| wav.to_bytes()).unwrap_or_default(),
+            offsets_ms,
+            duration_ms: to_ms(samples),
+        });
+    }
+
+    let mut output = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        let frames = mp3_frames(part);
+        let header = frames.first().map(|f| f.header).ok_or_else(|| format!("Part {} contains no MP3 audio", i + 1))?;
+        offsets_ms.push(to_ms(samples));
+        for frame in &frames {
+            output.extend_from_slice(&part[frame.offset..frame.offset + frame.len]);
+            samples += frame.header.samples_per_frame() as u64;
+        }
+        let silence = mp3_silence(&header, gap_after(i));
+        let silent_frames = silence.len() / silent_mp3_frame(&header).len().max(1);
+        samples += silent_frames as u64 * header.samples_per_frame() as u64;
+        output.extend_from_slice(&silence);
+        on_part(i);
+    }
+    Ok(Concatenated {
+        bytes: output,
+        offsets_ms,
+        duration_ms: to_ms(samples),
+    })
+}
+
+/// Like `concat_audio`, but each part fades into the next over
+/// `crossfade_ms` instead of cutting hard. The parts are decoded to PCM,
+/// mixed where they overlap and re-encoded in their own format, so the
+/// output is shorter than the parts combined by one crossfade per join.
+/// `on_part(i)` is called after part `i` is mixed in. A crossfade of 0 is a
+/// plain `concat_audio_with_progress`.
+pub fn concat_with_crossfade(
+    parts: &[Vec<u8>],
+    crossfade_ms: u64,
+    mut on_part: impl FnMut(usize),
+) -> Result<Concatenated, String> {
+    if crossfade_ms == 0 {
+        return concat_audio_with_progress(parts, &[], on_part);
+    }
+    let first = parts.first().ok_or("No audio parts to concatenate")?;
+    let expected = probe_spec(first).map_err(|e| format!("Part 1: {}", e))?;
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        let spec = probe_spec(part).map_err(|e| format!("Part {}: {}", i + 1, e))?;
+        if spec != expected {
+            return Err(format!("Part {} is {}, expected {}", i + 1, spec, expected));
+        }
+    }
+    if expected.container == Container::Ogg {
+        return Err("Crossfading is not supported for Ogg Opus audio".to_string());
+    }
+
+    let channels = expected.channels as usize;
+    let fade_frames = (crossfade_ms * expected.sample_rate as u64 / 1000) as usize;
+    let mut mixed: Vec<i16> = Vec::new();
+    let mut offsets_ms = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        let samples = decode_pcm(part).map_err(|e| format!("Part {}: {}", i + 1, e))?;
+        // A fade can't be longer than either side of the join
+        let overlap = fade_frames.min(mixed.len() / channels).min(samples.len() / channels) * channels;
+        let start = mixed.len() - overlap;
+        offsets_ms.push((start / channels) as u64 * 1000 / expected.sample_rate as u64);
+
+        for (j, &incoming) in samples[..overlap].iter().enumerate() {
+            let t = (j / channels) as f32 / (overlap / channels) as f32;
+            let outgoing = mixed[start + j] as f32;
+            mixed[start + j] = (outgoing * (1.0 - t) + incoming as f32 * t).round() as i16;
+        }
+        mixed.extend_from_slice(&samples[overlap..]);
+        on_part(i);
+    }
+
+    let duration_ms = (mixed.len() / channels) as u64 * 1000 / expected.sample_rate as u64;
+    let bytes = match expected.container {
+        Container::Mp3 => {
+            let bitrate = mp3_frames(first).first().map_or(32, |frame| frame.header.bitrate_kbps());
+            encode_mp3(&mixed, expected, bitrate)?
+        }
+        Container::M4a => encode_m4a(&mixed, expected, DEFAULT_AAC_BITRATE_KBPS)?,
+        _ => Wav {
+            sample_rate: expected.sample_rate,
+            channels: expected.channels,
+            bits_per_sample: 16,
+            data: mixed.iter().flat_map(|sample| sample.to_le_bytes()).collect(),
+        }
+        .to_bytes(),
+    };
+    Ok(Concatenated {
+        bytes,
+        offsets_ms,
+        duration_ms,
+    })
+}
+
+/// Decodes 16-bit WAV, MP3 or M4A into interleaved samples.
+fn decode_pcm(bytes: &[u8]) -> Result<Vec<i16>, String> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::probe::Hint;
+
+    if bytes.starts_with(b"RIFF") {
+        let wav = parse_wav(bytes)?;
+        if wav.bits_per_sample != 16 {
+            return Err(format!("Only 16-bit WAV can be mixed, not {}", wav.spec()));
+        }
+        return Ok(wav.data.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect());
+    }
+
+    let m4a = is_mp4(bytes).then(|| parse_m4a(bytes)).transpose()?;
+    let extension = if m4a.is_some() { "m4a" } else { "mp3" };
+    let stream = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes.to_vec())), Default::default());
+    let mut format = symphonia::default::get_probe()
+        .format(Hint::new().with_extension(extension), stream, &Default::default(), &Default::default())
+        .map_err(|e| format!("Unsupported audio data: {}", e))?
+        .format;
+    let track = format.default_track().ok_or("Audio data has no audio track")?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Cannot decode audio: {}", e))?;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(format!("Failed to read audio: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+                buffer.copy_interleaved_ref(decoded);
+                samples.extend_from_slice(buffer.samples());
+            }
+            // A damaged frame is skipped, as a player would
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        }
+    }
+
+    // Apply the edit list the decoder ignores
+    if let Some(info) = m4a {
+        let channels = info.spec.channels as usize;
+        let frames = (info.duration_secs * info.spec.sample_rate as f64).round() as usize;
+        let start = (info.priming_samples as usize * channels).min(samples.len());
+        samples.drain(..start);
+        samples.truncate(frames * channels);
+    }
+    Ok(samples)
+}
+
+/// Averages each frame of interleaved samples into one mono sample.
+pub fn downmix(samples: &[i16], channels: u16) -> Vec<i16> {
+    let channels = channels.max(1) as usize;
+    samples
+        .chunks_exact(channels)
+        .map(|frame| (frame.iter().map(|&s| s as f64).sum::<f64>() / channels as f64).round() as i16)
+        .collect()
+}
+
+/// Converts WAV, MP3 or M4A audio to mono, by averaging the channels, or to
+/// stereo, by repeating the mono mix on both sides. Audio that already has
+/// `channels` channels is returned as is.
+pub fn set_channels(bytes: &[u8], channels: u16) -> Result<Vec<u8>, String> {
+    let spec = probe_spec(bytes)?;
+    if spec.channels == channels {
+        return Ok(bytes.to_vec());
+    }
+    if !(1..=2).contains(&channels) {
+        return Err(format!("Unsupported channel count {}; use 1 (mono) or 2 (stereo)", channels));
+    }
+    if spec.container == Container::Ogg {
+        return Err("Ogg Opus can't be re-encoded locally to change its channels".to_string());
+    }
+
+    let mono = downmix(&decode_pcm(bytes)?, spec.channels);
+    let samples: Vec<i16> = if channels == 1 { mono } else { mono.iter().flat_map(|&s| [s, s]).collect() };
+    encode_like(bytes, &samples, AudioSpec { channels, ..spec })
+}
+
+/// Encodes samples decoded from `bytes` in the same format: MP3 at its
+/// bitrate, M4A at the default AAC bitrate, anything else as 16-bit WAV.
+fn encode_like(bytes: &[u8], samples: &[i16], spec: AudioSpec) -> Result<Vec<u8>, String> {
+    match spec.container {
+        Container::Mp3 => {
+            let frame = mp3_frames(bytes).into_iter().next().ok_or("No MP3 frames found")?;
+            encode_mp3(samples, spec, frame.header.bitrate_kbps())
+        }
+        Container::M4a => encode_m4a(samples, spec, DEFAULT_AAC_BITRATE_KBPS),
+        _ => Ok(Wav {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            bits_per_sample: 16,
+            data: samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+        }
+        .to_bytes()),
+    }
+}
+
+/// Encodes interleaved samples as constant bitrate MP3, at the closest rate
+/// LAME offers to `bitrate_kbps`.
+fn encode_mp3(samples: &[i16], spec: AudioSpec, bitrate_kbps: u32) -> Result<Vec<u8>, String> {
+    use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm, MonoPcm, Quality};
+
+    const BITRATES: [(u32, Bitrate); 16] = [
+        (8, Bitrate::Kbps8),
+        (16, Bitrate::Kbps16),
+        (24, Bitrate::Kbps24),
+        (32, Bitrate::Kbps32),
+        (40, Bitrate::Kbps40),
+        (48, Bitrate::Kbps48),
+        (64, Bitrate::Kbps64),
+        (80, Bitrate::Kbps80),
+        (96, Bitrate::Kbps96),
+        (112, Bitrate::Kbps112),
+        (128, Bitrate::Kbps128),
+        (160, Bitrate::Kbps160),
+        (192, Bitrate::Kbps192),
+        (224, Bitrate::Kbps224),
+        (256, Bitrate::Kbps256),
+        (320, Bitrate::Kbps320),
+    ];
+    let bitrate = BITRATES
+        .iter()
+        .min_by_key(|(kbps, _)| kbps.abs_diff(bitrate_kbps))
+        .map_or(Bitrate::Kbps32, |(_, bitrate)| *bitrate);
+
+    let build_error = |e: mp3lame_encoder::BuildError| format!("Cannot set up the MP3 encoder: {:?}", e);
+    let mut encoder = Builder::new()
+        .ok_or("Cannot set up the MP3 encoder")?
+        .with_num_channels(spec.channels as u8)
+        .and_then(|b| b.with_sample_rate(spec.sample_rate))
+        // Left alone, LAME resamples low bitrates, which would break later joins
+        .and_then(|b| b.with_output_sample_rate(std::num::NonZeroU32::new(spec.sample_rate)))
+        .and_then(|b| b.with_brate(bitrate))
+        .and_then(|b| b.with_quality(Quality::Good))
+        .and_then(|b| b.build())
+        .map_err(build_error)?;
+
+    let frames = samples.len() / spec.channels.max(1) as usize;
+    let mut output = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(frames));
+    let encoded = if spec.channels == 1 {
+        encoder.encode_to_vec(MonoPcm(samples), &mut output)
+    } else {
+        encoder.encode_to_vec(InterleavedPcm(samples), &mut output)
+    };
+    encoded.map_err(|e| format!("Failed to encode MP3: {:?}", e))?;
+    // The final frames need at least 7200 bytes of room
+    output.reserve(7200);
+    encoder
+        .flush_to_vec::<FlushNoGap>(&mut output)
+        .map_err(|e| format!("Failed to encode MP3: {:?}", e))?;
+    Ok(output)
+}
+
+/// Bitrate of locally encoded AAC; speech needs no more.
+pub const DEFAULT_AAC_BITRATE_KBPS: u32 = 64;
+
+/// Samples in one AAC-LC frame.
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+fn is_mp4(bytes: &[u8]) -> bool {
+    bytes.get(4..8) == Some(b"ftyp")
+}
+
+/// The boxes of type `kind` directly inside `data`, as their bodies.
+fn mp4_boxes<'a>(data: &'a [u8], kind: &'a [u8; 4]) -> impl Iterator<Item = &'a [u8]> + 'a {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        while rest.len() >= 8 {
+            let size = u32::from_be_bytes(rest[..4].try_into().ok()?) as usize;
+            let (size, header) = match size {
+                0 => (rest.len(), 8),
+                1 => (u64::from_be_bytes(rest.get(8..16)?.try_into().ok()?) as usize, 16),
+                size => (size, 8),
+            };
+            if size < header || size > rest.len() {
+                return None;
+            }
+            let (current, next) = rest.split_at(size);
+            rest = next;
+            if &current[4..8] == kind {
+                return Some(&current[header..]);
+            }
+        }
+        None
+    })
+}
+
+fn mp4_box_at<'a>(data: &'a [u8], path: &[&'a [u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |body, kind| mp4_boxes(body, kind).next())
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    bytes.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// What `probe_spec` and `duration_secs` need from an M4A file.
+#[derive(Debug, Clone, Copy)]
+pub struct M4aInfo {
+    pub spec: AudioSpec,
+    pub duration_secs: f64,
+    /// Encoder priming samples the edit list skips; decoders return them anyway.
+    pub priming_samples: u64,
+}
+
+/// Reads the first AAC track's format and the movie's length from an MP4 file.
+pub fn parse_m4a(bytes: &[u8]) -> Result<M4aInfo, String> {
+    let moov = mp4_box_at(bytes, &[b"moov"]).ok_or("MP4 file has no movie header")?;
+    let (trak, entry) = mp4_boxes(moov, b"trak")
+        .find_map(|trak| {
+            let stsd = mp4_box_at(trak, &[b"mdia", b"minf", b"stbl", b"stsd"])?;
+            // Skip the full box header and entry count to reach the first sample entry
+            Some((trak, mp4_boxes(stsd.get(8..)?, b"mp4a").next()?))
+        })
+        .ok_or("MP4 file contains no AAC audio")?;
+    let priming_samples = mp4_box_at(trak, &[b"edts", b"elst"])
+        .filter(|elst| elst.first() == Some(&0))
+        .and_then(|elst| be_u32(elst, 12))
+        .map_or(0, u64::from);
+    let channels = entry.get(16..18).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let sample_rate = be_u32(entry, 24).map(|rate| rate >> 16);
+
+    let mvhd = mp4_box_at(moov, &[b"mvhd"]).ok_or("MP4 file has no movie header")?;
+    let (timescale, duration) = match mvhd.first() {
+        Some(1) => (be_u32(mvhd, 20), mvhd.get(24..32).map(|b| u64::from_be_bytes(b.try_into().unwrap()))),
+        _ => (be_u32(mvhd, 12), be_u32(mvhd, 16).map(u64::from)),
+    };
+
+    match (channels, sample_rate, timescale.filter(|&t| t > 0), duration) {
+        (Some(channels), Some(sample_rate), Some(timescale), Some(duration)) => Ok(M4aInfo {
+            spec: AudioSpec {
+                container: Container::M4a,
+                sample_rate,
+                channels,
+                bits_per_sample: None,
+            },
+            duration_secs: duration as f64 / timescale as f64,
+            priming_samples,
+        }),
+        _ => Err("MP4 audio track header is truncated".to_string()),
+    }
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// A version 0 full box.
+fn mp4_full_box(kind: &[u8; 4], flags: u32, body: &[u8]) -> Vec<u8> {
+    mp4_box(kind, &[&flags.to_be_bytes()[..], body].concat())
+}
+
+/// An MPEG-4 descriptor, as found in `esds`; ours are all under 128 bytes.
+fn mp4_descriptor(tag: u8, body: &[u8]) -> Vec<u8> {
+    [&[tag, body.len() as u8][..], body].concat()
+}
+
+fn be_u32s(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+/// Identity transformation, as every movie and track header carries one.
+const MP4_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Encodes interleaved samples as AAC-LC in an M4A file, with the movie
+/// header (`moov`) ahead of the audio so players can start before the end
+/// has loaded. An edit list hides the encoder's priming samples.
+fn encode_m4a(samples: &[i16], spec: AudioSpec, bitrate_kbps: u32) -> Result<Vec<u8>, String> {
+    use fdk_aac::enc::{AudioObjectType, BitRate, ChannelMode, Encoder, EncoderParams, Transport};
+
+    let channel_mode = match spec.channels {
+        1 => ChannelMode::Mono,
+        2 => ChannelMode::Stereo,
+        channels => return Err(format!("AAC encoding supports mono or stereo, not {} channels", channels)),
+    };
+    let encoder = Encoder::new(EncoderParams {
+        bit_rate: BitRate::Cbr(bitrate_kbps * 1000),
+        sample_rate: spec.sample_rate,
+        transport: Transport::Raw,
+        channels: channel_mode,
+        audio_object_type: AudioObjectType::Mpeg4LowComplexity,
+    })
+    .map_err(|e| format!("Cannot set up the AAC encoder: {}", e))?;
+    let info = encoder.info().map_err(|e| format!("Cannot set up the AAC encoder: {}", e))?;
+    let config = &info.confBuf[..info.confSize as usize];
+    let channels = spec.channels as usize;
+
+    // Trailing silence pushes the last real samples out past the encoder delay
+    let mut input = samples.to_vec();
+    input.resize(input.len() + (info.nDelay + AAC_FRAME_SAMPLES) as usize * channels, 0);
+    let mut frames: Vec<Vec<u8>> = Vec::new();
+    let mut buffer = vec![0u8; info.maxOutBufBytes.max(8192) as usize];
+    let mut position = 0;
+    while position < input.len() {
+        let end = (position + AAC_FRAME_SAMPLES as usize * channels).min(input.len());
+        let encoded = encoder
+            .encode(&input[position..end], &mut buffer)
+            .map_err(|e| format!("Failed to encode AAC: {}", e))?;
+        if encoded.input_consumed == 0 && encoded.output_size == 0 {
+            break;
+        }
+        position += encoded.input_consumed;
+        if encoded.output_size > 0 {
+            frames.push(buffer[..encoded.output_size].to_vec());
+        }
+    }
+
+    let length = (samples.len() / channels) as u32;
+    let media_length = frames.len() as u32 * AAC_FRAME_SAMPLES;
+    let bitrate = bitrate_kbps * 1000;
+    let esds = mp4_full_box(
+        b"esds",
+        0,
+        &mp4_descriptor(
+            0x03,
+            &[
+                &[0, 1, 0][..],
+                &mp4_descriptor(
+                    0x04,
+                    &[&[0x40, 0x15, 0, 0x18, 0][..], &be_u32s(&[bitrate, bitrate]), &mp4_descriptor(0x05, config)].concat(),
+                ),
+                &mp4_descriptor(0x06, &[0x02]),
+            ]
+            .concat(),
+        ),
+    );
+    let mp4a = mp4_box(
+        b"mp4a",
+        &[
+            &[0, 0, 0, 0, 0, 0, 0, 1][..],
+            &[0; 8],
+            &spec.channels.to_be_bytes(),
+            &16u16.to_be_bytes(),
+            &[0; 4],
+            &(spec.sample_rate << 16).to_be_bytes(),
+            &esds,
+        ]
+        .concat(),
+    );
+
+    let moov = |data_offset: u32| {
+        let sizes: Vec<u32> = frames.iter().map(|frame| frame.len() as u32).collect();
+        let stbl = [
+            mp4_full_box(b"stsd", 0, &[&1u32.to_be_bytes()[..], &mp4a].concat()),
+            mp4_full_box(b"stts", 0, &be_u32s(&[1, frames.len() as u32, AAC_FRAME_SAMPLES])),
+            mp4_full_box(b"stsc", 0, &be_u32s(&[1, 1, frames.len() as u32, 1])),
+            mp4_full_box(b"stsz", 0, &be_u32s(&[&[0, frames.len() as u32][..], &sizes].concat())),
+            mp4_full_box(b"stco", 0, &be_u32s(&[1, data_offset])),
+        ]
+        .concat();
+        let dinf = mp4_box(b"dinf", &mp4_full_box(b"dref", 0, &[&1u32.to_be_bytes()[..], &mp4_full_box(b"url ", 1, &[])].concat()));
+        let minf = [mp4_full_box(b"smhd", 0, &[0; 4]), dinf, mp4_box(b"stbl", &stbl)].concat();
+        let mdia = [
+            // Language "und", packed as three 5-bit letters
+            mp4_full_box(b"mdhd", 0, &[&be_u32s(&[0, 0, spec.sample_rate, media_length])[..], &[0x55, 0xc4, 0, 0]].concat()),
+            mp4_full_box(b"hdlr", 0, &[&[0; 4][..], b"soun", &[0; 12], b"SoundHandler\0"].concat()),
+            mp4_box(b"minf", &minf),
+        ]
+        .concat();
+        let edts = mp4_box(b"edts", &mp4_full_box(b"elst", 0, &be_u32s(&[1, length, info.nDelay, 0x0001_0000])));
+        let tkhd = [
+            &be_u32s(&[0, 0, 1, 0, length, 0, 0])[..],
+            &[0, 0, 0, 0, 0x01, 0x00, 0, 0],
+            &be_u32s(&MP4_MATRIX),
+            &[0; 8],
+        ]
+        .concat();
+        let trak = [mp4_full_box(b"tkhd", 3, &tkhd), edts, mp4_box(b"mdia", &mdia)].concat();
+        let mvhd = [
+            &be_u32s(&[0, 0, spec.sample_rate, length, 0x0001_0000])[..],
+            &[0x01, 0x00],
+            &[0; 10],
+            &be_u32s(&MP4_MATRIX),
+            &[0; 24],
+            &2u32.to_be_bytes(),
+        ]
+        .concat();
+        mp4_box(b"moov", &[mp4_full_box(b"mvhd", 0, &mvhd), mp4_box(b"trak", &trak)].concat())
+    };
+
+    let ftyp = mp4_box(b"ftyp", &[&b"M4A "[..], &[0; 4], b"M4A ", b"mp42", b"isom"].concat());
+    // The chunk offset has a fixed width, so the header is the same size either way
+    let data_offset = (ftyp.len() + moov(0).len() + 8) as u32;
+    let mdat = mp4_box(b"mdat", &frames.concat());
+    Ok([ftyp, moov(data_offset), mdat].concat())
+}
+
+/// Encodes 16-bit WAV as M4A, for servers that can't produce AAC themselves.
+pub fn wav_to_m4a(wav_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let wav = parse_wav(wav_bytes)?;
+    if wav.bits_per_sample != 16 {
+        return Err(format!("Only 16-bit WAV can be encoded as AAC, not {}", wav.spec()));
+    }
+    let samples: Vec<i16> = wav.data.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect();
+    encode_m4a(&samples, wav.spec(), DEFAULT_AAC_BITRATE_KBPS)
+}
+
+fn wav_silence(wav: &Wav, duration_ms: u64) -> Vec<u8> {
+    let frames = (duration_ms * wav.sample_rate as u64 / 1000) as usize;
+    // 8-bit PCM is unsigned, so its midpoint rather than zero is silent
+    let fill = if wav.bits_per_sample == 8 { 0x80 } else { 0 };
+    vec![fill; frames * wav.block_align()]
+}
+
+fn mp3_silence(template: &Mp3Header, duration_ms: u64) -> Vec<u8> {
+    let samples = duration_ms * template.sample_rate() as u64 / 1000;
+    let per_frame = template.samples_per_frame() as u64;
+    let count = (samples + per_frame / 2) / per_frame;
+    silent_mp3_frame(template).repeat(count as usize)
+}
+
+/// `duration_ms` of silence as a file of its own in `format`: 16-bit WAV for
+/// LINEAR16, 32 kbps MP3 or M4A. Ogg Opus can't be encoded locally.
+pub fn generate_silence(duration_ms: u64, format: &str, sample_rate: u32, channels: u8) -> Result<Vec<u8>, String> {
+    if !(1..=2).contains(&channels) {
+        return Err(format!("Unsupported channel count {}; use 1 (mono) or 2 (stereo)", channels));
+    }
+    if !(8000..=48000).contains(&sample_rate) {
+        return Err(format!("Unsupported sample rate {} Hz; use 8000 to 48000 Hz", sample_rate));
+    }
+    let channels = channels as u16;
+    match normalize_format(format)? {
+        "LINEAR16" => {
+            let wav = Wav {
+                sample_rate,
+                channels,
+                bits_per_sample: 16,
+                data: Vec::new(),
+            };
+            Ok(Wav {
+                data: wav_silence(&wav, duration_ms),
+                ..wav
+            }
+            .to_bytes())
+        }
+        "MP3" => {
+            let kbps = DEFAULT_BITRATES_KBPS[0].1;
+            let header = Mp3Header::for_stream(sample_rate, channels, kbps)
+                .ok_or_else(|| format!("MP3 has no {} Hz sample rate", sample_rate))?;
+            Ok(mp3_silence(&header, duration_ms))
+        }
+        "M4A" => {
+            let spec = AudioSpec {
+                container: Container::M4a,
+                sample_rate,
+                channels,
+                bits_per_sample: None,
+            };
+            let frames = duration_ms * sample_rate as u64 / 1000;
+            encode_m4a(&vec![0; frames as usize * channels as usize], spec, DEFAULT_AAC_BITRATE_KBPS)
+        }
+        _ => Err("Ogg Opus can't be encoded locally; use MP3, LINEAR16 or M4A".to_string()),
+    }
+}
+
+/// `duration_ms` of silence encoded exactly like `bytes`, so the two can be
+/// joined.
+fn silence_like(bytes: &[u8], duration_ms: u64) -> Result<Vec<u8>, String> {
+    let spec = probe_spec(bytes)?;
+    match spec.container {
+        Container::Wav => {
+            let wav = parse_wav(bytes)?;
+            Ok(Wav {
+                data: wav_silence(&wav, duration_ms),
+                ..wav
+            }
+            .to_bytes())
+        }
+        Container::Mp3 => {
+            let frame = mp3_frames(bytes).into_iter().next().ok_or("No MP3 frames found")?;
+            Ok(mp3_silence(&frame.header, duration_ms))
+        }
+        Container::M4a => generate_silence(duration_ms, "M4A", spec.sample_rate, spec.channels as u8),
+        Container::Ogg => Err("Silence can't be added to Ogg Opus audio".to_string()),
+    }
+}
+
+/// Splices `duration_ms` of silence into `bytes` at `position_ms`, keeping
+/// its format. Cuts are as precise as `trim` makes them, so MP3 audio less
+/// than a frame from either end is dropped.
+pub fn insert_silence(bytes: &[u8], position_ms: u64, duration_ms: u64) -> Result<Vec<u8>, String> {
+    let total_ms = duration_secs(bytes)
+        .map(|secs| (secs * 1000.0).round() as u64)
+        .ok_or("Unrecognized audio data (expected WAV, MP3 or M4A)")?;
+    if position_ms > total_ms {
+        return Err(format!("Position ({} ms) is past the end of the audio ({} ms)", position_ms, total_ms));
+    }
+    let frame_ms = mp3_frames(bytes)
+        .first()
+        .map_or(1, |frame| frame.header.samples_per_frame() as u64 * 1000 / frame.header.sample_rate() as u64);
+    let piece = |start_ms: u64, end_ms: Option<u64>, len_ms: u64| match trim(bytes, start_ms, end_ms) {
+        Ok(piece) => Ok(Some(piece)),
+        Err(_) if len_ms < frame_ms => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    let head = piece(0, Some(position_ms), position_ms)?;
+    let tail = piece(position_ms, None, total_ms - position_ms)?;
+    let parts: Vec<Vec<u8>> = head
+        .into_iter()
+        .chain(std::iter::once(silence_like(bytes, duration_ms)?))
+        .chain(tail)
+        .collect();
+    concat_audio(&parts, &[])
+}
+
+/// Playback length of an encoded buffer, counted from PCM frames for WAV,
+/// from the actual frame headers for MP3 so VBR files come out right and
+/// from the final granule position for Ogg Opus and from the movie header
+/// for M4A.
+pub fn duration_secs(bytes: &[u8]) -> Option<f64> {
+    if bytes.starts_with(b"RIFF") {
+        let wav = parse_wav(bytes).ok()?;
+        let frames = wav.data.len() / wav.block_align();
+        return Some(frames as f64 / wav.sample_rate as f64);
+    }
+    if bytes.starts_with(b"OggS") {
+        return opus_samples(bytes).map(|samples| samples as f64 / OPUS_SAMPLE_RATE as f64);
+    }
+    if is_mp4(bytes) {
+        return parse_m4a(bytes).ok().map(|info| info.duration_secs);
+    }
+
+    let frames = mp3_frames(bytes);
+    if frames.is_empty() {
+        return None;
+    }
+    Some(
+        frames
+            .iter()
+            .map(|frame| frame.header.samples_per_frame() as f64 / frame.header.sample_rate() as f64)
+            .sum(),
+    )
+}
+
+/// Length in seconds of the audio file at `path`. WAV is computed from the
+/// sample count, MP3 from every frame header, so VBR files are exact, and
+/// M4A from its movie header.
+/// Other formats (OGG and anything else symphonia reads) use the frame count
+/// in the container, falling back to summing packet durations without
+/// decoding when the container doesn't record one.
+pub fn audio_duration(path: &std::path::Path) -> Result<f64, String> {
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    // Other containers can contain byte runs that look like MP3 frame headers
+    if is_mp4(&bytes) {
+        if let Ok(info) = parse_m4a(&bytes) {
+            return Ok(info.duration_secs);
+        }
+    }
+    let other_container = bytes.starts_with(b"OggS") || bytes.starts_with(b"fLaC") || is_mp4(&bytes);
+    if bytes.starts_with(b"RIFF") || (!other_container && !mp3_frames(&bytes).is_empty()) {
+        if let Some(secs) = duration_secs(&bytes) {
+            return Ok(secs);
+        }
+    }
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes)), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Unsupported audio file {}: {}", path.display(), e))?;
+    let mut format = probed.format;
+
+    let track = format.default_track().ok_or("Audio file has no audio track")?;
+    let (track_id, params) = (track.id, track.codec_params.clone());
+    let time_base = params
+        .time_base
+        .or_else(|| params.sample_rate.map(|rate| symphonia::core::units::TimeBase::new(1, rate)))
+        .ok_or("Audio file does not record its sample rate")?;
+
+    let frames = match params.n_frames {
+        Some(frames) => frames,
+        None => {
+            let mut total = 0;
+            while let Ok(packet) = format.next_packet() {
+                if packet.track_id() == track_id {
+                    total += packet.dur;
+                }
+            }
+            total
+        }
+    };
+    let time = time_base.calc_time(frames);
+    Ok(time.seconds as f64 + time.frac)
+}
+
+/// What `probe_audio` found out about a file; anything the container
+/// doesn't record is `None`.
+#[derive(Debug, Default, Serialize)]
+pub struct AudioInfo {
+    /// MP3, WAV, OGG, FLAC or M4A.
+    pub container: String,
+    /// Codec short name, e.g. `mp3`, `opus` or `aac`.
+    pub codec: Option<String>,
+    pub duration_seconds: Option<f64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Average over the whole file, container overhead included.
+    pub bitrate_kbps: Option<u32>,
+}
+
+fn container_name(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"RIFF") {
+        Some("WAV")
+    } else if bytes.starts_with(b"OggS") {
+        Some("OGG")
+    } else if bytes.starts_with(b"fLaC") {
+        Some("FLAC")
+    } else if is_mp4(bytes) {
+        Some("M4A")
+    } else if !mp3_frames(bytes).is_empty() {
+        Some("MP3")
+    } else {
+        None
+    }
+}
+
+/// Reads the container and codec parameters of any MP3, WAV, OGG, FLAC or
+/// M4A file, not only ones KIWI made, without decoding the audio.
+pub fn probe_audio(path: &std::path::Path) -> Result<AudioInfo, String> {
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let container = container_name(&bytes).ok_or_else(|| {
+        format!("{} is not an audio file KIWI recognizes; use MP3, WAV, OGG, FLAC or M4A", path.display())
+    })?;
+    let file_bytes = bytes.len();
+    // Fills in what symphonia leaves out, such as the channels of AAC in MP4
+    let spec = probe_spec(&bytes).ok();
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes)), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("Cannot read {} as {}: {}", path.display(), container, e))?;
+    let track = probed
+        .format
+        .default_track()
+        .ok_or_else(|| format!("{} has no audio track", path.display()))?;
+    let params = &track.codec_params;
+
+    let duration_seconds = audio_duration(path).ok();
+    Ok(AudioInfo {
+        container: container.to_string(),
+        // Only codecs with a decoder are registered, which leaves out Opus
+        codec: symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|codec| codec.short_name.to_string())
+            .or_else(|| (params.codec == symphonia::core::codecs::CODEC_TYPE_OPUS).then(|| "opus".to_string())),
+        sample_rate: params.sample_rate.or(spec.map(|spec| spec.sample_rate)),
+        channels: params
+            .channels
+            .map(|channels| channels.count() as u16)
+            .or(spec.map(|spec| spec.channels)),
+        bitrate_kbps: duration_seconds
+            .filter(|&secs| secs > 0.0)
+            .map(|secs| (file_bytes as f64 * 8.0 / secs / 1000.0).round() as u32),
+        duration_seconds,
+    })
+}
+
+/// Regions `clipping` groups clipped samples into.
+const CLIPPING_REGION_MS: u64 = 100;
+
+/// How many of the regions with the most clipping are reported.
+const WORST_CLIPPING_REGIONS: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct ClippedRegion {
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+    pub clipped_samples: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClippingReport {
+    pub clipped_samples: u64,
+    pub total_samples: u64,
+    pub clipped_percent: f64,
+    /// The stretches with the most clipping, in playback order.
+    pub worst_regions: Vec<ClippedRegion>,
+}
+
+/// Counts the samples of WAV, MP3 or M4A audio at full scale, where loud
+/// audio was cut off and likely sounds distorted.
+pub fn clipping(bytes: &[u8]) -> Result<ClippingReport, String> {
+    let spec = probe_spec(bytes)?;
+    let samples = decode_pcm(bytes)?;
+    let region_len = (CLIPPING_REGION_MS * spec.sample_rate as u64 / 1000) as usize * spec.channels.max(1) as usize;
+    let clipped = |region: &[i16]| region.iter().filter(|&&s| s == i16::MAX || s == i16::MIN).count() as u64;
+
+    let mut regions: Vec<(usize, u64)> = samples
+        .chunks(region_len.max(1))
+        .map(clipped)
+        .enumerate()
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    let clipped_samples = regions.iter().map(|(_, count)| count).sum();
+    regions.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    regions.truncate(WORST_CLIPPING_REGIONS);
+    regions.sort_by_key(|(index, _)| *index);
+
+    let seconds = |region: usize| (region as u64 * CLIPPING_REGION_MS) as f64 / 1000.0;
+    let duration = samples.len() as f64 / spec.channels.max(1) as f64 / spec.sample_rate as f64;
+    Ok(ClippingReport {
+        clipped_samples,
+        total_samples: samples.len() as u64,
+        clipped_percent: if samples.is_empty() { 0.0 } else { clipped_samples as f64 * 100.0 / samples.len() as f64 },
+        worst_regions: regions
+            .into_iter()
+            .map(|(index, clipped_samples)| ClippedRegion {
+                start_seconds: seconds(index),
+                end_seconds: seconds(index + 1).min(duration),
+                clipped_samples,
+            })
+            .collect(),
+    })
+}
+
+pub fn detect_clipping(path: &std::path::Path) -> Result<ClippingReport, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    clipping(&bytes)
+}
+
+/// Cuts `bytes` down to `start_ms..end_ms` (`None` meaning the end). WAV is
+/// cut at the exact sample without re-encoding. MP3 and M4A are decoded, cut
+/// at the sample and encoded again: copying whole MP3 frames would keep
+/// frames whose bit reservoir points into frames left out, and the first of
+/// them decodes as a glitch.
+pub fn trim(bytes: &[u8], start_ms: u64, end_ms: Option<u64>) -> Result<Vec<u8>, String> {
+    let duration_ms = duration_secs(bytes)
+        .map(|secs| (secs * 1000.0).round() as u64)
+        .ok_or("Unrecognized audio data (expected WAV, MP3 or M4A)")?;
+    let end_ms = end_ms.unwrap_or(duration_ms);
+    if start_ms >= end_ms {
+        return Err(format!("Start ({} ms) must be before end ({} ms)", start_ms, end_ms));
+    }
+    if end_ms > duration_ms {
+        return Err(format!("End ({} ms) is past the end of the audio ({} ms)", end_ms, duration_ms));
+    }
+
+    if bytes.starts_with(b"RIFF") {
+        let wav = parse_wav(bytes)?;
+        let align = wav.block_align();
+        let offset = |ms: u64| ((ms * wav.sample_rate as u64 / 1000) as usize * align).min(wav.data.len());
+        let data = wav.data[offset(start_ms)..offset(end_ms)].to_vec();
+        return Ok(Wav { data, ..wav }.to_bytes());
+    }
+    let spec = probe_spec(bytes)?;
+    if spec.container == Container::Ogg {
+        return Err("Ogg Opus can't be re-encoded locally to trim it".to_string());
+    }
+    let samples = decode_pcm(bytes)?;
+    let channels = spec.channels as usize;
+    let offset = |ms: u64| ((ms * spec.sample_rate as u64 / 1000) as usize * channels).min(samples.len());
+    let (start, end) = (offset(start_ms), offset(end_ms));
+    if start >= end {
+        return Err("Selected range is past the decoded audio".to_string());
+    }
+    encode_like(bytes, &samples[start..end], spec)
+}
+
+/// Windows loudness is measured over when looking for silence.
+const SILENCE_WINDOW_MS: u64 = 10;
+
+/// Bitrate of MP3 clips split from a recording, which may be music as well
+/// as speech.
+const SPLIT_MP3_BITRATE_KBPS: u32 = 128;
+
+/// Frame ranges of `samples` that aren't silence, silence being at least
+/// `min_silence_ms` quieter than `threshold_db` dBFS. A segment shorter than
+/// `min_segment_ms` is joined to the next one, or to the previous one at the
+/// end, so a breath or a click doesn't become a clip of its own.
+pub fn sound_segments(
+    samples: &[i16],
+    spec: AudioSpec,
+    min_silence_ms: u64,
+    threshold_db: f64,
+    min_segment_ms: u64,
+) -> Vec<Range<usize>> {
+    let channels = spec.channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    let ms_to_frames = |ms: u64| (ms * spec.sample_rate as u64 / 1000) as usize;
+    let window = ms_to_frames(SILENCE_WINDOW_MS).max(1);
+    let is_silent = |start: usize| {
+        let window = &samples[start * channels..((start + window).min(frames)) * channels];
+        let power = window.iter().map(|&s| (s as f64 / 32768.0).powi(2)).sum::<f64>() / window.len() as f64;
+        10.0 * power.log10() < threshold_db
+    };
+
+    let mut segments = Vec::new();
+    let (mut segment_start, mut silence_start) = (0, None);
+    for start in (0..frames).step_by(window) {
+        if is_silent(start) {
+            silence_start.get_or_insert(start);
+            continue;
+        }
+        if let Some(silence) = silence_start.take() {
+            if start - silence >= ms_to_frames(min_silence_ms) {
+                if silence > segment_start {
+                    segments.push(segment_start..silence);
+                }
+                segment_start = start;
+            }
+        }
+    }
+    let end = match silence_start {
+        Some(silence) if frames - silence >= ms_to_frames(min_silence_ms) => silence,
+        _ => frames,
+    };
+    if end > segment_start {
+        segments.push(segment_start..end);
+    }
+
+    let min_frames = ms_to_frames(min_segment_ms);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for segment in segments {
+        match merged.last_mut() {
+            Some(last) if last.len() < min_frames => last.end = segment.end,
+            _ => merged.push(segment),
+        }
+    }
+    if merged.len() > 1 && merged.last().is_some_and(|last| last.len() < min_frames) {
+        let last = merged.pop().unwrap();
+        merged.last_mut().unwrap().end = last.end;
+    }
+    merged
+}
+
+/// A piece of a longer recording and where it was in the original.
+pub struct Clip {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// Cuts WAV, MP3 or M4A audio at its pauses (see `sound_segments`), encoding
+/// each piece as `format`.
+pub fn split_by_silence(
+    bytes: &[u8],
+    min_silence_ms: u64,
+    threshold_db: f64,
+    min_segment_ms: u64,
+    format: &str,
+) -> Result<Vec<Clip>, String> {
+    let format = normalize_format(format)?;
+    if format == "OGG_OPUS" {
+        return Err("Opus can't be encoded locally; split into MP3, LINEAR16 or M4A".to_string());
+    }
+    let spec = probe_spec(bytes)?;
+    let samples = decode_pcm(bytes)?;
+    let channels = spec.channels.max(1) as usize;
+    let to_ms = |frame: usize| frame as u64 * 1000 / spec.sample_rate as u64;
+
+    sound_segments(&samples, spec, min_silence_ms, threshold_db, min_segment_ms)
+        .into_iter()
+        .map(|frames| {
+            let clip = &samples[frames.start * channels..frames.end * channels];
+            let bytes = match format {
+                "MP3" => encode_mp3(clip, spec, SPLIT_MP3_BITRATE_KBPS)?,
+                "M4A" => encode_m4a(clip, spec, DEFAULT_AAC_BITRATE_KBPS)?,
+                _ => Wav {
+                    sample_rate: spec.sample_rate,
+                    channels: spec.channels,
+                    bits_per_sample: 16,
+                    data: clip.iter().flat_map(|s| s.to_le_bytes()).collect(),
+                }
+                .to_bytes(),
+            };
+            Ok(Clip {
+                start_ms: to_ms(frames.start),
+                end_ms: to_ms(frames.end),
+                bytes,
+            })
+        })
+        .collect()
+}
+
+/// Milliseconds of silence at the start and at the end of `bytes`, silence
+/// being quieter than `threshold_db` dBFS. Audio that is silent throughout
+/// counts as all leading silence.
+pub fn edge_silence_ms(bytes: &[u8], threshold_db: f64) -> Result<(u64, u64), String> {
+    let spec = probe_spec(bytes)?;
+    let samples = decode_pcm(bytes)?;
+    let frames = samples.len() / spec.channels.max(1) as usize;
+    let to_ms = |frames: usize| frames as u64 * 1000 / spec.sample_rate as u64;
+    let segments = sound_segments(&samples, spec, SILENCE_WINDOW_MS, threshold_db, 0);
+    match (segments.first(), segments.last()) {
+        (Some(first), Some(last)) => Ok((to_ms(first.start), to_ms(frames - last.end))),
+        _ => Ok((to_ms(frames), 0)),
+    }
+}
+
+/// How much faster or slower `time_stretch` plays speech before it starts
+/// to sound processed.
+pub const STRETCH_RATES: std::ops::RangeInclusive<f64> = 0.8..=1.25;
+
+/// Length of the overlapping pieces `wsola` lays down, and how far each may
+/// be shifted to line up with the one before.
+const STRETCH_PIECE_MS: u64 = 30;
+const STRETCH_SEEK_MS: u64 = 10;
+
+/// Plays interleaved samples `rate` times as fast at the same pitch by
+/// waveform-similarity overlap-add: Hann-windowed pieces are read `rate`
+/// times further apart than they are written, each shifted within
+/// `STRETCH_SEEK_MS` to where it best continues the waveform of the last.
+/// The result is exactly `frames / rate` frames long.
+fn wsola(samples: &[i16], channels: usize, sample_rate: u32, rate: f64) -> Vec<i16> {
+    let frames = samples.len() / channels;
+    let out_frames = (frames as f64 / rate).round() as usize;
+    let size = (sample_rate as u64 * STRETCH_PIECE_MS / 1000) as usize;
+    let seek = (sample_rate as u64 * STRETCH_SEEK_MS / 1000) as usize;
+    let hop = size / 2;
+    if hop == 0 || frames < size + 2 * seek {
+        // Too short to hear the difference; fit the length with silence
+        let mut fitted = samples[..frames.min(out_frames) * channels].to_vec();
+        fitted.resize(out_frames * channels, 0);
+        return fitted;
+    }
+
+    let mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().map(|&s| s as f32).sum::<f32>() / channels as f32)
+        .collect();
+    // A periodic Hann window, whose halves sum to one when overlapped
+    let window: Vec<f32> = (0..size)
+        .map(|n| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / size as f32).cos())
+        .collect();
+    let last_start = frames - size;
+    let similarity = |from: usize, to: usize| (0..hop).step_by(2).map(|i| mono[from + i] * mono[to + i]).sum::<f32>();
+
+    let mut mixed = vec![0f32; (out_frames + size) * channels];
+    let mut previous = 0;
+    for piece in 0.. {
+        let at = piece * hop;
+        if at >= out_frames {
+            break;
+        }
+        let start = if piece == 0 {
+            0
+        } else {
+            let nominal = (at as f64 * rate).round() as usize;
+            let natural = (previous + hop).min(last_start);
+            let candidates = nominal.saturating_sub(seek).min(last_start)..=(nominal + seek).min(last_start);
+            candidates
+                .max_by(|a, b| similarity(natural, *a).total_cmp(&similarity(natural, *b)))
+                .unwrap_or(last_start)
+        };
+        for i in 0..size {
+            // Nothing overlaps the first piece's rising half
+            let weight = if piece == 0 && i < hop { 1.0 } else { window[i] };
+            for channel in 0..channels {
+                mixed[(at + i) * channels + channel] += weight * samples[(start + i) * channels + channel] as f32;
+            }
+        }
+        previous = start;
+    }
+    mixed.truncate(out_frames * channels);
+    mixed.iter().map(|&s| s.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).collect()
+}
+
+/// Plays WAV, MP3 or M4A audio `rate` times as fast without changing its
+/// pitch, keeping the format. `rate` must be within `STRETCH_RATES`.
+pub fn time_stretch(bytes: &[u8], rate: f64) -> Result<Vec<u8>, String> {
+    if !STRETCH_RATES.contains(&rate) {
+        return Err(format!(
+            "Speed changes are limited to {}x to {}x, not {:.2}x",
+            STRETCH_RATES.start(),
+            STRETCH_RATES.end(),
+            rate
+        ));
+    }
+    let spec = probe_spec(bytes)?;
+    if spec.container == Container::Ogg {
+        return Err("Ogg Opus can't be re-encoded locally to change its speed".to_string());
+    }
+    let stretched = wsola(&decode_pcm(bytes)?, spec.channels.max(1) as usize, spec.sample_rate, rate);
+    encode_like(bytes, &stretched, spec)
+}
+
+/// Every output format, in the order the app offers them.
+pub const FORMATS: [&str; 4] = ["MP3", "LINEAR16", "OGG_OPUS", "M4A"];
+
+/// Maps the format names users type (`mp3`, `wav`, `linear16`...) to the
+/// API's names, rejecting anything the server can't produce.
+pub fn normalize_format(format: &str) -> Result<&'static str, String> {
+    match format.trim().to_ascii_uppercase().as_str() {
+        "MP3" => Ok("MP3"),
+        "LINEAR16" | "WAV" => Ok("LINEAR16"),
+        "OGG_OPUS" | "OPUS" | "OGG" => Ok("OGG_OPUS"),
+        "M4A" | "AAC" | "MP4" => Ok("M4A"),
+        _ => Err(format!("Unsupported audio format '{}'; use MP3, LINEAR16, OGG_OPUS or M4A", format)),
+    }
+}
+
+/// Roughly what the API's Opus voices produce.
+pub const DEFAULT_OPUS_BITRATE_KBPS: u32 = 64;
+
+/// Refuses a bitrate for `format`. The API picks its own Opus bitrate and
+/// the other formats have none to set, so any bitrate would be ignored.
+pub fn check_bitrate(format: &str, bitrate_kbps: Option<u32>) -> Result<(), String> {
+    match bitrate_kbps {
+        None => Ok(()),
+        Some(_) if normalize_format(format) == Ok("OGG_OPUS") => {
+            Err("The server picks its own Opus bitrate; a bitrate cannot be set for OGG_OPUS output yet".to_string())
+        }
+        Some(_) => Err(format!("A bitrate can only be set for OGG_OPUS output, not {}", format)),
+    }
+}
+
+/// Default bitrate of each compressed format in kbps: what Chirp voices
+/// produce as MP3 and Opus, and what is encoded as AAC.
+pub const DEFAULT_BITRATES_KBPS: [(&str, u32); 3] =
+    [("MP3", 32), ("OGG_OPUS", DEFAULT_OPUS_BITRATE_KBPS), ("M4A", DEFAULT_AAC_BITRATE_KBPS)];
+
+/// Sample rate of the API's LINEAR16 output, which is 16-bit mono.
+pub const PCM_SAMPLE_RATE: u32 = 24_000;
+
+/// The default bitrate of `format`, or `None` for uncompressed PCM.
+pub fn default_bitrate_kbps(format: &str) -> Option<u32> {
+    let format = normalize_format(format).ok()?;
+    DEFAULT_BITRATES_KBPS.iter().find(|(name, _)| *name == format).map(|(_, kbps)| *kbps)
+}
+
+/// Rough encoded size of `seconds` of speech: the bitrate times the length
+/// for compressed formats, `bitrate_kbps` defaulting to the format's usual
+/// one, and sample rate times sample size for PCM.
+pub fn estimated_size(format: &str, seconds: f64, bitrate_kbps: Option<u32>) -> usize {
+    let bytes_per_second = match default_bitrate_kbps(format) {
+        Some(default) => bitrate_kbps.unwrap_or(default) as f64 * 1000.0 / 8.0,
+        None => PCM_SAMPLE_RATE as f64 * 2.0,
+    };
+    (seconds * bytes_per_second) as usize
+}
+
+/// Raw PCM output requested instead of a container format. The API produces
+/// 16-bit mono LINEAR16 at the requested rate; stereo is made by duplicating
+/// the channel.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+    pub channels: u16,
+    /// Keep a WAV header instead of writing headerless `.pcm` samples.
+    #[serde(default)]
+    pub wav_header: bool,
+}
+
+impl PcmFormat {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bit_depth != 16 {
+            return Err(format!("Unsupported bit depth {}; only 16-bit PCM is available", self.bit_depth));
+        }
+        if !(1..=2).contains(&self.channels) {
+            return Err(format!("Unsupported channel count {}; use 1 (mono) or 2 (stereo)", self.channels));
+        }
+        if !(8000..=48000).contains(&self.sample_rate) {
+            return Err(format!("Unsupported sample rate {} Hz; use 8000 to 48000 Hz", self.sample_rate));
+        }
+        Ok(())
+    }
+
+    pub fn extension(&self) -> &'static str {
+        if self.wav_header {
+            "wav"
+        } else {
+            "pcm"
+        }
+    }
+
+    /// Converts the server's LINEAR16 WAV into this format.
+    pub fn encode(&self, wav_bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let wav = parse_wav(wav_bytes)?;
+        if wav.sample_rate != self.sample_rate || wav.bits_per_sample != 16 || wav.channels != 1 {
+            return Err(format!(
+                "Server returned {}, expected WAV {} Hz mono 16-bit; it may not support custom sample rates",
+                wav.spec(),
+                self.sample_rate
+            ));
+        }
+
+        let data = if self.channels == 2 {
+            wav.data.chunks_exact(2).flat_map(|sample| [sample[0], sample[1], sample[0], sample[1]]).collect()
+        } else {
+            wav.data
+        };
+
+        let out = Wav {
+            channels: self.channels,
+            data,
+            ..wav
+        };
+        Ok(if self.wav_header { out.to_bytes() } else { out.data })
+    }
+}
+
+/// File extension for an API audio format name.
+pub fn file_extension(format: &str) -> &'static str {
+    match format.to_ascii_uppercase().as_str() {
+        "LINEAR16" | "WAV" => "wav",
+        "OGG_OPUS" | "OPUS" | "OGG" => "opus",
+        "M4A" | "AAC" | "MP4" => "m4a",
+        _ => "mp3",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16-bit PCM WAV holding `samples`, interleaved when `channels` > 1.
+    fn wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+        Wav {
+            sample_rate,
+            channels,
+            bits_per_sample: 16,
+            data: samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn duration_secs_counts_wav_frames() {
+        assert_eq!(duration_secs(&wav(&[0; 24_000], 24_000, 1)), Some(1.0));
+        assert_eq!(duration_secs(&wav(&[0; 24_000], 24_000, 2)), Some(0.5));
+    }
+
+    #[test]
+    fn duration_secs_rejects_unknown_data() {
+        assert_eq!(duration_secs(b"not audio at all"), None);
+    }
+
+    #[test]
+    fn file_extension_follows_the_format() {
+        assert_eq!(file_extension("LINEAR16"), "wav");
+        assert_eq!(file_extension("wav"), "wav");
+        assert_eq!(file_extension("MP3"), "mp3");
+    }
+
+    #[test]
+    fn estimated_size_uses_the_bitrate_or_pcm_rate() {
+        assert_eq!(estimated_size("MP3", 10.0, None), 40_000);
+        assert_eq!(estimated_size("MP3", 10.0, Some(128)), 160_000);
+        assert_eq!(estimated_size("LINEAR16", 1.0, None), 48_000);
+    }
+
+    fn pcm(sample_rate: u32, channels: u16, wav_header: bool) -> PcmFormat {
+        PcmFormat {
+            sample_rate,
+            bit_depth: 16,
+            channels,
+            wav_header,
+        }
+    }
+
+    #[test]
+    fn pcm_format_validates_its_parameters() {
+        assert!(pcm(16_000, 1, false).validate().is_ok());
+        assert!(pcm(48_000, 2, true).validate().is_ok());
+        assert!(pcm(4_000, 1, false).validate().is_err());
+        assert!(pcm(16_000, 3, false).validate().is_err());
+        assert!(PcmFormat { bit_depth: 24, ..pcm(16_000, 1, false) }.validate().is_err());
+    }
+
+    #[test]
+    fn pcm_encode_strips_the_header_or_duplicates_channels() {
+        let source = wav(&[1, -2, 3], 16_000, 1);
+
+        let raw = pcm(16_000, 1, false).encode(&source).unwrap();
+        assert_eq!(raw, [1i16, -2, 3].iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<u8>>());
+        assert_eq!(pcm(16_000, 1, false).extension(), "pcm");
+
+        let stereo = parse_wav(&pcm(16_000, 2, true).encode(&source).unwrap()).unwrap();
+        assert_eq!(stereo.channels, 2);
+        assert_eq!(stereo.data, [1i16, 1, -2, -2, 3, 3].iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn pcm_encode_rejects_a_different_rate() {
+        let source = wav(&[0; 10], 24_000, 1);
+        assert!(pcm(16_000, 1, false).encode(&source).is_err());
+    }
+
+    #[test]
+    fn trim_cuts_wav_at_the_sample() {
+        let samples: Vec<i16> = (0..1000).collect();
+        let trimmed = trim(&wav(&samples, 1000, 1), 200, Some(500)).unwrap();
+        let parsed = parse_wav(&trimmed).unwrap();
+        let kept: Vec<i16> = parsed.data.chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        assert_eq!(kept, (200..500).collect::<Vec<i16>>());
+
+        let tail = trim(&wav(&samples, 1000, 1), 900, None).unwrap();
+        assert_eq!(duration_secs(&tail), Some(0.1));
+    }
+
+    #[test]
+    fn trim_rejects_bad_ranges() {
+        let bytes = wav(&[0; 1000], 1000, 1);
+        assert!(trim(&bytes, 500, Some(500)).unwrap_err().contains("must be before end"));
+        assert!(trim(&bytes, 0, Some(1500)).unwrap_err().contains("past the end"));
+        assert!(trim(b"not audio", 0, None).is_err());
+    }
+
+    #[test]
+    fn trim_reencodes_mp3() {
+        let spec = AudioSpec {
+            container: Container::Mp3,
+            sample_rate: 24000,
+            channels: 1,
+            bits_per_sample: None,
+        };
+        let sine: Vec<i16> = (0..48000).map(|i| ((i as f64 * 0.05).sin() * 8000.0) as i16).collect();
+        let mp3 = encode_mp3(&sine, spec, 32).unwrap();
+
+        let trimmed = trim(&mp3, 500, Some(1500)).unwrap();
+        assert_eq!(probe_spec(&trimmed).unwrap().container, Container::Mp3);
+        let secs = duration_secs(&trimmed).unwrap();
+        assert!((secs - 1.0).abs() < 0.15, "trimmed to {} s", secs);
+    }
+
+    #[test]
+    fn concat_reports_part_offsets_and_progress() {
+        let parts = vec![wav(&[1; 500], 1000, 1), wav(&[2; 250], 1000, 1), wav(&[3; 1000], 1000, 1)];
+        let mut reported = Vec::new();
+        let joined = concat_audio_with_progress(&parts, &[100, 0], |i| reported.push(i)).unwrap();
+
+        assert_eq!(joined.offsets_ms, vec![0, 600, 850]);
+        assert_eq!(joined.duration_ms, 1850);
+        assert_eq!(duration_secs(&joined.bytes), Some(1.85));
+        assert_eq!(reported, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn concat_names_the_mismatched_part_before_copying() {
+        let parts = vec![wav(&[0; 100], 1000, 1), wav(&[0; 100], 1000, 1), wav(&[0; 100], 2000, 1)];
+        let mut calls = 0;
+        let err = concat_audio_with_progress(&parts, &[], |_| calls += 1).err().unwrap();
+        assert!(err.starts_with("Part 3 is"), "{}", err);
+        assert_eq!(calls, 0);
+    }
+
+    #[test]
+    fn audio_duration_of_wav_counts_samples() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.wav");
+        std::fs::write(&path, wav(&[0; 3675], 22050, 1)).unwrap();
+        assert_eq!(audio_duration(&path), Ok(3675.0 / 22050.0));
+
+        assert!(audio_duration(&dir.path().join("missing.wav")).unwrap_err().starts_with("Failed to read"));
+    }
+
+    #[test]
+    fn audio_duration_of_vbr_mp3_sums_every_frame() {
+        let spec = AudioSpec {
+            container: Container::Mp3,
+            sample_rate: 24000,
+            channels: 1,
+            bits_per_sample: None,
+        };
+        let sine: Vec<i16> = (0..24000).map(|i| ((i as f64 * 0.05).sin() * 8000.0) as i16).collect();
+        let low = encode_mp3(&sine, spec, 32).unwrap();
+        let high = encode_mp3(&sine, spec, 128).unwrap();
+        let frames = |bytes: &[u8]| mp3_frames(bytes).len();
+        let expected = (frames(&low) + frames(&high)) as f64 * 576.0 / 24000.0;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vbr.mp3");
+        std::fs::write(&path, [low, high].concat()).unwrap();
+        let secs = audio_duration(&path).unwrap();
+        assert!((secs - expected).abs() < 1e-9, "{} s, expected {} s", secs, expected);
+    }
+
+    /// An Ogg page holding one packet shorter than 255 bytes.
+    fn ogg_page(header_type: u8, granule: u64, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0, header_type]);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&[0; 12]);
+        page.extend_from_slice(&[1, packet.len() as u8]);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    /// A stereo Ogg Opus stream of `samples` samples after a 312 sample pre-skip.
+    fn opus_stream(samples: u64) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, 2]);
+        head.extend_from_slice(&312u16.to_le_bytes());
+        head.extend_from_slice(&24_000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        [
+            ogg_page(0x02, 0, &head),
+            ogg_page(0, 0, b"OpusTags"),
+            ogg_page(0x04, samples + 312, &[0; 40]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn ogg_opus_streams_are_probed_and_timed() {
+        let stream = opus_stream(48_000);
+        let head = parse_opus_head(&stream).unwrap();
+        assert_eq!((head.channels, head.pre_skip), (2, 312));
+        assert_eq!(probe_spec(&stream).unwrap(), head.spec());
+        assert_eq!(duration_secs(&stream), Some(1.0));
+
+        // Chained streams add up
+        let chain = [opus_stream(48_000), opus_stream(24_000)].concat();
+        assert_eq!(duration_secs(&chain), Some(1.5));
+
+        assert!(parse_opus_head(&ogg_page(0x02, 0, b"Vorbis")).is_err());
+    }
+
+    #[test]
+    fn ogg_opus_parts_join_as_a_chain() {
+        let parts = vec![opus_stream(48_000), opus_stream(96_000)];
+        let joined = concat_audio_with_progress(&parts, &[], |_| {}).unwrap();
+        assert_eq!(joined.offsets_ms, vec![0, 1000]);
+        assert_eq!(joined.duration_ms, 3000);
+        assert_eq!(joined.bytes, parts.concat());
+
+        assert!(concat_audio(&parts, &[500]).unwrap_err().contains("not supported for Ogg Opus"));
+    }
+
+    #[test]
+    fn check_bitrate_refuses_every_bitrate() {
+        assert_eq!(normalize_format(" opus "), Ok("OGG_OPUS"));
+        assert_eq!(check_bitrate("OGG_OPUS", None), Ok(()));
+        assert!(check_bitrate("ogg", Some(24)).unwrap_err().contains("picks its own Opus bitrate"));
+        assert_eq!(check_bitrate("MP3", None), Ok(()));
+        assert!(check_bitrate("MP3", Some(64)).unwrap_err().contains("not MP3"));
+    }
+
+    fn wav_samples(bytes: &[u8]) -> Vec<i16> {
+        parse_wav(bytes).unwrap().data.chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect()
+    }
+
+    #[test]
+    fn crossfade_overlaps_each_join() {
+        let parts = vec![wav(&[1000; 1000], 1000, 1), wav(&[-1000; 1000], 1000, 1), wav(&[0; 500], 1000, 1)];
+        let mut mixed_in = Vec::new();
+        let joined = concat_with_crossfade(&parts, 100, |i| mixed_in.push(i)).unwrap();
+
+        assert_eq!(joined.offsets_ms, vec![0, 900, 1800]);
+        assert_eq!(joined.duration_ms, 2300);
+        assert_eq!(mixed_in, vec![0, 1, 2]);
+
+        let samples = wav_samples(&joined.bytes);
+        assert_eq!(samples.len(), 2300);
+        assert_eq!(samples[899], 1000);
+        assert_eq!(samples[900], 1000);
+        assert_eq!(samples[950], 0);
+        assert_eq!(samples[1000], -1000);
+    }
+
+    #[test]
+    fn crossfade_is_no_longer_than_the_parts() {
+        let parts = vec![wav(&[0; 1000], 1000, 2), wav(&[0; 100], 1000, 2)];
+        let joined = concat_with_crossfade(&parts, 500, |_| {}).unwrap();
+        // The second part is only 50 ms long
+        assert_eq!(joined.duration_ms, 500);
+
+        let plain = concat_with_crossfade(&parts, 0, |_| {}).unwrap();
+        assert_eq!(plain.bytes, concat_audio(&parts, &[]).unwrap());
+
+        let ogg = vec![opus_stream(48_000), opus_stream(48_000)];
+        assert!(concat_with_crossfade(&ogg, 100, |_| {}).err().unwrap().contains("not supported for Ogg Opus"));
+    }
+
+    #[test]
+    fn wav_to_m4a_writes_a_playable_aac_file() {
+        let sine: Vec<i16> = (0..24_000).map(|i| ((i as f64 * 0.05).sin() * 8000.0) as i16).collect();
+        let m4a = wav_to_m4a(&wav(&sine, 24_000, 1)).unwrap();
+        assert!(is_mp4(&m4a));
+
+        let info = parse_m4a(&m4a).unwrap();
+        assert_eq!((info.spec.container, info.spec.sample_rate, info.spec.channels), (Container::M4a, 24_000, 1));
+        assert!((info.duration_secs - 1.0).abs() < 0.01, "{} s", info.duration_secs);
+        assert_eq!(duration_secs(&m4a), Some(info.duration_secs));
+
+        // Decoders return the priming samples and pad the last frame
+        let decoded = decode_pcm(&m4a).unwrap();
+        let extra = decoded.len() as i64 - sine.len() as i64;
+        assert!((0..=(info.priming_samples as i64 + AAC_FRAME_SAMPLES as i64)).contains(&extra), "{} extra", extra);
+    }
+
+    #[test]
+    fn wav_to_m4a_needs_16_bit_audio() {
+        let wav = Wav {
+            sample_rate: 24_000,
+            channels: 1,
+            bits_per_sample: 8,
+            data: vec![128; 100],
+        }
+        .to_bytes();
+        assert!(wav_to_m4a(&wav).unwrap_err().starts_with("Only 16-bit WAV"));
+        assert_eq!(normalize_format("aac"), Ok("M4A"));
+        assert_eq!(file_extension("M4A"), "m4a");
+    }
+
+    #[test]
+    fn probe_audio_reads_wav_parameters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speech.wav");
+        std::fs::write(&path, wav(&[0; 48_000], 24_000, 2)).unwrap();
+
+        let info = probe_audio(&path).unwrap();
+        assert_eq!(info.container, "WAV");
+        assert_eq!(info.codec.as_deref(), Some("pcm_s16le"));
+        assert_eq!((info.sample_rate, info.channels), (Some(24_000), Some(2)));
+        assert_eq!(info.duration_seconds, Some(1.0));
+        assert_eq!(info.bitrate_kbps, Some(768));
+    }
+
+    #[test]
+    fn probe_audio_reads_mp3_parameters() {
+        let spec = AudioSpec {
+            container: Container::Mp3,
+            sample_rate: 24000,
+            channels: 1,
+            bits_per_sample: None,
+        };
+        let sine: Vec<i16> = (0..48000).map(|i| ((i as f64 * 0.05).sin() * 8000.0) as i16).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speech.mp3");
+        std::fs::write(&path, encode_mp3(&sine, spec, 32).unwrap()).unwrap();
+
+        let info = probe_audio(&path).unwrap();
+        assert_eq!(info.container, "MP3");
+        assert_eq!(info.codec.as_deref(), Some("mp3"));
+        assert_eq!((info.sample_rate, info.channels), (Some(24_000), Some(1)));
+        let kbps = info.bitrate_kbps.unwrap();
+        assert!((30..=36).contains(&kbps), "{} kbps", kbps);
+    }
+
+    #[test]
+    fn probe_audio_reads_m4a_parameters() {
+        let spec = AudioSpec {
+            container: Container::M4a,
+            sample_rate: 24000,
+            channels: 2,
+            bits_per_sample: None,
+        };
+        let sine: Vec<i16> = (0..96000).map(|i| ((i as f64 * 0.05).sin() * 8000.0) as i16).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speech.m4a");
+        std::fs::write(&path, encode_m4a(&sine, spec, 64).unwrap()).unwrap();
+
+        let info = probe_audio(&path).unwrap();
+        assert_eq!(info.container, "M4A");
+        assert_eq!(info.codec.as_deref(), Some("aac"));
+        assert_eq!((info.sample_rate, info.channels), (Some(24_000), Some(2)));
+        let secs = info.duration_seconds.unwrap();
+        assert!((secs - 2.0).abs() < 0.01, "{} s", secs);
+        let kbps = info.bitrate_kbps.unwrap();
+        assert!((60..=72).contains(&kbps), "{} kbps", kbps);
+    }
+
+    /// One of the files in `tests/fixtures`, made by hand rather than by KIWI.
+    fn fixture(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    #[test]
+    fn probe_audio_reads_flac_parameters() {
+        // A second of 8 kHz mono silence in two 4000 sample frames
+        let info = probe_audio(&fixture("silence.flac")).unwrap();
+        assert_eq!(info.container, "FLAC");
+        assert_eq!(info.codec.as_deref(), Some("flac"));
+        assert_eq!((info.sample_rate, info.channels), (Some(8000), Some(1)));
+        assert_eq!(info.duration_seconds, Some(1.0));
+        // 68 bytes over a second
+        assert_eq!(info.bitrate_kbps, Some(1));
+    }
+
+    #[test]
+    fn probe_audio_reads_ogg_parameters() {
+        // A second of mono Opus silence: fifty 20 ms packets after a 312 sample pre-skip
+        let info = probe_audio(&fixture("silence.ogg")).unwrap();
+        assert_eq!(info.container, "OGG");
+        assert_eq!(info.codec.as_deref(), Some("opus"));
+        assert_eq!((info.sample_rate, info.channels), (Some(48_000), Some(1)));
+        assert_eq!(info.duration_seconds, Some(1.0));
+        assert_eq!(info.bitrate_kbps, Some(3));
+    }
+
+    #[test]
+    fn probe_audio_refuses_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "not audio").unwrap();
+        let err = probe_audio(&path).unwrap_err();
+        assert!(err.ends_with("is not an audio file KIWI recognizes; use MP3, WAV, OGG, FLAC or M4A"), "{}", err);
+        assert!(probe_audio(&dir.path().join("gone.wav")).unwrap_err().starts_with("Failed to read"));
+    }
+
+    /// `pattern` as (milliseconds, loud) runs of 8 kHz mono audio.
+    fn tone_and_silence(pattern: &[(usize, bool)]) -> Vec<i16> {
+        pattern
+            .iter()
+            .flat_map(|&(ms, loud)| (0..ms * 8).map(move |i| if loud { ((i as f64 * 0.3).sin() * 8000.0) as i16 } else { 0 }))
+            .collect()
+    }
+
+    const SPLIT_SPEC: AudioSpec = AudioSpec {
+        container: Container::Wav,
+        sample_rate: 8000,
+        channels: 1,
+        bits_per_sample: Some(16),
+    };
+
+    #[test]
+    fn sound_segments_cut_at_long_pauses() {
+        let samples = tone_and_silence(&[(100, false), (1500, true), (200, false), (1000, true), (600, false), (1200, true)]);
+        let segments = sound_segments(&samples, SPLIT_SPEC, 500, -40.0, 0);
+        // The 200 ms pause is too short to cut at; leading silence is kept
+        assert_eq!(segments, vec![0..2800 * 8, 3400 * 8..4600 * 8]);
+    }
+
+    #[test]
+    fn sound_segments_join_short_sounds_to_a_neighbour() {
+        let samples = tone_and_silence(&[(1500, true), (600, false), (300, true), (600, false), (1500, true), (600, false), (200, true)]);
+        let segments = sound_segments(&samples, SPLIT_SPEC, 500, -40.0, 1000);
+        // The 300 ms sound joins the next segment, the 200 ms one at the end the previous one
+        assert_eq!(segments, vec![0..1500 * 8, 2100 * 8..samples.len()]);
+        assert!(sound_segments(&[0; 8000], SPLIT_SPEC, 500, -40.0, 1000).is_empty());
+    }
+
+    #[test]
+    fn split_by_silence_encodes_each_clip() {
+        let samples = tone_and_silence(&[(1000, true), (600, false), (1000, true)]);
+        let clips = split_by_silence(&wav(&samples, 8000, 1), 500, -40.0, 0, "wav").unwrap();
+        let spans: Vec<(u64, u64)> = clips.iter().map(|clip| (clip.start_ms, clip.end_ms)).collect();
+        assert_eq!(spans, vec![(0, 1000), (1600, 2600)]);
+        assert!(clips.iter().all(|clip| duration_secs(&clip.bytes) == Some(1.0)));
+
+        let err = split_by_silence(&wav(&samples, 8000, 1), 500, -40.0, 0, "OGG_OPUS").err().unwrap();
+        assert_eq!(err, "Opus can't be encoded locally; split into MP3, LINEAR16 or M4A");
+    }
+
+    #[test]
+    fn downmix_averages_each_frame() {
+        assert_eq!(downmix(&[100, 200, -300, 301, i16::MAX, i16::MAX], 2), vec![150, 1, i16::MAX]);
+        assert_eq!(downmix(&[1, 2, 3, 4, 5, 6], 3), vec![2, 5]);
+        assert_eq!(downmix(&[7, -7], 1), vec![7, -7]);
+        // A trailing partial frame is dropped
+        assert_eq!(downmix(&[10, 20, 30], 2), vec![15]);
+    }
+
+    #[test]
+    fn set_channels_converts_wav_both_ways() {
+        let stereo = wav(&[100, 200, -100, -300], 8000, 2);
+        let mono = set_channels(&stereo, 1).unwrap();
+        assert_eq!(probe_spec(&mono).unwrap().channels, 1);
+        assert_eq!(wav_samples(&mono), vec![150, -200]);
+
+        let back = set_channels(&mono, 2).unwrap();
+        assert_eq!(wav_samples(&back), vec![150, 150, -200, -200]);
+        assert_eq!(set_channels(&stereo, 2).unwrap(), stereo);
+        assert_eq!(
+            set_channels(&stereo, 6),
+            Err("Unsupported channel count 6; use 1 (mono) or 2 (stereo)".to_string())
+        );
+    }
+
+    #[test]
+    fn generate_silence_in_each_format() {
+        let pcm = generate_silence(1500, "wav", 24_000, 2).unwrap();
+        assert_eq!(probe_spec(&pcm).unwrap().channels, 2);
+        assert_eq!(duration_secs(&pcm), Some(1.5));
+        assert!(wav_samples(&pcm).iter().all(|&s| s == 0));
+
+        let mp3 = generate_silence(1000, "MP3", 24_000, 1).unwrap();
+        let spec = probe_spec(&mp3).unwrap();
+        assert_eq!((spec.container, spec.sample_rate), (Container::Mp3, 24_000));
+        assert!((duration_secs(&mp3).unwrap() - 1.0).abs() < 0.05);
+
+        let m4a = generate_silence(1000, "M4A", 24_000, 1).unwrap();
+        assert_eq!(probe_spec(&m4a).unwrap().container, Container::M4a);
+    }
+
+    #[test]
+    fn generate_silence_refuses_what_it_cant_encode() {
+        assert_eq!(
+            generate_silence(1000, "OGG_OPUS", 24_000, 1),
+            Err("Ogg Opus can't be encoded locally; use MP3, LINEAR16 or M4A".to_string())
+        );
+        assert!(generate_silence(1000, "wav", 24_000, 3).unwrap_err().starts_with("Unsupported channel count 3"));
+        assert!(generate_silence(1000, "wav", 96_000, 1).unwrap_err().starts_with("Unsupported sample rate 96000 Hz"));
+        assert_eq!(generate_silence(1000, "MP3", 10_000, 1), Err("MP3 has no 10000 Hz sample rate".to_string()));
+    }
+
+    #[test]
+    fn insert_silence_splices_at_the_position() {
+        let speech = wav(&[5; 1000], 1000, 1);
+        let spliced = insert_silence(&speech, 400, 250).unwrap();
+        let samples = wav_samples(&spliced);
+        assert_eq!(samples.len(), 1250);
+        assert!(samples[..400].iter().all(|&s| s == 5));
+        assert!(samples[400..650].iter().all(|&s| s == 0));
+        assert!(samples[650..].iter().all(|&s| s == 5));
+
+        // At either end
+        assert_eq!(wav_samples(&insert_silence(&speech, 0, 100).unwrap())[..100], [0; 100]);
+        assert_eq!(wav_samples(&insert_silence(&speech, 1000, 100).unwrap()).len(), 1100);
+        assert_eq!(
+            insert_silence(&speech, 1001, 100),
+            Err("Position (1001 ms) is past the end of the audio (1000 ms)".to_string())
+        );
+    }
+
+    #[test]
+    fn clipping_reports_the_worst_regions_in_order() {
+        // 100 ms regions of 100 samples; region i has i clipped samples
+        let mut samples = vec![1000i16; 1050];
+        for region in 1..10 {
+            for k in 0..region {
+                samples[region * 100 + k] = if k % 2 == 0 { i16::MAX } else { i16::MIN };
+            }
+        }
+        let report = clipping(&wav(&samples, 1000, 1)).unwrap();
+        assert_eq!((report.clipped_samples, report.total_samples), (45, 1050));
+        assert!((report.clipped_percent - 45.0 * 100.0 / 1050.0).abs() < 1e-9);
+
+        let regions: Vec<(f64, f64, u64)> =
+            report.worst_regions.iter().map(|r| (r.start_seconds, r.end_seconds, r.clipped_samples)).collect();
+        assert_eq!(regions, vec![(0.5, 0.6, 5), (0.6, 0.7, 6), (0.7, 0.8, 7), (0.8, 0.9, 8), (0.9, 1.0, 9)]);
+    }
+
+    #[test]
+    fn clipping_is_zero_for_clean_audio() {
+        let report = clipping(&wav(&[i16::MAX - 1, i16::MIN + 1, 0, 0], 1000, 2)).unwrap();
+        assert_eq!((report.clipped_samples, report.total_samples, report.clipped_percent), (0, 4, 0.0));
+        assert!(report.worst_regions.is_empty());
+    }
+
+    #[test]
+    fn verify_accepts_playable_audio() {
+        assert_eq!(verify(&wav(&[0; 100], 8000, 1)), Ok(()));
+        assert_eq!(verify(&opus_stream(48_000)), Ok(()));
+        let spec = AudioSpec {
+            container: Container::Mp3,
+            sample_rate: 24000,
+            channels: 1,
+            bits_per_sample: None,
+        };
+        let sine: Vec<i16> = (0..4800).map(|i| ((i as f64 * 0.05).sin() * 8000.0) as i16).collect();
+        assert_eq!(verify(&encode_mp3(&sine, spec, 32).unwrap()), Ok(()));
+    }
+
+    #[test]
+    fn verify_refuses_empty_and_broken_audio() {
+        assert_eq!(verify(&[]), Err("The file is empty".to_string()));
+        assert_eq!(verify(&wav(&[], 8000, 1)), Err("The WAV file holds no samples".to_string()));
+        assert_eq!(verify(&opus_stream(0)), Err("The Ogg stream holds no complete audio".to_string()));
+        assert!(verify(b"<html>Internal error</html>").is_err());
+    }
+}
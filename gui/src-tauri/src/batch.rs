@@ -0,0 +1,1085 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{command, Emitter};
+
+use crate::audio::{self, Container};
+use crate::{
+    announce, client, format_file_size, history, paths, reveal_on_complete, settings, synthesize_long, text, voices,
+    AppHandle, TTSRequest,
+};
+
+/// Progress is recorded here, inside the output folder, after every item.
+const MANIFEST_NAME: &str = "batch-manifest.json";
+
+/// Written by `convert_lines_from_file`, mapping each line to its clip.
+const LINES_MANIFEST_NAME: &str = "lines-manifest.json";
+
+/// Written by `convert_folder`, mapping each source file to its audio.
+const FOLDER_MANIFEST_NAME: &str = "folder-manifest.json";
+
+/// Extensions `convert_folder` reads; Markdown is stripped of its markup.
+const TEXT_EXTENSIONS: [&str; 3] = ["txt", "md", "markdown"];
+
+/// Characters of a line used for its file name with `LineNaming::Text`.
+const LINE_NAME_CHARS: usize = 40;
+
+/// Items the throughput estimate averages over.
+const ETA_WINDOW: usize = 5;
+
+/// An item this many times faster or slower than the running average starts
+/// the estimate over, e.g. when short phrases give way to long chapters.
+const ETA_RESET_FACTOR: f64 = 4.0;
+
+/// List prices in USD per million characters by voice tier. Voices of other
+/// tiers are estimated at the Chirp 3 HD price.
+const PRICES_PER_MILLION: [(&str, f64); 6] = [
+    ("Chirp3-HD", 30.0),
+    ("Chirp-HD", 30.0),
+    ("Studio", 160.0),
+    ("Neural2", 16.0),
+    ("Wavenet", 16.0),
+    ("Standard", 4.0),
+];
+const DEFAULT_PRICE_PER_MILLION: f64 = 30.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchItem {
+    text: String,
+    voice: String,
+    format: String,
+    /// File stem for the output; defaults to the item's 1-based position.
+    #[serde(default)]
+    output_name: Option<String>,
+    /// Defaults to the language of the voice.
+    #[serde(default)]
+    language: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchOptions {
+    /// Skip items the manifest records as complete whose files are still intact.
+    resume: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompletedItem {
+    index: usize,
+    /// Fingerprint of the item's text, voice and format, so an edited batch
+    /// doesn't reuse audio generated for different input.
+    key: String,
+    path: String,
+    bytes: u64,
+    /// SHA-256 of the file as written; absent in manifests from older versions.
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BatchManifest {
+    completed: Vec<CompletedItem>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BatchProgress {
+    item: usize,
+    total: usize,
+    success: bool,
+    /// Estimated seconds until the batch finishes; `None` until an item has
+    /// been synthesized.
+    eta_seconds: Option<f64>,
+    chars_per_second: Option<f64>,
+}
+
+/// Throughput of recent items in characters per second, which copes with
+/// items of different lengths better than an average time per item.
+#[derive(Debug, Default)]
+struct EtaEstimator {
+    recent: VecDeque<(usize, Duration)>,
+}
+
+impl EtaEstimator {
+    fn record(&mut self, chars: usize, elapsed: Duration) {
+        if let (Some(average), Some(rate)) = (self.chars_per_second(), rate(chars, elapsed)) {
+            if rate > average * ETA_RESET_FACTOR || rate * ETA_RESET_FACTOR < average {
+                self.recent.clear();
+            }
+        }
+        self.recent.push_back((chars, elapsed));
+        if self.recent.len() > ETA_WINDOW {
+            self.recent.pop_front();
+        }
+    }
+
+    fn chars_per_second(&self) -> Option<f64> {
+        let chars = self.recent.iter().map(|(chars, _)| chars).sum();
+        let elapsed = self.recent.iter().map(|(_, elapsed)| *elapsed).sum();
+        rate(chars, elapsed)
+    }
+
+    fn remaining_secs(&self, remaining_chars: usize) -> Option<f64> {
+        self.chars_per_second().map(|rate| (remaining_chars as f64 / rate * 10.0).round() / 10.0)
+    }
+}
+
+fn rate(chars: usize, elapsed: Duration) -> Option<f64> {
+    let secs = elapsed.as_secs_f64();
+    (chars > 0 && secs > 0.0).then(|| chars as f64 / secs)
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BatchResumed {
+    skipped: usize,
+    remaining: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchItemResult {
+    index: usize,
+    success: bool,
+    output_path: Option<String>,
+    file_size: Option<String>,
+    error: Option<String>,
+    /// True when the file came from an earlier run and was not regenerated.
+    skipped: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    items: Vec<BatchItemResult>,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    manifest_path: String,
+    /// Non-fatal problems, such as a file manager that couldn't be opened.
+    warnings: Vec<String>,
+}
+
+/// FNV-1a over the fields that determine the audio. Stable across builds,
+/// unlike `std`'s hasher, so manifests stay valid after an update.
+fn item_key(item: &BatchItem) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for field in [&item.voice, &item.format, &item.text] {
+        for byte in field.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+fn output_path(output_dir: &Path, index: usize, item: &BatchItem) -> PathBuf {
+    let stem = item
+        .output_name
+        .clone()
+        .filter(|name| !name.trim().is_empty())
+        .map(|name| paths::sanitize_filename(&name))
+        .unwrap_or_else(|| format!("{:03}", index + 1));
+    output_dir.join(format!("{}.{}", stem, audio::file_extension(&item.format)))
+}
+
+fn load_manifest(path: &Path) -> BatchManifest {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Written through a temporary file like the audio, so a crash while saving
+/// leaves the previous manifest rather than a truncated one.
+fn save_manifest(path: &Path, manifest: &BatchManifest) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(manifest).map_err(|e| format!("Failed to encode manifest: {}", e))?;
+    let part = paths::part_path(path);
+    std::fs::write(&part, json)
+        .and_then(|_| std::fs::rename(&part, path))
+        .map_err(|e| format!("Failed to write manifest: {}", e))
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether a previously recorded output can be trusted: it must still exist
+/// with the recorded size and checksum and decode as the container its
+/// format implies.
+fn is_intact(record: &CompletedItem, format: &str) -> bool {
+    let Ok(bytes) = std::fs::read(&record.path) else {
+        return false;
+    };
+    if bytes.is_empty() || bytes.len() as u64 != record.bytes {
+        return false;
+    }
+    if record.sha256.as_ref().is_some_and(|sha256| *sha256 != sha256_hex(&bytes)) {
+        return false;
+    }
+    let expected = match audio::file_extension(format) {
+        "wav" => Container::Wav,
+        "opus" => Container::Ogg,
+        "m4a" => Container::M4a,
+        _ => Container::Mp3,
+    };
+    audio::probe_spec(&bytes).is_ok_and(|spec| spec.container == expected)
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TierEstimate {
+    /// `None` for voices whose name has no tier, like offline ones.
+    tier: Option<String>,
+    items: usize,
+    characters: usize,
+    estimated_seconds: f64,
+    estimated_cost_usd: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchEstimate {
+    items: usize,
+    /// Characters the API bills for, whitespace included.
+    characters: usize,
+    estimated_seconds: f64,
+    estimated_cost_usd: f64,
+    /// Per voice tier, in order of first use.
+    tiers: Vec<TierEstimate>,
+    /// The `character_budget_remaining` setting the batch was checked against.
+    budget_remaining: Option<u64>,
+    exceeds_budget: bool,
+}
+
+fn price_per_million(tier: Option<&str>) -> f64 {
+    PRICES_PER_MILLION
+        .iter()
+        .find(|(name, _)| Some(*name) == tier)
+        .map_or(DEFAULT_PRICE_PER_MILLION, |(_, price)| *price)
+}
+
+/// Totals what a batch would bill and how long it would play, without
+/// contacting the server, and checks it against the remaining budget.
+#[command]
+pub fn estimate_batch(app: AppHandle, items: Vec<BatchItem>) -> BatchEstimate {
+    estimate(&items, settings::current(&app).character_budget_remaining)
+}
+
+fn estimate(items: &[BatchItem], budget_remaining: Option<u64>) -> BatchEstimate {
+    let mut tiers: Vec<TierEstimate> = Vec::new();
+    for item in items {
+        let tier = voices::voice_tier(&item.voice);
+        let characters = item.text.chars().count();
+        let estimated_seconds = text::spoken_seconds(text::word_count(&item.text));
+        let cost = characters as f64 * price_per_million(tier.as_deref()) / 1_000_000.0;
+
+        let index = match tiers.iter().position(|t| t.tier == tier) {
+            Some(index) => index,
+            None => {
+                tiers.push(TierEstimate { tier, ..Default::default() });
+                tiers.len() - 1
+            }
+        };
+        let entry = &mut tiers[index];
+        entry.items += 1;
+        entry.characters += characters;
+        entry.estimated_seconds += estimated_seconds;
+        entry.estimated_cost_usd += cost;
+    }
+
+    let characters = tiers.iter().map(|t| t.characters).sum();
+    BatchEstimate {
+        items: items.len(),
+        characters,
+        estimated_seconds: tiers.iter().map(|t| t.estimated_seconds).sum(),
+        estimated_cost_usd: tiers.iter().map(|t| t.estimated_cost_usd).sum(),
+        tiers,
+        budget_remaining,
+        exceeds_budget: budget_remaining.is_some_and(|budget| characters as u64 > budget),
+    }
+}
+
+/// Synthesizes each item into its own file in `output_dir`, in order. A
+/// failed item doesn't stop the rest. With `resume`, items completed by an
+/// earlier run of the same batch are skipped.
+#[command]
+pub async fn convert_batch(
+    app: AppHandle,
+    items: Vec<BatchItem>,
+    output_dir: String,
+    options: Option<BatchOptions>,
+) -> Result<BatchResult, String> {
+    let options = options.unwrap_or_default();
+    let output_dir = PathBuf::from(output_dir);
+    std::fs::create_dir_all(&output_dir).map_err(|e| format!("Failed to create output folder: {}", e))?;
+
+    let manifest_path = output_dir.join(MANIFEST_NAME);
+    let mut manifest = if options.resume { load_manifest(&manifest_path) } else { BatchManifest::default() };
+
+    // Decide up front which items can be skipped, so the count can be reported
+    let mut reusable = vec![None; items.len()];
+    for (index, item) in items.iter().enumerate() {
+        let key = item_key(item);
+        let path = output_path(&output_dir, index, item).to_string_lossy().to_string();
+        reusable[index] = manifest
+            .completed
+            .iter()
+            .find(|c| c.index == index && c.key == key && c.path == path && is_intact(c, &item.format))
+            .cloned();
+    }
+    manifest.completed.retain(|c| reusable.iter().flatten().any(|r| r.index == c.index));
+
+    let total = items.len();
+    let skipped = reusable.iter().flatten().count();
+    if options.resume {
+        let _ = app.emit(
+            "batch-resumed",
+            BatchResumed {
+                skipped,
+                remaining: total - skipped,
+            },
+        );
+    }
+
+    let client = client::build(&app);
+    let mut results = Vec::with_capacity(total);
+    let mut eta = EtaEstimator::default();
+    let mut remaining_chars: usize = items
+        .iter()
+        .zip(&reusable)
+        .filter(|(_, reused)| reused.is_none())
+        .map(|(item, _)| item.text.chars().count())
+        .sum();
+
+    for (index, item) in items.into_iter().enumerate() {
+        if let Some(record) = reusable[index].take() {
+            results.push(BatchItemResult {
+                index,
+                success: true,
+                output_path: Some(record.path),
+                file_size: Some(format_file_size(record.bytes as usize)),
+                error: None,
+                skipped: true,
+            });
+            continue;
+        }
+
+        let path = output_path(&output_dir, index, &item);
+        let key = item_key(&item);
+        let request = TTSRequest {
+            language: item
+                .language
+                .or_else(|| text::language_from_voice(&item.voice))
+                .unwrap_or_else(|| "en-US".to_string()),
+            text: item.text,
+            voice: item.voice,
+            format: item.format,
+            ..Default::default()
+        };
+
+        let started = std::time::Instant::now();
+        let outcome = match synthesize_long(&app, &client, &request, None).await {
+            Ok(bytes) => paths::write_audio_atomic(&path, &bytes).map(|_| (bytes.len(), sha256_hex(&bytes))),
+            Err(e) => Err(e),
+        };
+
+        let chars = request.text.chars().count();
+        remaining_chars = remaining_chars.saturating_sub(chars);
+        if outcome.is_ok() {
+            eta.record(chars, started.elapsed());
+        }
+        let _ = app.emit(
+            "batch-progress",
+            BatchProgress {
+                item: index + 1,
+                total,
+                success: outcome.is_ok(),
+                eta_seconds: eta.remaining_secs(remaining_chars),
+                chars_per_second: eta.chars_per_second(),
+            },
+        );
+
+        let path = path.to_string_lossy().to_string();
+        match outcome {
+            Ok((len, sha256)) => {
+                history::record(
+                    &app,
+                    history::HistoryEntry::new(
+                        &request.text,
+                        &request.voice,
+                        &request.format,
+                        &request.language,
+                        &path,
+                        Some(format_file_size(len)),
+                    ),
+                );
+                manifest.completed.push(CompletedItem {
+                    index,
+                    key,
+                    path: path.clone(),
+                    bytes: len as u64,
+                    sha256: Some(sha256),
+                });
+                save_manifest(&manifest_path, &manifest)?;
+                results.push(BatchItemResult {
+                    index,
+                    success: true,
+                    output_path: Some(path),
+                    file_size: Some(format_file_size(len)),
+                    error: None,
+                    skipped: false,
+                });
+            }
+            Err(error) => results.push(BatchItemResult {
+                index,
+                success: false,
+                output_path: None,
+                file_size: None,
+                error: Some(error),
+                skipped: false,
+            }),
+        }
+    }
+    save_manifest(&manifest_path, &manifest)?;
+
+    let succeeded = results.iter().filter(|r| r.success).count();
+    announce::announce(&app, &format!("Batch complete, {} of {} converted", succeeded, total));
+    // Only the last file is shown, rather than a window per item
+    let last_output = results.iter().rev().find_map(|r| r.output_path.as_deref());
+    let warnings = match last_output {
+        Some(path) => reveal_on_complete(&app, path).await.into_iter().collect(),
+        None => Vec::new(),
+    };
+    Ok(BatchResult {
+        failed: results.len() - succeeded,
+        succeeded,
+        skipped,
+        items: results,
+        manifest_path: manifest_path.to_string_lossy().to_string(),
+        warnings,
+    })
+}
+
+/// How `convert_lines_from_file` names its clips.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineNaming {
+    /// The line number in the file, zero-padded: `007.mp3`.
+    #[default]
+    Number,
+    /// The start of the line's text, made safe for file names.
+    Text,
+}
+
+#[derive(Debug, Serialize)]
+struct LineRecord {
+    line: usize,
+    text: String,
+    output_path: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinesResult {
+    #[serde(flatten)]
+    batch: BatchResult,
+    lines_manifest_path: String,
+    /// Encoding the file was read as.
+    text_encoding: String,
+}
+
+/// Non-empty lines of a text file with their 1-based line numbers.
+type Lines = Vec<(usize, String)>;
+
+/// The lines of a text file and the encoding it was read as. CRLF endings
+/// are accepted.
+fn read_lines(path: &Path, encoding: Option<&str>) -> Result<(Lines, &'static str), String> {
+    let (contents, encoding) = text::read_text_file(path, encoding)?;
+    let lines = contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    Ok((lines, encoding))
+}
+
+/// File stems for `lines`. Text names that would collide get the line
+/// number appended, so every clip keeps its own file.
+fn line_names(lines: &[(usize, String)], naming: LineNaming) -> Vec<String> {
+    let width = lines.last().map_or(1, |(number, _)| number.to_string().len()).max(3);
+    match naming {
+        LineNaming::Number => lines.iter().map(|(number, _)| format!("{:0width$}", number)).collect(),
+        LineNaming::Text => {
+            let names: Vec<String> = lines
+                .iter()
+                .map(|(_, line)| paths::sanitize_filename(&line.chars().take(LINE_NAME_CHARS).collect::<String>()))
+                .collect();
+            names
+                .iter()
+                .zip(lines)
+                .map(|(name, (number, _))| {
+                    if names.iter().filter(|other| other.eq_ignore_ascii_case(name)).count() > 1 {
+                        format!("{}-{:0width$}", name, number)
+                    } else {
+                        name.clone()
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+/// Synthesizes every non-empty line of a text file as its own clip in
+/// `output_dir`, e.g. for flashcards. Runs as a batch, so `batch-progress`
+/// is emitted per line, and writes `lines-manifest.json` mapping each line
+/// to its file.
+#[command]
+#[allow(clippy::too_many_arguments)]
+pub async fn convert_lines_from_file(
+    app: AppHandle,
+    path: String,
+    voice: String,
+    format: String,
+    language: Option<String>,
+    output_dir: String,
+    naming: Option<LineNaming>,
+    encoding: Option<String>,
+) -> Result<LinesResult, String> {
+    let format = audio::normalize_format(&format)?.to_string();
+    let (lines, text_encoding) = read_lines(Path::new(&path), encoding.as_deref())?;
+    if lines.is_empty() {
+        return Err(format!("{} has no text to convert", path));
+    }
+
+    let names = line_names(&lines, naming.unwrap_or_default());
+    let items = lines
+        .iter()
+        .zip(names)
+        .map(|((_, line), name)| BatchItem {
+            text: line.clone(),
+            voice: voice.clone(),
+            format: format.clone(),
+            output_name: Some(name),
+            language: language.clone(),
+        })
+        .collect();
+    let batch = convert_batch(app, items, output_dir.clone(), None).await?;
+
+    let records: Vec<LineRecord> = lines
+        .into_iter()
+        .zip(&batch.items)
+        .map(|((line, text), item)| LineRecord {
+            line,
+            text,
+            output_path: item.output_path.clone(),
+            error: item.error.clone(),
+        })
+        .collect();
+    let manifest_path = Path::new(&output_dir).join(LINES_MANIFEST_NAME);
+    let json = serde_json::to_string_pretty(&records).map_err(|e| format!("Failed to encode manifest: {}", e))?;
+    std::fs::write(&manifest_path, json).map_err(|e| format!("Failed to write manifest: {}", e))?;
+
+    Ok(LinesResult {
+        batch,
+        lines_manifest_path: manifest_path.to_string_lossy().to_string(),
+        text_encoding: text_encoding.to_string(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct FolderRecord {
+    source: String,
+    output_path: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FolderResult {
+    #[serde(flatten)]
+    batch: BatchResult,
+    folder_manifest_path: String,
+    /// Files passed over because they aren't text, or have nothing to read.
+    skipped_files: usize,
+}
+
+fn is_text_file(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|e| TEXT_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// Text files in `dir`, and in its subfolders with `recursive`, sorted by
+/// path, plus how many other files were passed over. Hidden files and
+/// folders are ignored altogether, as is `exclude`, the output folder.
+fn discover_text_files(dir: &Path, recursive: bool, exclude: &Path) -> Result<(Vec<PathBuf>, usize), String> {
+    let mut files = Vec::new();
+    let mut skipped = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = std::fs::read_dir(&dir).map_err(|e| format!("Failed to read folder {}: {}", dir.display(), e))?;
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => {
+                    let is_output = path.canonicalize().ok() == exclude.canonicalize().ok();
+                    if recursive && !is_output {
+                        pending.push(path);
+                    }
+                }
+                Ok(kind) if kind.is_file() && is_text_file(&path) => files.push(path),
+                Ok(kind) if kind.is_file() => skipped += 1,
+                _ => {}
+            }
+        }
+    }
+    files.sort();
+    Ok((files, skipped))
+}
+
+/// Output stems for `files`, found under `root`: the source's own stem,
+/// unless files in different subfolders share it, in which case the
+/// relative path is spelled out, `part1-intro` for `part1/intro.md`.
+fn folder_names(root: &Path, files: &[PathBuf]) -> Vec<String> {
+    let stem = |path: &Path| path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let stems: Vec<String> = files.iter().map(|path| stem(path)).collect();
+    let mut names: Vec<String> = Vec::new();
+    for (path, own) in files.iter().zip(&stems) {
+        let shared = stems.iter().filter(|other| other.eq_ignore_ascii_case(own)).count() > 1;
+        let name = match path.strip_prefix(root).ok().and_then(Path::parent) {
+            Some(parent) if shared && !parent.as_os_str().is_empty() => {
+                let folders = parent.components().map(|c| c.as_os_str().to_string_lossy().to_string());
+                folders.chain(std::iter::once(own.clone())).collect::<Vec<_>>().join("-")
+            }
+            _ => own.clone(),
+        };
+        let mut name = paths::sanitize_filename(&name);
+        // Still taken, e.g. by `notes.txt` next to `notes.md`
+        let base = name.clone();
+        let mut n = 2;
+        while names.iter().any(|other| other.eq_ignore_ascii_case(&name)) {
+            name = format!("{}-{}", base, n);
+            n += 1;
+        }
+        names.push(name);
+    }
+    names
+}
+
+/// Converts every `.txt` and `.md` file in `input_dir`, and its subfolders
+/// with `recursive`, into its own file in `output_dir` named after the
+/// source. Runs as a batch, so `batch-progress` is emitted per file, and
+/// writes `folder-manifest.json` mapping each source to its audio.
+/// Encodings are detected per file.
+#[command]
+pub async fn convert_folder(
+    app: AppHandle,
+    input_dir: String,
+    output_dir: String,
+    voice: String,
+    format: String,
+    language: Option<String>,
+    recursive: bool,
+) -> Result<FolderResult, String> {
+    let format = audio::normalize_format(&format)?.to_string();
+    let root = Path::new(&input_dir);
+    if !root.is_dir() {
+        return Err(format!("Folder does not exist: {}", input_dir));
+    }
+    let (files, mut skipped_files) = discover_text_files(root, recursive, Path::new(&output_dir))?;
+
+    let mut warnings = Vec::new();
+    let mut sources = Vec::new();
+    for path in files {
+        let is_markdown = path.extension().is_some_and(|e| !e.eq_ignore_ascii_case("txt"));
+        match text::read_text_file(&path, None) {
+            Ok((contents, _)) => {
+                let contents = if is_markdown { text::strip_markdown(&contents) } else { contents };
+                if contents.trim().is_empty() {
+                    skipped_files += 1;
+                } else {
+                    sources.push((path, contents));
+                }
+            }
+            Err(e) => {
+                warnings.push(format!("Skipped {}", e));
+                skipped_files += 1;
+            }
+        }
+    }
+    if sources.is_empty() {
+        return Err(format!("{} has no text files to convert", input_dir));
+    }
+
+    let paths: Vec<PathBuf> = sources.iter().map(|(path, _)| path.clone()).collect();
+    let items = sources
+        .into_iter()
+        .zip(folder_names(root, &paths))
+        .map(|((_, text), name)| BatchItem {
+            text,
+            voice: voice.clone(),
+            format: format.clone(),
+            output_name: Some(name),
+            language: language.clone(),
+        })
+        .collect();
+    let mut batch = convert_batch(app, items, output_dir.clone(), None).await?;
+    batch.warnings.splice(0..0, warnings);
+
+    let records: Vec<FolderRecord> = paths
+        .iter()
+        .zip(&batch.items)
+        .map(|(source, item)| FolderRecord {
+            source: source.to_string_lossy().to_string(),
+            output_path: item.output_path.clone(),
+            error: item.error.clone(),
+        })
+        .collect();
+    let manifest_path = Path::new(&output_dir).join(FOLDER_MANIFEST_NAME);
+    let json = serde_json::to_string_pretty(&records).map_err(|e| format!("Failed to encode manifest: {}", e))?;
+    std::fs::write(&manifest_path, json).map_err(|e| format!("Failed to write manifest: {}", e))?;
+
+    Ok(FolderResult {
+        batch,
+        folder_manifest_path: manifest_path.to_string_lossy().to_string(),
+        skipped_files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::{self, MockServer, Reply, TestApp};
+
+    fn item(text: &str, voice: &str, format: &str) -> BatchItem {
+        BatchItem {
+            text: text.to_string(),
+            voice: voice.to_string(),
+            format: format.to_string(),
+            output_name: None,
+            language: None,
+        }
+    }
+
+    fn silent_wav() -> Vec<u8> {
+        audio::Wav {
+            sample_rate: 24_000,
+            channels: 1,
+            bits_per_sample: 16,
+            data: vec![0; 4800],
+        }
+        .to_bytes()
+    }
+
+    fn record(path: &Path, bytes: &[u8]) -> CompletedItem {
+        CompletedItem {
+            index: 0,
+            key: String::new(),
+            path: path.to_string_lossy().to_string(),
+            bytes: bytes.len() as u64,
+            sha256: None,
+        }
+    }
+
+    #[test]
+    fn item_key_is_stable_and_covers_every_field() {
+        let base = item("Hello", "en-US-Chirp3-HD-Charon", "MP3");
+        assert_eq!(item_key(&base), item_key(&base.clone()));
+        assert_eq!(item_key(&base).len(), 16);
+        assert_ne!(item_key(&base), item_key(&item("Hello!", "en-US-Chirp3-HD-Charon", "MP3")));
+        assert_ne!(item_key(&base), item_key(&item("Hello", "en-US-Chirp3-HD-Kore", "MP3")));
+        assert_ne!(item_key(&base), item_key(&item("Hello", "en-US-Chirp3-HD-Charon", "LINEAR16")));
+        // Field boundaries count, so text can't shift into the voice
+        assert_ne!(item_key(&item("ab", "c", "MP3")), item_key(&item("b", "ac", "MP3")));
+    }
+
+    #[test]
+    fn output_path_numbers_unnamed_items() {
+        let dir = Path::new("/out");
+        assert_eq!(output_path(dir, 4, &item("Hi", "v", "MP3")), PathBuf::from("/out/005.mp3"));
+        let named = BatchItem {
+            output_name: Some("Intro".to_string()),
+            ..item("Hi", "v", "LINEAR16")
+        };
+        assert_eq!(output_path(dir, 4, &named), PathBuf::from("/out/Intro.wav"));
+    }
+
+    #[test]
+    fn manifest_round_trips_and_tolerates_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(MANIFEST_NAME);
+        let manifest = BatchManifest {
+            completed: vec![record(&dir.path().join("001.wav"), b"data")],
+        };
+        save_manifest(&path, &manifest).unwrap();
+        let loaded = load_manifest(&path);
+        assert_eq!(loaded.completed.len(), 1);
+        assert_eq!(loaded.completed[0].bytes, 4);
+        assert!(!paths::part_path(&path).exists());
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(load_manifest(&path).completed.is_empty());
+        assert!(load_manifest(&dir.path().join("missing.json")).completed.is_empty());
+    }
+
+    #[test]
+    fn is_intact_needs_the_recorded_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("001.wav");
+        let wav = silent_wav();
+        std::fs::write(&path, &wav).unwrap();
+
+        assert!(is_intact(&record(&path, &wav), "LINEAR16"));
+        let resized = CompletedItem {
+            bytes: wav.len() as u64 + 1,
+            ..record(&path, &wav)
+        };
+        assert!(!is_intact(&resized, "LINEAR16"));
+        assert!(!is_intact(&record(&dir.path().join("002.wav"), &wav), "LINEAR16"));
+    }
+
+    #[test]
+    fn read_lines_skips_blank_lines_and_keeps_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cards.txt");
+        std::fs::write(&path, "\u{feff}Bonjour\r\n\r\n  Merci  \nAu revoir").unwrap();
+        let (lines, _) = read_lines(&path, None).unwrap();
+        assert_eq!(
+            lines,
+            vec![(1, "Bonjour".to_string()), (3, "Merci".to_string()), (4, "Au revoir".to_string())]
+        );
+    }
+
+    #[test]
+    fn line_names_pad_numbers_and_split_duplicate_text() {
+        let lines = vec![(1, "Hello".to_string()), (2, "hello".to_string()), (12, "Bye".to_string())];
+        assert_eq!(line_names(&lines, LineNaming::Number), vec!["001", "002", "012"]);
+        assert_eq!(line_names(&lines, LineNaming::Text), vec!["Hello-001", "hello-002", "Bye"]);
+
+        let long = vec![(1000, "x".repeat(60))];
+        assert_eq!(line_names(&long, LineNaming::Number), vec!["1000"]);
+        assert_eq!(line_names(&long, LineNaming::Text), vec!["x".repeat(LINE_NAME_CHARS)]);
+    }
+
+    #[test]
+    fn eta_is_unknown_until_something_finishes() {
+        let mut eta = EtaEstimator::default();
+        assert_eq!(eta.remaining_secs(1000), None);
+        eta.record(0, Duration::from_secs(1));
+        assert_eq!(eta.remaining_secs(1000), None);
+    }
+
+    #[test]
+    fn eta_averages_recent_items() {
+        let mut eta = EtaEstimator::default();
+        eta.record(100, Duration::from_secs(1));
+        eta.record(300, Duration::from_secs(2));
+        assert_eq!(eta.chars_per_second(), Some(400.0 / 3.0));
+        assert_eq!(eta.remaining_secs(1000), Some(7.5));
+
+        for _ in 0..ETA_WINDOW {
+            eta.record(100, Duration::from_secs(1));
+        }
+        assert_eq!(eta.recent.len(), ETA_WINDOW);
+        assert_eq!(eta.chars_per_second(), Some(100.0));
+    }
+
+    #[test]
+    fn eta_starts_over_when_throughput_jumps() {
+        let mut eta = EtaEstimator::default();
+        eta.record(100, Duration::from_secs(1));
+        eta.record(100, Duration::from_secs(1));
+        eta.record(100 * (ETA_RESET_FACTOR as usize + 1), Duration::from_secs(1));
+        assert_eq!(eta.recent.len(), 1);
+    }
+
+    #[test]
+    fn estimate_prices_each_voice_tier() {
+        let items = vec![
+            item(&"a".repeat(1000), "en-US-Chirp3-HD-Kore", "MP3"),
+            item(&"b".repeat(500), "en-US-Studio-O", "MP3"),
+            item(&"c".repeat(2000), "en-US-Chirp3-HD-Puck", "MP3"),
+            item(&"d".repeat(100), "Samantha", "LINEAR16"),
+        ];
+        let estimate = estimate(&items, None);
+        assert_eq!((estimate.items, estimate.characters), (4, 3600));
+
+        let tiers: Vec<(Option<&str>, usize, usize)> =
+            estimate.tiers.iter().map(|t| (t.tier.as_deref(), t.items, t.characters)).collect();
+        assert_eq!(tiers, vec![(Some("Chirp3-HD"), 2, 3000), (Some("Studio"), 1, 500), (None, 1, 100)]);
+        assert!((estimate.tiers[0].estimated_cost_usd - 0.09).abs() < 1e-9);
+        assert!((estimate.tiers[1].estimated_cost_usd - 0.08).abs() < 1e-9);
+        // Unpriced voices are estimated at the default price
+        assert!((estimate.tiers[2].estimated_cost_usd - 0.003).abs() < 1e-9);
+        assert!((estimate.estimated_cost_usd - 0.173).abs() < 1e-9);
+        assert!(!estimate.exceeds_budget);
+    }
+
+    #[test]
+    fn estimate_checks_the_budget() {
+        let items = vec![item("Hello there", "en-US-Chirp3-HD-Kore", "MP3")];
+        assert!(!estimate(&items, Some(11)).exceeds_budget);
+        let over = estimate(&items, Some(10));
+        assert!(over.exceeds_budget);
+        assert_eq!(over.budget_remaining, Some(10));
+    }
+
+    #[test]
+    fn is_intact_checks_the_recorded_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("001.wav");
+        let wav = silent_wav();
+        std::fs::write(&path, &wav).unwrap();
+        let checked = CompletedItem {
+            sha256: Some(sha256_hex(&wav)),
+            ..record(&path, &wav)
+        };
+        assert!(is_intact(&checked, "LINEAR16"));
+
+        // Same size and still a valid WAV, but not the bytes that were written
+        let mut corrupted = wav.clone();
+        *corrupted.last_mut().unwrap() ^= 0x7f;
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(!is_intact(&checked, "LINEAR16"));
+        assert!(is_intact(&record(&path, &corrupted), "LINEAR16"));
+    }
+
+    #[test]
+    fn sha256_hex_is_lowercase_hex() {
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn read_lines_reports_the_detected_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cards.txt");
+        std::fs::write(&path, b"Caf\xe9\r\nTr\xe8s bien et d\xe9j\xe0 fini\r\n").unwrap();
+        let (lines, encoding) = read_lines(&path, None).unwrap();
+        assert_eq!(encoding, "windows-1252");
+        assert_eq!(lines, vec![(1, "Café".to_string()), (2, "Très bien et déjà fini".to_string())]);
+    }
+
+    #[test]
+    fn discover_text_files_skips_hidden_output_and_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for (path, contents) in [
+            ("b.txt", "B"),
+            ("a.md", "A"),
+            ("cover.png", "png"),
+            (".notes.txt", "hidden"),
+            ("part1/intro.md", "Intro"),
+            (".git/HEAD.txt", "hidden folder"),
+            ("audio/old.txt", "output"),
+        ] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        let output = root.join("audio");
+
+        let (files, skipped) = discover_text_files(root, false, &output).unwrap();
+        assert_eq!(files, vec![root.join("a.md"), root.join("b.txt")]);
+        assert_eq!(skipped, 1);
+
+        let (files, _) = discover_text_files(root, true, &output).unwrap();
+        assert_eq!(files, vec![root.join("a.md"), root.join("b.txt"), root.join("part1/intro.md")]);
+        assert!(discover_text_files(&root.join("missing"), false, &output).is_err());
+    }
+
+    #[test]
+    fn folder_names_spell_out_shared_stems() {
+        let root = Path::new("/books");
+        let files: Vec<PathBuf> = ["/books/notes.md", "/books/notes.txt", "/books/part1/intro.md", "/books/part2/Intro.md", "/books/part2/outro.md"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(folder_names(root, &files), vec!["notes", "notes-2", "part1-intro", "part2-Intro", "outro"]);
+    }
+
+    fn wav_items() -> Vec<BatchItem> {
+        ["One", "Two", "Three"].iter().map(|text| item(text, "en-US-Chirp3-HD-Charon", "LINEAR16")).collect()
+    }
+
+    /// Texts the server was asked to synthesize, in order.
+    fn synthesized_texts(server: &MockServer) -> Vec<String> {
+        server
+            .requests_to("/synthesize")
+            .iter()
+            .map(|request| request.json()["text"].as_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn resume_converts_only_the_unfinished_items() {
+        let down = Arc::new(AtomicBool::new(true));
+        let server_down = down.clone();
+        let audio = testing::tone_wav(200);
+        let server = MockServer::start(move |request| {
+            if request.path == "/synthesize" && request.json()["text"] == "Two" && server_down.load(Ordering::SeqCst) {
+                return Reply::json(500, serde_json::json!({ "detail": "Synthesis failed" }));
+            }
+            testing::tts_reply(request, &audio)
+        })
+        .await;
+        let app = TestApp::with_server(&server);
+        let output_dir = app.dir().join("batch").to_string_lossy().to_string();
+        let resumed = testing::listen(app.handle(), "batch-resumed");
+
+        let first = convert_batch(app.handle().clone(), wav_items(), output_dir.clone(), None).await.unwrap();
+        assert_eq!((first.succeeded, first.failed, first.skipped), (2, 1, 0));
+        assert!(resumed.lock().unwrap().is_empty());
+
+        down.store(false, Ordering::SeqCst);
+        let before = synthesized_texts(&server).len();
+        let options = BatchOptions { resume: true };
+        let second = convert_batch(app.handle().clone(), wav_items(), output_dir, Some(options)).await.unwrap();
+        assert_eq!((second.succeeded, second.failed, second.skipped), (3, 0, 2));
+        assert_eq!(synthesized_texts(&server)[before..], ["Two"]);
+        let skipped: Vec<bool> = second.items.iter().map(|item| item.skipped).collect();
+        assert_eq!(skipped, [true, false, true]);
+        assert_eq!(*resumed.lock().unwrap(), [serde_json::json!({ "skipped": 2, "remaining": 1 })]);
+    }
+
+    #[tokio::test]
+    async fn resume_redoes_items_a_crash_left_unfinished() {
+        let audio = testing::tone_wav(200);
+        let server = MockServer::start(move |request| testing::tts_reply(request, &audio)).await;
+        let app = TestApp::with_server(&server);
+        let output_dir = app.dir().join("batch");
+        let dir = output_dir.to_string_lossy().to_string();
+
+        let first = convert_batch(app.handle().clone(), wav_items(), dir.clone(), None).await.unwrap();
+        assert_eq!(first.succeeded, 3);
+        let outputs: Vec<PathBuf> = first.items.iter().map(|item| PathBuf::from(item.output_path.clone().unwrap())).collect();
+
+        // "One" was rewritten in place and lost its last samples, keeping its length
+        let mut one = std::fs::read(&outputs[0]).unwrap();
+        let tail = one.len() - 100;
+        one[tail..].fill(0);
+        std::fs::write(&outputs[0], one).unwrap();
+        // "Two" stopped between writing its `.part` file and renaming it,
+        // before the manifest recorded it
+        std::fs::rename(&outputs[1], paths::part_path(&outputs[1])).unwrap();
+        let manifest_path = output_dir.join(MANIFEST_NAME);
+        let mut manifest = load_manifest(&manifest_path);
+        manifest.completed.retain(|c| c.index != 1);
+        save_manifest(&manifest_path, &manifest).unwrap();
+
+        let before = synthesized_texts(&server).len();
+        let options = BatchOptions { resume: true };
+        let second = convert_batch(app.handle().clone(), wav_items(), dir, Some(options)).await.unwrap();
+        assert_eq!((second.succeeded, second.failed, second.skipped), (3, 0, 1));
+        assert_eq!(synthesized_texts(&server)[before..], ["One", "Two"]);
+        assert!(!paths::part_path(&outputs[1]).exists());
+        let manifest = load_manifest(&manifest_path);
+        for (item, output) in manifest.completed.iter().zip(&outputs) {
+            assert!(is_intact(item, "LINEAR16"), "{}", output.display());
+        }
+        assert_eq!(manifest.completed.len(), 3);
+    }
+}
@@ -0,0 +1,205 @@
+//! Latency measurements for comparing servers. Each iteration synthesizes
+//! the same text and downloads the result into memory, timing the two
+//! phases separately; nothing is saved or cached.
+
+use std::time::Instant;
+
+use serde::Serialize;
+use tauri::command;
+
+use crate::{
+    client, download_audio, poll_synthesis, ratelimit, send_synthesis, settings, text, AppHandle, TTSRequest, API_BASE_URL,
+};
+
+const MAX_ITERATIONS: u32 = 100;
+
+#[derive(Debug, Serialize)]
+pub struct LatencyStats {
+    min_ms: f64,
+    max_ms: f64,
+    mean_ms: f64,
+    /// Nearest-rank 95th percentile.
+    p95_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkResult {
+    iterations: u32,
+    succeeded: u32,
+    failed: u32,
+    /// From sending the request to the server naming the download; `None`
+    /// when every iteration failed.
+    request: Option<LatencyStats>,
+    download: Option<LatencyStats>,
+    /// Characters synthesized per second of request and download time.
+    chars_per_second: Option<f64>,
+    /// Why iterations failed, one message per failure.
+    errors: Vec<String>,
+}
+
+fn latency_stats(samples: &[f64]) -> Option<LatencyStats> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = ((sorted.len() as f64 * 0.95).ceil() as usize).clamp(1, sorted.len());
+    Some(LatencyStats {
+        min_ms: sorted[0],
+        max_ms: sorted[sorted.len() - 1],
+        mean_ms: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        p95_ms: sorted[rank - 1],
+    })
+}
+
+/// Milliseconds the request and the download of one synthesis took. Time
+/// spent waiting on the client-side rate limits is left out.
+async fn run_once(app: &AppHandle, client: &reqwest::Client, request: &TTSRequest) -> Result<(f64, f64), String> {
+    ratelimit::acquire(app, request.text.chars().count()).await;
+    let slot = ratelimit::request_slot(app).await?;
+
+    let started = Instant::now();
+    let mut result = send_synthesis(app, client, request).await?;
+    if !result.success {
+        return Err(result.error.unwrap_or_else(|| "Synthesis failed".to_string()));
+    }
+    let server = result.server.clone().unwrap_or_else(|| API_BASE_URL.to_string());
+    if let (None, Some(job_id)) = (&result.download_url, &result.job_id) {
+        let cancel = tokio_util::sync::CancellationToken::new();
+        result.download_url = Some(poll_synthesis(app, client, &server, job_id, None, &cancel).await?);
+    }
+    let download_url = result.download_url.ok_or("API response did not include a download URL")?;
+    let request_ms = started.elapsed().as_secs_f64() * 1000.0;
+    drop(slot);
+
+    let started = Instant::now();
+    download_audio(client, &server, &download_url).await?;
+    Ok((request_ms, started.elapsed().as_secs_f64() * 1000.0))
+}
+
+/// Synthesizes `text` `iterations` times, one after another, and reports
+/// the spread of request and download latency. Failed iterations are
+/// counted and described, and the statistics cover the rest.
+#[command]
+pub async fn benchmark_synthesis(
+    app: AppHandle,
+    text: String,
+    voice: String,
+    iterations: u32,
+) -> Result<BenchmarkResult, String> {
+    if text.trim().is_empty() {
+        return Err("Text cannot be empty".to_string());
+    }
+    if !(1..=MAX_ITERATIONS).contains(&iterations) {
+        return Err(format!("Choose between 1 and {} iterations", MAX_ITERATIONS));
+    }
+    if settings::current(&app).offline_mode {
+        return Err("Benchmarks measure the API server, which offline mode doesn't use".to_string());
+    }
+
+    let request = TTSRequest {
+        language: text::language_from_voice(&voice).unwrap_or(settings::current(&app).default_language),
+        text,
+        voice,
+        format: "MP3".to_string(),
+        ..Default::default()
+    };
+    let client = client::build(&app);
+    let (mut request_ms, mut download_ms, mut errors) = (Vec::new(), Vec::new(), Vec::new());
+    for _ in 0..iterations {
+        match run_once(&app, &client, &request).await {
+            Ok((request_time, download_time)) => {
+                request_ms.push(request_time);
+                download_ms.push(download_time);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Ok(summarize(iterations, request.text.chars().count(), &request_ms, &download_ms, errors))
+}
+
+/// Statistics over the iterations that succeeded, each of which
+/// synthesized `chars` characters.
+fn summarize(
+    iterations: u32,
+    chars: usize,
+    request_ms: &[f64],
+    download_ms: &[f64],
+    errors: Vec<String>,
+) -> BenchmarkResult {
+    let succeeded = request_ms.len() as u32;
+    let total_secs = (request_ms.iter().sum::<f64>() + download_ms.iter().sum::<f64>()) / 1000.0;
+    let chars = chars as f64 * succeeded as f64;
+    BenchmarkResult {
+        iterations,
+        succeeded,
+        failed: iterations - succeeded,
+        request: latency_stats(request_ms),
+        download: latency_stats(download_ms),
+        chars_per_second: (total_secs > 0.0).then(|| chars / total_secs),
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::{self, MockServer, Reply, TestApp};
+
+    #[test]
+    fn latency_stats_uses_the_nearest_rank_p95() {
+        assert!(latency_stats(&[]).is_none());
+
+        let samples: Vec<f64> = (1..=20).rev().map(f64::from).collect();
+        let stats = latency_stats(&samples).unwrap();
+        assert_eq!((stats.min_ms, stats.max_ms, stats.mean_ms), (1.0, 20.0, 10.5));
+        assert_eq!(stats.p95_ms, 19.0);
+
+        let one = latency_stats(&[42.0]).unwrap();
+        assert_eq!((one.min_ms, one.max_ms, one.mean_ms, one.p95_ms), (42.0, 42.0, 42.0, 42.0));
+    }
+
+    #[test]
+    fn summarize_counts_failures_and_throughput() {
+        let result = summarize(3, 100, &[400.0, 600.0], &[100.0, 900.0], vec!["Server error".to_string()]);
+        assert_eq!((result.iterations, result.succeeded, result.failed), (3, 2, 1));
+        // 200 characters in 2 seconds
+        assert_eq!(result.chars_per_second, Some(100.0));
+        assert_eq!(result.request.unwrap().mean_ms, 500.0);
+        assert_eq!(result.errors, vec!["Server error".to_string()]);
+
+        let failed = summarize(2, 100, &[], &[], vec!["a".to_string(), "b".to_string()]);
+        assert_eq!((failed.succeeded, failed.failed), (0, 2));
+        assert!(failed.request.is_none() && failed.download.is_none() && failed.chars_per_second.is_none());
+    }
+
+    #[tokio::test]
+    async fn benchmark_times_each_phase_against_a_slow_server() {
+        let server = MockServer::start(|request| {
+            if request.path == "/synthesize" {
+                testing::synthesized("timed").after(Duration::from_millis(150))
+            } else if request.path == "/download/timed" {
+                Reply::bytes(200, "audio/mpeg", vec![0; 1000]).after(Duration::from_millis(50))
+            } else {
+                Reply::not_found()
+            }
+        })
+        .await;
+        let app = TestApp::with_server(&server);
+
+        let text = "Timing this sentence.".to_string();
+        let result = benchmark_synthesis(app.handle().clone(), text, "en-US-Chirp3-HD-Charon".to_string(), 3).await.unwrap();
+        assert_eq!((result.succeeded, result.failed), (3, 0), "{:?}", result.errors);
+        // Each phase takes at least its delay, plus little enough for a local server
+        let request = result.request.unwrap();
+        assert!(request.min_ms >= 150.0 && request.max_ms < 350.0, "{:?}", request);
+        let download = result.download.unwrap();
+        assert!(download.min_ms >= 50.0 && download.max_ms < 250.0, "{:?}", download);
+        let chars_per_second = result.chars_per_second.unwrap();
+        assert!((50.0..=105.0).contains(&chars_per_second), "{}", chars_per_second);
+        assert_eq!(server.requests_to("/synthesize").len(), 3);
+    }
+}
@@ -0,0 +1,302 @@
+//! Content-addressed cache of synthesized audio, so converting the same text
+//! with the same parameters again doesn't call the server. Entries live in
+//! the app cache directory as `<sha256>.<ext>`; a file's modification time
+//! records its last use, and the least recently used ones are evicted once
+//! the cache grows past `audio_cache_max_mb`.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tauri::{command, Manager};
+
+use crate::{
+    audio, format_file_size, run_conversion, settings, AppHandle, ConversionOptions, ConversionResult, TTSRequest,
+};
+
+const CACHE_DIR: &str = "audio-cache";
+
+#[derive(Debug, Serialize)]
+pub struct RequestHash {
+    content_hash: String,
+    /// Whether the cache holds audio for the request.
+    cached: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    entries: usize,
+    bytes: u64,
+    max_bytes: u64,
+}
+
+fn cache_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_cache_dir()
+        .map(|dir| dir.join(CACHE_DIR))
+        .map_err(|e| format!("Cannot determine cache directory: {}", e))
+}
+
+fn max_bytes(app: &AppHandle) -> u64 {
+    settings::current(app).audio_cache_max_mb * 1024 * 1024
+}
+
+/// Text with runs of whitespace collapsed and blank lines dropped, which the
+/// voice reads the same way.
+fn normalize_text(text: &str) -> String {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// SHA-256 hex of everything that determines the audio `request` produces:
+/// its text after `normalize_text`, voice, format and every parameter. The
+/// same request always hashes the same, and changing anything the voice
+/// would hear changes the hash.
+pub fn content_hash(request: &TTSRequest) -> String {
+    let normalized = TTSRequest {
+        text: normalize_text(&request.text),
+        ..request.clone()
+    };
+    let json = serde_json::to_vec(&normalized).unwrap_or_default();
+    Sha256::digest(&json).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn entry_path(app: &AppHandle, request: &TTSRequest) -> Result<PathBuf, String> {
+    Ok(cache_dir(app)?.join(format!("{}.{}", content_hash(request), audio::file_extension(&request.format))))
+}
+
+/// Cache files with their size and last use, oldest first.
+fn entries(dir: &Path) -> Vec<(PathBuf, u64, SystemTime)> {
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<_> = read_dir
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            Some((entry.path(), metadata.len(), used))
+        })
+        .collect();
+    entries.sort_by_key(|(_, _, used)| *used);
+    entries
+}
+
+/// Removes the least recently used entries until the cache fits `max_bytes`.
+fn evict(dir: &Path, max_bytes: u64) {
+    let entries = entries(dir);
+    let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    for (path, len, _) in entries {
+        if total <= max_bytes {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+/// Copies a cached rendering of `request` to `output_path` and describes it
+/// like a conversion would. `None` on a miss, when the cache is disabled or
+/// when the copy fails, so the caller synthesizes as usual.
+pub fn restore(app: &AppHandle, request: &TTSRequest, output_path: &str) -> Option<ConversionResult> {
+    if max_bytes(app) == 0 {
+        return None;
+    }
+    let start = std::time::Instant::now();
+    let entry = entry_path(app, request).ok()?;
+    let len = std::fs::copy(&entry, output_path).ok()?;
+
+    // Mark the entry as recently used
+    if let Ok(file) = std::fs::File::options().write(true).open(&entry) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+
+    Some(ConversionResult {
+        success: true,
+        output_path: Some(output_path.to_string()),
+        file_size: Some(format_file_size(len as usize)),
+        processing_time: Some(format!("{:.2}s", start.elapsed().as_secs_f64())),
+        cached: true,
+        ..Default::default()
+    })
+}
+
+/// Adds the audio at `audio_path`, produced for `request`, to the cache.
+/// Failures only cost a future cache hit, so they are logged, not returned.
+pub fn store(app: &AppHandle, request: &TTSRequest, audio_path: &str) {
+    let max_bytes = max_bytes(app);
+    if max_bytes == 0 {
+        return;
+    }
+    let stored = entry_path(app, request).and_then(|entry| {
+        let dir = entry.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create cache directory: {}", e))?;
+        // Copy under a temporary name so a reader never sees half a file
+        let temp = entry.with_extension("part");
+        std::fs::copy(audio_path, &temp)
+            .and_then(|_| std::fs::rename(&temp, &entry))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&temp);
+                format!("Failed to cache audio: {}", e)
+            })?;
+        evict(dir, max_bytes);
+        Ok(())
+    });
+    if let Err(e) = stored {
+        println!("{}", e);
+    }
+}
+
+#[command]
+pub fn get_cache_stats(app: AppHandle) -> Result<CacheStats, String> {
+    let entries = entries(&cache_dir(&app)?);
+    Ok(CacheStats {
+        entries: entries.len(),
+        bytes: entries.iter().map(|(_, len, _)| len).sum(),
+        max_bytes: max_bytes(&app),
+    })
+}
+
+/// Deletes every cached file and returns how many were removed.
+#[command]
+pub fn clear_audio_cache(app: AppHandle) -> Result<usize, String> {
+    remove_entries(&cache_dir(&app)?)
+}
+
+/// Empties the audio cache under the app cache directory `app_cache_dir`.
+pub fn clear_in(app_cache_dir: &Path) -> Result<usize, String> {
+    remove_entries(&app_cache_dir.join(CACHE_DIR))
+}
+
+fn remove_entries(dir: &Path) -> Result<usize, String> {
+    let mut removed = 0;
+    for (path, _, _) in entries(dir) {
+        std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// The `content_hash` converting `text` with these arguments would have,
+/// resolved the way `convert_text_to_speech` resolves them, so the frontend
+/// can spot text it has already converted. Makes the checks of a dry run
+/// and synthesizes nothing.
+#[command]
+pub async fn hash_request(
+    app: AppHandle,
+    text: String,
+    voice: Option<String>,
+    format: Option<String>,
+    options: Option<ConversionOptions>,
+) -> Result<RequestHash, String> {
+    // The dry run wants somewhere writable to describe the output file in
+    let dir = cache_dir(&app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let output_path = dir.join("request").to_string_lossy().to_string();
+    let options = ConversionOptions {
+        dry_run: true,
+        ..options.unwrap_or_default()
+    };
+
+    let result = run_conversion(app, text, voice, format, output_path, false, options).await?;
+    let content_hash = result.content_hash.ok_or("The request could not be hashed")?;
+    let cached = entries(&dir)
+        .iter()
+        .any(|(path, _, _)| path.file_stem().is_some_and(|stem| *stem == *content_hash));
+    Ok(RequestHash { content_hash, cached })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_text_collapses_spacing_but_keeps_lines() {
+        assert_eq!(normalize_text("  Hello,\t world  "), "Hello, world");
+        assert_eq!(normalize_text("One\r\n\n   \nTwo  three"), "One\nTwo three");
+        assert_eq!(normalize_text("One Two"), normalize_text("One  Two\n"));
+        assert_ne!(normalize_text("One Two"), normalize_text("One\nTwo"));
+    }
+
+    /// Writes a `len` byte cache file last used `age_secs` ago.
+    fn entry(dir: &Path, name: &str, len: usize, age_secs: u64) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, vec![0; len]).unwrap();
+        let used = SystemTime::now() - std::time::Duration::from_secs(age_secs);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(used).unwrap();
+        path
+    }
+
+    #[test]
+    fn evict_removes_the_least_recently_used_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let oldest = entry(dir.path(), "a.mp3", 100, 300);
+        let middle = entry(dir.path(), "b.mp3", 100, 200);
+        let newest = entry(dir.path(), "c.mp3", 100, 100);
+
+        let order: Vec<PathBuf> = entries(dir.path()).into_iter().map(|(path, _, _)| path).collect();
+        assert_eq!(order, vec![oldest.clone(), middle.clone(), newest.clone()]);
+
+        evict(dir.path(), 300);
+        assert!(oldest.exists());
+        evict(dir.path(), 250);
+        assert!(!oldest.exists() && middle.exists() && newest.exists());
+        evict(dir.path(), 0);
+        assert!(entries(dir.path()).is_empty());
+    }
+
+    fn request(text: &str) -> TTSRequest {
+        TTSRequest {
+            text: text.to_string(),
+            voice: "en-US-Chirp3-HD-Charon".to_string(),
+            format: "MP3".to_string(),
+            language: "en-US".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn content_hash_is_stable_sha256_hex() {
+        let hash = content_hash(&request("Hello, world."));
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(hash, content_hash(&request("Hello, world.")));
+        assert_eq!(hash, content_hash(&request("  Hello,   world.\n\n")));
+    }
+
+    #[test]
+    fn content_hash_changes_with_anything_the_voice_hears() {
+        let hash = content_hash(&request("Hello, world."));
+        let changed = [
+            request("Hello, world!"),
+            TTSRequest {
+                voice: "en-US-Chirp3-HD-Kore".to_string(),
+                ..request("Hello, world.")
+            },
+            TTSRequest {
+                format: "LINEAR16".to_string(),
+                ..request("Hello, world.")
+            },
+            TTSRequest {
+                speaking_rate: Some(1.25),
+                ..request("Hello, world.")
+            },
+            TTSRequest {
+                pitch: Some(-2.0),
+                ..request("Hello, world.")
+            },
+            TTSRequest {
+                sample_rate: Some(16000),
+                ..request("Hello, world.")
+            },
+        ];
+        for request in changed {
+            assert_ne!(content_hash(&request), hash, "{:?}", request);
+        }
+    }
+}
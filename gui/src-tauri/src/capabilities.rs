@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager};
+
+use crate::{client, servers, text, AppHandle};
+
+/// How long a fetched answer is reused before asking the server again.
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// The device profiles Google Cloud Text-to-Speech defines, from smallest
+/// speaker to phone line.
+pub const EFFECTS_PROFILES: [&str; 8] = [
+    "wearable-class-device",
+    "handset-class-device",
+    "headphone-class-device",
+    "small-bluetooth-speaker-class-device",
+    "medium-bluetooth-speaker-class-device",
+    "large-home-entertainment-class-device",
+    "large-automotive-class-device",
+    "telephony-class-application",
+];
+
+/// What the API server supports, from `GET /capabilities`. Fields the server
+/// leaves out, and servers without the endpoint, get the conservative
+/// defaults below: Chirp 3 HD only, MP3 and LINEAR16, 5000-byte requests,
+/// no streaming, no SSML and no effects profiles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Capabilities {
+    pub tiers: Vec<String>,
+    pub formats: Vec<String>,
+    pub max_request_bytes: usize,
+    pub streaming: bool,
+    pub ssml: bool,
+    /// SSML elements the server accepts, each with its allowed attributes;
+    /// empty when it doesn't say, in which case the common core is assumed.
+    pub ssml_elements: HashMap<String, Vec<String>>,
+    /// Device profiles, from `EFFECTS_PROFILES`, the server can tune audio for.
+    pub effects_profiles: Vec<String>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            tiers: vec!["Chirp3-HD".to_string()],
+            formats: vec!["MP3".to_string(), "LINEAR16".to_string()],
+            max_request_bytes: text::MAX_REQUEST_BYTES,
+            streaming: false,
+            ssml: false,
+            ssml_elements: HashMap::new(),
+            effects_profiles: Vec::new(),
+        }
+    }
+}
+
+impl Capabilities {
+    pub fn supports_format(&self, format: &str) -> bool {
+        self.formats.iter().any(|f| f.eq_ignore_ascii_case(format))
+    }
+}
+
+#[derive(Default)]
+pub struct CapabilitiesCache(Mutex<Option<(Capabilities, Instant)>>);
+
+async fn fetch(app: &AppHandle) -> Capabilities {
+    let client = client::build(app);
+    let url = format!("{}/capabilities", servers::primary(app, &client).await);
+    let response = match client.get(&url).timeout(Duration::from_secs(5)).send().await {
+        Ok(response) if response.status().is_success() => response,
+        _ => return Capabilities::default(),
+    };
+    response.json::<Capabilities>().await.unwrap_or_default()
+}
+
+/// The server's capabilities, cached for a few minutes.
+pub async fn get(app: &AppHandle) -> Capabilities {
+    let cache = app.state::<CapabilitiesCache>();
+    if let Some((capabilities, fetched)) = cache.0.lock().unwrap().as_ref() {
+        if fetched.elapsed() < CACHE_TTL {
+            return capabilities.clone();
+        }
+    }
+
+    let capabilities = fetch(app).await;
+    *cache.0.lock().unwrap() = Some((capabilities.clone(), Instant::now()));
+    capabilities
+}
+
+/// The last answer fetched, however old, without contacting the server;
+/// the defaults when nothing has been fetched yet.
+pub fn cached(app: &AppHandle) -> Capabilities {
+    let cache = app.state::<CapabilitiesCache>();
+    let cached = cache.0.lock().unwrap();
+    cached.as_ref().map(|(capabilities, _)| capabilities.clone()).unwrap_or_default()
+}
+
+/// Forgets the cached answer, e.g. after the server list changes.
+pub fn invalidate(app: &AppHandle) {
+    *app.state::<CapabilitiesCache>().0.lock().unwrap() = None;
+}
+
+#[command]
+pub async fn get_server_capabilities(app: AppHandle) -> Result<Capabilities, String> {
+    Ok(get(&app).await)
+}
@@ -0,0 +1,239 @@
+//! The HTTP client every request to the API servers goes through, carrying
+//! the `user_agent` and `extra_headers` from settings for proxies and
+//! gateways that require them, the API key in use, and the
+//! configured proxy and timeouts. One client is shared until any of those
+//! change, so requests reuse its pooled connections.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, USER_AGENT};
+use tauri::Manager;
+
+use crate::settings::{self, Settings};
+use crate::{secrets, AppHandle};
+
+/// What a client was built from: headers, proxy and the two timeouts.
+type ClientConfig = (HeaderMap, Option<String>, u64, u64);
+
+/// The client last built and the configuration it was built from.
+#[derive(Default)]
+pub struct SharedClient(Mutex<Option<(ClientConfig, reqwest::Client)>>);
+
+/// Headers reqwest and the server manage themselves; setting them by hand
+/// would break requests.
+const RESERVED_HEADERS: [&str; 5] = ["host", "content-length", "content-type", "transfer-encoding", "connection"];
+
+/// Parses the configured headers, failing on the first malformed or
+/// reserved one.
+pub fn parse_headers(user_agent: Option<&str>, extra: &HashMap<String, String>) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in extra {
+        let header = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name '{}'", name))?;
+        if RESERVED_HEADERS.contains(&header.as_str()) || header == USER_AGENT {
+            return Err(format!("The {} header can't be set here", name.trim()));
+        }
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| format!("Invalid value for header '{}'", name))?;
+        headers.insert(header, value);
+    }
+    if let Some(user_agent) = user_agent.filter(|ua| !ua.trim().is_empty()) {
+        let value = HeaderValue::from_str(user_agent.trim()).map_err(|_| "Invalid user agent".to_string())?;
+        headers.insert(USER_AGENT, value);
+    }
+    Ok(headers)
+}
+
+pub fn validate_proxy(proxy: &str) -> Result<reqwest::Proxy, String> {
+    reqwest::Proxy::all(proxy.trim()).map_err(|e| format!("Invalid proxy '{}': {}", proxy, e))
+}
+
+/// Checks the header and proxy settings before they are saved.
+pub fn validate(settings: &Settings) -> Result<(), String> {
+    parse_headers(settings.user_agent.as_deref(), &settings.extra_headers)?;
+    if let Some(proxy) = &settings.proxy {
+        validate_proxy(proxy)?;
+    }
+    Ok(())
+}
+
+/// The configured headers. A settings file edited by hand may hold bad
+/// ones, which are logged and left out rather than failing every request.
+pub fn headers(app: &AppHandle) -> HeaderMap {
+    let settings = settings::current(app);
+    let mut headers = parse_headers(settings.user_agent.as_deref(), &settings.extra_headers).unwrap_or_else(|e| {
+        println!("Ignoring custom headers: {}", e);
+        HeaderMap::new()
+    });
+    if let Some(api_key) = secrets::active_api_key(app) {
+        match bearer(&api_key) {
+            Some(value) => {
+                headers.insert(AUTHORIZATION, value);
+            }
+            None => println!("Ignoring an API key that isn't valid in a header"),
+        }
+    }
+    headers
+}
+
+/// `Bearer <api_key>` as a header value kept out of debug output, or `None`
+/// when the key holds characters a header can't.
+fn bearer(api_key: &str) -> Option<HeaderValue> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", api_key)).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+fn build_new(config: &ClientConfig) -> reqwest::Client {
+    let (headers, proxy, connect_timeout_secs, request_timeout_secs) = config;
+    let mut builder = reqwest::Client::builder().default_headers(headers.clone());
+    if let Some(proxy) = proxy {
+        match validate_proxy(proxy) {
+            Ok(proxy) => builder = builder.proxy(proxy),
+            Err(e) => println!("Ignoring proxy: {}", e),
+        }
+    }
+    if *connect_timeout_secs > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(*connect_timeout_secs));
+    }
+    if *request_timeout_secs > 0 {
+        builder = builder.timeout(Duration::from_secs(*request_timeout_secs));
+    }
+    builder.build().unwrap_or_else(|e| {
+        println!("Falling back to the default HTTP client: {}", e);
+        reqwest::Client::new()
+    })
+}
+
+/// A client sending the configured headers with every request, through the
+/// configured proxy and with the configured timeouts. Clients share one
+/// connection pool until the configuration changes.
+pub fn build(app: &AppHandle) -> reqwest::Client {
+    let settings = settings::current(app);
+    let config = (headers(app), settings.proxy, settings.connect_timeout_secs, settings.request_timeout_secs);
+    app.state::<SharedClient>().get_or_build(config, build_new)
+}
+
+impl SharedClient {
+    /// The shared client when it was built from `config`, otherwise a new
+    /// one from `build` that replaces it.
+    fn get_or_build(&self, config: ClientConfig, build: impl FnOnce(&ClientConfig) -> reqwest::Client) -> reqwest::Client {
+        let mut cached = self.0.lock().unwrap();
+        match cached.as_ref() {
+            Some((built_from, client)) if *built_from == config => client.clone(),
+            _ => {
+                let client = build(&config);
+                *cached = Some((config, client.clone()));
+                client
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockServer, Reply, TestApp};
+
+    fn extra(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_headers_adds_the_user_agent_and_extras() {
+        let headers = parse_headers(Some(" KIWI/1.0 "), &extra(&[(" X-Team ", " audio ")])).unwrap();
+        assert_eq!(headers[USER_AGENT], "KIWI/1.0");
+        assert_eq!(headers["x-team"], "audio");
+
+        assert!(parse_headers(Some("  "), &HashMap::new()).unwrap().is_empty());
+        assert!(parse_headers(None, &HashMap::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_headers_refuses_bad_and_reserved_headers() {
+        assert_eq!(
+            parse_headers(None, &extra(&[("Content-Length", "5")])),
+            Err("The Content-Length header can't be set here".to_string())
+        );
+        assert!(parse_headers(None, &extra(&[("User-Agent", "other")])).is_err());
+        assert_eq!(parse_headers(None, &extra(&[("bad name", "x")])), Err("Invalid header name 'bad name'".to_string()));
+        assert_eq!(
+            parse_headers(None, &extra(&[("X-Token", "line\nbreak")])),
+            Err("Invalid value for header 'X-Token'".to_string())
+        );
+        assert_eq!(parse_headers(Some("bad\nagent"), &HashMap::new()), Err("Invalid user agent".to_string()));
+    }
+
+    #[test]
+    fn bearer_marks_the_key_sensitive() {
+        let value = bearer("s3cret").unwrap();
+        assert_eq!(value, "Bearer s3cret");
+        assert!(value.is_sensitive());
+        assert_eq!(format!("{:?}", value), "Sensitive");
+        assert!(bearer("line\nbreak").is_none());
+    }
+
+    #[test]
+    fn validate_proxy_names_the_bad_proxy() {
+        assert!(validate_proxy(" http://proxy.local:3128 ").is_ok());
+        assert!(validate_proxy("socks5://127.0.0.1:1080").is_ok());
+        let err = validate_proxy("not a proxy").err().unwrap();
+        assert!(err.starts_with("Invalid proxy 'not a proxy'"), "{}", err);
+    }
+
+    #[test]
+    fn shared_client_is_rebuilt_only_when_the_config_changes() {
+        let shared = SharedClient::default();
+        let mut builds = 0;
+        let mut get = |config: ClientConfig| {
+            shared.get_or_build(config, |config| {
+                builds += 1;
+                build_new(config)
+            })
+        };
+        let config = |proxy: Option<&str>, timeout: u64| (HeaderMap::new(), proxy.map(str::to_string), 5, timeout);
+
+        get(config(None, 60));
+        get(config(None, 60));
+        get(config(None, 30));
+        get(config(Some("http://proxy.local:3128"), 30));
+        get(config(Some("http://proxy.local:3128"), 30));
+        assert_eq!(builds, 3);
+    }
+
+    #[tokio::test]
+    async fn built_client_sends_the_configured_headers() {
+        let server = MockServer::start(|_| Reply::json(200, serde_json::json!({}))).await;
+        let app = TestApp::new(Settings {
+            user_agent: Some("KIWI-Test/2.0".to_string()),
+            extra_headers: extra(&[("X-Team", "audio"), ("X-Gateway-Key", "k-123")]),
+            ..Settings::default()
+        });
+
+        build(app.handle()).get(format!("{}/voices", server.url)).send().await.unwrap();
+        let sent = &server.requests()[0];
+        assert_eq!(sent.header("User-Agent"), Some("KIWI-Test/2.0"));
+        assert_eq!(sent.header("X-Team"), Some("audio"));
+        assert_eq!(sent.header("X-Gateway-Key"), Some("k-123"));
+        assert_eq!(sent.header("Authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn shared_client_reuses_one_connection() {
+        let server = MockServer::start(|_| Reply::json(200, serde_json::json!({}))).await;
+        let app = TestApp::with_server(&server);
+
+        for _ in 0..4 {
+            let response = build(app.handle()).get(format!("{}/health", server.url)).send().await.unwrap();
+            response.bytes().await.unwrap();
+        }
+        assert_eq!(server.requests().len(), 4);
+        assert_eq!(server.connections(), 1);
+
+        // A new configuration means a new client and pool
+        settings::update(app.handle(), |s| s.request_timeout_secs += 1).unwrap();
+        build(app.handle()).get(format!("{}/health", server.url)).send().await.unwrap();
+        assert_eq!(server.connections(), 2);
+    }
+}
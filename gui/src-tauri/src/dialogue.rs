@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Emitter};
+
+use crate::audio::{self, AudioSpec};
+use crate::capabilities;
+use crate::text;
+use crate::{client, format_file_size, history, paths, synthesize_bytes, AppHandle, ConversionResult, TTSRequest};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DialogueLine {
+    speaker_voice: String,
+    text: String,
+    pause_after_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DialogueProgress {
+    line: usize,
+    total: usize,
+    speaker_voice: String,
+}
+
+/// Synthesizes a scripted conversation, one voice per line, into a single file.
+/// Lines are stitched in order with each line's `pause_after_ms` of silence after it.
+#[command]
+pub async fn synthesize_dialogue(
+    app: AppHandle,
+    lines: Vec<DialogueLine>,
+    output: String,
+    format: String,
+) -> Result<ConversionResult, String> {
+    if lines.is_empty() {
+        return Err("Dialogue has no lines".to_string());
+    }
+
+    let start = std::time::Instant::now();
+    let client = client::build(&app);
+    let total = lines.len();
+
+    let mut parts = Vec::new();
+    let mut gaps = Vec::new();
+    let mut first_spec: Option<(usize, AudioSpec)> = None;
+    let max_bytes = capabilities::get(&app).await.max_request_bytes;
+
+    for (index, line) in lines.iter().enumerate() {
+        let language = text::language_from_voice(&line.speaker_voice)
+            .ok_or_else(|| format!("Line {}: cannot determine language of voice '{}'", index + 1, line.speaker_voice))?;
+
+        let chunks = text::split_text(&line.text, max_bytes);
+        if chunks.is_empty() {
+            return Err(format!("Line {} has no text", index + 1));
+        }
+
+        let chunk_count = chunks.len();
+        for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+            let request = TTSRequest {
+                text: chunk,
+                voice: line.speaker_voice.clone(),
+                format: format.clone(),
+                language: language.clone(),
+                ..Default::default()
+            };
+            let bytes = synthesize_bytes(&app, &client, &request)
+                .await
+                .map_err(|e| format!("Line {} ({}): {}", index + 1, line.speaker_voice, e))?;
+
+            // Voices must agree on sample rate and channels to be stitched
+            let spec = audio::probe_spec(&bytes).map_err(|e| format!("Line {}: {}", index + 1, e))?;
+            match first_spec {
+                None => first_spec = Some((index, spec)),
+                Some((first_index, expected)) if spec != expected => {
+                    return Err(format!(
+                        "Line {} ({}) produced {}, but line {} produced {}; these voices cannot be combined",
+                        index + 1,
+                        line.speaker_voice,
+                        spec,
+                        first_index + 1,
+                        expected
+                    ));
+                }
+                Some(_) => {}
+            }
+
+            parts.push(bytes);
+            gaps.push(if chunk_index + 1 == chunk_count { line.pause_after_ms.unwrap_or(0) } else { 0 });
+        }
+
+        let _ = app.emit(
+            "dialogue-progress",
+            DialogueProgress {
+                line: index + 1,
+                total,
+                speaker_voice: line.speaker_voice.clone(),
+            },
+        );
+    }
+
+    // The final line's pause would only pad the end of the file
+    if let Some(last) = gaps.last_mut() {
+        *last = 0;
+    }
+
+    let combined = audio::concat_audio(&parts, &gaps)?;
+    let file_size = format_file_size(combined.len());
+    paths::write_audio(Path::new(&output), &combined)?;
+
+    // Record the script as "voice: text" lines
+    let script: Vec<String> = lines.iter().map(|l| format!("{}: {}", l.speaker_voice, l.text)).collect();
+    let voices: Vec<&str> = lines.iter().map(|l| l.speaker_voice.as_str()).collect();
+    let language = text::language_from_voice(voices[0]).unwrap_or_default();
+    history::record(
+        &app,
+        history::HistoryEntry::new(
+            &script.join("\n"),
+            &voices.join(", "),
+            &format,
+            &language,
+            &output,
+            Some(file_size.clone()),
+        ),
+    );
+
+    Ok(ConversionResult {
+        success: true,
+        output_path: Some(output),
+        file_size: Some(file_size),
+        processing_time: Some(format!("{:.2}s", start.elapsed().as_secs_f64())),
+        ..Default::default()
+    })
+}
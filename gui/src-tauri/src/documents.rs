@@ -0,0 +1,497 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Emitter, Manager};
+use tokio_util::sync::CancellationToken;
+
+use crate::{audio, client, format_file_size, history, jobs, paths, synthesize_long, text, AppHandle, TTSRequest};
+
+/// A titled section of a document, synthesized as one audio file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Extraction {
+    chapters: Vec<Chapter>,
+    /// Extraction was cancelled and `chapters` holds only what came before.
+    cancelled: bool,
+    /// Encoding a plain text file was read as; EPUBs declare their own.
+    text_encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ExtractionProgress {
+    done: usize,
+    total: usize,
+    percent: f64,
+}
+
+/// Reports progress through the document, and whether to stop.
+struct Progress<'a> {
+    emit: &'a dyn Fn(ExtractionProgress),
+    cancel: &'a CancellationToken,
+}
+
+impl Progress<'_> {
+    fn report(&self, done: usize, total: usize) {
+        let percent = if total == 0 { 100.0 } else { done as f64 * 100.0 / total as f64 };
+        (self.emit)(ExtractionProgress { done, total, percent });
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+}
+
+/// Extracts the readable text of a document. EPUBs yield one chapter per
+/// spine entry with text; plain text and Markdown files yield a single chapter.
+/// Progress is emitted per spine entry as `extraction-progress`, and passing
+/// an `extraction_id` lets `cancel_conversion` stop a long extraction early.
+/// The encoding of plain text is detected unless `encoding` names it.
+#[command]
+pub async fn extract_text_from_file(
+    app: AppHandle,
+    path: String,
+    extraction_id: Option<String>,
+    encoding: Option<String>,
+) -> Result<Extraction, String> {
+    let path = PathBuf::from(path);
+    if !path.is_file() {
+        return Err(format!("File does not exist: {}", path.display()));
+    }
+
+    let registry = app.state::<jobs::JobRegistry>();
+    let cancel = match &extraction_id {
+        Some(id) => registry.register(id)?,
+        None => CancellationToken::new(),
+    };
+
+    // Parsing a large EPUB takes a while; keep it off the async runtime
+    let (task_app, task_cancel) = (app.clone(), cancel.clone());
+    let extracted = tauri::async_runtime::spawn_blocking(move || {
+        extract(
+            &path,
+            encoding.as_deref(),
+            &Progress {
+                emit: &|progress| {
+                    let _ = task_app.emit("extraction-progress", progress);
+                },
+                cancel: &task_cancel,
+            },
+        )
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Text extraction crashed: {}", e)));
+
+    if let Some(id) = &extraction_id {
+        registry.finish(id);
+    }
+    let (chapters, text_encoding) = extracted?;
+    Ok(Extraction {
+        chapters,
+        cancelled: cancel.is_cancelled(),
+        text_encoding: text_encoding.map(str::to_string),
+    })
+}
+
+fn extract(
+    path: &Path,
+    encoding: Option<&str>,
+    progress: &Progress,
+) -> Result<(Vec<Chapter>, Option<&'static str>), String> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "epub" => Ok((extract_epub(path, progress)?, None)),
+        "txt" | "md" | "markdown" => {
+            let (text, encoding) = text::read_text_file(path, encoding)?;
+            let title = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            progress.report(1, 1);
+            Ok((vec![Chapter { title, text }], Some(encoding)))
+        }
+        _ => Err(format!("Unsupported file type: .{}", extension)),
+    }
+}
+
+fn extract_epub(path: &Path, progress: &Progress) -> Result<Vec<Chapter>, String> {
+    let mut doc = epub::doc::EpubDoc::new(path).map_err(|e| format!("Failed to open EPUB: {}", e))?;
+
+    // Map content documents to their table-of-contents labels
+    let mut toc_titles = Vec::new();
+    let mut pending: Vec<&epub::doc::NavPoint> = doc.toc.iter().collect();
+    while let Some(point) = pending.pop() {
+        let content = point.content.to_string_lossy();
+        let file = content.split('#').next().unwrap_or_default().to_string();
+        toc_titles.push((PathBuf::from(file), point.label.trim().to_string()));
+        pending.extend(point.children.iter());
+    }
+
+    let spine: Vec<String> = doc.spine.iter().map(|item| item.idref.clone()).collect();
+    let total = spine.len();
+    let mut chapters = Vec::new();
+    for (done, idref) in spine.into_iter().enumerate() {
+        if progress.cancelled() {
+            return Ok(chapters);
+        }
+        progress.report(done, total);
+
+        let Some(resource_path) = doc.resources.get(&idref).map(|r| r.path.clone()) else {
+            continue;
+        };
+        let Some((html, _mime)) = doc.get_resource_str(&idref) else {
+            continue;
+        };
+
+        let text = html_to_text(&html);
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let title = toc_titles
+            .iter()
+            .find(|(file, _)| *file == resource_path)
+            .map(|(_, label)| label.clone())
+            .filter(|label| !label.is_empty())
+            .unwrap_or_else(|| format!("Chapter {}", chapters.len() + 1));
+        chapters.push(Chapter { title, text });
+    }
+    progress.report(total, total);
+
+    if chapters.is_empty() {
+        return Err("EPUB contains no readable text".to_string());
+    }
+    Ok(chapters)
+}
+
+/// Reduces XHTML to plain text: tags are dropped, block elements become line
+/// breaks, scripts and styles are skipped and common entities are decoded.
+fn html_to_text(html: &str) -> String {
+    const BLOCK_TAGS: [&str; 16] = [
+        "p", "div", "br", "h1", "h2", "h3", "h4", "h5", "h6", "li", "tr", "blockquote", "section", "article", "pre",
+        "hr",
+    ];
+
+    let mut text = String::new();
+    let mut rest = html;
+    let mut skipping: Option<String> = None;
+
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            text.push_str(&decode_entities(&rest[..start]));
+        }
+        let Some(len) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + len];
+        rest = &rest[start + len + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match &skipping {
+            Some(skipped) if closing && name == *skipped => skipping = None,
+            Some(_) => {}
+            None if !closing && ["script", "style", "head"].contains(&name.as_str()) && !tag.ends_with('/') => {
+                skipping = Some(name);
+            }
+            None if BLOCK_TAGS.contains(&name.as_str()) => text.push('\n'),
+            None => {}
+        }
+    }
+    if skipping.is_none() {
+        text.push_str(&decode_entities(rest));
+    }
+
+    // Collapse the whitespace soup left behind by markup
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let decoded = after.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let entity = &after[..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+                }
+                _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            c.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Builds a file stem like `03-The Return` that is safe on every platform.
+fn chapter_file_stem(index: usize, title: &str) -> String {
+    if title.trim().is_empty() {
+        format!("{:02}", index + 1)
+    } else {
+        format!("{:02}-{}", index + 1, paths::sanitize_filename(title))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ChapterProgress {
+    chapter: usize,
+    total: usize,
+    title: String,
+    success: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChapterFile {
+    index: usize,
+    title: String,
+    path: String,
+    duration_seconds: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChapterError {
+    index: usize,
+    title: String,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChapterConversion {
+    files: Vec<ChapterFile>,
+    errors: Vec<ChapterError>,
+    manifest_path: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ChapterManifest<'a> {
+    voice: &'a str,
+    format: &'a str,
+    files: &'a [ChapterFile],
+}
+
+/// Synthesizes each chapter into its own file in `output_dir`. A failed
+/// chapter doesn't stop the rest; failures are collected in the result and
+/// successful files are listed in `manifest.json`.
+#[command]
+pub async fn convert_chapters(
+    app: AppHandle,
+    chapters: Vec<Chapter>,
+    output_dir: String,
+    voice: String,
+    format: String,
+) -> Result<ChapterConversion, String> {
+    let output_dir = PathBuf::from(output_dir);
+    std::fs::create_dir_all(&output_dir).map_err(|e| format!("Failed to create output folder: {}", e))?;
+
+    let language = text::language_from_voice(&voice)
+        .ok_or_else(|| format!("Cannot determine language of voice '{}'", voice))?;
+    let extension = audio::file_extension(&format);
+    let client = client::build(&app);
+    let total = chapters.len();
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, chapter) in chapters.into_iter().enumerate() {
+        let request = TTSRequest {
+            text: chapter.text,
+            voice: voice.clone(),
+            format: format.clone(),
+            language: language.clone(),
+            ..Default::default()
+        };
+
+        let path = output_dir.join(format!("{}.{}", chapter_file_stem(index, &chapter.title), extension));
+        let outcome = match synthesize_long(&app, &client, &request, None).await {
+            Ok(bytes) => {
+                let duration_seconds = audio::duration_secs(&bytes);
+                paths::write_audio(&path, &bytes)
+                    .map(|_| {
+                        history::record(
+                            &app,
+                            history::HistoryEntry::new(
+                                &request.text,
+                                &voice,
+                                &format,
+                                &language,
+                                &path.to_string_lossy(),
+                                Some(format_file_size(bytes.len())),
+                            ),
+                        );
+                        duration_seconds
+                    })
+            }
+            Err(e) => Err(e),
+        };
+
+        let _ = app.emit(
+            "chapter-progress",
+            ChapterProgress {
+                chapter: index + 1,
+                total,
+                title: chapter.title.clone(),
+                success: outcome.is_ok(),
+            },
+        );
+
+        match outcome {
+            Ok(duration_seconds) => files.push(ChapterFile {
+                index,
+                title: chapter.title,
+                path: path.to_string_lossy().to_string(),
+                duration_seconds,
+            }),
+            Err(error) => errors.push(ChapterError {
+                index,
+                title: chapter.title,
+                error,
+            }),
+        }
+    }
+
+    let manifest_path = if files.is_empty() {
+        None
+    } else {
+        let manifest = ChapterManifest {
+            voice: &voice,
+            format: &format,
+            files: &files,
+        };
+        let path = output_dir.join("manifest.json");
+        let json = serde_json::to_string_pretty(&manifest).map_err(|e| format!("Failed to encode manifest: {}", e))?;
+        std::fs::write(&path, json).map_err(|e| format!("Failed to write manifest: {}", e))?;
+        Some(path.to_string_lossy().to_string())
+    };
+
+    Ok(ChapterConversion {
+        files,
+        errors,
+        manifest_path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_to_text_breaks_blocks_and_skips_scripts() {
+        let html = "<html><head><title>Skip me</title></head><body>\
+                    <h1>Chapter  One</h1><p>It was a <em>dark</em>  night.</p>\
+                    <script>alert('no')</script><p>The end<br/>really</p></body></html>";
+        assert_eq!(html_to_text(html), "Chapter One\nIt was a dark night.\nThe end\nreally");
+    }
+
+    #[test]
+    fn decode_entities_handles_named_and_numeric_forms() {
+        assert_eq!(decode_entities("Tom &amp; Jerry &lt;3 &#233;t&#xE9; &quot;ok&quot;"), "Tom & Jerry <3 été \"ok\"");
+    }
+
+    #[test]
+    fn decode_entities_keeps_unknown_ampersands() {
+        assert_eq!(decode_entities("R&D &bogus; fish & chips"), "R&D &bogus; fish & chips");
+    }
+
+    #[test]
+    fn chapter_file_stem_numbers_from_one() {
+        assert_eq!(chapter_file_stem(2, "The Return"), "03-The Return");
+        assert_eq!(chapter_file_stem(0, "  "), "01");
+        assert_eq!(chapter_file_stem(9, "Part 1: Why?"), "10-Part 1_ Why_");
+    }
+
+    #[test]
+    fn extract_reads_plain_text_as_one_chapter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        std::fs::write(&path, "# Notes\nSome text.").unwrap();
+
+        let reported = std::cell::RefCell::new(Vec::new());
+        let cancel = CancellationToken::new();
+        let progress = Progress {
+            emit: &|p| reported.borrow_mut().push((p.done, p.total, p.percent)),
+            cancel: &cancel,
+        };
+        let (chapters, encoding) = extract(&path, None, &progress).unwrap();
+        assert_eq!(chapters.len(), 1);
+        assert_eq!(chapters[0].title, "notes");
+        assert_eq!(chapters[0].text, "# Notes\nSome text.");
+        assert!(encoding.is_some());
+        assert_eq!(*reported.borrow(), vec![(1, 1, 100.0)]);
+    }
+
+    #[test]
+    fn extract_rejects_unknown_file_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.pdf");
+        std::fs::write(&path, b"%PDF").unwrap();
+        let cancel = CancellationToken::new();
+        let progress = Progress {
+            emit: &|_| {},
+            cancel: &cancel,
+        };
+        assert_eq!(extract(&path, None, &progress).unwrap_err(), "Unsupported file type: .pdf");
+    }
+
+    #[test]
+    fn progress_of_an_empty_document_is_complete() {
+        let last = std::cell::Cell::new(0.0);
+        let cancel = CancellationToken::new();
+        let progress = Progress {
+            emit: &|p| last.set(p.percent),
+            cancel: &cancel,
+        };
+        progress.report(0, 0);
+        assert_eq!(last.get(), 100.0);
+        progress.report(1, 4);
+        assert_eq!(last.get(), 25.0);
+
+        assert!(!progress.cancelled());
+        cancel.cancel();
+        assert!(progress.cancelled());
+    }
+}
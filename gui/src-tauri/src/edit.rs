@@ -0,0 +1,794 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Emitter};
+
+use crate::{audio, client, format_file_size, paths, settings, synthesize_long, text, AppHandle, TTSRequest};
+
+/// Writes the `start_ms..end_ms` section of `input` to `output` in the same
+/// format. `end_ms` of `None` keeps everything after `start_ms`.
+pub fn trim_audio(input: &Path, output: &Path, start_ms: u64, end_ms: Option<u64>) -> Result<(), String> {
+    let bytes = std::fs::read(input).map_err(|e| format!("Failed to read {}: {}", input.display(), e))?;
+    let trimmed = audio::trim(&bytes, start_ms, end_ms)?;
+    paths::write_audio(output, &trimmed)
+}
+
+#[command]
+pub async fn trim_audio_file(input: String, output: String, start_ms: u64, end_ms: Option<u64>) -> Result<(), String> {
+    trim_audio(Path::new(&input), Path::new(&output), start_ms, end_ms)
+}
+
+/// Writes `input` to `output` with `channels` channels (see
+/// `audio::set_channels`), returning the size written.
+pub fn remix_channels(input: &Path, output: &Path, channels: u16) -> Result<usize, String> {
+    let bytes = std::fs::read(input).map_err(|e| format!("Failed to read {}: {}", input.display(), e))?;
+    let remixed = audio::set_channels(&bytes, channels)?;
+    paths::write_audio(output, &remixed)?;
+    Ok(remixed.len())
+}
+
+/// Averages the channels of `input` into a mono copy at `output`, e.g. when
+/// a server only returns stereo.
+#[command]
+pub async fn downmix_to_mono(input: String, output: String) -> Result<(), String> {
+    remix_channels(Path::new(&input), Path::new(&output), 1).map(|_| ())
+}
+
+/// Writes `input` to `output` with `duration_ms` of silence at `position_ms`,
+/// e.g. to leave room before a chapter.
+#[command]
+pub async fn insert_silence(input: String, output: String, position_ms: u64, duration_ms: u64) -> Result<(), String> {
+    if !(1..=MAX_SILENCE_MS).contains(&duration_ms) {
+        return Err(format!("Silence must last between 1 ms and {} s", MAX_SILENCE_MS / 1000));
+    }
+    let bytes = std::fs::read(&input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let spliced = audio::insert_silence(&bytes, position_ms, duration_ms)?;
+    paths::write_audio(Path::new(&output), &spliced)
+}
+
+/// How `fit_duration` reaches its target. Each works in one direction, and
+/// a target out of its reach leaves the audio as close as it can get.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FitStrategy {
+    /// Shortens by cutting silence from the end, then from the start.
+    TrimSilence,
+    /// Lengthens by adding silence at the end.
+    PadSilence,
+    /// Speeds up or slows down, within `audio::STRETCH_RATES`, keeping the pitch.
+    TimeStretch,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FittedAudio {
+    duration_ms: u64,
+    /// Whether the result came within `FIT_TOLERANCE_MS` of the target.
+    reachable: bool,
+}
+
+fn duration_ms(bytes: &[u8]) -> Result<u64, String> {
+    audio::duration_secs(bytes)
+        .map(|secs| (secs * 1000.0).round() as u64)
+        .ok_or_else(|| "Unrecognized audio data (expected WAV, MP3 or M4A)".to_string())
+}
+
+fn fit(bytes: &[u8], target_ms: u64, strategy: FitStrategy) -> Result<Vec<u8>, String> {
+    let duration = duration_ms(bytes)?;
+    match strategy {
+        FitStrategy::TrimSilence if duration > target_ms => {
+            let (leading, trailing) = audio::edge_silence_ms(bytes, FIT_SILENCE_DB)?;
+            let excess = duration - target_ms;
+            let from_end = excess.min(trailing.saturating_sub(FIT_EDGE_MARGIN_MS));
+            let from_start = (excess - from_end).min(leading.saturating_sub(FIT_EDGE_MARGIN_MS));
+            if from_start + from_end == 0 {
+                return Ok(bytes.to_vec());
+            }
+            audio::trim(bytes, from_start, Some(duration - from_end))
+        }
+        FitStrategy::PadSilence if duration < target_ms => audio::insert_silence(bytes, duration, target_ms - duration),
+        FitStrategy::TimeStretch if duration != target_ms => {
+            let rate = duration as f64 / target_ms as f64;
+            audio::time_stretch(bytes, rate.clamp(*audio::STRETCH_RATES.start(), *audio::STRETCH_RATES.end()))
+        }
+        _ => Ok(bytes.to_vec()),
+    }
+}
+
+/// Writes `input` to `output` made `target_ms` long, e.g. to fill a slot in
+/// a video, and reports the length it came out at.
+#[command]
+pub async fn fit_duration(
+    input: String,
+    output: String,
+    target_ms: u64,
+    strategy: FitStrategy,
+) -> Result<FittedAudio, String> {
+    if target_ms == 0 {
+        return Err("The target duration must be above 0 ms".to_string());
+    }
+    let bytes = std::fs::read(&input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let fitted = fit(&bytes, target_ms, strategy)?;
+    paths::write_audio(Path::new(&output), &fitted)?;
+    let duration_ms = duration_ms(&fitted)?;
+    Ok(FittedAudio {
+        duration_ms,
+        reachable: duration_ms.abs_diff(target_ms) <= FIT_TOLERANCE_MS,
+    })
+}
+
+#[command]
+pub async fn get_audio_duration(path: String) -> Result<f64, String> {
+    audio::audio_duration(Path::new(&path))
+}
+
+/// How much of a file is clipped, e.g. after a high `volume_gain_db`.
+#[command]
+pub async fn detect_clipping(path: String) -> Result<audio::ClippingReport, String> {
+    audio::detect_clipping(Path::new(&path))
+}
+
+/// Format, duration, sample rate, channels and bitrate of any audio file,
+/// e.g. one dragged in from elsewhere.
+#[command]
+pub async fn probe_audio(path: String) -> Result<audio::AudioInfo, String> {
+    audio::probe_audio(Path::new(&path))
+}
+
+/// Longest silence `insert_silence` adds.
+const MAX_SILENCE_MS: u64 = 600_000;
+
+/// Quieter than this counts as silence `fit_duration` may cut.
+const FIT_SILENCE_DB: f64 = -50.0;
+
+/// Silence `fit_duration` leaves next to the speech, so soft onsets and
+/// endings aren't clipped.
+const FIT_EDGE_MARGIN_MS: u64 = 50;
+
+/// How far from the target `fit_duration` may land, about two MP3 frames.
+const FIT_TOLERANCE_MS: u64 = 50;
+
+/// Shortest clip `split_audio_by_silence` produces; shorter sounds are kept
+/// with their neighbours rather than scattered over tiny files.
+const MIN_CLIP_MS: u64 = 1000;
+
+#[derive(Debug, Serialize)]
+pub struct AudioClip {
+    path: String,
+    /// Where the clip starts in the original.
+    start_seconds: f64,
+    duration_seconds: f64,
+}
+
+/// Cuts a recording into numbered clips, `<name>-001.mp3` and so on in
+/// `output_dir`, at every pause of at least `min_silence_ms` below
+/// `threshold_db` dBFS (e.g. -40). The input may be WAV, MP3 or M4A.
+#[command]
+pub async fn split_audio_by_silence(
+    input: String,
+    output_dir: String,
+    min_silence_ms: u64,
+    threshold_db: f64,
+    format: String,
+) -> Result<Vec<AudioClip>, String> {
+    if !(50..=60_000).contains(&min_silence_ms) {
+        return Err("The minimum silence must be between 50 ms and 60 s".to_string());
+    }
+    if !(-100.0..0.0).contains(&threshold_db) {
+        return Err(format!("Silence threshold {} dB must be below 0 and above -100 dBFS", threshold_db));
+    }
+    let input = Path::new(&input);
+    let bytes = std::fs::read(input).map_err(|e| format!("Failed to read {}: {}", input.display(), e))?;
+    let clips = audio::split_by_silence(&bytes, min_silence_ms, threshold_db, MIN_CLIP_MS, &format)?;
+    if clips.is_empty() {
+        return Err(format!("{} is silent at {} dB", input.display(), threshold_db));
+    }
+
+    let output_dir = Path::new(&output_dir);
+    std::fs::create_dir_all(output_dir).map_err(|e| format!("Failed to create output folder: {}", e))?;
+    let stem = input.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let width = clips.len().to_string().len().max(3);
+    let extension = audio::file_extension(&format);
+    let mut written = Vec::new();
+    for (i, clip) in clips.into_iter().enumerate() {
+        let path = output_dir.join(format!("{}-{:0width$}.{}", paths::sanitize_filename(&stem), i + 1, extension));
+        paths::write_audio(&path, &clip.bytes)?;
+        written.push(AudioClip {
+            path: path.to_string_lossy().to_string(),
+            start_seconds: clip.start_ms as f64 / 1000.0,
+            duration_seconds: (clip.end_ms - clip.start_ms) as f64 / 1000.0,
+        });
+    }
+    Ok(written)
+}
+
+/// A titled span of a combined file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChapterMarker {
+    pub title: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// Turns part start offsets into markers; each part ends where the next begins.
+pub fn chapter_markers(titles: &[String], offsets_ms: &[u64], duration_ms: u64) -> Vec<ChapterMarker> {
+    offsets_ms
+        .iter()
+        .enumerate()
+        .map(|(i, &start_ms)| ChapterMarker {
+            title: titles.get(i).cloned().unwrap_or_else(|| format!("Chapter {}", i + 1)),
+            start_ms,
+            end_ms: offsets_ms.get(i + 1).copied().unwrap_or(duration_ms),
+        })
+        .collect()
+}
+
+/// Embeds `chapters` in an MP3 as ID3v2.4 `CHAP` frames under a single
+/// top-level `CTOC`, or in an M4A as MP4 chapter atoms, replacing any
+/// chapters already there, then reads the tags back to make sure players
+/// will see them. Other formats are rejected.
+pub fn embed_chapters(path: &Path, chapters: &[ChapterMarker]) -> Result<(), String> {
+    if chapters.is_empty() {
+        return Err("No chapters to write".to_string());
+    }
+    for (i, chapter) in chapters.iter().enumerate() {
+        if chapter.start_ms >= chapter.end_ms {
+            return Err(format!("Chapter {} ('{}') ends before it starts", i + 1, chapter.title));
+        }
+        if chapter.end_ms > u32::MAX as u64 {
+            return Err(format!("Chapter {} ('{}') is beyond the ID3 time range", i + 1, chapter.title));
+        }
+    }
+
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let spec = audio::probe_spec(&bytes)?;
+    let read_back = match spec.container {
+        audio::Container::Mp3 => write_id3_chapters(path, chapters)?,
+        audio::Container::M4a => write_mp4_chapters(path, chapters)?,
+        _ => return Err(format!("Chapter markers are only supported in MP3 and M4A files, not {}", spec)),
+    };
+    if read_back != chapters.len() {
+        return Err(format!("Wrote {} chapters but read back {}", chapters.len(), read_back));
+    }
+    Ok(())
+}
+
+/// Writes the chapters as ID3 frames and returns how many read back.
+fn write_id3_chapters(path: &Path, chapters: &[ChapterMarker]) -> Result<usize, String> {
+    use id3::frame::{Chapter, TableOfContents};
+    use id3::{Frame, Tag, TagLike, Version};
+
+    let mut tag = match Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Tag::new(),
+        Err(e) => return Err(format!("Failed to read tags of {}: {}", path.display(), e)),
+    };
+    tag.remove_all_chapters();
+    tag.remove_all_tables_of_contents();
+
+    let ids: Vec<String> = (0..chapters.len()).map(|i| format!("chp{}", i)).collect();
+    for (id, chapter) in ids.iter().zip(chapters) {
+        tag.add_frame(Chapter {
+            element_id: id.clone(),
+            start_time: chapter.start_ms as u32,
+            end_time: chapter.end_ms as u32,
+            // No byte offsets; players seek by time
+            start_offset: u32::MAX,
+            end_offset: u32::MAX,
+            frames: vec![Frame::text("TIT2", chapter.title.clone())],
+        });
+    }
+    tag.add_frame(TableOfContents {
+        element_id: "toc".to_string(),
+        top_level: true,
+        ordered: true,
+        elements: ids,
+        frames: Vec::new(),
+    });
+    tag.write_to_path(path, Version::Id3v24)
+        .map_err(|e| format!("Failed to write chapters to {}: {}", path.display(), e))?;
+
+    let written = Tag::read_from_path(path).map_err(|e| format!("Failed to read back chapters: {}", e))?;
+    Ok(written.chapters().count())
+}
+
+/// Writes the chapters both as a Nero chapter list and as a QuickTime
+/// chapter track, since players each read one or the other, and returns
+/// how many read back. MP4 chapters have only a start; each runs to the
+/// next.
+fn write_mp4_chapters(path: &Path, chapters: &[ChapterMarker]) -> Result<usize, String> {
+    use mp4ameta::{Chapter, Tag};
+    use std::time::Duration;
+
+    let mut tag = Tag::read_from_path(path).map_err(|e| format!("Failed to read tags of {}: {}", path.display(), e))?;
+    let list: Vec<Chapter> = chapters
+        .iter()
+        .map(|chapter| Chapter::new(Duration::from_millis(chapter.start_ms), chapter.title.clone()))
+        .collect();
+    *tag.chapter_list_mut() = list.clone();
+    *tag.chapter_track_mut() = list;
+    tag.write_to_path(path)
+        .map_err(|e| format!("Failed to write chapters to {}: {}", path.display(), e))?;
+
+    let written = Tag::read_from_path(path).map_err(|e| format!("Failed to read back chapters: {}", e))?;
+    Ok(written.chapter_list().len())
+}
+
+#[command]
+pub async fn write_chapters(path: String, chapters: Vec<ChapterMarker>) -> Result<(), String> {
+    embed_chapters(Path::new(&path), &chapters)
+}
+
+/// Reads audio files to be joined, failing on the first one whose format
+/// differs from the first file's.
+fn read_parts(paths: &[String]) -> Result<(Vec<Vec<u8>>, audio::AudioSpec), String> {
+    let mut parts = Vec::with_capacity(paths.len());
+    let mut expected: Option<audio::AudioSpec> = None;
+    for path in paths {
+        let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let spec = audio::probe_spec(&bytes).map_err(|e| format!("{}: {}", path, e))?;
+        match expected {
+            Some(expected) if spec != expected => {
+                return Err(format!("{} is {}, but {} is {}", path, spec, paths[0], expected));
+            }
+            _ => expected = Some(spec),
+        }
+        parts.push(bytes);
+    }
+    let spec = expected.ok_or("No files to combine")?;
+    Ok((parts, spec))
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ConcatProgress {
+    part: usize,
+    total: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Audiobook {
+    output_path: String,
+    file_size: String,
+    duration_seconds: f64,
+    markers: Vec<ChapterMarker>,
+    /// `<output stem>.chapters.json`, when requested.
+    manifest_path: Option<String>,
+}
+
+/// How `build_audiobook` joins its files. Every field defaults to off.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AudiobookOptions {
+    /// Silence between files.
+    gap_ms: u64,
+    /// Overlap in which each file fades into the next; excludes `gap_ms`.
+    crossfade_ms: u64,
+    /// Also write `<output stem>.chapters.json`.
+    write_manifest: bool,
+    /// Write the chapter markers into the MP3 or M4A output.
+    embed_chapters: bool,
+}
+
+/// Joins audio files into one, in order, with silence or a crossfade between
+/// them as `options` say, emitting `concat-progress` per file. `titles`, when given, must have
+/// one entry per file and names the chapter markers, which `embed_chapters`
+/// writes into MP3 or M4A output. The files must share a format; the first one that
+/// doesn't is named in the error.
+#[command]
+pub async fn build_audiobook(
+    app: AppHandle,
+    paths: Vec<String>,
+    output: String,
+    titles: Option<Vec<String>>,
+    options: Option<AudiobookOptions>,
+) -> Result<Audiobook, String> {
+    if paths.is_empty() {
+        return Err("No files to combine".to_string());
+    }
+    let options = options.unwrap_or_default();
+    let (gap_ms, crossfade_ms) = (options.gap_ms, options.crossfade_ms);
+    if gap_ms > 0 && crossfade_ms > 0 {
+        return Err("Choose either a gap or a crossfade between files, not both".to_string());
+    }
+    let titles = match titles {
+        Some(titles) if titles.len() != paths.len() => {
+            return Err(format!("Got {} titles for {} files", titles.len(), paths.len()));
+        }
+        Some(titles) => titles,
+        None => paths
+            .iter()
+            .map(|path| Path::new(path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default())
+            .collect(),
+    };
+
+    let (parts, _) = read_parts(&paths)?;
+
+    let total = parts.len();
+    let on_part = |i: usize| {
+        let _ = app.emit("concat-progress", ConcatProgress { part: i + 1, total });
+    };
+    let joined = if crossfade_ms > 0 {
+        audio::concat_with_crossfade(&parts, crossfade_ms, on_part)?
+    } else {
+        audio::concat_audio_with_progress(&parts, &vec![gap_ms; total.saturating_sub(1)], on_part)?
+    };
+
+    paths::write_audio(Path::new(&output), &joined.bytes)?;
+    let markers = chapter_markers(&titles, &joined.offsets_ms, joined.duration_ms);
+    if options.embed_chapters {
+        self::embed_chapters(Path::new(&output), &markers)?;
+    }
+
+    let manifest_path = if options.write_manifest {
+        let path = Path::new(&output).with_extension("chapters.json");
+        let json = serde_json::to_string_pretty(&markers).map_err(|e| format!("Failed to encode manifest: {}", e))?;
+        std::fs::write(&path, json).map_err(|e| format!("Failed to write manifest: {}", e))?;
+        Some(path.to_string_lossy().to_string())
+    } else {
+        None
+    };
+
+    let file_size = std::fs::metadata(&output).map_or(joined.bytes.len(), |m| m.len() as usize);
+    Ok(Audiobook {
+        output_path: output,
+        file_size: format_file_size(file_size),
+        duration_seconds: joined.duration_ms as f64 / 1000.0,
+        markers,
+        manifest_path,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct Appended {
+    output_path: String,
+    file_size: String,
+    /// Length of the whole file after appending.
+    duration_seconds: f64,
+    appended_seconds: f64,
+}
+
+/// Synthesizes `new_text` and appends it to the audio file `base` in place.
+/// The speech is requested in the file's own format and sample rate and the
+/// result is checked against it before anything is written; the file is
+/// replaced through a temporary copy so a failure leaves it untouched. ID3
+/// tags on an MP3 base are not carried over.
+#[command]
+pub async fn append_to_audio(
+    app: AppHandle,
+    base: String,
+    new_text: String,
+    voice: Option<String>,
+    format: Option<String>,
+    language: Option<String>,
+) -> Result<Appended, String> {
+    if new_text.trim().is_empty() {
+        return Err("Text cannot be empty".to_string());
+    }
+
+    let base_path = Path::new(&base);
+    let existing = std::fs::read(base_path).map_err(|e| format!("Failed to read {}: {}", base, e))?;
+    let spec = audio::probe_spec(&existing).map_err(|e| format!("{}: {}", base, e))?;
+    let base_format = spec.container.format();
+    if let Some(format) = format {
+        let format = audio::normalize_format(&format)?;
+        if format != base_format {
+            return Err(format!("Cannot append {} audio to {}, which is {}", format, base, spec));
+        }
+    }
+
+    let settings = settings::current(&app);
+    let voice = voice.unwrap_or(settings.default_voice);
+    let language = language
+        .or_else(|| text::language_from_voice(&voice))
+        .unwrap_or(settings.default_language);
+    // M4A is joined by decoding anyway, so the addition is encoded here from PCM
+    let request_format = if base_format == "M4A" { "LINEAR16" } else { base_format };
+    let request = TTSRequest {
+        text: new_text,
+        voice,
+        format: request_format.to_string(),
+        language,
+        sample_rate: Some(spec.sample_rate),
+        ..Default::default()
+    };
+
+    let client = client::build(&app);
+    let mut addition = synthesize_long(&app, &client, &request, None).await?;
+    if base_format == "M4A" {
+        addition = audio::wav_to_m4a(&addition)?;
+    }
+    append_bytes(base, existing, addition)
+}
+
+/// Replaces the file `base`, holding `existing`, with `existing` followed by
+/// `addition`, through a temporary copy. `addition` must match the format
+/// of `existing` exactly.
+fn append_bytes(base: String, existing: Vec<u8>, addition: Vec<u8>) -> Result<Appended, String> {
+    let base_path = Path::new(&base);
+    let spec = audio::probe_spec(&existing).map_err(|e| format!("{}: {}", base, e))?;
+    let added_spec = audio::probe_spec(&addition)?;
+    if added_spec != spec {
+        return Err(format!("The new speech is {}, but {} is {}", added_spec, base, spec));
+    }
+
+    let previous_ms = audio::concat_audio_with_progress(std::slice::from_ref(&existing), &[], |_| {})?.duration_ms;
+    let joined = audio::concat_audio_with_progress(&[existing, addition], &[], |_| {})?;
+
+    let temp = base_path.with_extension("append.part");
+    paths::write_audio(&temp, &joined.bytes)?;
+    if let Err(e) = std::fs::rename(&temp, base_path) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(format!("Failed to replace {}: {}", base, e));
+    }
+
+    Ok(Appended {
+        output_path: base,
+        file_size: format_file_size(joined.bytes.len()),
+        duration_seconds: joined.duration_ms as f64 / 1000.0,
+        appended_seconds: (joined.duration_ms - previous_ms) as f64 / 1000.0,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct Merged {
+    output_path: String,
+    file_size: String,
+    duration_seconds: f64,
+    /// Source files removed after merging.
+    deleted: Vec<String>,
+}
+
+/// How `merge_outputs` joins its files. Every field defaults to off.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MergeOptions {
+    /// Silence between files.
+    gap_ms: u64,
+    /// Remove the source files once the merged file is written.
+    delete_sources: bool,
+}
+
+/// Joins finished outputs, such as a batch's files, into `output` in the
+/// order given. Every file must exist and be in `format` before anything is
+/// written; the first one that isn't is named in the error. Sources are
+/// only deleted, when asked, after the merged file is safely on disk.
+#[command]
+pub async fn merge_outputs(
+    paths: Vec<String>,
+    output: String,
+    format: String,
+    options: Option<MergeOptions>,
+) -> Result<Merged, String> {
+    let options = options.unwrap_or_default();
+    let format = audio::normalize_format(&format)?;
+    if paths.is_empty() {
+        return Err("No files to merge".to_string());
+    }
+    if let Some(missing) = paths.iter().find(|path| !Path::new(path).is_file()) {
+        return Err(format!("File not found: {}", missing));
+    }
+    if paths.iter().any(|path| Path::new(path) == Path::new(&output)) {
+        return Err(format!("{} is one of the files being merged; choose another output", output));
+    }
+
+    let (parts, spec) = read_parts(&paths)?;
+    if spec.container.format() != format {
+        return Err(format!("The files are {}, not {}", spec, format));
+    }
+    let gaps = vec![options.gap_ms; parts.len().saturating_sub(1)];
+    let joined = audio::concat_audio_with_progress(&parts, &gaps, |_| {})?;
+    paths::write_audio(Path::new(&output), &joined.bytes)?;
+
+    let mut deleted = Vec::new();
+    if options.delete_sources {
+        for path in paths {
+            match std::fs::remove_file(&path) {
+                Ok(()) => deleted.push(path),
+                Err(e) => println!("Failed to remove {}: {}", path, e),
+            }
+        }
+    }
+
+    Ok(Merged {
+        output_path: output,
+        file_size: format_file_size(joined.bytes.len()),
+        duration_seconds: joined.duration_ms as f64 / 1000.0,
+        deleted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapter_markers_end_where_the_next_part_starts() {
+        let titles = vec!["Intro".to_string(), "Middle".to_string()];
+        let markers = chapter_markers(&titles, &[0, 600, 850], 1850);
+
+        let spans: Vec<(&str, u64, u64)> = markers.iter().map(|m| (m.title.as_str(), m.start_ms, m.end_ms)).collect();
+        assert_eq!(spans, vec![("Intro", 0, 600), ("Middle", 600, 850), ("Chapter 3", 850, 1850)]);
+    }
+
+    /// A silent mono 16-bit WAV of `ms` milliseconds at `sample_rate`.
+    fn silence(ms: usize, sample_rate: u32) -> Vec<u8> {
+        audio::Wav {
+            sample_rate,
+            channels: 1,
+            bits_per_sample: 16,
+            data: vec![0; ms * sample_rate as usize / 1000 * 2],
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn append_bytes_replaces_the_base_with_both_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("story.wav");
+        std::fs::write(&base, silence(1000, 8000)).unwrap();
+
+        let base_name = base.to_string_lossy().to_string();
+        let appended = append_bytes(base_name, silence(1000, 8000), silence(500, 8000)).unwrap();
+        assert_eq!(appended.duration_seconds, 1.5);
+        assert_eq!(appended.appended_seconds, 0.5);
+        assert_eq!(audio::duration_secs(&std::fs::read(&base).unwrap()), Some(1.5));
+        assert!(!base.with_extension("append.part").exists());
+    }
+
+    #[test]
+    fn append_bytes_leaves_the_base_alone_on_a_format_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("story.wav");
+        std::fs::write(&base, silence(1000, 8000)).unwrap();
+
+        let err = append_bytes(base.to_string_lossy().to_string(), silence(1000, 8000), silence(500, 16000)).err().unwrap();
+        assert!(err.starts_with("The new speech is"), "{}", err);
+        assert_eq!(std::fs::read(&base).unwrap(), silence(1000, 8000));
+    }
+
+    #[tokio::test]
+    async fn merge_outputs_joins_with_gaps_and_deletes_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = ["a.wav", "b.wav"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, silence(1000, 8000)).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        let output = dir.path().join("all.wav").to_string_lossy().to_string();
+
+        let options = MergeOptions { gap_ms: 500, delete_sources: true };
+        let merged = merge_outputs(paths.clone(), output.clone(), "wav".to_string(), Some(options)).await.unwrap();
+        assert_eq!(merged.duration_seconds, 2.5);
+        assert_eq!(merged.deleted, paths);
+        assert!(paths.iter().all(|path| !Path::new(path).exists()));
+        assert_eq!(audio::duration_secs(&std::fs::read(&output).unwrap()), Some(2.5));
+    }
+
+    #[tokio::test]
+    async fn merge_outputs_checks_every_file_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("a.wav");
+        let second = dir.path().join("b.wav");
+        std::fs::write(&first, silence(1000, 8000)).unwrap();
+        std::fs::write(&second, silence(1000, 16000)).unwrap();
+        let (first, second) = (first.to_string_lossy().to_string(), second.to_string_lossy().to_string());
+        let output = dir.path().join("all.wav");
+        let merge = |paths: Vec<String>, format: &str| {
+            merge_outputs(paths, output.to_string_lossy().to_string(), format.to_string(), None)
+        };
+
+        let err = merge(vec![first.clone(), second.clone()], "wav").await.unwrap_err();
+        assert!(err.starts_with(&format!("{} is ", second)), "{}", err);
+        let err = merge(vec![first.clone()], "mp3").await.unwrap_err();
+        assert!(err.starts_with("The files are"), "{}", err);
+        let missing = dir.path().join("gone.wav").to_string_lossy().to_string();
+        assert_eq!(merge(vec![first.clone(), missing.clone()], "wav").await.unwrap_err(), format!("File not found: {}", missing));
+        assert_eq!(merge(Vec::new(), "wav").await.unwrap_err(), "No files to merge");
+        assert!(!output.exists());
+
+        let err = merge_outputs(vec![first.clone()], first.clone(), "wav".to_string(), None).await.unwrap_err();
+        assert!(err.contains("is one of the files being merged"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn split_audio_by_silence_numbers_the_clips() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("my talk.wav");
+        let tone: Vec<u8> = (0..8000i32).flat_map(|i| (((i as f64 * 0.3).sin() * 8000.0) as i16).to_le_bytes()).collect();
+        let data = [tone.clone(), vec![0; 9600], tone].concat();
+        let wav = audio::Wav {
+            sample_rate: 8000,
+            channels: 1,
+            bits_per_sample: 16,
+            data,
+        };
+        std::fs::write(&input, wav.to_bytes()).unwrap();
+        let input = input.to_string_lossy().to_string();
+        let output_dir = dir.path().join("clips").to_string_lossy().to_string();
+
+        let clips = split_audio_by_silence(input.clone(), output_dir.clone(), 500, -40.0, "wav".to_string()).await.unwrap();
+        let names: Vec<String> = clips
+            .iter()
+            .map(|clip| Path::new(&clip.path).file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["my talk-001.wav", "my talk-002.wav"]);
+        assert_eq!((clips[1].start_seconds, clips[1].duration_seconds), (1.6, 1.0));
+
+        let err = split_audio_by_silence(input.clone(), output_dir.clone(), 10, -40.0, "wav".to_string()).await.unwrap_err();
+        assert_eq!(err, "The minimum silence must be between 50 ms and 60 s");
+        let err = split_audio_by_silence(input, output_dir, 500, 3.0, "wav".to_string()).await.unwrap_err();
+        assert_eq!(err, "Silence threshold 3 dB must be below 0 and above -100 dBFS");
+    }
+
+    #[tokio::test]
+    async fn insert_silence_writes_the_longer_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("chapter.wav");
+        let output = dir.path().join("padded.wav");
+        std::fs::write(&input, silence(1000, 8000)).unwrap();
+        let (input, output) = (input.to_string_lossy().to_string(), output.to_string_lossy().to_string());
+
+        insert_silence(input.clone(), output.clone(), 0, 500).await.unwrap();
+        assert_eq!(audio::duration_secs(&std::fs::read(&output).unwrap()), Some(1.5));
+        let err = insert_silence(input, output, 0, 0).await.unwrap_err();
+        assert_eq!(err, "Silence must last between 1 ms and 600 s");
+    }
+
+    /// Half a second of silence, a second of tone and half a second of silence.
+    fn padded_tone() -> Vec<u8> {
+        let tone: Vec<u8> = (0..8000i32).flat_map(|i| (((i as f64 * 0.3).sin() * 8000.0) as i16).to_le_bytes()).collect();
+        audio::Wav {
+            sample_rate: 8000,
+            channels: 1,
+            bits_per_sample: 16,
+            data: [vec![0; 8000], tone, vec![0; 8000]].concat(),
+        }
+        .to_bytes()
+    }
+
+    fn fitted_ms(target_ms: u64, strategy: FitStrategy) -> u64 {
+        duration_ms(&fit(&padded_tone(), target_ms, strategy).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn fit_trims_edge_silence_but_keeps_a_margin() {
+        assert!(fitted_ms(1500, FitStrategy::TrimSilence).abs_diff(1500) <= FIT_TOLERANCE_MS);
+        // Only the silence can go, less the margin on each side
+        let shortest = fitted_ms(1000, FitStrategy::TrimSilence);
+        assert!(shortest.abs_diff(1000 + 2 * FIT_EDGE_MARGIN_MS) <= FIT_TOLERANCE_MS, "{}", shortest);
+        assert_eq!(fitted_ms(2500, FitStrategy::TrimSilence), 2000);
+    }
+
+    #[test]
+    fn fit_pads_with_silence_at_the_end() {
+        assert_eq!(fitted_ms(3000, FitStrategy::PadSilence), 3000);
+        assert_eq!(fitted_ms(1500, FitStrategy::PadSilence), 2000);
+    }
+
+    #[test]
+    fn fit_stretches_within_the_safe_rates() {
+        assert!(fitted_ms(2200, FitStrategy::TimeStretch).abs_diff(2200) <= FIT_TOLERANCE_MS);
+        assert!(fitted_ms(1800, FitStrategy::TimeStretch).abs_diff(1800) <= FIT_TOLERANCE_MS);
+        // Twice as fast is past the limit, so it stops at 1.25x
+        assert!(fitted_ms(1000, FitStrategy::TimeStretch).abs_diff(1600) <= FIT_TOLERANCE_MS);
+    }
+
+    #[tokio::test]
+    async fn fit_duration_reports_whether_the_target_was_reached() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("narration.wav");
+        let output = dir.path().join("fitted.wav");
+        std::fs::write(&input, padded_tone()).unwrap();
+        let (input, output) = (input.to_string_lossy().to_string(), output.to_string_lossy().to_string());
+
+        let fitted = fit_duration(input.clone(), output.clone(), 3000, FitStrategy::PadSilence).await.unwrap();
+        assert_eq!((fitted.duration_ms, fitted.reachable), (3000, true));
+        assert_eq!(audio::duration_secs(&std::fs::read(&output).unwrap()), Some(3.0));
+        let fitted = fit_duration(input.clone(), output.clone(), 1000, FitStrategy::TimeStretch).await.unwrap();
+        assert!(!fitted.reachable);
+        let err = fit_duration(input, output, 0, FitStrategy::PadSilence).await.unwrap_err();
+        assert_eq!(err, "The target duration must be above 0 ms");
+    }
+}
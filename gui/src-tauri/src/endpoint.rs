@@ -0,0 +1,176 @@
+//! Opt-in local HTTP endpoint so scripts and other apps can have KIWI read
+//! text aloud. It listens on 127.0.0.1 only and plays through the same
+//! player as the rest of the app, using the current settings.
+//!
+//! `POST /speak` with `Content-Type: application/json` and a body of
+//! `{ "text": "...", "voice": "en-US-Chirp3-HD-Charon", "format": "MP3" }`,
+//! where `voice` and `format` are optional. The response is
+//! `{ "ok": true }` once playback has started, or `{ "ok": false,
+//! "error": "..." }` with status 400 for bad input, 403 for requests from
+//! outside this machine and 502 when synthesis fails. Bodies over 1 MiB are
+//! refused with 413.
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use axum::extract::{ConnectInfo, DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager};
+use tokio_util::sync::CancellationToken;
+
+use crate::playback::Player;
+use crate::{audio, client, settings, synthesize_long, text, AppHandle, TTSRequest};
+
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Port and shutdown token of the running listener, if any.
+#[derive(Default)]
+pub struct LocalEndpoint(Mutex<Option<(u16, CancellationToken)>>);
+
+#[derive(Debug, Deserialize)]
+struct SpeakRequest {
+    text: String,
+    voice: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SpeakResponse {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+type Reply = (StatusCode, Json<SpeakResponse>);
+
+fn reply(status: StatusCode, error: Option<String>) -> Reply {
+    (status, Json(SpeakResponse { ok: error.is_none(), error }))
+}
+
+/// Browsers send an `Origin` header, so a page on some website could
+/// otherwise post to the endpoint; only pages served from this machine pass.
+fn is_local_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    let host = origin.split("://").nth(1).unwrap_or_default();
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+async fn speak(
+    State(app): State<AppHandle>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(body): Json<SpeakRequest>,
+) -> Reply {
+    if !peer.ip().is_loopback() || !is_local_origin(&headers) {
+        return reply(StatusCode::FORBIDDEN, Some("Only local requests are accepted".to_string()));
+    }
+    if body.text.trim().is_empty() {
+        return reply(StatusCode::BAD_REQUEST, Some("Text cannot be empty".to_string()));
+    }
+    let format = match audio::normalize_format(body.format.as_deref().unwrap_or("MP3")) {
+        Ok(format) => format,
+        Err(e) => return reply(StatusCode::BAD_REQUEST, Some(e)),
+    };
+
+    let settings = settings::current(&app);
+    let voice = body.voice.unwrap_or(settings.default_voice);
+    let request = TTSRequest {
+        text: body.text,
+        language: text::language_from_voice(&voice).unwrap_or(settings.default_language),
+        voice,
+        format: format.to_string(),
+        ..Default::default()
+    };
+
+    let played = match synthesize_long(&app, &client::build(&app), &request, None).await {
+        Ok(bytes) => app.state::<Player>().play(&app, bytes),
+        Err(e) => Err(e),
+    };
+    match played {
+        Ok(()) => reply(StatusCode::OK, None),
+        Err(e) => reply(StatusCode::BAD_GATEWAY, Some(e)),
+    }
+}
+
+async fn start(app: &AppHandle, port: u16) -> Result<(), String> {
+    let running = app.state::<LocalEndpoint>().0.lock().unwrap().as_ref().map(|(port, _)| *port);
+    if running == Some(port) {
+        return Ok(());
+    }
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .map_err(|e| format!("Cannot listen on 127.0.0.1:{}: {}", port, e))?;
+
+    let cancel = CancellationToken::new();
+    let previous = app.state::<LocalEndpoint>().0.lock().unwrap().replace((port, cancel.clone()));
+    if let Some((_, previous)) = previous {
+        previous.cancel();
+    }
+
+    let router = Router::new()
+        .route("/speak", post(speak))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .with_state(app.clone());
+    tauri::async_runtime::spawn(async move {
+        let served = axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(cancel.cancelled_owned())
+            .await;
+        if let Err(e) = served {
+            println!("Local endpoint stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Starts the endpoint again on startup if it was left on. Failures are
+/// logged rather than fatal, since another program may now hold the port.
+pub fn restore(app: &AppHandle) {
+    if let Some(port) = settings::current(app).local_endpoint_port {
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = start(&app, port).await {
+                println!("{}", e);
+            }
+        });
+    }
+}
+
+/// Starts the local endpoint on `port`, replacing a running one, and keeps
+/// it on across restarts until `stop_local_endpoint`.
+#[command]
+pub async fn start_local_endpoint(app: AppHandle, port: u16) -> Result<(), String> {
+    if port == 0 {
+        return Err("Choose a port between 1 and 65535".to_string());
+    }
+    start(&app, port).await?;
+    settings::update(&app, |s| s.local_endpoint_port = Some(port))?;
+    Ok(())
+}
+
+/// Stops the listener without changing the setting, so it starts again
+/// next time the app does.
+pub fn shut_down(app: &AppHandle) {
+    if let Some((_, cancel)) = app.state::<LocalEndpoint>().0.lock().unwrap().take() {
+        cancel.cancel();
+    }
+}
+
+#[command]
+pub fn stop_local_endpoint(app: AppHandle) -> Result<(), String> {
+    shut_down(&app);
+    settings::update(&app, |s| s.local_endpoint_port = None)?;
+    Ok(())
+}
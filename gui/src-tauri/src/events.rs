@@ -0,0 +1,372 @@
+//! Progress of a conversion, reported on the single `job-event` channel so
+//! the frontend can follow one state machine instead of an event per step.
+//! Every payload names its job and stage, plus the stage's own fields:
+//!
+//! ```json
+//! { "job_id": "job-3", "stage": "synthesizing", "chunk": 2, "total": 5, "percent": null }
+//! ```
+//!
+//! A conversion goes through `queued`, `normalizing`, `chunking`, then
+//! `synthesizing` per chunk and `downloading`, any `post_processing` steps,
+//! and ends with exactly one `completed` or `failed`. Audio taken from the
+//! cache skips straight from `chunking` to `completed`. Batches, dialogues
+//! and chapter conversions report per item on their own events.
+//!
+//! The last event of each job is also kept, for `list_jobs` and `get_job`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tauri::{Emitter, Manager};
+
+use crate::{settings, AppHandle};
+
+pub const JOB_EVENT: &str = "job-event";
+
+/// Least time between two progress events of one job.
+const MIN_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum JobEvent {
+    Queued,
+    /// Resolving the preset, language and format and checking the request.
+    Normalizing,
+    /// The text was split into `total` requests.
+    Chunking { total: usize },
+    /// `percent` is the server's own progress on the chunk, when it reports it.
+    Synthesizing {
+        chunk: usize,
+        total: usize,
+        percent: Option<f64>,
+    },
+    /// `percent` is `None` when the server doesn't send the file size.
+    Downloading { percent: Option<f64> },
+    /// A local step after the download, e.g. `encoding_m4a`.
+    PostProcessing { step: String },
+    Completed { path: String },
+    Failed { error: String },
+}
+
+impl JobEvent {
+    fn stage(&self) -> &'static str {
+        match self {
+            JobEvent::Queued => "queued",
+            JobEvent::Normalizing => "normalizing",
+            JobEvent::Chunking { .. } => "chunking",
+            JobEvent::Synthesizing { .. } => "synthesizing",
+            JobEvent::Downloading { .. } => "downloading",
+            JobEvent::PostProcessing { .. } => "post_processing",
+            JobEvent::Completed { .. } => "completed",
+            JobEvent::Failed { .. } => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct JobEventPayload<'a> {
+    job_id: &'a str,
+    #[serde(flatten)]
+    event: JobEvent,
+}
+
+/// Coalesces progress updates into at most ten events a second, however
+/// fast they come.
+#[derive(Default)]
+pub struct Throttle {
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    /// Whether an event may go out at `now`; if so it counts as sent.
+    pub fn ready(&mut self, now: Instant) -> bool {
+        if self.last.is_some_and(|last| now.duration_since(last) < MIN_PROGRESS_INTERVAL) {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+/// Where a job stands after its latest event.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    job_id: String,
+    /// The `stage` of the latest event.
+    stage: &'static str,
+    /// From `synthesizing` and `downloading` events; 100 once completed.
+    percent: Option<f64>,
+    voice: Option<String>,
+    output_path: Option<String>,
+    /// Seconds since the Unix epoch.
+    started_at: u64,
+    error: Option<String>,
+}
+
+impl JobStatus {
+    fn finished(&self) -> bool {
+        matches!(self.stage, "completed" | "failed")
+    }
+}
+
+/// Running jobs and the most recently finished ones, oldest first.
+#[derive(Default)]
+pub struct JobTracker(Mutex<Vec<JobStatus>>);
+
+impl JobTracker {
+    /// Applies `event` to `job_id`'s status, starting over on `queued` so a
+    /// reused id doesn't inherit the old job's fields. Finished jobs beyond
+    /// `keep_finished` are dropped, oldest first.
+    fn record(&self, job_id: &str, event: &JobEvent, keep_finished: usize) {
+        let mut jobs = self.0.lock().unwrap();
+        if matches!(event, JobEvent::Queued) {
+            jobs.retain(|status| status.job_id != job_id);
+        }
+        let index = match jobs.iter().position(|status| status.job_id == job_id) {
+            Some(index) => index,
+            None => {
+                jobs.push(JobStatus {
+                    job_id: job_id.to_string(),
+                    stage: "queued",
+                    percent: None,
+                    voice: None,
+                    output_path: None,
+                    started_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                    error: None,
+                });
+                jobs.len() - 1
+            }
+        };
+
+        let status = &mut jobs[index];
+        status.stage = event.stage();
+        status.percent = match event {
+            JobEvent::Synthesizing { percent, .. } | JobEvent::Downloading { percent } => *percent,
+            JobEvent::Completed { .. } => Some(100.0),
+            _ => None,
+        };
+        match event {
+            JobEvent::Completed { path } => status.output_path = Some(path.clone()),
+            JobEvent::Failed { error } => status.error = Some(error.clone()),
+            _ => {}
+        }
+
+        if status.finished() {
+            let mut excess = jobs.iter().filter(|status| status.finished()).count().saturating_sub(keep_finished);
+            jobs.retain(|status| {
+                let drop = excess > 0 && status.finished();
+                excess -= usize::from(drop);
+                !drop
+            });
+        }
+    }
+
+    /// Newest first.
+    pub fn list(&self) -> Vec<JobStatus> {
+        self.0.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<JobStatus> {
+        self.0.lock().unwrap().iter().find(|status| status.job_id == job_id).cloned()
+    }
+}
+
+/// Fills in the voice and output file of `job` once they are resolved.
+pub fn describe(app: &AppHandle, job: Option<&str>, voice: &str, output_path: &str) {
+    let Some(job_id) = job else {
+        return;
+    };
+    let tracker = app.state::<JobTracker>();
+    let mut jobs = tracker.0.lock().unwrap();
+    if let Some(status) = jobs.iter_mut().find(|status| status.job_id == job_id) {
+        status.voice = Some(voice.to_string());
+        status.output_path = Some(output_path.to_string());
+    }
+}
+
+/// An id for a conversion the frontend didn't name itself.
+pub fn new_job_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    format!("job-{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Emits `event` for `job` and records it; work that isn't a job, like a dry run or a
+/// voice preview, passes `None` and reports nothing.
+pub fn emit(app: &AppHandle, job: Option<&str>, event: JobEvent) {
+    if let Some(job_id) = job {
+        let keep_finished = settings::current(app).max_finished_jobs;
+        app.state::<JobTracker>().record(job_id, &event, keep_finished);
+        let _ = app.emit(JOB_EVENT, JobEventPayload { job_id, event });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn payload(event: JobEvent) -> serde_json::Value {
+        serde_json::to_value(JobEventPayload { job_id: "job-3", event }).unwrap()
+    }
+
+    #[test]
+    fn payload_names_the_job_and_stage() {
+        let event = JobEvent::Synthesizing {
+            chunk: 2,
+            total: 5,
+            percent: None,
+        };
+        assert_eq!(
+            payload(event),
+            serde_json::json!({ "job_id": "job-3", "stage": "synthesizing", "chunk": 2, "total": 5, "percent": null })
+        );
+        assert_eq!(payload(JobEvent::Queued), serde_json::json!({ "job_id": "job-3", "stage": "queued" }));
+        assert_eq!(
+            payload(JobEvent::PostProcessing { step: "encoding_m4a".to_string() }),
+            serde_json::json!({ "job_id": "job-3", "stage": "post_processing", "step": "encoding_m4a" })
+        );
+    }
+
+    #[test]
+    fn stage_matches_the_serialized_tag() {
+        let events = [
+            JobEvent::Queued,
+            JobEvent::Normalizing,
+            JobEvent::Chunking { total: 1 },
+            JobEvent::Synthesizing {
+                chunk: 1,
+                total: 1,
+                percent: Some(50.0),
+            },
+            JobEvent::Downloading { percent: None },
+            JobEvent::PostProcessing { step: "trimming".to_string() },
+            JobEvent::Completed { path: "out.mp3".to_string() },
+            JobEvent::Failed { error: "Server error".to_string() },
+        ];
+        for event in events {
+            let stage = event.stage();
+            assert_eq!(payload(event)["stage"], stage);
+        }
+    }
+
+    #[test]
+    fn throttle_allows_ten_events_a_second() {
+        let start = Instant::now();
+        let mut throttle = Throttle::default();
+        assert!(throttle.ready(start));
+        assert!(!throttle.ready(start + Duration::from_millis(50)));
+        assert!(!throttle.ready(start + Duration::from_millis(99)));
+        assert!(throttle.ready(start + Duration::from_millis(100)));
+        // Held-back events don't push the next one further out
+        assert!(!throttle.ready(start + Duration::from_millis(150)));
+        assert!(throttle.ready(start + Duration::from_millis(200)));
+    }
+
+    fn completed(path: &str) -> JobEvent {
+        JobEvent::Completed { path: path.to_string() }
+    }
+
+    #[test]
+    fn tracker_follows_a_job_to_completion() {
+        let tracker = JobTracker::default();
+        tracker.record("job-1", &JobEvent::Queued, 10);
+        let status = tracker.get("job-1").unwrap();
+        assert_eq!((status.stage, status.percent), ("queued", None));
+
+        let synthesizing = JobEvent::Synthesizing {
+            chunk: 1,
+            total: 2,
+            percent: Some(40.0),
+        };
+        tracker.record("job-1", &synthesizing, 10);
+        assert_eq!(tracker.get("job-1").unwrap().percent, Some(40.0));
+
+        tracker.record("job-1", &completed("out.mp3"), 10);
+        let status = tracker.get("job-1").unwrap();
+        assert_eq!(status.stage, "completed");
+        assert_eq!(status.percent, Some(100.0));
+        assert_eq!(status.output_path.as_deref(), Some("out.mp3"));
+        assert!(tracker.get("job-2").is_none());
+    }
+
+    #[test]
+    fn tracker_keeps_the_error_of_a_failed_job() {
+        let tracker = JobTracker::default();
+        tracker.record("job-1", &JobEvent::Queued, 10);
+        tracker.record("job-1", &JobEvent::Failed { error: "Server error".to_string() }, 10);
+        let status = tracker.get("job-1").unwrap();
+        assert_eq!(status.stage, "failed");
+        assert_eq!(status.error.as_deref(), Some("Server error"));
+    }
+
+    #[test]
+    fn queued_starts_a_reused_id_over() {
+        let tracker = JobTracker::default();
+        tracker.record("job-1", &JobEvent::Failed { error: "Server error".to_string() }, 10);
+        tracker.record("job-1", &JobEvent::Queued, 10);
+        let status = tracker.get("job-1").unwrap();
+        assert_eq!(status.stage, "queued");
+        assert_eq!(status.error, None);
+        assert_eq!(tracker.list().len(), 1);
+    }
+
+    #[test]
+    fn list_is_newest_first() {
+        let tracker = JobTracker::default();
+        for job_id in ["job-1", "job-2", "job-3"] {
+            tracker.record(job_id, &JobEvent::Queued, 10);
+        }
+        let ids: Vec<String> = tracker.list().into_iter().map(|status| status.job_id).collect();
+        assert_eq!(ids, ["job-3", "job-2", "job-1"]);
+    }
+
+    #[test]
+    fn only_the_newest_finished_jobs_are_kept() {
+        let tracker = JobTracker::default();
+        tracker.record("running", &JobEvent::Normalizing, 2);
+        for job_id in ["job-1", "job-2", "job-3"] {
+            tracker.record(job_id, &completed("out.mp3"), 2);
+        }
+        assert!(tracker.get("job-1").is_none());
+        assert!(tracker.get("job-2").is_some());
+        assert!(tracker.get("job-3").is_some());
+        // Running jobs never count against the limit
+        assert_eq!(tracker.get("running").unwrap().stage, "normalizing");
+        assert_eq!(tracker.list().len(), 3);
+    }
+
+    /// The stages `payloads` went through, repeats of one stage counted once.
+    fn stages(payloads: &[serde_json::Value]) -> Vec<String> {
+        let mut stages: Vec<String> = payloads.iter().map(|p| p["stage"].as_str().unwrap_or_default().to_string()).collect();
+        stages.dedup();
+        stages
+    }
+
+    #[tokio::test]
+    async fn conversion_reports_each_stage_in_order() {
+        let audio = testing::tone_wav(300);
+        let server = testing::MockServer::start(move |request| testing::tts_reply(request, &audio)).await;
+        let app = testing::TestApp::with_server(&server);
+        let events = testing::listen(app.handle(), JOB_EVENT);
+        let convert = |name: &str| {
+            let output = app.dir().join(name).to_string_lossy().to_string();
+            let format = Some("LINEAR16".to_string());
+            crate::convert_text_to_speech(app.handle().clone(), "Hello there.".to_string(), None, format, output, false, None)
+        };
+
+        let result = convert("first.wav").await.unwrap();
+        assert!(result.success, "{:?}", result.error);
+        let first = events.lock().unwrap().drain(..).collect::<Vec<_>>();
+        assert_eq!(stages(&first), ["queued", "normalizing", "chunking", "synthesizing", "downloading", "completed"]);
+        let job = &first[0]["job_id"];
+        assert!(first.iter().all(|event| event["job_id"] == *job));
+        assert_eq!(first.last().unwrap()["path"], result.output_path.unwrap());
+
+        // The same request again comes from the cache
+        convert("second.wav").await.unwrap();
+        assert_eq!(stages(&events.lock().unwrap()), ["queued", "normalizing", "chunking", "completed"]);
+        assert_eq!(server.requests_to("/synthesize").len(), 1);
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tauri::command;
+
+use crate::{history, AppHandle};
+
+#[derive(Debug, Serialize)]
+pub struct DeleteResult {
+    path: String,
+    success: bool,
+    error: Option<String>,
+}
+
+/// Ids of the history entries whose output is the file at `canonical`.
+/// Recorded paths are canonicalized too, so `..` segments and symlinks can
+/// neither pass another file off as generated output nor hide a real one.
+fn recorded_ids(entries: &[history::HistoryEntry], canonical: &Path) -> Vec<String> {
+    entries
+        .iter()
+        .filter(|entry| Path::new(&entry.output_path).canonicalize().is_ok_and(|path| path == canonical))
+        .map(|entry| entry.id.clone())
+        .collect()
+}
+
+/// Moves one generated file to the OS trash. Only files the history records
+/// KIWI generating are accepted unless `force` is set.
+fn trash_output(app: &AppHandle, path: &str, force: bool) -> Result<(), String> {
+    let file = PathBuf::from(path);
+    let canonical = match file.canonicalize() {
+        Ok(canonical) if canonical.is_file() => canonical,
+        _ => return Err(format!("File does not exist: {}", path)),
+    };
+    // Found before the file is gone and recorded paths no longer resolve
+    let recorded = recorded_ids(&history::entries(app), &canonical);
+    if !force && recorded.is_empty() {
+        return Err(format!("Refusing to delete {}: it is not a file KIWI has generated", path));
+    }
+
+    trash::delete(&file).map_err(|e| format!("Failed to move {} to the trash: {}", path, e))?;
+
+    let marked = history::update(app, |entries| {
+        for entry in entries.iter_mut().filter(|e| recorded.contains(&e.id)) {
+            entry.deleted = true;
+        }
+    });
+    if let Err(e) = marked {
+        println!("{}", e);
+    }
+    Ok(())
+}
+
+#[command]
+pub fn delete_output_file(app: AppHandle, path: String, force: Option<bool>) -> Result<(), String> {
+    trash_output(&app, &path, force.unwrap_or(false))
+}
+
+/// Trashes each path independently; one failure doesn't stop the others.
+#[command]
+pub fn delete_output_files(app: AppHandle, paths: Vec<String>, force: Option<bool>) -> Vec<DeleteResult> {
+    let force = force.unwrap_or(false);
+    paths
+        .into_iter()
+        .map(|path| match trash_output(&app, &path, force) {
+            Ok(()) => DeleteResult {
+                path,
+                success: true,
+                error: None,
+            },
+            Err(error) => DeleteResult {
+                path,
+                success: false,
+                error: Some(error),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, output_path: &Path) -> history::HistoryEntry {
+        history::HistoryEntry {
+            id: id.to_string(),
+            ..history::HistoryEntry::new("text", "voice", "MP3", "en-US", &output_path.to_string_lossy(), None)
+        }
+    }
+
+    #[test]
+    fn recorded_ids_matches_only_the_recorded_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let generated = dir.path().join("speech.mp3");
+        let other = dir.path().join("notes.txt");
+        std::fs::write(&generated, b"audio").unwrap();
+        std::fs::write(&other, b"mine").unwrap();
+        let entries = [entry("a", &generated)];
+
+        assert_eq!(recorded_ids(&entries, &generated.canonicalize().unwrap()), ["a"]);
+        // A neighbour in the same folder isn't generated output
+        assert!(recorded_ids(&entries, &other.canonicalize().unwrap()).is_empty());
+        assert!(recorded_ids(&entries, &dir.path().canonicalize().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn recorded_ids_compares_canonical_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let generated = dir.path().join("speech.mp3");
+        std::fs::write(&generated, b"audio").unwrap();
+        let canonical = generated.canonicalize().unwrap();
+
+        // Recorded through a roundabout path, as well as two entries for one file
+        let roundabout = dir.path().join("sub").join("..").join("speech.mp3");
+        let entries = [entry("a", &roundabout), entry("b", &generated)];
+        assert_eq!(recorded_ids(&entries, &canonical), ["a", "b"]);
+
+        // A recorded file that has since been removed matches nothing
+        let gone = [entry("c", &dir.path().join("gone.mp3"))];
+        assert!(recorded_ids(&gone, &canonical).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recorded_ids_sees_through_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let generated = dir.path().join("speech.mp3");
+        let link = dir.path().join("link.mp3");
+        std::fs::write(&generated, b"audio").unwrap();
+        std::os::unix::fs::symlink(&generated, &link).unwrap();
+
+        let entries = [entry("a", &generated)];
+        assert_eq!(recorded_ids(&entries, &link.canonicalize().unwrap()), ["a"]);
+    }
+}
@@ -0,0 +1,372 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager, State};
+
+use crate::ssml::InputType;
+use crate::{convert_text_to_speech, paths, AppHandle, ConversionOptions, ConversionResult};
+
+const HISTORY_FILE: &str = "history.json";
+
+/// Oldest entries are dropped beyond this many.
+const MAX_ENTRIES: usize = 1000;
+
+/// Characters of each entry's text included in a CSV export.
+const EXPORT_SNIPPET_CHARS: usize = 200;
+
+/// A file KIWI generated, with the request that produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub text: String,
+    pub voice: String,
+    pub format: String,
+    pub language: String,
+    pub output_path: String,
+    #[serde(default)]
+    pub file_size: Option<String>,
+    /// Set once the file has been moved to the trash from within KIWI.
+    #[serde(default)]
+    pub deleted: bool,
+    /// Prosody and encoding the file was made with, so it can be regenerated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaking_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pitch: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_gain_db: Option<f64>,
+    /// `text` is the SSML document that was sent.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ssml: bool,
+    /// The copy of the audio from before local processing, when one was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_path: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(text: &str, voice: &str, format: &str, language: &str, output_path: &str, file_size: Option<String>) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        HistoryEntry {
+            id: format!("{}-{:09}", now.as_secs(), now.subsec_nanos()),
+            created_at: now.as_secs(),
+            text: text.to_string(),
+            voice: voice.to_string(),
+            format: format.to_string(),
+            language: language.to_string(),
+            output_path: output_path.to_string(),
+            file_size,
+            deleted: false,
+            speaking_rate: None,
+            pitch: None,
+            volume_gain_db: None,
+            ssml: false,
+            raw_path: None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HistoryState(pub Mutex<Vec<HistoryEntry>>);
+
+fn history_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join(HISTORY_FILE))
+        .map_err(|e| format!("Cannot determine data directory: {}", e))
+}
+
+/// Reads the history file; a missing or unreadable file starts an empty history.
+pub fn load(app: &AppHandle) -> Vec<HistoryEntry> {
+    history_path(app).map(|path| read(&path)).unwrap_or_default()
+}
+
+fn read(path: &Path) -> Vec<HistoryEntry> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            println!("Ignoring invalid history file {}: {}", path.display(), e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn save(app: &AppHandle, entries: &[HistoryEntry]) -> Result<(), String> {
+    write(&history_path(app)?, entries)
+}
+
+fn write(path: &Path, entries: &[HistoryEntry]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create data directory: {}", e))?;
+    }
+    let json = serde_json::to_string_pretty(entries).map_err(|e| format!("Failed to encode history: {}", e))?;
+    std::fs::write(path, json).map_err(|e| format!("Failed to save history: {}", e))
+}
+
+/// Empties the history file in `data_dir` and returns how many entries it
+/// held. The in-memory history is left to the caller.
+pub fn clear_in(data_dir: &Path) -> Result<usize, String> {
+    let path = data_dir.join(HISTORY_FILE);
+    let count = read(&path).len();
+    write(&path, &[])?;
+    Ok(count)
+}
+
+/// Writes the in-memory history to disk.
+pub fn flush(app: &AppHandle) -> Result<(), String> {
+    save(app, &app.state::<HistoryState>().0.lock().unwrap())
+}
+
+/// Applies `change` to the in-memory history and persists the result.
+pub fn update<T>(app: &AppHandle, change: impl FnOnce(&mut Vec<HistoryEntry>) -> T) -> Result<T, String> {
+    let state = app.state::<HistoryState>();
+    let mut entries = state.0.lock().unwrap();
+    let result = change(&mut entries);
+    save(app, &entries)?;
+    Ok(result)
+}
+
+/// Whether an earlier conversion kept the raw copy at `path`. History stops
+/// recording it, as the caller is about to remove it.
+pub fn forget_raw_copy(app: &AppHandle, path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    let kept = |entry: &HistoryEntry| entry.raw_path.as_deref() == Some(&*path);
+    if !app.state::<HistoryState>().0.lock().unwrap().iter().any(kept) {
+        return false;
+    }
+    let forgotten = update(app, |entries| {
+        entries.iter_mut().filter(|entry| kept(entry)).for_each(|entry| entry.raw_path = None);
+    });
+    if let Err(e) = forgotten {
+        println!("{}", e);
+    }
+    true
+}
+
+/// Adds an entry. History is a convenience, so failing to persist it is
+/// logged rather than failing the conversion that produced the file.
+pub fn record(app: &AppHandle, entry: HistoryEntry) {
+    let saved = update(app, |entries| {
+        entries.push(entry);
+        let excess = entries.len().saturating_sub(MAX_ENTRIES);
+        entries.drain(..excess);
+    });
+    if let Err(e) = saved {
+        println!("{}", e);
+    }
+}
+
+/// A copy of the history, oldest first.
+pub fn entries(app: &AppHandle) -> Vec<HistoryEntry> {
+    app.state::<HistoryState>().0.lock().unwrap().clone()
+}
+
+/// Newest first.
+#[command]
+pub fn get_history(state: State<'_, HistoryState>) -> Vec<HistoryEntry> {
+    state.0.lock().unwrap().iter().rev().cloned().collect()
+}
+
+pub fn find(app: &AppHandle, id: &str) -> Result<HistoryEntry, String> {
+    app.state::<HistoryState>()
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|entry| entry.id == id)
+        .cloned()
+        .ok_or_else(|| format!("No history entry with id '{}'", id))
+}
+
+/// The text an entry was made from: the stored text, or for entries saved
+/// without it, the transcript written next to the audio.
+pub fn source_text(entry: &HistoryEntry) -> Result<String, String> {
+    if !entry.text.trim().is_empty() {
+        return Ok(entry.text.clone());
+    }
+    let transcript = Path::new(&entry.output_path).with_extension("txt");
+    std::fs::read_to_string(&transcript)
+        .ok()
+        .filter(|text| !text.trim().is_empty())
+        .ok_or_else(|| "The full text of this history entry is not available".to_string())
+}
+
+/// Synthesizes a history entry again with the voice, format and prosody it
+/// was made with. The new file goes to `output_path`, or next to the
+/// original under a free name so the original is kept.
+#[command]
+pub async fn regenerate_from_history(
+    app: AppHandle,
+    id: String,
+    output_path: Option<String>,
+) -> Result<ConversionResult, String> {
+    let entry = find(&app, &id)?;
+    let text = source_text(&entry)?;
+    let output_path = output_path
+        .unwrap_or_else(|| paths::available_path(Path::new(&entry.output_path)).to_string_lossy().to_string());
+
+    let options = ConversionOptions {
+        speaking_rate: entry.speaking_rate,
+        pitch: entry.pitch,
+        volume_gain_db: entry.volume_gain_db,
+        input_type: if entry.ssml { InputType::Ssml } else { InputType::Text },
+        ..Default::default()
+    };
+    convert_text_to_speech(app, text, Some(entry.voice), Some(entry.format), output_path, false, Some(options)).await
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryExport {
+    path: String,
+    rows: usize,
+}
+
+/// One CSV row; the text is shortened since the audio file is the record.
+#[derive(Debug, Serialize)]
+struct CsvRow<'a> {
+    created_at: String,
+    voice: &'a str,
+    language: &'a str,
+    format: &'a str,
+    characters: usize,
+    file_size: &'a str,
+    output_path: &'a str,
+    deleted: bool,
+    text: String,
+}
+
+/// `seconds` since the Unix epoch as an ISO 8601 UTC timestamp.
+fn iso_timestamp(seconds: u64) -> String {
+    let (days, rest) = ((seconds / 86_400) as i64, seconds % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+fn snapshot(state: &HistoryState) -> Vec<HistoryEntry> {
+    state.0.lock().unwrap().clone()
+}
+
+/// Writes the history, oldest first, as CSV with standard quoting.
+#[command]
+pub fn export_history_csv(state: State<'_, HistoryState>, output_path: String) -> Result<HistoryExport, String> {
+    write_csv(&snapshot(&state), output_path)
+}
+
+fn write_csv(entries: &[HistoryEntry], output_path: String) -> Result<HistoryExport, String> {
+    let mut writer =
+        csv::Writer::from_path(&output_path).map_err(|e| format!("Failed to create {}: {}", output_path, e))?;
+    for entry in entries {
+        writer
+            .serialize(CsvRow {
+                created_at: iso_timestamp(entry.created_at),
+                voice: &entry.voice,
+                language: &entry.language,
+                format: &entry.format,
+                characters: entry.text.chars().count(),
+                file_size: entry.file_size.as_deref().unwrap_or_default(),
+                output_path: &entry.output_path,
+                deleted: entry.deleted,
+                text: entry.text.chars().take(EXPORT_SNIPPET_CHARS).collect(),
+            })
+            .map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
+    }
+    writer.flush().map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
+    Ok(HistoryExport {
+        path: output_path,
+        rows: entries.len(),
+    })
+}
+
+/// Writes the full history, oldest first, in the format of the history file.
+#[command]
+pub fn export_history_json(state: State<'_, HistoryState>, output_path: String) -> Result<HistoryExport, String> {
+    write_json(&snapshot(&state), output_path)
+}
+
+fn write_json(entries: &[HistoryEntry], output_path: String) -> Result<HistoryExport, String> {
+    let json = serde_json::to_string_pretty(entries).map_err(|e| format!("Failed to encode history: {}", e))?;
+    std::fs::write(&output_path, json).map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
+    Ok(HistoryExport {
+        path: output_path,
+        rows: entries.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso_timestamp_formats_utc_dates() {
+        assert_eq!(iso_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    fn exported(text: &str) -> HistoryEntry {
+        HistoryEntry {
+            created_at: 0,
+            ..HistoryEntry::new(text, "en-US-Chirp3-HD-Kore", "MP3", "en-US", "/tmp/a.mp3", Some("1.0 KB".to_string()))
+        }
+    }
+
+    #[test]
+    fn csv_export_quotes_fields_and_shortens_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.csv").to_string_lossy().to_string();
+        let entries = vec![exported("Hello, \"world\""), exported(&"x".repeat(500))];
+
+        assert_eq!(write_csv(&entries, path.clone()).unwrap().rows, 2);
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        assert_eq!(&reader.headers().unwrap()[0], "created_at");
+        assert_eq!(&rows[0][0], "1970-01-01T00:00:00Z");
+        assert_eq!(&rows[0][8], "Hello, \"world\"");
+        assert_eq!(&rows[1][4], "500");
+        assert_eq!(rows[1][8].len(), EXPORT_SNIPPET_CHARS);
+    }
+
+    #[test]
+    fn json_export_reads_back_as_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json").to_string_lossy().to_string();
+        write_json(&[exported("Hello")], path.clone()).unwrap();
+
+        let read: Vec<HistoryEntry> = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].text, "Hello");
+    }
+
+    #[test]
+    fn source_text_falls_back_to_the_transcript() {
+        let dir = tempfile::tempdir().unwrap();
+        let audio = dir.path().join("story.mp3").to_string_lossy().to_string();
+        let stored = HistoryEntry::new("Once upon a time", "Kore", "MP3", "en-US", &audio, None);
+        assert_eq!(source_text(&stored).unwrap(), "Once upon a time");
+
+        let without_text = HistoryEntry::new("", "Kore", "MP3", "en-US", &audio, None);
+        assert!(source_text(&without_text).unwrap_err().contains("not available"));
+        std::fs::write(dir.path().join("story.txt"), "The whole story").unwrap();
+        assert_eq!(source_text(&without_text).unwrap(), "The whole story");
+    }
+}
@@ -0,0 +1,99 @@
+//! Opt-in global shortcut that reads the clipboard aloud with the default voice.
+
+use tauri::{command, Emitter, Manager};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+
+use crate::playback::Player;
+use crate::settings;
+use crate::{client, synthesize_bytes, AppHandle, TTSRequest};
+
+pub fn plugin() -> tauri::plugin::TauriPlugin<crate::Runtime> {
+    tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(|app, _shortcut, event| {
+            if event.state() == ShortcutState::Pressed {
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = speak_clipboard(&app).await {
+                        let _ = app.emit("hotkey-error", e);
+                    }
+                });
+            }
+        })
+        .build()
+}
+
+async fn speak_clipboard(app: &AppHandle) -> Result<(), String> {
+    let text = app
+        .clipboard()
+        .read_text()
+        .map_err(|e| format!("Failed to read clipboard: {}", e))?;
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+
+    let settings = settings::current(app);
+    let request = TTSRequest {
+        text,
+        voice: settings.default_voice,
+        format: "MP3".to_string(),
+        language: settings.default_language,
+        ..Default::default()
+    };
+    let bytes = synthesize_bytes(app, &client::build(app), &request).await?;
+    app.state::<Player>().play(app, bytes)
+}
+
+fn register(app: &AppHandle, accelerator: &str) -> Result<(), String> {
+    let shortcut: Shortcut = accelerator
+        .parse()
+        .map_err(|e| format!("Invalid shortcut '{}': {}", accelerator, e))?;
+
+    app.global_shortcut().register(shortcut).map_err(|e| {
+        format!(
+            "Shortcut '{}' could not be registered; it may already be in use by another application ({})",
+            accelerator, e
+        )
+    })
+}
+
+/// Re-registers the saved shortcut on startup. Failures are logged rather than
+/// fatal, since another app may have claimed the shortcut in the meantime.
+pub fn restore(app: &AppHandle) {
+    if let Some(accelerator) = settings::current(app).global_hotkey {
+        if let Err(e) = register(app, &accelerator) {
+            println!("{}", e);
+        }
+    }
+}
+
+/// Enables the clipboard shortcut, replacing any previously registered one.
+#[command]
+pub fn register_hotkey(app: AppHandle, accelerator: String) -> Result<(), String> {
+    let previous = settings::current(&app).global_hotkey;
+    if let Some(previous) = &previous {
+        let _ = app.global_shortcut().unregister(previous.as_str());
+    }
+
+    if let Err(e) = register(&app, &accelerator) {
+        // Put the old shortcut back so a failed change doesn't leave nothing bound
+        if let Some(previous) = &previous {
+            let _ = register(&app, previous);
+        }
+        return Err(e);
+    }
+
+    settings::update(&app, |s| s.global_hotkey = Some(accelerator))?;
+    Ok(())
+}
+
+#[command]
+pub fn unregister_hotkey(app: AppHandle) -> Result<(), String> {
+    if let Some(accelerator) = settings::current(&app).global_hotkey {
+        app.global_shortcut()
+            .unregister(accelerator.as_str())
+            .map_err(|e| format!("Failed to unregister shortcut '{}': {}", accelerator, e))?;
+    }
+    settings::update(&app, |s| s.global_hotkey = None)?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{command, Emitter, Manager, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::events::{JobStatus, JobTracker};
+use crate::{ratelimit, stream, AppHandle};
+
+/// Cancellation tokens of running conversions, keyed by the id the frontend
+/// chose when starting them.
+#[derive(Default)]
+pub struct JobRegistry(Mutex<HashMap<String, CancellationToken>>);
+
+impl JobRegistry {
+    pub fn register(&self, id: &str) -> Result<CancellationToken, String> {
+        let mut jobs = self.0.lock().unwrap();
+        if jobs.contains_key(id) {
+            return Err(format!("A conversion with id '{}' is already running", id));
+        }
+        let cancel = CancellationToken::new();
+        jobs.insert(id.to_string(), cancel.clone());
+        Ok(cancel)
+    }
+
+    pub fn finish(&self, id: &str) {
+        self.0.lock().unwrap().remove(id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Cancels every running conversion and returns how many there were.
+    pub fn cancel_all(&self) -> usize {
+        let jobs = self.0.lock().unwrap();
+        for cancel in jobs.values() {
+            cancel.cancel();
+        }
+        jobs.len()
+    }
+
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.0.lock().unwrap().get(id) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Cancels a conversion started with a `conversion_id`. Returns false when no
+/// such conversion is running.
+#[command]
+pub fn cancel_conversion(registry: State<'_, JobRegistry>, conversion_id: String) -> bool {
+    registry.cancel(&conversion_id)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CancelSummary {
+    cancelled_running: usize,
+    cleared_pending: usize,
+}
+
+impl CancelSummary {
+    /// `cancelled` jobs include the `cleared_pending` ones still waiting for
+    /// a request slot, which are only counted as pending.
+    fn new(cancelled: usize, cleared_pending: usize) -> Self {
+        CancelSummary {
+            cancelled_running: cancelled.saturating_sub(cleared_pending),
+            cleared_pending,
+        }
+    }
+}
+
+/// Cancels every running conversion and stream and turns away the requests
+/// waiting for a slot, then emits `all-cancelled` with the same summary.
+/// A conversion still waiting for a slot counts as pending, not running.
+/// Cancelled downloads remove their partial files as they stop.
+#[command]
+pub fn cancel_all(app: AppHandle) -> CancelSummary {
+    let cleared_pending = ratelimit::clear_queue(&app);
+    let cancelled = app.state::<JobRegistry>().cancel_all() + app.state::<stream::StreamRegistry>().cancel_all();
+    let summary = CancelSummary::new(cancelled, cleared_pending);
+    let _ = app.emit("all-cancelled", summary.clone());
+    summary
+}
+
+/// Running conversions and the most recently finished ones, newest first,
+/// for frontends that poll or missed `job-event`s while in the background.
+#[command]
+pub fn list_jobs(tracker: State<'_, JobTracker>) -> Vec<JobStatus> {
+    tracker.list()
+}
+
+#[command]
+pub fn get_job(tracker: State<'_, JobTracker>, job_id: String) -> Result<JobStatus, String> {
+    tracker.get(&job_id).ok_or_else(|| format!("No job with id '{}'", job_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_rejects_duplicate_ids_until_finished() {
+        let registry = JobRegistry::default();
+        registry.register("a").unwrap();
+        assert!(registry.register("a").unwrap_err().contains("already running"));
+        registry.finish("a");
+        assert!(registry.register("a").is_ok());
+    }
+
+    #[test]
+    fn cancel_all_cancels_every_running_job() {
+        let registry = JobRegistry::default();
+        let (a, b) = (registry.register("a").unwrap(), registry.register("b").unwrap());
+        assert_eq!(registry.cancel_all(), 2);
+        assert!(a.is_cancelled() && b.is_cancelled());
+        // Cancelled jobs stay registered until they finish winding down
+        assert_eq!(registry.len(), 2);
+
+        assert!(registry.cancel("a"));
+        assert!(!registry.cancel("missing"));
+    }
+
+    #[test]
+    fn cancel_summary_counts_queued_jobs_as_pending() {
+        let summary = CancelSummary::new(5, 2);
+        assert_eq!((summary.cancelled_running, summary.cleared_pending), (3, 2));
+        // Queued requests outside any registered job
+        let summary = CancelSummary::new(1, 3);
+        assert_eq!((summary.cancelled_running, summary.cleared_pending), (0, 3));
+    }
+}
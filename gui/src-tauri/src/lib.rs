@@ -1,29 +1,119 @@
 use serde::{Deserialize, Serialize};
-use tauri::command;
+use tauri::{command, Manager};
 
-#[derive(Debug, Serialize, Deserialize)]
+use events::JobEvent;
+
+mod announce;
+mod audio;
+mod batch;
+mod benchmark;
+mod cache;
+mod capabilities;
+mod client;
+mod dialogue;
+mod documents;
+mod edit;
+mod endpoint;
+mod events;
+#[cfg(desktop)]
+mod files;
+mod history;
+mod jobfile;
+mod jobs;
+#[cfg(desktop)]
+mod hotkey;
+mod offline;
+mod paths;
+mod playback;
+mod presets;
+mod profiles;
+mod ratelimit;
+mod reset;
+mod secrets;
+mod servers;
+mod settings;
+mod shutdown;
+mod ssml;
+mod stream;
+#[cfg(test)]
+mod testing;
+mod text;
+mod version;
+mod voices;
+
+/// The runtime the app runs on. Tests use Tauri's mock runtime instead,
+/// which needs no window system, so commands can run end to end.
+#[cfg(not(test))]
+type Runtime = tauri::Wry;
+#[cfg(test)]
+type Runtime = tauri::test::MockRuntime;
+
+type AppHandle = tauri::AppHandle<Runtime>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Voice {
     name: String,
     language_code: String,
     ssml_gender: String,
     display_name: Option<String>,
+    /// The following are only sent by newer servers.
+    #[serde(default)]
+    natural_sample_rate_hz: Option<u32>,
+    #[serde(default)]
+    natural_channels: Option<u16>,
+    /// Audio formats the voice can produce; empty when the server doesn't say.
+    #[serde(default)]
+    supported_formats: Vec<String>,
+    #[serde(default)]
+    model_tier: Option<String>,
+    /// Named speaking styles the voice offers, e.g. `newscast` or `calm`.
+    #[serde(default)]
+    speaking_styles: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct VoicesResponse {
+/// Voices for a language, and why the fallback list was used instead of
+/// the server's, if it was.
+#[derive(Debug, Serialize)]
+struct VoiceList {
     voices: Vec<Voice>,
-    language_code: String,
+    fallback_reason: Option<String>,
+    /// Entries of the server's answer that couldn't be read as voices and
+    /// were left out.
+    skipped_voices: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct TTSRequest {
     text: String,
     voice: String,
     format: String,
     language: String,
+    /// Output sample rate; the server uses the voice's natural rate when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<u32>,
+    /// Output channel count, 1 or 2; the voice's own when absent. Fixed up
+    /// locally when the server sends something else.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    channels: Option<u16>,
+    /// Prosody adjustments; the server's defaults apply when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    speaking_rate: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pitch: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volume_gain_db: Option<f64>,
+    /// One of the voice's `speaking_styles`; its default delivery when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    speaking_style: Option<String>,
+    /// Device profile the audio is tuned for, e.g. `headphone-class-device`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    effects_profile_id: Option<String>,
+    /// `text` is an SSML document rather than plain text.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    ssml: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct ConversionResult {
     success: bool,
     output_path: Option<String>,
@@ -31,182 +121,1543 @@ struct ConversionResult {
     file_size: Option<String>,
     processing_time: Option<String>,
     download_url: Option<String>,
+    /// Where the `.txt` transcript sidecar was written, if one was requested and saved.
+    #[serde(default)]
+    transcript_path: Option<String>,
+    /// Where the audio was saved as it arrived, before any local processing,
+    /// when `keep_raw` was set.
+    #[serde(default)]
+    raw_path: Option<String>,
+    /// Non-fatal problems, such as a sidecar that could not be written.
+    #[serde(default)]
+    warnings: Vec<String>,
+    /// True when the audio came from the local engine rather than the API server.
+    #[serde(default)]
+    offline: bool,
+    /// The voice actually used when the requested one was missing and
+    /// `auto_substitute_voice` picked a replacement.
+    #[serde(default)]
+    substituted_voice: Option<String>,
+    /// The parameters the conversion ran with, when a preset was applied.
+    #[serde(default)]
+    resolved_preset: Option<presets::Preset>,
+    /// Base URL of the server that handled the request; set by the client.
+    #[serde(default)]
+    server: Option<String>,
+    /// Set by servers that synthesize asynchronously; see `poll_synthesis`.
+    #[serde(default)]
+    job_id: Option<String>,
+    /// Id the conversion's `job-event`s carry: the `conversion_id` it was
+    /// given, or a generated one.
+    #[serde(default)]
+    conversion_id: Option<String>,
+    /// Encoding the input was read as, for conversions from a text file.
+    #[serde(default)]
+    text_encoding: Option<String>,
+    /// True when nothing was synthesized and the sizes are estimates.
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    estimated_duration_seconds: Option<f64>,
+    /// True when the API refused the request because a quota was exceeded.
+    #[serde(default)]
+    rate_limited: bool,
+    /// How long the API asked to wait before trying again, from `Retry-After`.
+    #[serde(default)]
+    retry_after_secs: Option<u64>,
+    /// True when the audio was copied from the local cache instead of synthesized.
+    #[serde(default)]
+    cached: bool,
+    /// Hash of the request as sent to the server, which keys its audio
+    /// cache entry; see `cache::content_hash`.
+    #[serde(default)]
+    content_hash: Option<String>,
+}
+
+/// Optional behaviour for `convert_text_to_speech`. Every field defaults to
+/// off so callers only pass what they need.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ConversionOptions {
+    /// Write the text next to the audio as `<output_stem>.txt`.
+    save_transcript: bool,
+    /// Save the text exactly as submitted instead of the text that was sent for synthesis.
+    raw_transcript: bool,
+    /// Validate the request and report what would be produced without synthesizing.
+    dry_run: bool,
+    /// Caller-chosen id that `cancel_conversion` accepts while this conversion runs.
+    conversion_id: Option<String>,
+    /// Produce raw PCM (or PCM WAV) with these parameters; overrides `format`.
+    pcm: Option<audio::PcmFormat>,
+    /// Name of a saved preset that fills in any parameter not given here.
+    preset: Option<String>,
+    /// Language code; derived from the voice when absent.
+    language: Option<String>,
+    speaking_rate: Option<f64>,
+    pitch: Option<f64>,
+    volume_gain_db: Option<f64>,
+    /// Refused for now: the API picks its own Opus bitrate.
+    bitrate_kbps: Option<u32>,
+    /// Named speaking style of the voice, e.g. `newscast`.
+    speaking_style: Option<String>,
+    /// Playback device to tune the audio for, one of the server's
+    /// `effects_profiles`, e.g. `telephony-class-application`.
+    effects_profile_id: Option<String>,
+    /// 1 for mono or 2 for stereo; the voice's own channels when absent.
+    channels: Option<u8>,
+    /// Play the audio this many times, e.g. for drilling vocabulary. It is
+    /// synthesized once and repeated locally.
+    repeat_count: Option<u32>,
+    /// Silence between repetitions; one second when absent.
+    repeat_gap_ms: Option<u64>,
+    /// Warn when the finished audio clips, e.g. from a high `volume_gain_db`.
+    check_clipping: bool,
+    /// Always ask the server, even when the audio cache has this request.
+    bypass_cache: bool,
+    /// Whether `text` is plain, SSML or plain with inline markup.
+    input_type: ssml::InputType,
+    /// Also save the audio as the server sent it, as `<output_stem>.raw.<ext>`,
+    /// to compare with the processed file.
+    keep_raw: bool,
 }
 
 const API_BASE_URL: &str = "http://127.0.0.1:8000";
 
+const MAX_REPEAT_COUNT: u32 = 100;
+const DEFAULT_REPEAT_GAP_MS: u64 = 1000;
+const MAX_REPEAT_GAP_MS: u64 = 60_000;
+
 #[command]
-async fn get_available_voices(language_code: String) -> Result<Vec<Voice>, String> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/voices/{}", API_BASE_URL, language_code);
-    
-    match client.get(&url).send().await {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<VoicesResponse>().await {
-                    Ok(voices_response) => Ok(voices_response.voices),
-                    Err(e) => Err(format!("Failed to parse voices response: {}", e)),
+async fn get_available_voices(app: AppHandle, language_code: String) -> Result<VoiceList, String> {
+    if settings::current(&app).offline_mode {
+        let voices = tauri::async_runtime::spawn_blocking(move || offline::list_voices(&language_code))
+            .await
+            .map_err(|e| format!("Failed to list system voices: {}", e))??;
+        return Ok(VoiceList {
+            voices,
+            fallback_reason: None,
+            skipped_voices: 0,
+        });
+    }
+
+    Ok(fetch_voices_with_source(&app, &language_code).await)
+}
+
+/// Asks the API server for its voices, using the fallback list when it can't be reached.
+async fn fetch_voices(app: &AppHandle, language_code: &str) -> Result<Vec<Voice>, String> {
+    Ok(fetch_voices_with_source(app, language_code).await.voices)
+}
+
+/// Reads the `voices` array of a `/voices` answer one entry at a time, so
+/// entries KIWI can't read are skipped instead of losing the whole list,
+/// and a body cut short keeps the entries before the break. Returns the
+/// readable voices and how many entries were skipped, counting a cut-off
+/// tail as one.
+fn parse_voices(body: &str) -> Result<(Vec<Voice>, usize), String> {
+    let (mut voices, mut skipped) = (Vec::new(), 0);
+    let reader = VoicesReader {
+        voices: &mut voices,
+        skipped: &mut skipped,
+    };
+    let read = serde::Deserializer::deserialize_map(&mut serde_json::Deserializer::from_str(body), reader);
+    match read {
+        Ok(true) => Ok((voices, skipped)),
+        Err(e) if (e.is_eof() || e.is_syntax()) && !voices.is_empty() => Ok((voices, skipped + 1)),
+        Err(e) if e.is_eof() || e.is_syntax() => Err(e.to_string()),
+        Ok(false) | Err(_) => Err("it has no voices array".to_string()),
+    }
+}
+
+/// Visits a `/voices` answer, collecting the voices as they are read so
+/// they outlast a syntax error further on. Yields whether there was a
+/// `voices` array.
+struct VoicesReader<'a> {
+    voices: &'a mut Vec<Voice>,
+    skipped: &'a mut usize,
+}
+
+impl<'de> serde::de::Visitor<'de> for VoicesReader<'_> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an object with a voices array")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<bool, A::Error> {
+        let mut entries = Some(VoiceEntries(self));
+        while let Some(key) = map.next_key::<String>()? {
+            match entries.take_if(|_| key == "voices") {
+                Some(seed) => map.next_value_seed(seed)?,
+                None => map.next_value::<serde::de::IgnoredAny>().map(|_| ())?,
+            }
+        }
+        Ok(entries.is_none())
+    }
+}
+
+/// The `voices` array itself; each entry is read as plain JSON first so
+/// one that isn't a voice only skips that entry.
+struct VoiceEntries<'a>(VoicesReader<'a>);
+
+impl<'de> serde::de::DeserializeSeed<'de> for VoiceEntries<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for VoiceEntries<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a voices array")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(entry) = seq.next_element::<serde_json::Value>()? {
+            match serde_json::from_value(entry) {
+                Ok(voice) => self.0.voices.push(voice),
+                Err(_) => *self.0.skipped += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Like `fetch_voices`, also telling why the list is the fallback one
+/// when it is. Server answers are cached; fallback lists are not, so the
+/// server is asked again once it is back.
+async fn fetch_voices_with_source(app: &AppHandle, language_code: &str) -> VoiceList {
+    if let Some(voices) = voices::cached(app, language_code) {
+        return VoiceList {
+            voices,
+            fallback_reason: None,
+            skipped_voices: 0,
+        };
+    }
+
+    let client = client::build(app);
+    let url = format!("{}/voices/{}", servers::primary(app, &client).await, language_code);
+
+    let reason = match client.get(&url).send().await {
+        Ok(response) if response.status().is_success() => {
+            let parsed = response.text().await.map_err(|e| e.to_string()).and_then(|body| parse_voices(&body));
+            match parsed {
+                // Every entry unreadable, as opposed to a language without voices
+                Ok((voices, skipped)) if voices.is_empty() && skipped > 0 => {
+                    format!("None of the {} voices the API server sent could be read", skipped)
                 }
-            } else {
-                // Fallback voices when API server is not running
-                let chirp_voices = vec![
-                    "Charon", "Kore", "Zephyr", "Achernar", "Pulcherrima", "Leda", 
-                    "Aoede", "Callirrhoe", "Despina", "Enceladus", "Puck", "Umbriel"
-                ];
-                
-                let voices: Vec<Voice> = chirp_voices.iter().map(|&voice_name| {
-                    let gender = if ["Charon", "Kore", "Leda", "Aoede", "Callirrhoe", "Pulcherrima", "Despina"].contains(&voice_name) {
-                        "FEMALE"
-                    } else {
-                        "MALE"
-                    };
-                    
-                    Voice {
-                        name: format!("{}-Chirp3-HD-{}", language_code, voice_name),
-                        language_code: language_code.clone(),
-                        ssml_gender: gender.to_string(),
-                        display_name: Some(format!("{} (HD)", voice_name)),
+                Ok((voices, skipped)) => {
+                    if skipped > 0 {
+                        println!("Skipped {} unreadable entries in the voice list for {}", skipped, language_code);
                     }
-                }).collect();
-                
-                Ok(voices)
+                    voices::store(app, language_code, &voices);
+                    return VoiceList {
+                        voices,
+                        fallback_reason: None,
+                        skipped_voices: skipped,
+                    };
+                }
+                Err(e) => format!("The API server sent an unreadable voice list: {}", e),
             }
         }
-        Err(e) => {
-            // Network error - return fallback voices
-            let chirp_voices = vec![
-                "Charon", "Kore", "Zephyr", "Achernar", "Pulcherrima", "Leda", 
-                "Aoede", "Callirrhoe", "Despina", "Enceladus", "Puck", "Umbriel"
-            ];
-            
-            let voices: Vec<Voice> = chirp_voices.iter().map(|&voice_name| {
-                let gender = if ["Charon", "Kore", "Leda", "Aoede", "Callirrhoe", "Pulcherrima", "Despina"].contains(&voice_name) {
-                    "FEMALE"
+        Ok(response) => format!("The API server answered with status {}", response.status()),
+        Err(e) => format!("The API server is not available: {}", e),
+    };
+
+    let voices = voices::fallback(app, language_code);
+    let reason = if voices.is_empty() {
+        format!("{}, and there are no fallback voices for {}", reason, language_code)
+    } else {
+        reason
+    };
+    println!("{}; using fallback voices", reason);
+    VoiceList {
+        voices,
+        fallback_reason: Some(reason),
+        skipped_voices: 0,
+    }
+}
+
+impl ConversionResult {
+    fn failure(error: String) -> Self {
+        ConversionResult {
+            success: false,
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+}
+
+/// Seconds to wait according to a `Retry-After` header, which holds either
+/// a number of seconds or an HTTP date.
+fn retry_after_secs(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(secs);
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(std::time::SystemTime::now()).map_or(0, |wait| wait.as_secs()))
+}
+
+fn rate_limited_failure(retry_after: Option<u64>) -> ConversionResult {
+    let error = match retry_after {
+        Some(secs) => format!("The API rate limit was reached; try again in {} seconds", secs),
+        None => "The API rate limit was reached; try again later".to_string(),
+    };
+    ConversionResult {
+        rate_limited: true,
+        retry_after_secs: retry_after,
+        ..ConversionResult::failure(error)
+    }
+}
+
+/// Start of the error reported when no server could be reached.
+const SERVER_UNREACHABLE: &str = "Failed to connect to API server";
+
+fn format_file_size(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_048_576.0)
+}
+
+/// Sends a synthesis request to the API server. Transport and HTTP errors are
+/// reported as an unsuccessful `ConversionResult`, the same way the server
+/// reports synthesis failures; only an unreadable response is an `Err`.
+/// With several servers configured, one that can't be reached or answers
+/// with a gateway error is skipped in favour of the next. A 429 is retried
+/// up to `rate_limit_retries` times after the delay the server asks for.
+async fn request_synthesis(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    request: &TTSRequest,
+) -> Result<ConversionResult, String> {
+    let chars = request.text.chars().count();
+    let retries = settings::current(app).rate_limit_retries;
+    let mut attempt = 0;
+    loop {
+        // Every attempt is a request the server counts, retries included
+        ratelimit::acquire(app, chars).await;
+        let slot = ratelimit::request_slot(app).await?;
+        let result = send_synthesis(app, client, request).await;
+        drop(slot);
+        let result = result?;
+        if !result.rate_limited || attempt >= retries {
+            return Ok(result);
+        }
+        attempt += 1;
+        let wait = result.retry_after_secs.unwrap_or(ratelimit::DEFAULT_RETRY_AFTER_SECS);
+        ratelimit::back_off(app, std::time::Duration::from_secs(wait)).await;
+    }
+}
+
+async fn send_synthesis(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    request: &TTSRequest,
+) -> Result<ConversionResult, String> {
+    let mut last_failure = None;
+    for server in servers::candidates(app, client).await {
+        let url = format!("{}/synthesize", server);
+        let mut result = match client.post(&url).json(request).send().await {
+            Ok(response) => {
+                let status = response.status();
+                if status.is_success() {
+                    response
+                        .json::<ConversionResult>()
+                        .await
+                        .map_err(|e| format!("Failed to parse TTS response: {}", e))?
+                } else if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    rate_limited_failure(retry_after_secs(response.headers()))
+                } else if matches!(status.as_u16(), 502..=504) {
+                    servers::mark_unhealthy(app, &server);
+                    last_failure = Some(ConversionResult::failure(format!("API returned status: {}", status)));
+                    continue;
                 } else {
-                    "MALE"
-                };
-                
-                Voice {
-                    name: format!("{}-Chirp3-HD-{}", language_code, voice_name),
-                    language_code: language_code.clone(),
-                    ssml_gender: gender.to_string(),
-                    display_name: Some(format!("{} (HD)", voice_name)),
+                    match response.text().await {
+                        Ok(error_text) => ConversionResult::failure(format!("API error: {}", error_text)),
+                        Err(_) => ConversionResult::failure(format!("API returned status: {}", status)),
+                    }
+                }
+            }
+            Err(e) => {
+                servers::mark_unhealthy(app, &server);
+                last_failure = Some(ConversionResult::failure(format!(
+                    "{}: {}. Make sure the server is running with 'uv run kiwi server'",
+                    SERVER_UNREACHABLE, e
+                )));
+                continue;
+            }
+        };
+        result.server = Some(server);
+        return Ok(result);
+    }
+
+    Ok(last_failure.unwrap_or_else(|| ConversionResult::failure("No API server is configured".to_string())))
+}
+
+/// The address of a download the server named. Absolute URLs, e.g. to a
+/// CDN, are used as they are; relative ones are appended to the server's
+/// base URL, keeping any path it has, whether or not they start with `/`.
+fn resolve_download_url(server: &str, download_url: &str) -> String {
+    let download_url = download_url.trim();
+    match reqwest::Url::parse(download_url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url.to_string(),
+        _ => format!("{}/{}", server.trim_end_matches('/'), download_url.trim_start_matches('/')),
+    }
+}
+
+/// Downloads a generated audio file given the URL from a synthesis result.
+async fn download_audio(client: &reqwest::Client, server: &str, download_url: &str) -> Result<Vec<u8>, String> {
+    let full_download_url = resolve_download_url(server, download_url);
+
+    let response = client
+        .get(&full_download_url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download audio file: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Download failed with status: {}", response.status()));
+    }
+
+    response
+        .bytes()
+        .await
+        .map(|bytes| bytes.to_vec())
+        .map_err(|e| format!("Failed to read audio data: {}", e))
+}
+
+/// Streams a generated audio file to `output_path`. Data goes to a `.part`
+/// file that only replaces the destination once the transfer is complete
+/// (checked against `Content-Length` when the server sends it), so an
+/// interrupted download never leaves a truncated file that looks valid.
+async fn download_to_file(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    server: &str,
+    download_url: &str,
+    output_path: &str,
+    job: Option<&str>,
+    cancel: &tokio_util::sync::CancellationToken,
+) -> Result<u64, String> {
+    use std::io::Write;
+
+    let full_download_url = resolve_download_url(server, download_url);
+    let mut response = client
+        .get(&full_download_url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download audio file: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Download failed with status: {}", response.status()));
+    }
+
+    let expected = response.content_length();
+    let output_path = std::path::Path::new(output_path);
+    if let Some(expected) = expected {
+        paths::check_free_space(output_path, expected)?;
+    }
+    let part = paths::part_path(output_path);
+    let chunk_bytes = settings::current(app).download_chunk_bytes.clamp(4 * 1024, 16 * 1024 * 1024);
+    let file = std::fs::File::create(&part).map_err(|e| paths::save_error(&part, e))?;
+    let mut file = std::io::BufWriter::with_capacity(chunk_bytes, file);
+    shutdown::track_temp_file(app, &part);
+
+    let (mut received, mut reported): (u64, u64) = (0, 0);
+    let mut throttle = events::Throttle::default();
+    let percent = |received: u64| expected.filter(|&e| e > 0).map(|e| (received * 100 / e) as f64);
+    events::emit(app, job, JobEvent::Downloading { percent: percent(0) });
+    throttle.ready(std::time::Instant::now());
+    let progress = |received: u64| match expected {
+        Some(expected) => format!("received {} of {} bytes", received, expected),
+        None => format!("received {} bytes", received),
+    };
+
+    let transfer = loop {
+        let chunk = tokio::select! {
+            _ = cancel.cancelled() => break Err("Conversion cancelled".to_string()),
+            chunk = response.chunk() => chunk,
+        };
+        match chunk {
+            Ok(Some(chunk)) => {
+                if let Err(e) = file.write_all(&chunk) {
+                    break Err(paths::save_error(output_path, e));
+                }
+                received += chunk.len() as u64;
+                let due = received - reported >= chunk_bytes as u64 && percent(received) != percent(reported);
+                if due && throttle.ready(std::time::Instant::now()) {
+                    events::emit(app, job, JobEvent::Downloading { percent: percent(received) });
+                    reported = received;
                 }
-            }).collect();
-            
-            println!("API server not available ({}), using fallback voices", e);
-            Ok(voices)
+            }
+            Ok(None) => {
+                // The last update may have been held back
+                if percent(received) != percent(reported) {
+                    events::emit(app, job, JobEvent::Downloading { percent: percent(received) });
+                }
+                break Ok(());
+            }
+            Err(e) => break Err(format!("Download incomplete ({}): {}", progress(received), e)),
+        }
+    };
+
+    let finished = transfer
+        .and_then(|_| match expected {
+            Some(expected) if received != expected => Err(format!("Download incomplete: {}", progress(received))),
+            _ => Ok(()),
+        })
+        .and_then(|_| {
+            file.into_inner()
+                .map_err(|e| e.into_error())
+                .and_then(|file| file.sync_all())
+                .map_err(|e| paths::save_error(output_path, e))
+        });
+
+    let finished = finished
+        .and_then(|_| std::fs::rename(&part, output_path).map_err(|e| format!("Failed to save audio file: {}", e)));
+    if finished.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+    shutdown::untrack_temp_file(app, &part);
+    finished?;
+
+    Ok(received)
+}
+
+/// With `verify_audio` on, fails for audio that won't play and deletes it.
+/// Servers occasionally answer 200 with an empty or broken body.
+fn verify_download(app: &AppHandle, path: &std::path::Path) -> Result<(), String> {
+    if !settings::current(app).verify_audio {
+        return Ok(());
+    }
+    let verified = std::fs::read(path)
+        .map_err(|e| format!("Failed to read audio file: {}", e))
+        .and_then(|bytes| audio::verify(&bytes).map_err(|e| format!("Server returned invalid audio: {}", e)));
+    if verified.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    verified
+}
+
+/// Synthesizes `request` and returns the encoded audio without writing it anywhere.
+async fn synthesize_bytes(app: &AppHandle, client: &reqwest::Client, request: &TTSRequest) -> Result<Vec<u8>, String> {
+    let result = request_synthesis(app, client, request).await?;
+    if !result.success {
+        return Err(result.error.unwrap_or_else(|| "Synthesis failed".to_string()));
+    }
+
+    let download_url = result
+        .download_url
+        .ok_or("API response did not include a download URL")?;
+    let server = result.server.unwrap_or_else(|| API_BASE_URL.to_string());
+    let bytes = download_audio(client, &server, &download_url).await?;
+    if settings::current(app).verify_audio {
+        audio::verify(&bytes).map_err(|e| format!("Server returned invalid audio: {}", e))?;
+    }
+    Ok(bytes)
+}
+
+/// Synthesizes text of any length by splitting it into request-sized chunks
+/// and stitching the resulting audio back together. Up to
+/// `chunk_download_concurrency` chunks are in flight at once; parts are
+/// joined in text order however they finish. For a `job`, `synthesizing`
+/// events count finished chunks.
+async fn synthesize_long(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    request: &TTSRequest,
+    job: Option<&str>,
+) -> Result<Vec<u8>, String> {
+    let max_bytes = capabilities::get(app).await.max_request_bytes;
+    let chunks = text::split_text(&request.text, max_bytes);
+    if chunks.is_empty() {
+        return Err("Text is empty".to_string());
+    }
+
+    let total = chunks.len();
+    events::emit(app, job, JobEvent::Chunking { total });
+    let downloads = chunks.into_iter().map(|chunk| {
+        let chunk_request = TTSRequest {
+            text: chunk,
+            ..request.clone()
+        };
+        async move { synthesize_bytes(app, client, &chunk_request).await }
+    });
+    let limit = settings::current(app).chunk_download_concurrency;
+    let mut parts = run_limited(downloads, limit, |chunk| {
+        events::emit(app, job, JobEvent::Synthesizing { chunk, total, percent: None });
+    })
+    .await?;
+
+    if parts.len() == 1 {
+        return Ok(parts.remove(0));
+    }
+    audio::concat_audio(&parts, &[])
+}
+
+/// Runs `tasks` with at most `limit` (at least 1) in progress at once and
+/// returns their results in input order, stopping at the first failure.
+/// `on_done(n)` is called as the `n`th task to finish completes.
+async fn run_limited<T, F>(
+    tasks: impl IntoIterator<Item = F>,
+    limit: usize,
+    on_done: impl Fn(usize),
+) -> Result<Vec<T>, String>
+where
+    F: std::future::Future<Output = Result<T, String>>,
+{
+    let limit = tokio::sync::Semaphore::new(limit.max(1));
+    let completed = std::sync::atomic::AtomicUsize::new(0);
+    let (limit, completed, on_done) = (&limit, &completed, &on_done);
+    let tasks = tasks.into_iter().map(|task| async move {
+        let _permit = limit.acquire().await.map_err(|e| format!("Download queue closed: {}", e))?;
+        let result = task.await?;
+        on_done(completed.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1);
+        Ok::<_, String>(result)
+    });
+    // try_join_all keeps results in input order and stops at the first failure
+    futures_util::future::try_join_all(tasks).await
+}
+
+/// `<output_stem>.raw.<extension>` beside the audio file.
+fn raw_audio_path(audio_path: &str, extension: &str) -> std::path::PathBuf {
+    std::path::Path::new(audio_path).with_extension(format!("raw.{}", extension))
+}
+
+/// Copies the audio at `audio_path` to its raw path when `keep` is set and
+/// returns that path. Otherwise removes a raw copy left there by an earlier
+/// run that kept it, since it no longer matches the output; `kept_before`
+/// tells whether the file is one, so a file of that name KIWI didn't write
+/// is left alone.
+fn keep_raw_audio(
+    audio_path: &str,
+    extension: &str,
+    keep: bool,
+    kept_before: impl FnOnce(&std::path::Path) -> bool,
+) -> Result<Option<String>, String> {
+    let path = raw_audio_path(audio_path, extension);
+    if !keep {
+        if kept_before(&path) {
+            let _ = std::fs::remove_file(&path);
+        }
+        return Ok(None);
+    }
+    std::fs::copy(audio_path, &path).map_err(|e| format!("Failed to keep the raw audio {}: {}", path.display(), e))?;
+    Ok(Some(path.to_string_lossy().to_string()))
+}
+
+/// Writes `text` to `<output_stem>.txt` beside the audio file and returns its path.
+fn write_transcript(audio_path: &str, text: &str) -> Result<String, String> {
+    let path = std::path::Path::new(audio_path).with_extension("txt");
+    std::fs::write(&path, text).map_err(|e| format!("Failed to save transcript {}: {}", path.display(), e))?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Status of a server-side synthesis job, from `GET /synthesize/status/{job_id}`:
+///
+/// ```json
+/// { "status": "processing", "stage": "synthesizing", "percent": 42.5, "download_url": null, "error": null }
+/// ```
+///
+/// `status` is one of `queued`, `processing`, `done` or `error`. `stage` and
+/// `percent` are optional; `percent` is passed on in `synthesizing` events
+/// and `stage` is only informational. `download_url` is set once the job is
+/// `done`, `error` once it has failed.
+#[derive(Debug, Deserialize)]
+struct JobStatus {
+    status: String,
+    #[serde(default)]
+    percent: Option<f64>,
+    #[serde(default)]
+    download_url: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Polls an asynchronous synthesis job until it finishes, emitting a
+/// `synthesizing` event for `job` per poll, and returns its download URL.
+async fn poll_synthesis(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    server: &str,
+    job_id: &str,
+    job: Option<&str>,
+    cancel: &tokio_util::sync::CancellationToken,
+) -> Result<String, String> {
+    let url = format!("{}/synthesize/status/{}", server, job_id);
+    let interval = std::time::Duration::from_millis(settings::current(app).status_poll_interval_ms.max(100));
+
+    loop {
+        let response = client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to check synthesis status: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Status check failed with status: {}", response.status()));
+        }
+        let status = response
+            .json::<JobStatus>()
+            .await
+            .map_err(|e| format!("Failed to parse synthesis status: {}", e))?;
+
+        events::emit(
+            app,
+            job,
+            JobEvent::Synthesizing {
+                chunk: 1,
+                total: 1,
+                percent: status.percent,
+            },
+        );
+
+        match status.status.as_str() {
+            "done" => return status.download_url.ok_or_else(|| "Finished job has no download URL".to_string()),
+            "error" => return Err(status.error.unwrap_or_else(|| "Synthesis failed".to_string())),
+            _ => {}
+        }
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Err("Conversion cancelled".to_string()),
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Runs the normal HTTP synthesis and downloads the result to `output_path`.
+/// Servers that answer with a `job_id` instead of a file are polled until
+/// the job is done; ones that return the file right away are used as is.
+async fn convert_online(
+    app: &AppHandle,
+    request_body: &TTSRequest,
+    output_path: String,
+    verbose: bool,
+    job: Option<&str>,
+    cancel: &tokio_util::sync::CancellationToken,
+) -> Result<ConversionResult, String> {
+    let client = client::build(app);
+
+    if verbose {
+        println!("Sending TTS request to API server...");
+    }
+    events::emit(
+        app,
+        job,
+        JobEvent::Synthesizing {
+            chunk: 1,
+            total: 1,
+            percent: None,
+        },
+    );
+    
+    let mut result = tokio::select! {
+        _ = cancel.cancelled() => return Err("Conversion cancelled".to_string()),
+        result = request_synthesis(app, &client, request_body) => result?,
+    };
+    let server = result.server.clone().unwrap_or_else(|| API_BASE_URL.to_string());
+    if verbose && result.server.is_some() {
+        println!("Request served by {}", server);
+    }
+
+    if let (true, None, Some(job_id)) = (result.success, &result.download_url, &result.job_id) {
+        match poll_synthesis(app, &client, &server, job_id, job, cancel).await {
+            Ok(download_url) => result.download_url = Some(download_url),
+            Err(e) => {
+                result.success = false;
+                result.error = Some(e);
+                return Ok(result);
+            }
         }
     }
+
+    // Download the file if API returned a download URL
+    let download_url = match (result.success, &result.download_url) {
+        (true, Some(download_url)) => download_url.clone(),
+        _ => return Ok(result),
+    };
+
+    let downloaded = download_to_file(app, &client, &server, &download_url, &output_path, job, cancel)
+        .await
+        .and_then(|_| verify_download(app, std::path::Path::new(&output_path)));
+    match downloaded {
+        Ok(_) => {
+            if verbose {
+                println!("Audio file downloaded successfully");
+            }
+            result.output_path = Some(output_path);
+        }
+        Err(e) => {
+            result.success = false;
+            result.error = Some(e);
+        }
+    }
+
+    Ok(result)
+}
+
+/// Where the local engine saves audio asked for as `format`. It only
+/// produces WAV, so other formats get a `.wav` extension instead.
+fn offline_output_path(output_path: &str, format: &str) -> std::path::PathBuf {
+    let mut path = std::path::PathBuf::from(output_path);
+    if audio::file_extension(format) != "wav" {
+        path.set_extension("wav");
+    }
+    path
+}
+
+/// Synthesizes with the local engine. Local engines only produce WAV, so
+/// other formats are saved with a `.wav` extension and a warning.
+async fn convert_offline(request_body: &TTSRequest, output_path: String, verbose: bool) -> ConversionResult {
+    let start = std::time::Instant::now();
+    let mut warnings = Vec::new();
+
+    let path = offline_output_path(&output_path, &request_body.format);
+    if audio::file_extension(&request_body.format) != "wav" {
+        warnings.push(format!(
+            "Offline mode only produces WAV audio; saved as {} instead of {}",
+            path.display(),
+            request_body.format
+        ));
+    }
+
+    if verbose {
+        println!("Offline mode: synthesizing with the local speech engine...");
+    }
+
+    let (text, voice, target) = (request_body.text.clone(), request_body.voice.clone(), path.clone());
+    let outcome = tauri::async_runtime::spawn_blocking(move || offline::synthesize_to_file(&text, &voice, &target))
+        .await
+        .unwrap_or_else(|e| Err(format!("Local speech engine crashed: {}", e)));
+
+    let mut result = match outcome {
+        Ok(()) => ConversionResult {
+            success: true,
+            file_size: std::fs::metadata(&path).ok().map(|m| format_file_size(m.len() as usize)),
+            output_path: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        },
+        Err(e) => ConversionResult::failure(e),
+    };
+    result.processing_time = Some(format!("{:.2}s", start.elapsed().as_secs_f64()));
+    result.offline = true;
+    result.warnings = warnings;
+    result
+}
+
+/// Rejects sample rates above what the voice produces natively, when the
+/// server reports it; upsampling only adds size, not quality.
+async fn check_pcm_rate(app: &AppHandle, request: &TTSRequest, sample_rate: u32) -> Result<(), String> {
+    let voices = fetch_voices(app, &request.language).await?;
+    let natural = voices
+        .iter()
+        .find(|voice| voice.name == request.voice)
+        .and_then(|voice| voice.natural_sample_rate_hz);
+    match natural {
+        Some(natural) if sample_rate > natural => Err(format!(
+            "Voice '{}' produces at most {} Hz; requested {} Hz",
+            request.voice, natural, sample_rate
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Rejects a format the voice doesn't list among its `supported_formats`.
+/// Voices the server reports nothing about, or an unreachable voice list,
+/// pass; the server has the final say then.
+async fn check_voice_format(app: &AppHandle, request: &TTSRequest) -> Result<(), String> {
+    match fetch_voices(app, &request.language).await {
+        Ok(voices) => check_format_in(&voices, request),
+        Err(_) => Ok(()),
+    }
+}
+
+/// `check_voice_format` against an already fetched voice list.
+fn check_format_in(voices: &[Voice], request: &TTSRequest) -> Result<(), String> {
+    let Some(voice) = voices.iter().find(|voice| voice.name == request.voice) else {
+        return Ok(());
+    };
+    let format = audio::normalize_format(&request.format).unwrap_or(&request.format);
+    let supports = |format: &str| voice.supported_formats.iter().any(|f| f.eq_ignore_ascii_case(format));
+    // M4A can be encoded locally from the voice's PCM
+    if voice.supported_formats.is_empty() || supports(format) || (format == "M4A" && supports("LINEAR16")) {
+        return Ok(());
+    }
+    Err(format!(
+        "Voice '{}' can't produce {} audio; it supports {}",
+        request.voice,
+        format,
+        voice.supported_formats.join(", ")
+    ))
 }
 
+/// The formats in `audio::FORMATS` both the server and the voice offer. M4A
+/// counts wherever LINEAR16 does, since it is encoded locally from PCM, and a
+/// voice without a `supported_formats` list leaves the server's unnarrowed.
+fn usable_formats(server_formats: &[String], voice_formats: &[String]) -> Vec<String> {
+    let offers = |formats: &[String], format: &str| formats.iter().any(|f| f.eq_ignore_ascii_case(format));
+    let usable = |format: &str| {
+        offers(server_formats, format) && (voice_formats.is_empty() || offers(voice_formats, format))
+    };
+    audio::FORMATS
+        .iter()
+        .filter(|format| usable(format) || (**format == "M4A" && usable("LINEAR16")))
+        .map(|format| format.to_string())
+        .collect()
+}
+
+/// Formats a conversion with `voice` can be asked for right now, for the
+/// format picker. The capabilities and voice list behind it are cached, so
+/// this only contacts the server when they have expired. Offline, the local
+/// engine only writes WAV.
+#[command]
+async fn get_supported_formats(app: AppHandle, voice: String, language: String) -> Vec<String> {
+    if settings::current(&app).offline_mode {
+        return vec!["LINEAR16".to_string()];
+    }
+    let capabilities = capabilities::get(&app).await;
+    let voices = fetch_voices_with_source(&app, &language).await.voices;
+    let voice_formats = voices
+        .iter()
+        .find(|v| v.name == voice)
+        .map(|v| v.supported_formats.clone())
+        .unwrap_or_default();
+    usable_formats(&capabilities.formats, &voice_formats)
+}
+
+/// Rejects anything but mono or stereo, and warns when the voice is known to
+/// be mono but stereo was asked for, since both channels will be the same.
+async fn check_channels(app: &AppHandle, request: &TTSRequest) -> Result<Option<String>, String> {
+    // Only a stereo request needs to know the voice's own channels
+    let voices = match request.channels {
+        Some(2) => fetch_voices(app, &request.language).await.unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    check_channels_in(&voices, request)
+}
+
+/// `check_channels` against an already fetched voice list.
+fn check_channels_in(voices: &[Voice], request: &TTSRequest) -> Result<Option<String>, String> {
+    let Some(channels) = request.channels else {
+        return Ok(None);
+    };
+    if !(1..=2).contains(&channels) {
+        return Err(format!("Unsupported channel count {}; use 1 (mono) or 2 (stereo)", channels));
+    }
+    let natural = voices
+        .iter()
+        .find(|voice| voice.name == request.voice)
+        .and_then(|voice| voice.natural_channels);
+    Ok(match natural {
+        Some(natural) if channels > natural => Some(format!(
+            "Voice '{}' is mono; stereo output repeats the same channel and only adds size",
+            request.voice
+        )),
+        _ => None,
+    })
+}
+
+/// Rejects a speaking style the voice doesn't list, naming the ones it
+/// has. As with formats, an unknown voice or unreachable voice list passes.
+async fn check_speaking_style(app: &AppHandle, request: &TTSRequest) -> Result<(), String> {
+    if request.speaking_style.is_none() {
+        return Ok(());
+    }
+    match fetch_voices(app, &request.language).await {
+        Ok(voices) => check_style_in(&voices, request),
+        Err(_) => Ok(()),
+    }
+}
+
+/// `check_speaking_style` against an already fetched voice list.
+fn check_style_in(voices: &[Voice], request: &TTSRequest) -> Result<(), String> {
+    let Some(style) = &request.speaking_style else {
+        return Ok(());
+    };
+    let Some(voice) = voices.iter().find(|voice| voice.name == request.voice) else {
+        return Ok(());
+    };
+    if voice.speaking_styles.iter().any(|s| s.eq_ignore_ascii_case(style)) {
+        return Ok(());
+    }
+    if voice.speaking_styles.is_empty() {
+        return Err(format!("Voice '{}' has no speaking styles", request.voice));
+    }
+    Err(format!(
+        "Voice '{}' has no '{}' speaking style; it offers {}",
+        request.voice,
+        style,
+        voice.speaking_styles.join(", ")
+    ))
+}
+
+/// Fails unless the request's effects profile is one Google defines and the
+/// server offers.
+async fn check_effects_profile(app: &AppHandle, request: &TTSRequest) -> Result<(), String> {
+    if request.effects_profile_id.is_none() {
+        return Ok(());
+    }
+    check_effects_profile_in(&capabilities::get(app).await.effects_profiles, request)
+}
+
+/// `check_effects_profile` against the profiles the server `offered`.
+fn check_effects_profile_in(offered: &[String], request: &TTSRequest) -> Result<(), String> {
+    let Some(profile) = &request.effects_profile_id else {
+        return Ok(());
+    };
+    if !capabilities::EFFECTS_PROFILES.contains(&profile.as_str()) {
+        return Err(format!(
+            "Unknown effects profile '{}'; use {}",
+            profile,
+            capabilities::EFFECTS_PROFILES.join(", ")
+        ));
+    }
+    if offered.iter().any(|p| p.eq_ignore_ascii_case(profile)) {
+        return Ok(());
+    }
+    if offered.is_empty() {
+        return Err("The server does not apply effects profiles".to_string());
+    }
+    Err(format!("The server does not offer the '{}' effects profile; it offers {}", profile, offered.join(", ")))
+}
+
+/// Fails unless a file can be created in the folder that will hold `output_path`.
+fn check_output_writable(output_path: &str) -> Result<(), String> {
+    let path = std::path::Path::new(output_path);
+    if path.file_name().is_none() {
+        return Err(format!("Output path has no file name: {}", output_path));
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Err(format!("Output folder does not exist: {}", dir.display()));
+    }
+
+    let probe = dir.join(format!(".kiwi-write-test-{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|e| format!("Output folder is not writable: {} ({})", dir.display(), e))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Rejects text that is blank or longer than `max_chars` characters (0
+/// disabling the limit).
+fn check_input_text(text: &str, max_chars: usize) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("Text is empty".to_string());
+    }
+    let chars = text.chars().count();
+    if max_chars > 0 && chars > max_chars {
+        return Err(format!(
+            "Text is {} characters, over the {} character limit; convert long documents as a batch or by chapter instead",
+            chars, max_chars
+        ));
+    }
+    Ok(())
+}
+
+/// How many times to play the audio and the silence between repetitions,
+/// with the defaults filled in.
+fn repeat_settings(options: &ConversionOptions) -> Result<(u32, u64), String> {
+    let repeat_count = options.repeat_count.unwrap_or(1);
+    let repeat_gap_ms = options.repeat_gap_ms.unwrap_or(DEFAULT_REPEAT_GAP_MS);
+    if !(1..=MAX_REPEAT_COUNT).contains(&repeat_count) {
+        return Err(format!("Repeat the audio between 1 and {} times", MAX_REPEAT_COUNT));
+    }
+    if repeat_gap_ms > MAX_REPEAT_GAP_MS {
+        return Err(format!("The gap between repetitions can be at most {} ms", MAX_REPEAT_GAP_MS));
+    }
+    Ok((repeat_count, repeat_gap_ms))
+}
+
+/// Replaces the audio at `path` with `count` copies of itself `gap_ms`
+/// apart, returning the new size.
+fn repeat_file(path: &std::path::Path, count: u32, gap_ms: u64) -> Result<usize, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read audio file: {}", e))?;
+    let parts = vec![bytes; count.max(1) as usize];
+    let joined = audio::concat_audio(&parts, &vec![gap_ms; parts.len() - 1])?;
+    paths::write_audio(path, &joined)?;
+    Ok(joined.len())
+}
+
+/// Fails unless the server can take `request_body` as one request: a format
+/// it offers, text within its request size and a voice with a language.
+/// Dry runs and real conversions both check this before anything is sent,
+/// so a dry run fails exactly when the conversion would.
+fn check_request(
+    request_body: &TTSRequest,
+    offline: bool,
+    capabilities: &capabilities::Capabilities,
+) -> Result<(), String> {
+    let format = audio::normalize_format(&request_body.format)?;
+    if offline {
+        return Ok(());
+    }
+    // M4A is encoded locally when the server can't produce it
+    if !capabilities.supports_format(format) && format != "M4A" {
+        return Err(format!("The server does not offer {} output", format));
+    }
+
+    if request_body.text.len() > capabilities.max_request_bytes {
+        return Err(format!(
+            "Text is {} bytes; a single request is limited to {} bytes",
+            request_body.text.len(),
+            capabilities.max_request_bytes
+        ));
+    }
+
+    // Offline voices are engine-specific names without a locale prefix
+    if text::language_from_voice(&request_body.voice).is_none() {
+        return Err(format!("Cannot determine language of voice '{}'", request_body.voice));
+    }
+    Ok(())
+}
+
+/// Describes the file a conversion that passed `check_request` would
+/// produce, without contacting the server.
+fn dry_run(request_body: &TTSRequest, output_path: String, offline: bool) -> Result<ConversionResult, String> {
+    let format = audio::normalize_format(&request_body.format)?;
+
+    // The same path the conversion would write; only offline audio moves
+    let output_path = if offline {
+        offline_output_path(&output_path, format).to_string_lossy().to_string()
+    } else {
+        output_path
+    };
+    let format = if offline { "LINEAR16" } else { format };
+    check_output_writable(&output_path)?;
+
+    let seconds = text::spoken_seconds(text::word_count(&request_body.text));
+    Ok(ConversionResult {
+        success: true,
+        output_path: Some(output_path),
+        file_size: Some(format_file_size(audio::estimated_size(format, seconds, None))),
+        offline,
+        dry_run: true,
+        estimated_duration_seconds: Some(seconds),
+        ..Default::default()
+    })
+}
+
+/// Converts `text` to an audio file, reporting its progress as `job-event`s
+/// (see `events`) under the `conversion_id` from `options` or a generated
+/// id, which the result carries either way. Dry runs report nothing.
 #[command]
 async fn convert_text_to_speech(
+    app: AppHandle,
     text: String,
-    voice: String,
-    format: String,
+    voice: Option<String>,
+    format: Option<String>,
     output_path: String,
     verbose: bool,
+    options: Option<ConversionOptions>,
 ) -> Result<ConversionResult, String> {
-    let client = reqwest::Client::new();
-    let url = format!("{}/synthesize", API_BASE_URL);
-    
-    let request_body = TTSRequest {
-        text,
+    let mut options = options.unwrap_or_default();
+    let conversion_id = options.conversion_id.get_or_insert_with(events::new_job_id).clone();
+    let job = (!options.dry_run).then_some(conversion_id.as_str());
+    events::emit(&app, job, JobEvent::Queued);
+
+    let result = run_conversion(app.clone(), text, voice, format, output_path, verbose, options).await;
+    match &result {
+        Ok(result) if result.success => {
+            let path = result.output_path.clone().unwrap_or_default();
+            events::emit(&app, job, JobEvent::Completed { path });
+        }
+        Ok(result) => {
+            let error = result.error.clone().unwrap_or_else(|| "Conversion failed".to_string());
+            events::emit(&app, job, JobEvent::Failed { error });
+        }
+        Err(error) => events::emit(&app, job, JobEvent::Failed { error: error.clone() }),
+    }
+    result.map(|result| ConversionResult {
+        conversion_id: Some(conversion_id),
+        ..result
+    })
+}
+
+async fn run_conversion(
+    app: AppHandle,
+    text: String,
+    voice: Option<String>,
+    format: Option<String>,
+    output_path: String,
+    verbose: bool,
+    mut options: ConversionOptions,
+) -> Result<ConversionResult, String> {
+    let job = options.conversion_id.clone().filter(|_| !options.dry_run);
+    let job = job.as_deref();
+    events::emit(&app, job, JobEvent::Normalizing);
+    let raw_text = options.raw_transcript.then(|| text.clone());
+    let output_path = paths::resolve_output_path(&output_path, settings::current(&app).default_output_dir.as_deref())?;
+
+    // Catch empty input and accidental huge pastes before any network call
+    check_input_text(&text, settings::current(&app).max_text_chars)?;
+    if !settings::current(&app).allow_symbol_only_text && !text::has_speakable_content(&text) {
+        return Err("Nothing to speak: the text has no letters or digits".to_string());
+    }
+
+    // Explicit arguments win over the preset, which wins over the app defaults
+    let requested = presets::Preset {
         voice,
-        format: format.clone(),
-        language: "en-US".to_string(),
+        format,
+        language: options.language.clone(),
+        speaking_rate: options.speaking_rate,
+        pitch: options.pitch,
+        volume_gain_db: options.volume_gain_db,
+        save_transcript: options.save_transcript.then_some(true),
+        bitrate_kbps: options.bitrate_kbps,
+        speaking_style: options.speaking_style.clone(),
+        effects_profile_id: options.effects_profile_id.clone(),
+        channels: options.channels,
+        repeat_count: options.repeat_count,
+        repeat_gap_ms: options.repeat_gap_ms,
+        check_clipping: options.check_clipping.then_some(true),
     };
-    
-    if verbose {
-        println!("Sending TTS request to API server...");
+    let preset = match &options.preset {
+        Some(name) => requested.or(presets::get(&app, name)?),
+        None => requested,
+    };
+    let resolved = preset.resolve(&app);
+    options.save_transcript = resolved.save_transcript.unwrap_or(false);
+    options.check_clipping = resolved.check_clipping.unwrap_or(false);
+    options.bitrate_kbps = resolved.bitrate_kbps;
+    options.speaking_style = resolved.speaking_style.clone();
+    options.effects_profile_id = resolved.effects_profile_id.clone();
+    options.channels = resolved.channels;
+    options.repeat_count = resolved.repeat_count;
+    options.repeat_gap_ms = resolved.repeat_gap_ms;
+
+    let mut request_body = TTSRequest {
+        text,
+        voice: resolved.voice.clone().unwrap_or_default(),
+        format: resolved.format.clone().unwrap_or_default(),
+        language: resolved.language.clone().unwrap_or_default(),
+        speaking_rate: resolved.speaking_rate,
+        pitch: resolved.pitch,
+        volume_gain_db: resolved.volume_gain_db,
+        ..Default::default()
+    };
+    let resolved_preset = options.preset.is_some().then_some(resolved);
+
+    let settings = settings::current(&app);
+    let offline = settings.offline_mode;
+    request_body.language =
+        text::reconcile_language(&request_body.voice, &request_body.language, settings.strict_language)?;
+
+    match options.input_type {
+        ssml::InputType::Text => {}
+        ssml::InputType::Ssml => request_body.ssml = true,
+        ssml::InputType::Markup => {
+            request_body.text = ssml::markup_to_ssml(&request_body.text);
+            request_body.ssml = true;
+        }
     }
-    
-    match client.post(&url).json(&request_body).send().await {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<ConversionResult>().await {
-                    Ok(mut result) => {
-                        // Download the file if API returned a download URL
-                        if result.success && result.download_url.is_some() {
-                            let download_url = result.download_url.as_ref().unwrap();
-                            let full_download_url = format!("{}{}", API_BASE_URL, download_url);
-                            
-                            match client.get(&full_download_url).send().await {
-                                Ok(download_response) => {
-                                    if download_response.status().is_success() {
-                                        match download_response.bytes().await {
-                                            Ok(bytes) => {
-                                                match std::fs::write(&output_path, bytes) {
-                                                    Ok(_) => {
-                                                        result.output_path = Some(output_path);
-                                                        if verbose {
-                                                            println!("Audio file downloaded successfully");
-                                                        }
-                                                        Ok(result)
-                                                    }
-                                                    Err(e) => {
-                                                        result.success = false;
-                                                        result.error = Some(format!("Failed to save audio file: {}", e));
-                                                        Ok(result)
-                                                    }
-                                                }
-                                            }
-                                            Err(e) => {
-                                                result.success = false;
-                                                result.error = Some(format!("Failed to read audio data: {}", e));
-                                                Ok(result)
-                                            }
-                                        }
-                                    } else {
-                                        result.success = false;
-                                        result.error = Some(format!("Download failed with status: {}", download_response.status()));
-                                        Ok(result)
-                                    }
-                                }
-                                Err(e) => {
-                                    result.success = false;
-                                    result.error = Some(format!("Failed to download audio file: {}", e));
-                                    Ok(result)
-                                }
-                            }
-                        } else {
-                            Ok(result)
-                        }
-                    }
-                    Err(e) => Err(format!("Failed to parse TTS response: {}", e)),
+    if request_body.ssml && offline {
+        return Err("SSML needs the API server; the local speech engine reads only plain text".to_string());
+    }
+    if request_body.ssml && !capabilities::get(&app).await.ssml {
+        return Err(format!("The server does not accept SSML, which marked-up text is also sent as; {}", ssml::NEEDS_SSML_SERVER));
+    }
+
+    let mut output_path = output_path;
+    if let Some(pcm) = &options.pcm {
+        if offline {
+            return Err("Custom PCM output is not available in offline mode".to_string());
+        }
+        pcm.validate()?;
+        check_pcm_rate(&app, &request_body, pcm.sample_rate).await?;
+        request_body.format = "LINEAR16".to_string();
+        request_body.sample_rate = Some(pcm.sample_rate);
+        output_path = std::path::Path::new(&output_path)
+            .with_extension(pcm.extension())
+            .to_string_lossy()
+            .to_string();
+    }
+    request_body.speaking_style = options
+        .speaking_style
+        .as_deref()
+        .map(str::trim)
+        .filter(|style| !style.is_empty())
+        .map(str::to_string);
+    if offline && request_body.speaking_style.is_some() {
+        return Err("Speaking styles are not available in offline mode".to_string());
+    }
+    request_body.effects_profile_id = options
+        .effects_profile_id
+        .as_deref()
+        .map(|profile| profile.trim().to_ascii_lowercase())
+        .filter(|profile| !profile.is_empty());
+    if offline && request_body.effects_profile_id.is_some() {
+        return Err("Effects profiles are not available in offline mode".to_string());
+    }
+    request_body.channels = options.channels.map(u16::from);
+    if let (Some(pcm), Some(channels)) = (&options.pcm, request_body.channels) {
+        if pcm.channels != channels {
+            return Err(format!("The PCM output already asks for {} channels", pcm.channels));
+        }
+        // PCM encoding makes the channels itself from the server's mono
+        request_body.channels = None;
+    }
+    events::describe(&app, job, &request_body.voice, &output_path);
+    let channel_warning = check_channels(&app, &request_body).await?;
+    let (repeat_count, repeat_gap_ms) = repeat_settings(&options)?;
+    audio::check_bitrate(&request_body.format, options.bitrate_kbps)?;
+    if !offline {
+        check_voice_format(&app, &request_body).await?;
+        check_speaking_style(&app, &request_body).await?;
+        check_effects_profile(&app, &request_body).await?;
+    }
+
+    // Servers without AAC send PCM, which is encoded once it arrives
+    let encode_m4a = !offline
+        && audio::normalize_format(&request_body.format) == Ok("M4A")
+        && !capabilities::get(&app).await.supports_format("M4A");
+
+    let capabilities = if offline { capabilities::Capabilities::default() } else { capabilities::get(&app).await };
+    check_request(&request_body, offline, &capabilities)?;
+
+    if options.dry_run {
+        let sent = TTSRequest {
+            format: if encode_m4a { "LINEAR16".to_string() } else { request_body.format.clone() },
+            ..request_body.clone()
+        };
+        return dry_run(&request_body, output_path, offline).map(|result| ConversionResult {
+            resolved_preset,
+            content_hash: Some(cache::content_hash(&sent)),
+            ..result
+        });
+    }
+
+    if encode_m4a {
+        request_body.format = "LINEAR16".to_string();
+    }
+
+    events::emit(&app, job, JobEvent::Chunking { total: 1 });
+    let cached = (!offline && !options.bypass_cache)
+        .then(|| cache::restore(&app, &request_body, &output_path))
+        .flatten();
+
+    let mut result = if offline {
+        events::emit(
+            &app,
+            job,
+            JobEvent::Synthesizing {
+                chunk: 1,
+                total: 1,
+                percent: None,
+            },
+        );
+        convert_offline(&request_body, output_path, verbose).await
+    } else if let Some(cached) = cached {
+        cached
+    } else {
+        let registry = app.state::<jobs::JobRegistry>();
+        let cancel = match &options.conversion_id {
+            Some(id) => registry.register(id)?,
+            None => tokio_util::sync::CancellationToken::new(),
+        };
+        let mut result = convert_online(&app, &request_body, output_path.clone(), verbose, job, &cancel).await;
+
+        // Retry once with the closest available voice if the requested one is gone
+        let missing_voice = matches!(&result, Ok(r) if r.error.as_deref().is_some_and(voices::is_voice_missing));
+        if missing_voice {
+            // The cached list still names the voice the server just rejected
+            voices::invalidate(&app);
+        }
+        if missing_voice && settings.auto_substitute_voice {
+            let available = fetch_voices(&app, &request_body.language).await.unwrap_or_default();
+            if let Some(replacement) = voices::closest_voice(&request_body.voice, &available) {
+                if verbose {
+                    println!("Voice {} is unavailable, retrying with {}", request_body.voice, replacement.name);
                 }
-            } else {
-                let status = response.status();
-                match response.text().await {
-                    Ok(error_text) => Ok(ConversionResult {
-                        success: false,
-                        output_path: None,
-                        error: Some(format!("API error: {}", error_text)),
-                        file_size: None,
-                        processing_time: None,
-                        download_url: None,
-                    }),
-                    Err(_) => Ok(ConversionResult {
-                        success: false,
-                        output_path: None,
-                        error: Some(format!("API returned status: {}", status)),
-                        file_size: None,
-                        processing_time: None,
-                        download_url: None,
-                    })
+                request_body.voice = replacement.name;
+                events::describe(&app, job, &request_body.voice, &output_path);
+                result = convert_online(&app, &request_body, output_path, verbose, job, &cancel).await;
+                if let Ok(result) = &mut result {
+                    result.substituted_voice = Some(request_body.voice.clone());
                 }
             }
         }
-        Err(e) => Ok(ConversionResult {
-            success: false,
-            output_path: None,
-            error: Some(format!("Failed to connect to API server: {}. Make sure the server is running with 'uv run kiwi server'", e)),
-            file_size: None,
-            processing_time: None,
-            download_url: None,
-        })
+
+        if let Some(id) = &options.conversion_id {
+            registry.finish(id);
+        }
+        let result = result?;
+        if let (true, Some(output_path)) = (result.success, &result.output_path) {
+            cache::store(&app, &request_body, output_path);
+        }
+        result
+    };
+    if !offline && result.success {
+        result.content_hash = Some(cache::content_hash(&request_body));
+    }
+
+    // Offline audio is always WAV, whatever was asked for
+    let raw_extension = if offline { "wav" } else { audio::file_extension(&request_body.format) };
+    let mut raw_path = None;
+    if let (true, Some(output_path)) = (result.success, &result.output_path) {
+        match keep_raw_audio(output_path, raw_extension, options.keep_raw, |path| history::forget_raw_copy(&app, path)) {
+            Ok(path) => raw_path = path,
+            Err(e) => result.warnings.push(e),
+        }
+    }
+
+    if let (true, Some(output_path)) = (result.success && repeat_count > 1, &result.output_path) {
+        let step = "repeating".to_string();
+        events::emit(&app, job, JobEvent::PostProcessing { step });
+        match repeat_file(std::path::Path::new(output_path), repeat_count, repeat_gap_ms) {
+            Ok(len) => result.file_size = Some(format_file_size(len)),
+            Err(e) => {
+                let _ = std::fs::remove_file(output_path);
+                result = ConversionResult::failure(e);
+            }
+        }
+    }
+
+    if let (true, Some(pcm), Some(output_path)) = (result.success, &options.pcm, &result.output_path) {
+        let step = "encoding_pcm".to_string();
+        events::emit(&app, job, JobEvent::PostProcessing { step });
+        let encoded = std::fs::read(output_path)
+            .map_err(|e| format!("Failed to read audio file: {}", e))
+            .and_then(|bytes| pcm.encode(&bytes))
+            .and_then(|pcm_bytes| {
+                paths::write_audio(std::path::Path::new(output_path), &pcm_bytes).map(|_| pcm_bytes.len())
+            });
+        match encoded {
+            Ok(len) => result.file_size = Some(format_file_size(len)),
+            Err(e) => {
+                let _ = std::fs::remove_file(output_path);
+                result = ConversionResult::failure(e);
+            }
+        }
+    }
+
+    if let (true, Some(channels), Some(output_path)) = (result.success, request_body.channels, &result.output_path) {
+        let step = "mixing_channels".to_string();
+        events::emit(&app, job, JobEvent::PostProcessing { step });
+        let path = std::path::Path::new(output_path);
+        match edit::remix_channels(path, path, channels) {
+            Ok(len) => result.file_size = Some(format_file_size(len)),
+            Err(e) => {
+                let _ = std::fs::remove_file(output_path);
+                result = ConversionResult::failure(e);
+            }
+        }
+    }
+    if result.success {
+        result.warnings.extend(channel_warning);
     }
+
+    if let (true, true, Some(output_path)) = (result.success, encode_m4a, &result.output_path) {
+        let step = "encoding_m4a".to_string();
+        events::emit(&app, job, JobEvent::PostProcessing { step });
+        let encoded = std::fs::read(output_path)
+            .map_err(|e| format!("Failed to read audio file: {}", e))
+            .and_then(|bytes| audio::wav_to_m4a(&bytes))
+            .and_then(|m4a| paths::write_audio(std::path::Path::new(output_path), &m4a).map(|_| m4a.len()));
+        match encoded {
+            Ok(len) => result.file_size = Some(format_file_size(len)),
+            Err(e) => {
+                let _ = std::fs::remove_file(output_path);
+                result = ConversionResult::failure(e);
+            }
+        }
+    }
+
+    if let (true, true, Some(output_path)) = (result.success, options.check_clipping, &result.output_path) {
+        let step = "checking_clipping".to_string();
+        events::emit(&app, job, JobEvent::PostProcessing { step });
+        match audio::detect_clipping(std::path::Path::new(output_path)) {
+            Ok(report) if report.clipped_samples > 0 => result.warnings.push(format!(
+                "Audio may be distorted: {:.2}% of samples clip; reduce the volume gain",
+                report.clipped_percent
+            )),
+            Ok(_) => {}
+            Err(e) => result.warnings.push(format!("Could not check the audio for clipping: {}", e)),
+        }
+    }
+
+    // Reported even when processing failed, since that is when it helps most
+    result.raw_path = raw_path;
+
+    if let (true, true, Some(output_path)) = (result.success, options.save_transcript, &result.output_path) {
+        let step = "writing_transcript".to_string();
+        events::emit(&app, job, JobEvent::PostProcessing { step });
+        let transcript = raw_text.as_deref().unwrap_or(&request_body.text);
+        match write_transcript(output_path, transcript) {
+            Ok(path) => result.transcript_path = Some(path),
+            Err(e) => result.warnings.push(e),
+        }
+    }
+
+    if let (true, Some(output_path)) = (result.success, &result.output_path) {
+        history::record(
+            &app,
+            history::HistoryEntry {
+                speaking_rate: request_body.speaking_rate,
+                pitch: request_body.pitch,
+                volume_gain_db: request_body.volume_gain_db,
+                ssml: request_body.ssml,
+                raw_path: result.raw_path.clone(),
+                ..history::HistoryEntry::new(
+                    &request_body.text,
+                    &request_body.voice,
+                    if encode_m4a { "M4A" } else { &request_body.format },
+                    &request_body.language,
+                    output_path,
+                    result.file_size.clone(),
+                )
+            },
+        );
+    }
+
+    if let (true, Some(output_path)) = (result.success, &result.output_path) {
+        result.warnings.extend(reveal_on_complete(&app, output_path).await);
+    }
+
+    let status = match &result.error {
+        None if result.success => "Conversion complete",
+        Some(error) if error.starts_with(SERVER_UNREACHABLE) => "Server unreachable",
+        _ => "Conversion failed",
+    };
+    announce::announce(&app, status);
+    result.resolved_preset = resolved_preset;
+    Ok(result)
+}
+
+/// `convert_text_to_speech` for text the frontend saved to a file, so large
+/// documents don't have to travel through IPC. The encoding is detected
+/// unless `encoding` names it; see `text::read_text_file`.
+#[command]
+#[allow(clippy::too_many_arguments)]
+async fn convert_text_from_file(
+    app: AppHandle,
+    input_text_path: String,
+    voice: Option<String>,
+    format: Option<String>,
+    language: Option<String>,
+    output_path: String,
+    options: Option<ConversionOptions>,
+    encoding: Option<String>,
+) -> Result<ConversionResult, String> {
+    let input = std::path::Path::new(&input_text_path);
+    if !input.is_file() {
+        return Err(format!("Text file does not exist: {}", input_text_path));
+    }
+    let (text, text_encoding) = text::read_text_file(input, encoding.as_deref())?;
+
+    let mut options = options.unwrap_or_default();
+    if language.is_some() {
+        options.language = language;
+    }
+    let mut result = convert_text_to_speech(app, text, voice, format, output_path, false, Some(options)).await?;
+    result.text_encoding = Some(text_encoding.to_string());
+    Ok(result)
 }
 
 #[command]
@@ -238,8 +1689,25 @@ async fn open_file_path(path: String) -> Result<(), String> {
     Ok(())
 }
 
+/// Shows a finished file in the file manager when `auto_reveal_on_complete`
+/// is on, except during quiet hours. Returns a warning when that fails,
+/// since the conversion itself succeeded.
+async fn reveal_on_complete(app: &AppHandle, path: &str) -> Option<String> {
+    let settings = settings::current(app);
+    if !settings.auto_reveal_on_complete || announce::is_quiet_now(settings.quiet_hours.as_ref()) {
+        return None;
+    }
+    open_folder_path(path.to_string(), Some(true))
+        .await
+        .err()
+        .map(|e| format!("Could not show the file in the file manager: {}", e))
+}
+
+/// Opens the folder containing `path`. With `reveal_file` (the default) a
+/// file is also selected in the file manager; pass `false` to just open the
+/// folder. A directory is always opened as is.
 #[command]
-async fn open_folder_path(path: String) -> Result<(), String> {
+async fn open_folder_path(path: String, reveal_file: Option<bool>) -> Result<(), String> {
     let file_path = std::path::Path::new(&path);
     
     // Check if the path exists
@@ -257,11 +1725,12 @@ async fn open_folder_path(path: String) -> Result<(), String> {
         // If it's already a directory, use it directly
         path.clone()
     };
+    let reveal = reveal_file.unwrap_or(true) && file_path.is_file();
 
     #[cfg(target_os = "windows")]
     {
         // On Windows, use explorer with /select to highlight the file
-        if file_path.is_file() {
+        if reveal {
             std::process::Command::new("explorer")
                 .args(["/select,", &path])
                 .output()
@@ -276,7 +1745,7 @@ async fn open_folder_path(path: String) -> Result<(), String> {
     
     #[cfg(target_os = "macos")]
     {
-        if file_path.is_file() {
+        if reveal {
             // On macOS, use -R flag to reveal the file in Finder
             std::process::Command::new("open")
                 .args(["-R", &path])
@@ -297,7 +1766,7 @@ async fn open_folder_path(path: String) -> Result<(), String> {
         let mut success = false;
         
         for cmd in &commands {
-            if file_path.is_file() {
+            if reveal {
                 // Try to select the file if supported
                 match std::process::Command::new(cmd)
                     .arg("--select")
@@ -325,6 +1794,38 @@ async fn open_folder_path(path: String) -> Result<(), String> {
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+struct OpenedDir {
+    path: String,
+    success: bool,
+    error: Option<String>,
+}
+
+/// Creates an app directory if needed and reveals it in the file manager.
+/// The path is returned even when opening fails so the UI can show it.
+async fn reveal_app_dir(dir: tauri::Result<std::path::PathBuf>) -> Result<OpenedDir, String> {
+    let dir = dir.map_err(|e| format!("Cannot determine directory: {}", e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let path = dir.to_string_lossy().to_string();
+    let opened = open_folder_path(path.clone(), None).await;
+    Ok(OpenedDir {
+        path,
+        success: opened.is_ok(),
+        error: opened.err(),
+    })
+}
+
+#[command]
+async fn open_config_dir(app: AppHandle) -> Result<OpenedDir, String> {
+    reveal_app_dir(app.path().app_config_dir()).await
+}
+
+#[command]
+async fn open_log_dir(app: AppHandle) -> Result<OpenedDir, String> {
+    reveal_app_dir(app.path().app_log_dir()).await
+}
+
 #[command]
 async fn select_output_folder() -> Result<Option<String>, String> {
     use std::process::Command;
@@ -360,7 +1861,7 @@ async fn select_output_folder() -> Result<Option<String>, String> {
             .map_err(|e| format!("Failed to open folder dialog: {}", e))?;
         
         if output.status.success() {
-            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let path = paths::path_to_string(paths::path_from_stdout(&output.stdout))?;
             // Convert AppleScript path format to Unix path
             let unix_path = path.replace(":", "/").replace("Macintosh HD", "");
             Ok(Some(unix_path))
@@ -381,9 +1882,9 @@ async fn select_output_folder() -> Result<Option<String>, String> {
         for (cmd, args) in &commands {
             match Command::new(cmd).args(args).output() {
                 Ok(output) if output.status.success() => {
-                    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                    if !path.is_empty() {
-                        return Ok(Some(path));
+                    let path = paths::path_from_stdout(&output.stdout);
+                    if !path.as_os_str().is_empty() {
+                        return paths::path_to_string(path).map(Some);
                     }
                 }
                 _ => continue,
@@ -399,17 +1900,696 @@ async fn select_output_folder() -> Result<Option<String>, String> {
     }
 }
 
+/// Registers the state every command relies on, apart from the settings
+/// and history, which are loaded once the app can find its folders.
+fn with_state(builder: tauri::Builder<Runtime>) -> tauri::Builder<Runtime> {
+    builder
+        .manage(stream::StreamRegistry::default())
+        .manage(playback::Player::default())
+        .manage(jobs::JobRegistry::default())
+        .manage(events::JobTracker::default())
+        .manage(ratelimit::RateLimiter::default())
+        .manage(ratelimit::InFlight::default())
+        .manage(servers::ServerPool::default())
+        .manage(client::SharedClient::default())
+        .manage(secrets::ActiveApiKey::default())
+        .manage(capabilities::CapabilitiesCache::default())
+        .manage(voices::VoiceCache::default())
+        .manage(endpoint::LocalEndpoint::default())
+        .manage(announce::Announcer::default())
+        .manage(shutdown::Shutdown::default())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tauri::Builder::default()
+    with_state(tauri::Builder::<Runtime>::new())
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .setup(|app| {
+            app.manage(settings::SettingsState(std::sync::Mutex::new(settings::load(app.handle()))));
+            app.manage(history::HistoryState(std::sync::Mutex::new(history::load(app.handle()))));
+            playback::restore_device(app.handle(), &app.state::<playback::Player>());
+            secrets::restore(app.handle());
+            endpoint::restore(app.handle());
+            servers::start_keep_alive(app.handle());
+
+            #[cfg(desktop)]
+            {
+                app.handle().plugin(hotkey::plugin())?;
+                hotkey::restore(app.handle());
+            }
+
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_available_voices,
+            get_supported_formats,
             convert_text_to_speech,
+            convert_text_from_file,
+            dialogue::synthesize_dialogue,
+            batch::convert_batch,
+            batch::convert_lines_from_file,
+            batch::convert_folder,
+            batch::estimate_batch,
+            benchmark::benchmark_synthesis,
+            stream::synthesize_stream,
+            stream::cancel_stream,
+            jobs::cancel_conversion,
+            jobs::cancel_all,
+            jobs::list_jobs,
+            jobs::get_job,
+            paths::check_output_folder,
+            paths::set_default_output_dir,
+            paths::get_default_output_dir,
+            paths::estimate_file_size,
+            documents::extract_text_from_file,
+            documents::convert_chapters,
+            text::text_stats,
+            text::detect_language,
+            text::preview_chunks,
+            text::get_script_analysis,
+            ssml::insert_break,
+            ssml::wrap_emphasis,
+            ssml::synthesize_ipa,
+            ssml::validate_ssml,
+            cache::get_cache_stats,
+            cache::clear_audio_cache,
+            cache::hash_request,
+            history::get_history,
+            history::export_history_csv,
+            history::export_history_json,
+            history::regenerate_from_history,
+            jobfile::export_job,
+            jobfile::import_job,
+            #[cfg(desktop)]
+            files::delete_output_file,
+            #[cfg(desktop)]
+            files::delete_output_files,
+            settings::get_settings,
+            settings::update_settings,
+            edit::trim_audio_file,
+            edit::get_audio_duration,
+            edit::probe_audio,
+            edit::split_audio_by_silence,
+            edit::downmix_to_mono,
+            edit::insert_silence,
+            edit::fit_duration,
+            edit::detect_clipping,
+            edit::build_audiobook,
+            edit::merge_outputs,
+            edit::append_to_audio,
+            edit::write_chapters,
+            capabilities::get_server_capabilities,
+            version::get_version_info,
+            voices::voice_exists,
+            voices::compare_voices,
+            voices::preview_voice,
+            voices::test_pronunciation,
+            endpoint::start_local_endpoint,
+            endpoint::stop_local_endpoint,
+            announce::speak_announcement,
+            announce::stop_announcement,
+            servers::list_servers,
+            servers::add_server,
+            servers::remove_server,
+            servers::warm_connection,
+            profiles::list_profiles,
+            profiles::save_profile,
+            profiles::switch_profile,
+            profiles::delete_profile,
+            reset::reset_settings,
+            reset::reset_all,
+            secrets::set_api_key,
+            secrets::get_api_key,
+            presets::save_preset,
+            presets::list_presets,
+            presets::delete_preset,
+            presets::apply_preset,
+            playback::list_audio_output_devices,
+            playback::set_output_device,
+            playback::play_audio,
+            playback::stop_audio,
+            #[cfg(desktop)]
+            hotkey::register_hotkey,
+            #[cfg(desktop)]
+            hotkey::unregister_hotkey,
             open_file_path,
             open_folder_path,
-            select_output_folder
+            open_config_dir,
+            open_log_dir,
+            select_output_folder,
+            shutdown::confirm_exit
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
-}
\ No newline at end of file
+        .on_window_event(shutdown::on_window_event)
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(shutdown::on_run_event);
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(text: &str, voice: &str, format: &str) -> TTSRequest {
+        TTSRequest {
+            text: text.to_string(),
+            voice: voice.to_string(),
+            format: format.to_string(),
+            language: "en-US".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dry_run_reports_the_path_the_conversion_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("speech.audio").to_string_lossy().to_string();
+        let request = request("One two three four five.", "en-US-Chirp3-HD-Charon", "MP3");
+        let result = dry_run(&request, output.clone(), false).unwrap();
+        assert!(result.dry_run && result.success);
+        assert_eq!(result.output_path, Some(output));
+        assert_eq!(result.estimated_duration_seconds, Some(2.0));
+        assert!(!dir.path().join("speech.audio").exists());
+    }
+
+    #[test]
+    fn dry_run_offline_moves_to_wav_like_the_local_engine() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("speech.mp3").to_string_lossy().to_string();
+        let request = request("Hello there.", "Samantha", "MP3");
+        let result = dry_run(&request, output, true).unwrap();
+        let expected = offline_output_path(&dir.path().join("speech.mp3").to_string_lossy(), "MP3");
+        assert_eq!(result.output_path, Some(expected.to_string_lossy().to_string()));
+        assert!(expected.ends_with("speech.wav"));
+    }
+
+    #[test]
+    fn check_request_rejects_what_the_server_would() {
+        let capabilities = capabilities::Capabilities::default();
+
+        let opus = request("Hello.", "en-US-Chirp3-HD-Charon", "OGG_OPUS");
+        assert_eq!(
+            check_request(&opus, false, &capabilities),
+            Err("The server does not offer OGG_OPUS output".to_string())
+        );
+
+        let long = request(&"word ".repeat(2000), "en-US-Chirp3-HD-Charon", "MP3");
+        assert_eq!(
+            check_request(&long, false, &capabilities),
+            Err("Text is 10000 bytes; a single request is limited to 5000 bytes".to_string())
+        );
+
+        let unnamed = request("Hello.", "Samantha", "MP3");
+        assert_eq!(
+            check_request(&unnamed, false, &capabilities),
+            Err("Cannot determine language of voice 'Samantha'".to_string())
+        );
+        // The local engine has none of the server's limits
+        assert!(check_request(&long, true, &capabilities).is_ok());
+        assert!(check_request(&unnamed, true, &capabilities).is_ok());
+    }
+
+    #[test]
+    fn check_request_follows_the_server_limit() {
+        let capabilities = capabilities::Capabilities {
+            max_request_bytes: 20_000,
+            ..Default::default()
+        };
+        let long = request(&"word ".repeat(2000), "en-US-Chirp3-HD-Charon", "MP3");
+        assert!(check_request(&long, false, &capabilities).is_ok());
+        // Encoded locally from LINEAR16 when the server lacks it
+        let m4a = request("Hello.", "en-US-Chirp3-HD-Charon", "M4A");
+        assert!(check_request(&m4a, false, &capabilities).is_ok());
+    }
+
+    #[test]
+    fn dry_run_needs_an_existing_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("missing").join("speech.mp3").to_string_lossy().to_string();
+        let request = request("Hello.", "en-US-Chirp3-HD-Charon", "MP3");
+        let error = dry_run(&request, output, false).unwrap_err();
+        assert!(error.starts_with("Output folder does not exist"), "{}", error);
+    }
+
+    #[test]
+    fn check_input_text_rejects_blank_text() {
+        assert_eq!(check_input_text("", 100), Err("Text is empty".to_string()));
+        assert_eq!(check_input_text(" \n\t ", 100), Err("Text is empty".to_string()));
+        assert!(check_input_text(" Hi ", 100).is_ok());
+    }
+
+    #[test]
+    fn check_input_text_limits_characters() {
+        assert!(check_input_text(&"é".repeat(10), 10).is_ok());
+        let err = check_input_text(&"é".repeat(11), 10).unwrap_err();
+        assert!(err.contains("11 characters, over the 10 character limit"), "{}", err);
+        assert!(check_input_text(&"a".repeat(5000), 0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn run_limited_keeps_input_order_within_the_limit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let finished = Mutex::new(Vec::new());
+        let tasks = (0..6u64).map(|i| {
+            let (running, peak) = (&running, &peak);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                // Later tasks finish first
+                tokio::time::sleep(std::time::Duration::from_millis(30 - i * 5)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(i)
+            }
+        });
+
+        let results = run_limited(tasks, 2, |n| finished.lock().unwrap().push(n)).await.unwrap();
+        assert_eq!(results, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(*finished.lock().unwrap(), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[tokio::test]
+    async fn run_limited_stops_at_the_first_failure() {
+        let tasks = (0..3).map(|i| async move {
+            if i == 1 {
+                Err(format!("chunk {} failed", i))
+            } else {
+                Ok(i)
+            }
+        });
+        assert_eq!(run_limited(tasks, 0, |_| {}).await, Err("chunk 1 failed".to_string()));
+    }
+
+    fn retry_after(value: &str) -> Option<u64> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::RETRY_AFTER, value.parse().unwrap());
+        retry_after_secs(&headers)
+    }
+
+    #[test]
+    fn retry_after_secs_reads_seconds_and_dates() {
+        assert_eq!(retry_after("120"), Some(120));
+        assert_eq!(retry_after(" 7 "), Some(7));
+        assert_eq!(retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(90);
+        let secs = retry_after(&httpdate::fmt_http_date(later)).unwrap();
+        assert!((88..=90).contains(&secs), "{}", secs);
+
+        assert_eq!(retry_after("soon"), None);
+        assert_eq!(retry_after_secs(&reqwest::header::HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn rate_limited_failure_carries_the_wait() {
+        let result = rate_limited_failure(Some(30));
+        assert!(result.rate_limited);
+        assert_eq!(result.retry_after_secs, Some(30));
+        assert!(result.error.unwrap().contains("30 seconds"));
+    }
+
+    #[tokio::test]
+    async fn request_synthesis_waits_out_a_429_and_retries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let attempts = AtomicUsize::new(0);
+        let server = testing::MockServer::start(move |request| {
+            if request.path != "/synthesize" {
+                testing::Reply::not_found()
+            } else if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                testing::Reply::json(429, serde_json::json!({ "detail": "Too Many Requests" })).header("Retry-After", "1")
+            } else {
+                testing::synthesized("retried")
+            }
+        })
+        .await;
+        let app = testing::TestApp::new(settings::Settings {
+            servers: vec![server.url.clone()],
+            rate_limit_retries: 1,
+            ..settings::Settings::default()
+        });
+        let throttled = testing::listen(app.handle(), "throttled");
+
+        let started = std::time::Instant::now();
+        let client = reqwest::Client::new();
+        let result = request_synthesis(app.handle(), &client, &request("Hello", "en-US-Chirp3-HD-Charon", "MP3"))
+            .await
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert!(!result.rate_limited);
+        assert_eq!(result.download_url.as_deref(), Some("/download/retried"));
+        assert_eq!(server.requests_to("/synthesize").len(), 2);
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+        assert_eq!(*throttled.lock().unwrap(), vec![serde_json::json!({ "wait_ms": 1000 })]);
+    }
+
+    #[tokio::test]
+    async fn request_synthesis_reports_a_429_once_retries_run_out() {
+        let server = testing::MockServer::start(|_| {
+            testing::Reply::json(429, serde_json::json!({ "detail": "Too Many Requests" })).header("Retry-After", "30")
+        })
+        .await;
+        let app = testing::TestApp::with_server(&server);
+
+        let client = reqwest::Client::new();
+        let result = request_synthesis(app.handle(), &client, &request("Hello", "en-US-Chirp3-HD-Charon", "MP3"))
+            .await
+            .unwrap();
+        assert!(result.rate_limited);
+        assert_eq!(result.retry_after_secs, Some(30));
+        assert_eq!(server.requests_to("/synthesize").len(), 1);
+    }
+
+    fn voice_with_formats(name: &str, formats: &[&str]) -> Voice {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "language_code": "en-US",
+            "ssml_gender": "FEMALE",
+            "supported_formats": formats,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn check_format_in_follows_the_voice_list() {
+        let voices = vec![
+            voice_with_formats("en-US-Chirp3-HD-Kore", &["MP3", "LINEAR16"]),
+            voice_with_formats("en-US-Chirp3-HD-Puck", &[]),
+        ];
+        assert!(check_format_in(&voices, &request("Hi", "en-US-Chirp3-HD-Kore", "mp3")).is_ok());
+        // Encoded locally from PCM
+        assert!(check_format_in(&voices, &request("Hi", "en-US-Chirp3-HD-Kore", "M4A")).is_ok());
+        assert_eq!(
+            check_format_in(&voices, &request("Hi", "en-US-Chirp3-HD-Kore", "OGG_OPUS")),
+            Err("Voice 'en-US-Chirp3-HD-Kore' can't produce OGG_OPUS audio; it supports MP3, LINEAR16".to_string())
+        );
+
+        // Voices that list nothing, or aren't listed at all, are left to the server
+        assert!(check_format_in(&voices, &request("Hi", "en-US-Chirp3-HD-Puck", "OGG_OPUS")).is_ok());
+        assert!(check_format_in(&voices, &request("Hi", "en-US-Chirp3-HD-Zephyr", "OGG_OPUS")).is_ok());
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn usable_formats_needs_both_server_and_voice() {
+        let server = strings(&["mp3", "LINEAR16", "OGG_OPUS"]);
+        assert_eq!(usable_formats(&server, &[]), strings(&["MP3", "LINEAR16", "OGG_OPUS", "M4A"]));
+        assert_eq!(usable_formats(&server, &strings(&["MP3", "OGG_OPUS"])), strings(&["MP3", "OGG_OPUS"]));
+        assert!(usable_formats(&[], &strings(&["MP3"])).is_empty());
+    }
+
+    #[test]
+    fn usable_formats_offers_m4a_wherever_pcm_works() {
+        let server = strings(&["MP3", "LINEAR16"]);
+        assert_eq!(usable_formats(&server, &strings(&["LINEAR16"])), strings(&["LINEAR16", "M4A"]));
+        assert_eq!(usable_formats(&strings(&["MP3", "M4A"]), &[]), strings(&["MP3", "M4A"]));
+        assert_eq!(usable_formats(&strings(&["MP3"]), &[]), strings(&["MP3"]));
+    }
+
+    #[test]
+    fn resolve_download_url_keeps_absolute_urls() {
+        assert_eq!(
+            resolve_download_url("http://127.0.0.1:8000", " https://cdn.example.com/audio/1.mp3?sig=abc "),
+            "https://cdn.example.com/audio/1.mp3?sig=abc"
+        );
+        assert_eq!(
+            resolve_download_url("https://tts.example.com/api", "http://other.host/file.wav"),
+            "http://other.host/file.wav"
+        );
+    }
+
+    #[test]
+    fn resolve_download_url_appends_relative_paths_to_the_base() {
+        let expected = "https://tts.example.com/api/download/1.mp3";
+        assert_eq!(resolve_download_url("https://tts.example.com/api", "/download/1.mp3"), expected);
+        assert_eq!(resolve_download_url("https://tts.example.com/api/", "download/1.mp3"), expected);
+        assert_eq!(resolve_download_url("https://tts.example.com/api/", "//download/1.mp3"), expected);
+    }
+
+    #[test]
+    fn ssml_flag_is_only_sent_when_set() {
+        let plain = serde_json::to_value(request("Hi", "en-US-Chirp3-HD-Charon", "MP3")).unwrap();
+        assert!(plain.get("ssml").is_none());
+        let ssml = TTSRequest {
+            ssml: true,
+            ..request("<speak>Hi</speak>", "en-US-Chirp3-HD-Charon", "MP3")
+        };
+        assert_eq!(serde_json::to_value(ssml).unwrap()["ssml"], true);
+    }
+
+    #[test]
+    fn check_style_in_follows_the_voice_list() {
+        let mut styled = voice_with_formats("en-US-Studio-O", &[]);
+        styled.speaking_styles = vec!["newscast".to_string(), "calm".to_string()];
+        let voices = vec![styled, voice_with_formats("en-US-Chirp3-HD-Kore", &[])];
+        let with_style = |voice: &str, style: Option<&str>| TTSRequest {
+            speaking_style: style.map(str::to_string),
+            ..request("Hi", voice, "MP3")
+        };
+
+        assert!(check_style_in(&voices, &with_style("en-US-Studio-O", Some("Newscast"))).is_ok());
+        assert!(check_style_in(&voices, &with_style("en-US-Studio-O", None)).is_ok());
+        assert_eq!(
+            check_style_in(&voices, &with_style("en-US-Studio-O", Some("cheerful"))),
+            Err("Voice 'en-US-Studio-O' has no 'cheerful' speaking style; it offers newscast, calm".to_string())
+        );
+        assert_eq!(
+            check_style_in(&voices, &with_style("en-US-Chirp3-HD-Kore", Some("calm"))),
+            Err("Voice 'en-US-Chirp3-HD-Kore' has no speaking styles".to_string())
+        );
+        // Unlisted voices are left to the server
+        assert!(check_style_in(&voices, &with_style("en-US-Chirp3-HD-Puck", Some("calm"))).is_ok());
+    }
+
+    #[test]
+    fn check_channels_in_warns_about_stereo_from_mono_voices() {
+        let mut mono = voice_with_formats("en-US-Chirp3-HD-Kore", &[]);
+        mono.natural_channels = Some(1);
+        let voices = vec![mono, voice_with_formats("en-US-Chirp3-HD-Puck", &[])];
+        let with_channels = |voice: &str, channels: Option<u16>| TTSRequest {
+            channels,
+            ..request("Hi", voice, "MP3")
+        };
+
+        assert_eq!(
+            check_channels_in(&voices, &with_channels("en-US-Chirp3-HD-Kore", Some(2))),
+            Ok(Some("Voice 'en-US-Chirp3-HD-Kore' is mono; stereo output repeats the same channel and only adds size".to_string()))
+        );
+        assert_eq!(check_channels_in(&voices, &with_channels("en-US-Chirp3-HD-Kore", Some(1))), Ok(None));
+        assert_eq!(check_channels_in(&voices, &with_channels("en-US-Chirp3-HD-Puck", Some(2))), Ok(None));
+        assert_eq!(check_channels_in(&voices, &with_channels("en-US-Chirp3-HD-Kore", None)), Ok(None));
+        assert_eq!(
+            check_channels_in(&[], &with_channels("en-US-Chirp3-HD-Kore", Some(6))),
+            Err("Unsupported channel count 6; use 1 (mono) or 2 (stereo)".to_string())
+        );
+    }
+
+    #[test]
+    fn repeat_settings_fill_in_and_limit() {
+        assert_eq!(repeat_settings(&ConversionOptions::default()), Ok((1, DEFAULT_REPEAT_GAP_MS)));
+        let options = |count: u32, gap_ms: u64| ConversionOptions {
+            repeat_count: Some(count),
+            repeat_gap_ms: Some(gap_ms),
+            ..Default::default()
+        };
+        assert_eq!(repeat_settings(&options(3, 0)), Ok((3, 0)));
+        assert_eq!(repeat_settings(&options(0, 500)), Err("Repeat the audio between 1 and 100 times".to_string()));
+        assert_eq!(repeat_settings(&options(101, 500)), Err("Repeat the audio between 1 and 100 times".to_string()));
+        assert_eq!(
+            repeat_settings(&options(2, 60_001)),
+            Err("The gap between repetitions can be at most 60000 ms".to_string())
+        );
+    }
+
+    #[test]
+    fn repeat_file_plays_the_audio_again_after_each_gap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("word.wav");
+        let word = audio::Wav {
+            sample_rate: 1000,
+            channels: 1,
+            bits_per_sample: 16,
+            data: vec![1; 1000],
+        };
+        std::fs::write(&path, word.to_bytes()).unwrap();
+
+        let len = repeat_file(&path, 3, 250).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), len);
+        // Three half-second clips and two quarter-second gaps
+        assert_eq!(audio::duration_secs(&bytes), Some(2.0));
+    }
+
+    #[test]
+    fn raw_audio_is_kept_from_before_processing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("word.wav");
+        let word = audio::Wav {
+            sample_rate: 1000,
+            channels: 1,
+            bits_per_sample: 16,
+            data: vec![1; 1000],
+        };
+        std::fs::write(&path, word.to_bytes()).unwrap();
+        let output = path.to_string_lossy().to_string();
+
+        let raw = keep_raw_audio(&output, "wav", true, |_| false).unwrap().unwrap();
+        assert_eq!(std::path::Path::new(&raw), dir.path().join("word.raw.wav"));
+        repeat_file(&path, 2, 250).unwrap();
+        let raw_bytes = std::fs::read(&raw).unwrap();
+        assert_eq!(raw_bytes, word.to_bytes());
+        assert_ne!(raw_bytes, std::fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn raw_audio_from_an_earlier_run_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("word.mp3");
+        std::fs::write(&path, b"audio").unwrap();
+        let raw = dir.path().join("word.raw.mp3");
+        std::fs::write(&raw, b"old audio").unwrap();
+
+        // A file of the same name KIWI didn't keep stays
+        let mut asked = None;
+        let output = path.to_string_lossy();
+        assert_eq!(keep_raw_audio(&output, "mp3", false, |p| asked.replace(p.to_path_buf()).is_some()), Ok(None));
+        assert_eq!(asked.as_deref(), Some(raw.as_path()));
+        assert!(raw.exists());
+
+        assert_eq!(keep_raw_audio(&output, "mp3", false, |_| true), Ok(None));
+        assert!(!raw.exists());
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn only_raw_copies_history_records_are_removed() {
+        let audio = testing::tone_wav(200);
+        let server = testing::MockServer::start(move |request| testing::tts_reply(request, &audio)).await;
+        let app = testing::TestApp::with_server(&server);
+        let output = app.dir().join("word.wav");
+        let raw = app.dir().join("word.raw.wav");
+        let convert = |keep_raw: bool| {
+            let options = ConversionOptions {
+                keep_raw,
+                ..Default::default()
+            };
+            let format = Some("LINEAR16".to_string());
+            let output = output.to_string_lossy().to_string();
+            convert_text_to_speech(app.handle().clone(), "Word.".to_string(), None, format, output, false, Some(options))
+        };
+
+        // The user's own file of that name is left alone
+        std::fs::write(&raw, b"mine").unwrap();
+        convert(false).await.unwrap();
+        assert_eq!(std::fs::read(&raw).unwrap(), b"mine");
+
+        let kept = convert(true).await.unwrap();
+        assert_eq!(kept.raw_path.as_deref(), Some(&*raw.to_string_lossy()));
+        assert_eq!(history::load(app.handle()).last().unwrap().raw_path, kept.raw_path);
+
+        // A copy KIWI kept goes once a later run doesn't keep one
+        convert(false).await.unwrap();
+        assert!(!raw.exists());
+        assert!(history::load(app.handle()).iter().all(|entry| entry.raw_path.is_none()));
+    }
+
+    #[test]
+    fn parse_voices_skips_unreadable_entries() {
+        let body = serde_json::json!({
+            "voices": [
+                { "name": "en-US-Chirp3-HD-Charon", "language_code": "en-US", "ssml_gender": "MALE", "display_name": null },
+                { "name": "en-US-Broken" },
+                "not a voice",
+                {
+                    "name": "en-US-Chirp3-HD-Kore",
+                    "language_code": "en-US",
+                    "ssml_gender": "FEMALE",
+                    "display_name": "Kore",
+                    "supported_formats": ["MP3"],
+                    "added_later": true
+                }
+            ]
+        });
+        let (voices, skipped) = parse_voices(&body.to_string()).unwrap();
+        let names: Vec<&str> = voices.iter().map(|voice| voice.name.as_str()).collect();
+        assert_eq!(names, ["en-US-Chirp3-HD-Charon", "en-US-Chirp3-HD-Kore"]);
+        assert_eq!(voices[1].supported_formats, strings(&["MP3"]));
+        assert_eq!(skipped, 2);
+    }
+
+    #[test]
+    fn parse_voices_needs_a_voices_array() {
+        let (voices, skipped) = parse_voices(r#"{"voices": []}"#).unwrap();
+        assert!(voices.is_empty() && skipped == 0);
+        assert_eq!(parse_voices(r#"{"error": "down"}"#).unwrap_err(), "it has no voices array");
+        assert!(parse_voices(r#"{"voices": [{"name": "#).is_err());
+        assert_eq!(parse_voices(r#"{"voices": "none"}"#).unwrap_err(), "it has no voices array");
+        assert_eq!(parse_voices(r#"["en-US-Chirp3-HD-Kore"]"#).unwrap_err(), "it has no voices array");
+    }
+
+    #[test]
+    fn parse_voices_keeps_the_entries_before_a_truncation() {
+        let body = serde_json::json!({
+            "voices": [
+                { "name": "en-US-Chirp3-HD-Charon", "language_code": "en-US", "ssml_gender": "MALE" },
+                { "name": "en-US-Broken" },
+                { "name": "en-US-Chirp3-HD-Kore", "language_code": "en-US", "ssml_gender": "FEMALE" },
+                { "name": "en-US-Chirp3-HD-Puck", "language_code": "en-US", "ssml_gender": "MALE" }
+            ],
+            "count": 4
+        })
+        .to_string();
+        let cut = body.find("en-US-Chirp3-HD-Puck").unwrap();
+
+        let (voices, skipped) = parse_voices(&body[..cut]).unwrap();
+        let names: Vec<&str> = voices.iter().map(|voice| voice.name.as_str()).collect();
+        assert_eq!(names, ["en-US-Chirp3-HD-Charon", "en-US-Chirp3-HD-Kore"]);
+        assert_eq!(skipped, 2);
+
+        // Broken JSON rather than an early end also stops the list there
+        let garbled = body.replace(r#"{"language_code":"en-US","name":"en-US-Chirp3-HD-Puck""#, "{oops");
+        let (voices, skipped) = parse_voices(&garbled).unwrap();
+        assert_eq!((voices.len(), skipped), (2, 2));
+        assert_eq!(parse_voices(&body).unwrap().0.len(), 3);
+    }
+
+    #[test]
+    fn check_effects_profile_in_needs_a_known_offered_profile() {
+        let with_profile = |profile: Option<&str>| TTSRequest {
+            effects_profile_id: profile.map(str::to_string),
+            ..request("Hi", "en-US-Chirp3-HD-Kore", "MP3")
+        };
+        let offered = strings(&["headphone-class-device", "telephony-class-application"]);
+
+        assert!(check_effects_profile_in(&[], &with_profile(None)).is_ok());
+        assert!(check_effects_profile_in(&offered, &with_profile(Some("telephony-class-application"))).is_ok());
+        assert!(check_effects_profile_in(&offered, &with_profile(Some("bullhorn")))
+            .unwrap_err()
+            .starts_with("Unknown effects profile 'bullhorn'; use wearable-class-device, "));
+        assert_eq!(
+            check_effects_profile_in(&offered, &with_profile(Some("wearable-class-device"))),
+            Err("The server does not offer the 'wearable-class-device' effects profile; it offers headphone-class-device, telephony-class-application".to_string())
+        );
+        assert_eq!(
+            check_effects_profile_in(&[], &with_profile(Some("headphone-class-device"))),
+            Err("The server does not apply effects profiles".to_string())
+        );
+    }
+
+    #[test]
+    fn effects_profile_is_sent_only_when_set() {
+        let plain = serde_json::to_value(request("Hi", "en-US-Chirp3-HD-Kore", "MP3")).unwrap();
+        assert!(plain.get("effects_profile_id").is_none());
+        let tuned = TTSRequest {
+            effects_profile_id: Some("headphone-class-device".to_string()),
+            ..request("Hi", "en-US-Chirp3-HD-Kore", "MP3")
+        };
+        assert_eq!(serde_json::to_value(tuned).unwrap()["effects_profile_id"], "headphone-class-device");
+    }
+}
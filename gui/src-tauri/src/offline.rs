@@ -0,0 +1,172 @@
+//! Local speech synthesis for offline mode, using whatever engine the OS
+//! provides: `say` on macOS, System.Speech on Windows, eSpeak NG elsewhere.
+//! These engines only write WAV audio.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::Voice;
+
+#[derive(Debug, Clone, Copy)]
+enum Engine {
+    #[cfg(target_os = "macos")]
+    Say,
+    #[cfg(target_os = "windows")]
+    Sapi,
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    Espeak(&'static str),
+}
+
+fn engine() -> Result<Engine, String> {
+    #[cfg(target_os = "macos")]
+    {
+        Ok(Engine::Say)
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        Ok(Engine::Sapi)
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        ["espeak-ng", "espeak"]
+            .into_iter()
+            .find(|cmd| Command::new(cmd).arg("--version").output().is_ok())
+            .map(Engine::Espeak)
+            .ok_or_else(|| "Offline mode needs a local speech engine; install espeak-ng to use it".to_string())
+    }
+}
+
+fn offline_voice(name: String, language_code: &str, gender: &str) -> Voice {
+    Voice {
+        display_name: Some(format!("{} (Offline)", name)),
+        name,
+        language_code: language_code.to_string(),
+        ssml_gender: gender.to_string(),
+        natural_sample_rate_hz: None,
+        natural_channels: None,
+        supported_formats: vec!["LINEAR16".to_string()],
+        model_tier: Some("System".to_string()),
+        speaking_styles: Vec::new(),
+    }
+}
+
+/// Lists the local engine's voices for `language_code` (e.g. `en-US`),
+/// matching on the primary language when there's no exact regional match.
+pub fn list_voices(language_code: &str) -> Result<Vec<Voice>, String> {
+    let wanted = language_code.to_ascii_lowercase().replace('_', "-");
+    let primary = wanted.split('-').next().unwrap_or_default().to_string();
+    let matches = |lang: &str| {
+        let lang = lang.to_ascii_lowercase().replace('_', "-");
+        lang == wanted || lang.split('-').next() == Some(primary.as_str())
+    };
+
+    match engine()? {
+        #[cfg(target_os = "macos")]
+        Engine::Say => {
+            // Lines look like: "Samantha            en_US    # Hello, my name is Samantha."
+            let output = Command::new("say")
+                .args(["-v", "?"])
+                .output()
+                .map_err(|e| format!("Failed to list system voices: {}", e))?;
+            Ok(String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| {
+                    let (info, _) = line.split_once('#')?;
+                    let mut fields = info.split_whitespace().collect::<Vec<_>>();
+                    let lang = fields.pop()?;
+                    matches(lang).then(|| offline_voice(fields.join(" "), language_code, "NEUTRAL"))
+                })
+                .collect())
+        }
+
+        #[cfg(target_os = "windows")]
+        Engine::Sapi => {
+            let output = Command::new("powershell")
+                .args([
+                    "-NoProfile",
+                    "-Command",
+                    "Add-Type -AssemblyName System.Speech; (New-Object System.Speech.Synthesis.SpeechSynthesizer).GetInstalledVoices() | ForEach-Object { $v = $_.VoiceInfo; \"$($v.Culture.Name)|$($v.Gender)|$($v.Name)\" }",
+                ])
+                .output()
+                .map_err(|e| format!("Failed to list system voices: {}", e))?;
+            Ok(String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| {
+                    let mut fields = line.trim().splitn(3, '|');
+                    let (lang, gender, name) = (fields.next()?, fields.next()?, fields.next()?);
+                    matches(lang).then(|| offline_voice(name.to_string(), language_code, &gender.to_ascii_uppercase()))
+                })
+                .collect())
+        }
+
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        Engine::Espeak(cmd) => {
+            // Columns: Pty Language Age/Gender VoiceName File Other Languages
+            let output = Command::new(cmd)
+                .arg("--voices")
+                .output()
+                .map_err(|e| format!("Failed to list system voices: {}", e))?;
+            Ok(String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .skip(1)
+                .filter_map(|line| {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    let (lang, gender) = (*fields.get(1)?, *fields.get(2)?);
+                    let gender = match gender.rsplit('/').next() {
+                        Some("F") => "FEMALE",
+                        Some("M") => "MALE",
+                        _ => "NEUTRAL",
+                    };
+                    // The language code is the identifier espeak accepts with -v
+                    matches(lang).then(|| offline_voice(lang.to_string(), language_code, gender))
+                })
+                .collect())
+        }
+    }
+}
+
+/// Speaks `text` with the local engine into a WAV file at `output_path`.
+pub fn synthesize_to_file(text: &str, voice: &str, output_path: &Path) -> Result<(), String> {
+    let output = match engine()? {
+        #[cfg(target_os = "macos")]
+        Engine::Say => Command::new("say")
+            .args(["-v", voice, "--file-format=WAVE", "--data-format=LEI16@22050", "-o"])
+            .arg(output_path)
+            .arg(text)
+            .output(),
+
+        #[cfg(target_os = "windows")]
+        Engine::Sapi => {
+            // Hand text and paths over through the environment to avoid quoting issues
+            Command::new("powershell")
+                .args([
+                    "-NoProfile",
+                    "-Command",
+                    "Add-Type -AssemblyName System.Speech; $s = New-Object System.Speech.Synthesis.SpeechSynthesizer; try { $s.SelectVoice($env:KIWI_VOICE) } catch {}; $s.SetOutputToWaveFile($env:KIWI_OUTPUT); $s.Speak($env:KIWI_TEXT); $s.Dispose()",
+                ])
+                .env("KIWI_VOICE", voice)
+                .env("KIWI_OUTPUT", output_path)
+                .env("KIWI_TEXT", text)
+                .output()
+        }
+
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        Engine::Espeak(cmd) => Command::new(cmd)
+            .args(["-v", voice, "-w"])
+            .arg(output_path)
+            .arg("--")
+            .arg(text)
+            .output(),
+    }
+    .map_err(|e| format!("Failed to run local speech engine: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Local speech engine failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
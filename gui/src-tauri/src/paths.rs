@@ -0,0 +1,520 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tauri::command;
+
+use crate::{audio, settings, text, AppHandle};
+
+/// Longest file name produced, in bytes of UTF-8, leaving room for an
+/// extension and a numeric prefix within common 255-byte limits.
+const MAX_NAME_BYTES: usize = 200;
+
+/// Free space below which an output folder is reported as running low.
+const LOW_SPACE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Used when the original name has nothing usable in it.
+const FALLBACK_NAME: &str = "untitled";
+
+/// Device names Windows reserves regardless of extension.
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters no file name may contain on some platform.
+fn is_reserved(c: char) -> bool {
+    matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control()
+}
+
+/// Turns arbitrary text (a title, the start of a document) into a file name
+/// that is valid on Windows, macOS and Linux. Reserved and control characters
+/// become `_`, leading and trailing dots and spaces are dropped, reserved
+/// Windows device names get a `_` suffix, and long names are cut to
+/// `MAX_NAME_BYTES` without splitting a character. Text with nothing but
+/// such characters becomes `untitled`, so the result is never empty.
+pub fn sanitize_filename(name: &str) -> String {
+    let usable = name.chars().any(|c| !is_reserved(c) && c != '.' && !c.is_whitespace());
+    if !usable {
+        return FALLBACK_NAME.to_string();
+    }
+
+    let replaced: String = name.chars().map(|c| if is_reserved(c) { '_' } else { c }).collect();
+    let trimmed = replaced.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    let mut end = trimmed.len().min(MAX_NAME_BYTES);
+    while !trimmed.is_char_boundary(end) {
+        end -= 1;
+    }
+    // Truncation can expose a new trailing dot or space
+    let mut cleaned = trimmed[..end].trim_end_matches(|c: char| c == '.' || c.is_whitespace()).to_string();
+
+    let base = cleaned.split('.').next().unwrap_or_default().trim_end();
+    if WINDOWS_RESERVED.iter().any(|r| r.eq_ignore_ascii_case(base)) {
+        cleaned.insert(base.len(), '_');
+    }
+    cleaned
+}
+
+/// The path a dialog printed on stdout, with only the line ending removed.
+/// On Unix the bytes become the path as they are, so names that aren't valid
+/// UTF-8 still point at the folder the user picked.
+pub fn path_from_stdout(stdout: &[u8]) -> PathBuf {
+    let stdout = stdout.strip_suffix(b"\n").unwrap_or(stdout);
+    let stdout = stdout.strip_suffix(b"\r").unwrap_or(stdout);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(std::ffi::OsStr::from_bytes(stdout))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(stdout).into_owned())
+    }
+}
+
+/// Converts a path for the frontend. Paths that aren't valid UTF-8 can't make
+/// the round trip through JSON unchanged, so they are refused instead of
+/// being handed over in a lossy form that no longer names the real file.
+pub fn path_to_string(path: PathBuf) -> Result<String, String> {
+    path.into_os_string().into_string().map_err(|os| {
+        format!(
+            "The path {} contains characters that are not valid UTF-8; please rename it or choose another",
+            PathBuf::from(os).display()
+        )
+    })
+}
+
+/// Whether a failed write means the disk (or quota) is full.
+pub fn is_disk_full(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded | io::ErrorKind::WriteZero)
+}
+
+/// The error to show for a failed audio write to `path`. A full disk gets a
+/// plain explanation naming the folder instead of the OS message.
+pub fn save_error(path: &Path, error: io::Error) -> String {
+    if is_disk_full(&error) {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        format!("Not enough disk space to save the audio file in {}", dir.display())
+    } else {
+        format!("Failed to save audio file: {}", error)
+    }
+}
+
+/// Writes audio to `path`, removing whatever was written if it fails so a
+/// truncated file isn't left behind.
+pub fn write_audio(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    std::fs::write(path, bytes).map_err(|e| {
+        let _ = std::fs::remove_file(path);
+        save_error(path, e)
+    })
+}
+
+/// `<path>.part`, where a file is written before it is renamed into place.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Like `write_audio`, but through `<path>.part` renamed into place once it
+/// is fully on disk, so a crash mid-write never leaves a partial file under
+/// the final name.
+pub fn write_audio_atomic(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    let part = part_path(path);
+    let written = std::fs::File::create(&part)
+        .and_then(|mut file| file.write_all(bytes).and_then(|_| file.sync_all()))
+        .map_err(|e| save_error(path, e))
+        .and_then(|_| std::fs::rename(&part, path).map_err(|e| format!("Failed to save audio file: {}", e)));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+    written
+}
+
+/// Fails early when the disk holding `path` has less than `needed` bytes
+/// free. Filesystems that can't report free space pass.
+pub fn check_free_space(path: &Path, needed: u64) -> Result<(), String> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    match fs4::available_space(dir) {
+        Ok(available) if available < needed => Err(format!(
+            "Not enough disk space to save the audio file in {}: {} bytes needed, {} available",
+            dir.display(),
+            needed,
+            available
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// `path` if nothing is there yet, otherwise the first free
+/// `<stem> (2).<ext>`, `<stem> (3).<ext>` and so on beside it.
+pub fn available_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderLevel {
+    Ok,
+    /// Writable, but with less than `LOW_SPACE_BYTES` free.
+    Low,
+    /// Missing or not writable.
+    Unusable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FolderStatus {
+    exists: bool,
+    writable: bool,
+    /// `None` when the filesystem doesn't report free space.
+    free_bytes: Option<u64>,
+    free_human: Option<String>,
+    level: FolderLevel,
+}
+
+fn human_size(bytes: u64) -> String {
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+    if bytes as f64 >= GIB {
+        format!("{:.1} GB", bytes as f64 / GIB)
+    } else {
+        format!("{:.1} MB", bytes as f64 / 1_048_576.0)
+    }
+}
+
+/// Whether a file can be created in `dir`, found by creating and removing
+/// one, since permission bits miss ACLs, read-only mounts and the like.
+fn can_write_to(dir: &Path) -> bool {
+    let probe = dir.join(format!(".kiwi-write-test-{}", std::process::id()));
+    let created = std::fs::File::create(&probe).is_ok();
+    if created {
+        let _ = std::fs::remove_file(&probe);
+    }
+    created
+}
+
+/// Checks that `path` is a folder audio can be saved to, for the UI to show
+/// before a long batch starts.
+#[command]
+pub fn check_output_folder(path: String) -> FolderStatus {
+    let dir = Path::new(&path);
+    let exists = dir.is_dir();
+    let writable = exists && can_write_to(dir);
+    let free_bytes = exists.then(|| fs4::available_space(dir).ok()).flatten();
+
+    let level = match free_bytes {
+        _ if !writable => FolderLevel::Unusable,
+        Some(free) if free < LOW_SPACE_BYTES => FolderLevel::Low,
+        _ => FolderLevel::Ok,
+    };
+    FolderStatus {
+        exists,
+        writable,
+        free_bytes,
+        free_human: free_bytes.map(human_size),
+        level,
+    }
+}
+
+/// `output_path` as given when it names a folder, otherwise the file name
+/// inside `default_dir`.
+pub fn resolve_output_path(output_path: &str, default_dir: Option<&str>) -> Result<String, String> {
+    if output_path.trim().is_empty() {
+        return Err("No output file was given".to_string());
+    }
+    let path = Path::new(output_path);
+    if path.parent().is_some_and(|dir| !dir.as_os_str().is_empty()) {
+        return Ok(output_path.to_string());
+    }
+    match default_dir {
+        Some(dir) => Ok(Path::new(dir).join(path).to_string_lossy().to_string()),
+        None => Err(format!(
+            "'{}' is only a file name; choose a folder or set a default output folder",
+            output_path
+        )),
+    }
+}
+
+/// Makes `path` the folder bare file names are saved in, creating it first
+/// if `create` is set, or forgets the default when `path` is `None`. The
+/// folder must be given in full and be writable.
+#[command]
+pub fn set_default_output_dir(app: AppHandle, path: Option<String>, create: bool) -> Result<Option<String>, String> {
+    let path = path.map(|path| path.trim().to_string()).filter(|path| !path.is_empty());
+    if let Some(path) = &path {
+        check_default_output_dir(path, create)?;
+    }
+    settings::update(&app, |s| s.default_output_dir = path.clone())?;
+    Ok(path)
+}
+
+fn check_default_output_dir(path: &str, create: bool) -> Result<(), String> {
+    let dir = Path::new(path);
+    if !dir.is_absolute() {
+        return Err(format!("Give the full path of the folder, not '{}'", path));
+    }
+    if create && !dir.exists() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    }
+    if !dir.is_dir() {
+        return Err(format!("Folder does not exist: {}", path));
+    }
+    if !can_write_to(dir) {
+        return Err(format!("Folder is not writable: {}", path));
+    }
+    Ok(())
+}
+
+#[command]
+pub fn get_default_output_dir(app: AppHandle) -> Option<String> {
+    settings::current(&app).default_output_dir
+}
+
+#[derive(Debug, Serialize)]
+pub struct SizeEstimate {
+    bytes: u64,
+    human: String,
+    estimated_seconds: f64,
+}
+
+/// Estimates how large the audio for `char_count` characters of text will
+/// be, from the narration length and the format's bitrate, so the UI can
+/// compare it with the free space `check_output_folder` reports.
+#[command]
+pub fn estimate_file_size(char_count: usize, format: String, bitrate_kbps: Option<u32>) -> Result<SizeEstimate, String> {
+    let format = audio::normalize_format(&format)?;
+    match bitrate_kbps {
+        Some(_) if audio::default_bitrate_kbps(format).is_none() => {
+            return Err(format!("{} is uncompressed and has no bitrate", format));
+        }
+        Some(0) => return Err("Bitrate must be above 0 kbps".to_string()),
+        _ => {}
+    }
+    let estimated_seconds = text::spoken_seconds_for_chars(char_count);
+    let bytes = audio::estimated_size(format, estimated_seconds, bitrate_kbps) as u64;
+    Ok(SizeEstimate {
+        bytes,
+        human: human_size(bytes),
+        estimated_seconds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_path_appends_to_the_whole_name() {
+        assert_eq!(part_path(Path::new("/out/speech.mp3")), PathBuf::from("/out/speech.mp3.part"));
+        assert_eq!(part_path(Path::new("notes")), PathBuf::from("notes.part"));
+    }
+
+    #[test]
+    fn sanitize_filename_replaces_reserved_characters() {
+        assert_eq!(sanitize_filename("Chapter 1: A/B <test>?"), "Chapter 1_ A_B _test__");
+        assert_eq!(sanitize_filename("tab\there"), "tab_here");
+        assert_eq!(sanitize_filename("  ..hidden name.. "), "hidden name");
+    }
+
+    #[test]
+    fn sanitize_filename_suffixes_windows_device_names() {
+        assert_eq!(sanitize_filename("con"), "con_");
+        assert_eq!(sanitize_filename("LPT1.txt"), "LPT1_.txt");
+        assert_eq!(sanitize_filename("console"), "console");
+    }
+
+    #[test]
+    fn sanitize_filename_falls_back_only_without_usable_characters() {
+        assert_eq!(sanitize_filename(""), "untitled");
+        assert_eq!(sanitize_filename(" ?*. "), "untitled");
+        // Underscores are the user's own, not left over from replacements
+        assert_eq!(sanitize_filename("___"), "___");
+        assert_eq!(sanitize_filename("?a?"), "_a_");
+    }
+
+    #[test]
+    fn sanitize_filename_limits_bytes_at_a_character_boundary() {
+        let ascii = sanitize_filename(&"a".repeat(300));
+        assert_eq!(ascii.len(), MAX_NAME_BYTES);
+
+        // Three bytes each, so 200 bytes would split the 67th
+        let wide = sanitize_filename(&"日".repeat(100));
+        assert_eq!(wide, "日".repeat(66));
+        assert!(wide.len() <= MAX_NAME_BYTES);
+
+        // A space exposed by the cut is dropped too
+        let spaced = format!("{} tail", "b".repeat(MAX_NAME_BYTES - 1));
+        assert_eq!(sanitize_filename(&spaced), "b".repeat(MAX_NAME_BYTES - 1));
+    }
+
+    #[test]
+    fn path_from_stdout_strips_only_the_line_ending() {
+        assert_eq!(path_from_stdout(b"/home/me/Audio\n"), PathBuf::from("/home/me/Audio"));
+        assert_eq!(path_from_stdout(b"C:\\Audio\r\n"), PathBuf::from("C:\\Audio"));
+        assert_eq!(path_from_stdout(b" /tmp/x \n"), PathBuf::from(" /tmp/x "));
+        assert_eq!(path_from_stdout(b""), PathBuf::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_keep_their_bytes_but_are_refused() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = path_from_stdout(b"/tmp/caf\xe9\n");
+        assert_eq!(path.as_os_str().as_bytes(), b"/tmp/caf\xe9");
+        assert!(path_to_string(path).unwrap_err().contains("not valid UTF-8"));
+        assert_eq!(path_to_string(PathBuf::from("/tmp/café")), Ok("/tmp/café".to_string()));
+    }
+
+    #[test]
+    fn full_disks_get_a_plain_explanation() {
+        let full = io::Error::from(io::ErrorKind::StorageFull);
+        assert!(is_disk_full(&full));
+        assert!(is_disk_full(&io::Error::from(io::ErrorKind::QuotaExceeded)));
+        assert!(!is_disk_full(&io::Error::from(io::ErrorKind::PermissionDenied)));
+
+        let path = Path::new("/media/usb/speech.mp3");
+        assert_eq!(save_error(path, full), "Not enough disk space to save the audio file in /media/usb");
+        assert_eq!(
+            save_error(Path::new("speech.mp3"), io::Error::from(io::ErrorKind::StorageFull)),
+            "Not enough disk space to save the audio file in ."
+        );
+        assert!(save_error(path, io::Error::from(io::ErrorKind::PermissionDenied)).starts_with("Failed to save audio file:"));
+    }
+
+    #[test]
+    fn check_free_space_compares_against_the_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speech.mp3");
+        assert!(check_free_space(&path, 0).is_ok());
+        assert!(check_free_space(&path, u64::MAX).unwrap_err().starts_with("Not enough disk space"));
+    }
+
+    #[test]
+    fn write_audio_leaves_nothing_behind_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("speech.mp3");
+        assert!(write_audio(&path, b"audio").is_err());
+        assert!(!path.exists());
+
+        let path = dir.path().join("speech.mp3");
+        write_audio(&path, b"audio").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"audio");
+    }
+
+    #[test]
+    fn human_size_switches_to_gigabytes() {
+        assert_eq!(human_size(512 * 1024 * 1024), "512.0 MB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024 / 2), "1.5 GB");
+    }
+
+    #[test]
+    fn check_output_folder_probes_without_leaving_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = check_output_folder(dir.path().to_string_lossy().to_string());
+        assert!(status.exists && status.writable);
+        assert!(!matches!(status.level, FolderLevel::Unusable));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let missing = check_output_folder(dir.path().join("missing").to_string_lossy().to_string());
+        assert!(!missing.exists && !missing.writable);
+        assert_eq!(missing.free_bytes, None);
+        assert!(matches!(missing.level, FolderLevel::Unusable));
+    }
+
+    #[test]
+    fn available_path_numbers_taken_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("story.mp3");
+        assert_eq!(available_path(&path), path);
+
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(available_path(&path), dir.path().join("story (2).mp3"));
+        std::fs::write(dir.path().join("story (2).mp3"), b"").unwrap();
+        assert_eq!(available_path(&path), dir.path().join("story (3).mp3"));
+
+        let bare = dir.path().join("notes");
+        std::fs::write(&bare, b"").unwrap();
+        assert_eq!(available_path(&bare), dir.path().join("notes (2)"));
+    }
+
+    #[test]
+    fn estimate_file_size_from_characters_and_bitrate() {
+        let minute = estimate_file_size(900, "mp3".to_string(), None).unwrap();
+        assert_eq!((minute.bytes, minute.estimated_seconds), (240_000, 60.0));
+        assert_eq!(minute.human, "0.2 MB");
+        let faster = estimate_file_size(900, "MP3".to_string(), Some(64)).unwrap();
+        assert_eq!(faster.bytes, 480_000);
+        let pcm = estimate_file_size(900, "wav".to_string(), None).unwrap();
+        assert_eq!(pcm.bytes, 60 * 48_000);
+    }
+
+    #[test]
+    fn estimate_file_size_refuses_meaningless_bitrates() {
+        let err = estimate_file_size(900, "wav".to_string(), Some(64)).unwrap_err();
+        assert_eq!(err, "LINEAR16 is uncompressed and has no bitrate");
+        let err = estimate_file_size(900, "mp3".to_string(), Some(0)).unwrap_err();
+        assert_eq!(err, "Bitrate must be above 0 kbps");
+        assert!(estimate_file_size(900, "flac".to_string(), None).is_err());
+    }
+
+    #[test]
+    fn write_audio_atomic_renames_the_part_file_into_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("001.mp3");
+        std::fs::write(&path, b"old").unwrap();
+        write_audio_atomic(&path, b"new audio").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new audio");
+        assert!(!part_path(&path).exists());
+        assert_eq!(part_path(&path), dir.path().join("001.mp3.part"));
+    }
+
+    #[test]
+    fn write_audio_atomic_leaves_nothing_behind_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("001.mp3");
+        assert!(write_audio_atomic(&path, b"audio").is_err());
+        assert!(!path.exists() && !part_path(&path).exists());
+    }
+
+    #[test]
+    fn bare_file_names_go_to_the_default_folder() {
+        let dir = std::env::temp_dir().join("kiwi-out");
+        let dir = dir.to_string_lossy();
+        let expected = Path::new(dir.as_ref()).join("speech.mp3").to_string_lossy().to_string();
+        assert_eq!(resolve_output_path("speech.mp3", Some(&dir)), Ok(expected));
+        let given = Path::new("other").join("speech.mp3").to_string_lossy().to_string();
+        assert_eq!(resolve_output_path(&given, Some(&dir)), Ok(given.clone()));
+        assert_eq!(resolve_output_path(&given, None), Ok(given));
+    }
+
+    #[test]
+    fn bare_file_names_need_a_default_folder() {
+        assert_eq!(
+            resolve_output_path("speech.mp3", None),
+            Err("'speech.mp3' is only a file name; choose a folder or set a default output folder".to_string())
+        );
+        assert_eq!(resolve_output_path("  ", Some("/tmp")), Err("No output file was given".to_string()));
+    }
+
+    #[test]
+    fn default_output_dir_must_be_a_full_existing_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("audio");
+        let missing = missing.to_string_lossy();
+        assert_eq!(check_default_output_dir("audio", true), Err("Give the full path of the folder, not 'audio'".to_string()));
+        assert_eq!(check_default_output_dir(&missing, false), Err(format!("Folder does not exist: {}", missing)));
+        assert_eq!(check_default_output_dir(&missing, true), Ok(()));
+        assert!(Path::new(missing.as_ref()).is_dir());
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::Cursor;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use rodio::cpal::traits::HostTrait;
+use rodio::{Decoder, Device, DeviceTrait, OutputStream, OutputStreamHandle, Sink};
+use serde::Serialize;
+use tauri::{command, Emitter, State};
+
+use crate::{settings, AppHandle};
+
+/// How often a playing thread checks that its output device is still present.
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioDevice {
+    /// The device name, suffixed with `#2`, `#3`... when several share a name.
+    /// A uniquely named device keeps its id across replugging and restarts,
+    /// but suffixes follow the order the host lists devices in, so identically
+    /// named devices may swap ids when that order changes.
+    id: String,
+    name: String,
+    is_default: bool,
+}
+
+/// Enumerates output devices as `(id, device)` pairs. The host only exposes
+/// names, so duplicates are numbered in enumeration order.
+fn output_devices() -> Vec<(String, Device)> {
+    let Ok(devices) = rodio::cpal::default_host().output_devices() else {
+        return Vec::new();
+    };
+
+    let mut seen: Vec<String> = Vec::new();
+    devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            let count = seen.iter().filter(|n| **n == name).count();
+            seen.push(name.clone());
+            let id = if count == 0 { name } else { format!("{} #{}", name, count + 1) };
+            Some((id, device))
+        })
+        .collect()
+}
+
+fn find_device(id: &str) -> Option<Device> {
+    output_devices().into_iter().find(|(device_id, _)| device_id == id).map(|(_, device)| device)
+}
+
+/// Opens `device_id`, or the system default when it is `None` or has gone away.
+/// The second value is false when a requested device could not be used.
+fn open_output(device_id: Option<&str>) -> Result<((OutputStream, OutputStreamHandle), bool), String> {
+    if let Some(device) = device_id.and_then(find_device) {
+        if let Ok(stream) = OutputStream::try_from_device(&device) {
+            return Ok((stream, true));
+        }
+    }
+    let stream = OutputStream::try_default().map_err(|e| format!("No audio output device available: {}", e))?;
+    Ok((stream, device_id.is_none()))
+}
+
+/// The sink of whatever is currently playing, so it can be stopped or replaced,
+/// and the output device new playback should use.
+#[derive(Default)]
+pub struct Player {
+    sink: Arc<Mutex<Option<Arc<Sink>>>>,
+    device_id: Mutex<Option<String>>,
+}
+
+impl Player {
+    /// Starts playing encoded audio, replacing anything already playing.
+    /// Returns once playback has started; the audio keeps playing in the background.
+    pub fn play(&self, app: &AppHandle, bytes: Vec<u8>) -> Result<(), String> {
+        self.stop();
+
+        let device_id = self.device_id.lock().unwrap().clone();
+        let current = self.sink.clone();
+        let app = app.clone();
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        // The output stream is not `Send`, so it lives on its own thread for
+        // the duration of playback.
+        std::thread::spawn(move || {
+            let start = |device_id: Option<&str>, position: Duration| {
+                let ((stream, handle), used_requested) = open_output(device_id)?;
+                let sink = Sink::try_new(&handle).map_err(|e| format!("Failed to open audio output: {}", e))?;
+                let source =
+                    Decoder::new(Cursor::new(bytes.clone())).map_err(|e| format!("Failed to decode audio: {}", e))?;
+                sink.append(source);
+                if !position.is_zero() {
+                    let _ = sink.try_seek(position);
+                }
+                Ok::<_, String>((stream, Arc::new(sink), used_requested))
+            };
+
+            let (mut stream, sink, used_requested) = match start(device_id.as_deref(), Duration::ZERO) {
+                Ok(started) => started,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            if !used_requested {
+                let _ = app.emit("audio-device-lost", device_id.clone());
+            }
+            *current.lock().unwrap() = Some(sink.clone());
+            let _ = ready_tx.send(Ok(()));
+
+            let mut sink = sink;
+            let mut watched = device_id.filter(|_| used_requested);
+            while !sink.empty() {
+                std::thread::sleep(DEVICE_CHECK_INTERVAL);
+
+                // Move to the default device if ours was unplugged mid-playback
+                let Some(id) = &watched else { continue };
+                if find_device(id).is_some() {
+                    continue;
+                }
+                let _ = app.emit("audio-device-lost", Some(id.clone()));
+                let position = sink.get_pos();
+                match start(None, position) {
+                    Ok((new_stream, new_sink, _)) => {
+                        let mut current = current.lock().unwrap();
+                        // Playback may have been stopped or replaced meanwhile
+                        if !current.as_ref().is_some_and(|s| Arc::ptr_eq(s, &sink)) {
+                            break;
+                        }
+                        *current = Some(new_sink.clone());
+                        stream = new_stream;
+                        sink = new_sink;
+                    }
+                    Err(_) => break,
+                }
+                watched = None;
+            }
+            drop(stream);
+        });
+
+        ready_rx
+            .recv()
+            .map_err(|_| "Playback thread exited unexpectedly".to_string())?
+    }
+
+    pub fn stop(&self) {
+        if let Some(sink) = self.sink.lock().unwrap().take() {
+            sink.stop();
+        }
+    }
+
+    pub fn set_device(&self, device_id: Option<String>) {
+        *self.device_id.lock().unwrap() = device_id;
+    }
+}
+
+/// Selects the saved output device on startup, reverting to the system
+/// default (and forgetting the setting) if that device is no longer present.
+pub fn restore_device(app: &AppHandle, player: &Player) {
+    let Some(device_id) = settings::current(app).output_device else {
+        return;
+    };
+    if find_device(&device_id).is_some() {
+        player.set_device(Some(device_id));
+    } else {
+        let _ = settings::update(app, |s| s.output_device = None);
+    }
+}
+
+#[command]
+pub fn list_audio_output_devices() -> Vec<AudioDevice> {
+    let default_name = rodio::cpal::default_host()
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+
+    output_devices()
+        .into_iter()
+        .map(|(id, device)| {
+            let name = device.name().unwrap_or_else(|_| id.clone());
+            let is_default = default_name.as_deref() == Some(id.as_str());
+            AudioDevice { id, name, is_default }
+        })
+        .collect()
+}
+
+/// Routes future playback to `device_id`, or to the system default when `None`.
+#[command]
+pub fn set_output_device(app: AppHandle, player: State<'_, Player>, device_id: Option<String>) -> Result<(), String> {
+    if let Some(id) = &device_id {
+        if find_device(id).is_none() {
+            return Err(format!("Audio output device not found: {}", id));
+        }
+    }
+    player.set_device(device_id.clone());
+    settings::update(&app, |s| s.output_device = device_id)?;
+    Ok(())
+}
+
+#[command]
+pub async fn play_audio(app: AppHandle, player: State<'_, Player>, path: String) -> Result<(), String> {
+    let bytes = std::fs::read(&path).map_err(|e| format!("Failed to read audio file: {}", e))?;
+    player.play(&app, bytes)
+}
+
+#[command]
+pub fn stop_audio(player: State<'_, Player>) {
+    player.stop();
+}
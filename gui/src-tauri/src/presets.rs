@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager};
+
+use crate::{settings, text, AppHandle};
+
+const PRESETS_FILE: &str = "presets.json";
+
+/// A reusable bundle of synthesis parameters and local post-processing,
+/// mirroring the `ConversionOptions` of the same names. Unset fields leave
+/// the value to the caller or the app defaults. Settings that belong to a
+/// single run, such as dry runs, custom PCM output, the input type and cache
+/// bypass, are not part of a preset.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub voice: Option<String>,
+    pub language: Option<String>,
+    pub format: Option<String>,
+    pub speaking_rate: Option<f64>,
+    pub pitch: Option<f64>,
+    pub volume_gain_db: Option<f64>,
+    pub save_transcript: Option<bool>,
+    pub bitrate_kbps: Option<u32>,
+    pub speaking_style: Option<String>,
+    pub effects_profile_id: Option<String>,
+    /// 1 for mono or 2 for stereo.
+    pub channels: Option<u8>,
+    pub repeat_count: Option<u32>,
+    pub repeat_gap_ms: Option<u64>,
+    pub check_clipping: Option<bool>,
+}
+
+impl Preset {
+    /// Fills every unset field of `self` from `fallback`.
+    pub fn or(self, fallback: Preset) -> Preset {
+        Preset {
+            voice: self.voice.or(fallback.voice),
+            language: self.language.or(fallback.language),
+            format: self.format.or(fallback.format),
+            speaking_rate: self.speaking_rate.or(fallback.speaking_rate),
+            pitch: self.pitch.or(fallback.pitch),
+            volume_gain_db: self.volume_gain_db.or(fallback.volume_gain_db),
+            save_transcript: self.save_transcript.or(fallback.save_transcript),
+            bitrate_kbps: self.bitrate_kbps.or(fallback.bitrate_kbps),
+            speaking_style: self.speaking_style.or(fallback.speaking_style),
+            effects_profile_id: self.effects_profile_id.or(fallback.effects_profile_id),
+            channels: self.channels.or(fallback.channels),
+            repeat_count: self.repeat_count.or(fallback.repeat_count),
+            repeat_gap_ms: self.repeat_gap_ms.or(fallback.repeat_gap_ms),
+            check_clipping: self.check_clipping.or(fallback.check_clipping),
+        }
+    }
+
+    /// Completes the preset with the app defaults, so every synthesis
+    /// parameter is set. Processing nobody asked for stays unset, which
+    /// conversions take as off or their own default.
+    pub fn resolve(self, app: &AppHandle) -> Preset {
+        let settings = settings::current(app);
+        let mut resolved = self.or(Preset {
+            voice: Some(settings.default_voice),
+            format: Some("MP3".to_string()),
+            speaking_rate: Some(1.0),
+            pitch: Some(0.0),
+            volume_gain_db: Some(0.0),
+            save_transcript: Some(false),
+            ..Default::default()
+        });
+        if resolved.language.is_none() {
+            let from_voice = resolved.voice.as_deref().and_then(text::language_from_voice);
+            resolved.language = Some(from_voice.unwrap_or(settings.default_language));
+        }
+        resolved
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NamedPreset {
+    name: String,
+    preset: Preset,
+}
+
+fn presets_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join(PRESETS_FILE))
+        .map_err(|e| format!("Cannot determine config directory: {}", e))
+}
+
+fn load(app: &AppHandle) -> Result<BTreeMap<String, Preset>, String> {
+    read(&presets_path(app)?)
+}
+
+fn read(path: &Path) -> Result<BTreeMap<String, Preset>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("Invalid presets file {}: {}", path.display(), e)),
+        Err(_) => Ok(BTreeMap::new()),
+    }
+}
+
+fn save(app: &AppHandle, presets: &BTreeMap<String, Preset>) -> Result<(), String> {
+    write(&presets_path(app)?, presets)
+}
+
+fn write(path: &Path, presets: &BTreeMap<String, Preset>) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create config directory: {}", e))?;
+    }
+    let json = serde_json::to_string_pretty(presets).map_err(|e| format!("Failed to encode presets: {}", e))?;
+    std::fs::write(path, json).map_err(|e| format!("Failed to save presets: {}", e))
+}
+
+/// Finds a preset's stored name regardless of case.
+fn stored_name(presets: &BTreeMap<String, Preset>, name: &str) -> Option<String> {
+    presets.keys().find(|key| key.eq_ignore_ascii_case(name.trim())).cloned()
+}
+
+/// Looks up a preset by name (case-insensitive).
+pub fn get(app: &AppHandle, name: &str) -> Result<Preset, String> {
+    let presets = load(app)?;
+    stored_name(&presets, name)
+        .and_then(|key| presets.get(&key).cloned())
+        .ok_or_else(|| format!("Preset '{}' does not exist", name))
+}
+
+/// Saves a preset. An existing preset with the same name (ignoring case) is
+/// only replaced when `overwrite` is set.
+#[command]
+pub fn save_preset(app: AppHandle, name: String, preset: Preset, overwrite: Option<bool>) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Preset name cannot be empty".to_string());
+    }
+
+    let mut presets = load(&app)?;
+    if let Some(existing) = stored_name(&presets, &name) {
+        if !overwrite.unwrap_or(false) {
+            return Err(format!("A preset named '{}' already exists", existing));
+        }
+        presets.remove(&existing);
+    }
+    presets.insert(name, preset);
+    save(&app, &presets)
+}
+
+#[command]
+pub fn list_presets(app: AppHandle) -> Result<Vec<NamedPreset>, String> {
+    Ok(load(&app)?
+        .into_iter()
+        .map(|(name, preset)| NamedPreset { name, preset })
+        .collect())
+}
+
+/// Deletes every preset in `config_dir` and returns how many there were.
+pub fn clear_in(config_dir: &Path) -> Result<usize, String> {
+    let path = config_dir.join(PRESETS_FILE);
+    let count = read(&path).map(|presets| presets.len()).unwrap_or(0);
+    write(&path, &BTreeMap::new())?;
+    Ok(count)
+}
+
+/// Returns false when no preset had that name.
+#[command]
+pub fn delete_preset(app: AppHandle, name: String) -> Result<bool, String> {
+    let mut presets = load(&app)?;
+    let Some(existing) = stored_name(&presets, &name) else {
+        return Ok(false);
+    };
+    presets.remove(&existing);
+    save(&app, &presets)?;
+    Ok(true)
+}
+
+/// Returns the preset with every unset field filled from the app defaults,
+/// i.e. exactly the parameters a conversion using it would run with.
+#[command]
+pub fn apply_preset(app: AppHandle, name: String) -> Result<Preset, String> {
+    Ok(get(&app, &name)?.resolve(&app))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn or_keeps_set_fields_and_fills_the_rest() {
+        let explicit = Preset {
+            voice: Some("en-US-Chirp3-HD-Kore".to_string()),
+            repeat_count: Some(3),
+            ..Default::default()
+        };
+        let saved = Preset {
+            voice: Some("en-GB-Chirp3-HD-Charon".to_string()),
+            channels: Some(2),
+            repeat_count: Some(5),
+            check_clipping: Some(true),
+            ..Default::default()
+        };
+        let merged = explicit.or(saved);
+        assert_eq!(merged.voice.as_deref(), Some("en-US-Chirp3-HD-Kore"));
+        assert_eq!(merged.repeat_count, Some(3));
+        assert_eq!(merged.channels, Some(2));
+        assert_eq!(merged.check_clipping, Some(true));
+        assert_eq!(merged.effects_profile_id, None);
+    }
+
+    #[test]
+    fn post_processing_fields_survive_a_round_trip() {
+        let json = r#"{"format": "OGG_OPUS", "bitrate_kbps": 96, "repeat_gap_ms": 500, "speaking_style": "calm"}"#;
+        let preset: Preset = serde_json::from_str(json).unwrap();
+        assert_eq!(preset.bitrate_kbps, Some(96));
+        assert_eq!(preset.repeat_gap_ms, Some(500));
+        let again: Preset = serde_json::from_str(&serde_json::to_string(&preset).unwrap()).unwrap();
+        assert_eq!(again.speaking_style.as_deref(), Some("calm"));
+    }
+}
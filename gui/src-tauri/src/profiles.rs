@@ -0,0 +1,211 @@
+//! Named backend profiles, each bundling the server URLs, proxy and timeouts
+//! for one backend, such as a local development server and production.
+//! Switching copies a profile into the live settings; since every request
+//! builds its HTTP client from those, the next request already goes to the
+//! new backend. A profile's API key is kept in the OS keyring, never in the
+//! settings file, and is sent while the profile is active; see `secrets`.
+
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::settings::{self, Settings};
+use crate::{capabilities, client, secrets, servers, voices, AppHandle};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// API server base URLs; empty means the built-in local server.
+    pub servers: Vec<String>,
+    pub proxy: Option<String>,
+    /// 0 leaves the timeout off.
+    pub connect_timeout_secs: u64,
+    pub request_timeout_secs: u64,
+    /// Only read from `save_profile`: `None` keeps the stored key and an
+    /// empty one removes it. It goes to the keyring and is never written out.
+    #[serde(skip_serializing)]
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileSummary {
+    name: String,
+    #[serde(flatten)]
+    profile: Profile,
+    has_api_key: bool,
+    active: bool,
+}
+
+fn normalize_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Profile name cannot be empty".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// Trims the URLs and proxy of a profile being saved, dropping a blank
+/// proxy, and fails on the first one that isn't usable.
+fn normalize_profile(mut profile: Profile) -> Result<Profile, String> {
+    profile.servers = profile
+        .servers
+        .iter()
+        .map(|url| servers::normalize_url(url))
+        .collect::<Result<_, _>>()?;
+    profile.proxy = profile.proxy.map(|proxy| proxy.trim().to_string()).filter(|proxy| !proxy.is_empty());
+    if let Some(proxy) = &profile.proxy {
+        client::validate_proxy(proxy)?;
+    }
+    Ok(profile)
+}
+
+/// Copies `profile` into the live connection settings.
+fn apply_to(settings: &mut Settings, name: &str, profile: &Profile) {
+    settings.servers = profile.servers.clone();
+    settings.proxy = profile.proxy.clone();
+    settings.connect_timeout_secs = profile.connect_timeout_secs;
+    settings.request_timeout_secs = profile.request_timeout_secs;
+    settings.active_profile = Some(name.to_string());
+}
+
+/// Forgets everything learned from the previous backend.
+fn reset_caches(app: &AppHandle) {
+    capabilities::invalidate(app);
+    voices::invalidate(app);
+    servers::reset_health(app);
+}
+
+fn activate(app: &AppHandle, name: &str, profile: &Profile) -> Result<Settings, String> {
+    let settings = settings::update(app, |s| apply_to(s, name, profile))?;
+    secrets::reload(app)?;
+    reset_caches(app);
+    Ok(settings)
+}
+
+#[command]
+pub fn list_profiles(app: AppHandle) -> Vec<ProfileSummary> {
+    let settings = settings::current(&app);
+    let mut profiles: Vec<ProfileSummary> = settings
+        .profiles
+        .into_iter()
+        .map(|(name, profile)| ProfileSummary {
+            has_api_key: secrets::read(Some(&name)).is_ok_and(|key| key.is_some()),
+            active: settings.active_profile.as_deref() == Some(name.as_str()),
+            name,
+            profile,
+        })
+        .collect();
+    profiles.sort_by_key(|profile| profile.name.to_lowercase());
+    profiles
+}
+
+/// Creates or replaces a profile. Saving the active profile applies the
+/// changes straight away.
+#[command]
+pub fn save_profile(app: AppHandle, name: String, profile: Profile) -> Result<Vec<ProfileSummary>, String> {
+    let name = normalize_name(&name)?;
+    let mut profile = normalize_profile(profile)?;
+
+    if let Some(api_key) = profile.api_key.take() {
+        secrets::store(Some(&name), &api_key)?;
+    }
+    let settings = settings::update(&app, |s| {
+        s.profiles.insert(name.clone(), profile.clone());
+    })?;
+    if settings.active_profile.as_deref() == Some(name.as_str()) {
+        activate(&app, &name, &profile)?;
+    }
+    Ok(list_profiles(app))
+}
+
+/// Makes `name` the active profile, replacing the server, proxy and timeout
+/// settings and clearing the voice, capability and server health caches.
+#[command]
+pub fn switch_profile(app: AppHandle, name: String) -> Result<Settings, String> {
+    let name = name.trim();
+    let profile = settings::current(&app)
+        .profiles
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("No profile named '{}'", name))?;
+    activate(&app, name, &profile)
+}
+
+/// Deletes a profile and its stored key. Deleting the active one keeps its
+/// connection settings but goes back to the key used without a profile.
+#[command]
+pub fn delete_profile(app: AppHandle, name: String) -> Result<Vec<ProfileSummary>, String> {
+    let name = name.trim().to_string();
+    let settings = settings::current(&app);
+    if !settings.profiles.contains_key(&name) {
+        return Err(format!("No profile named '{}'", name));
+    }
+    let was_active = settings.active_profile.as_deref() == Some(name.as_str());
+    secrets::delete(Some(&name))?;
+    settings::update(&app, |s| {
+        s.profiles.remove(&name);
+        if was_active {
+            s.active_profile = None;
+        }
+    })?;
+    if was_active {
+        secrets::reload(&app)?;
+        reset_caches(&app);
+    }
+    Ok(list_profiles(app))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_name_trims_and_refuses_blank_names() {
+        assert_eq!(normalize_name("  Production "), Ok("Production".to_string()));
+        assert_eq!(normalize_name(" \t"), Err("Profile name cannot be empty".to_string()));
+    }
+
+    #[test]
+    fn normalize_profile_cleans_urls_and_proxy() {
+        let profile = Profile {
+            servers: vec![" https://tts.example.com/ ".to_string()],
+            proxy: Some("  ".to_string()),
+            ..Profile::default()
+        };
+        let profile = normalize_profile(profile).unwrap();
+        assert_eq!(profile.servers, vec!["https://tts.example.com".to_string()]);
+        assert_eq!(profile.proxy, None);
+
+        let proxied = Profile {
+            proxy: Some(" http://proxy.local:3128 ".to_string()),
+            ..Profile::default()
+        };
+        assert_eq!(normalize_profile(proxied).unwrap().proxy.as_deref(), Some("http://proxy.local:3128"));
+
+        let bad_url = Profile {
+            servers: vec!["tts.example.com".to_string()],
+            ..Profile::default()
+        };
+        assert!(normalize_profile(bad_url).unwrap_err().starts_with("Invalid server URL"));
+    }
+
+    #[test]
+    fn apply_to_replaces_only_connection_settings() {
+        let mut settings = Settings {
+            max_text_chars: 42,
+            ..Settings::default()
+        };
+        let profile = Profile {
+            servers: vec!["https://tts.example.com".to_string()],
+            proxy: Some("http://proxy.local:3128".to_string()),
+            connect_timeout_secs: 5,
+            request_timeout_secs: 60,
+            api_key: None,
+        };
+        apply_to(&mut settings, "Production", &profile);
+        assert_eq!(settings.servers, profile.servers);
+        assert_eq!(settings.proxy, profile.proxy);
+        assert_eq!((settings.connect_timeout_secs, settings.request_timeout_secs), (5, 60));
+        assert_eq!(settings.active_profile.as_deref(), Some("Production"));
+        assert_eq!(settings.max_text_chars, 42);
+    }
+}
@@ -0,0 +1,321 @@
+//! Client-side throttling so large jobs stay inside the API quota. Every
+//! synthesis request waits here first; when a limit is reached the caller is
+//! delayed, not failed, and a `throttled` event tells the UI why. A separate
+//! cap on requests in flight keeps overlapping conversions from piling onto
+//! the server, announced with `request-queued`.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{Emitter, Manager};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::{settings, AppHandle};
+
+/// Bursts may use up to this fraction of a minute's allowance at once;
+/// anything beyond is spread out at the steady rate.
+const BURST_FRACTION: f64 = 0.25;
+
+/// Delay before retrying a 429 that came without a `Retry-After` header.
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 10;
+
+struct Bucket {
+    tokens: f64,
+    /// Limit the bucket was last refilled for, so a settings change applies at once.
+    per_minute: u32,
+}
+
+impl Bucket {
+    fn capacity(&self) -> f64 {
+        (self.per_minute as f64 * BURST_FRACTION).max(1.0)
+    }
+
+    fn refill(&mut self, per_minute: u32, elapsed: Duration) {
+        if per_minute != self.per_minute {
+            self.per_minute = per_minute;
+            self.tokens = self.capacity();
+        }
+        let rate = per_minute as f64 / 60.0;
+        self.tokens = (self.tokens + rate * elapsed.as_secs_f64()).min(self.capacity());
+    }
+
+    /// How long until `cost` tokens are available; zero when they already are.
+    fn wait_for(&self, cost: f64) -> Duration {
+        if self.per_minute == 0 {
+            return Duration::ZERO;
+        }
+        // A single request larger than the burst can still go once the bucket is full
+        let missing = cost.min(self.capacity()) - self.tokens;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing * 60.0 / self.per_minute as f64)
+        }
+    }
+
+    fn take(&mut self, cost: f64) {
+        if self.per_minute != 0 {
+            self.tokens -= cost.min(self.capacity());
+        }
+    }
+}
+
+struct Buckets {
+    requests: Bucket,
+    characters: Bucket,
+    refilled_at: Instant,
+}
+
+/// Token buckets for requests and characters per minute. The async mutex is
+/// held while waiting, so callers are served in arrival order.
+pub struct RateLimiter(tokio::sync::Mutex<Buckets>);
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter(tokio::sync::Mutex::new(Buckets {
+            requests: Bucket { tokens: 0.0, per_minute: u32::MAX },
+            characters: Bucket { tokens: 0.0, per_minute: u32::MAX },
+            refilled_at: Instant::now(),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Throttled {
+    wait_ms: u64,
+}
+
+/// Waits until a request of `characters` characters fits within the
+/// configured limits, then counts it against them.
+pub async fn acquire(app: &AppHandle, characters: usize) {
+    let limiter = app.state::<RateLimiter>();
+    let mut buckets = limiter.0.lock().await;
+    let cost = characters as f64;
+
+    loop {
+        let settings = settings::current(app);
+        let now = Instant::now();
+        let elapsed = now - buckets.refilled_at;
+        buckets.refilled_at = now;
+        buckets.requests.refill(settings.requests_per_minute, elapsed);
+        buckets.characters.refill(settings.characters_per_minute, elapsed);
+
+        let wait = buckets.requests.wait_for(1.0).max(buckets.characters.wait_for(cost));
+        if wait.is_zero() {
+            buckets.requests.take(1.0);
+            buckets.characters.take(cost);
+            return;
+        }
+
+        let _ = app.emit("throttled", Throttled { wait_ms: wait.as_millis() as u64 });
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Waits out a rate limit the server reported, telling the UI like `acquire` does.
+pub async fn back_off(app: &AppHandle, wait: Duration) {
+    let _ = app.emit("throttled", Throttled { wait_ms: wait.as_millis() as u64 });
+    tokio::time::sleep(wait).await;
+}
+
+/// Number of synthesis requests currently being sent, across all commands.
+#[derive(Default)]
+pub struct InFlight {
+    active: Mutex<usize>,
+    released: Notify,
+    /// Requests waiting for a slot.
+    queued: Mutex<usize>,
+    /// Cancelled by `clear_queue` to turn the waiting requests away.
+    cleared: Mutex<CancellationToken>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RequestQueued {
+    in_flight: usize,
+    limit: usize,
+}
+
+/// A claimed request slot; dropping it lets the next queued request go.
+pub struct RequestSlot {
+    app: AppHandle,
+}
+
+impl Drop for RequestSlot {
+    fn drop(&mut self) {
+        self.app.state::<InFlight>().release();
+    }
+}
+
+impl InFlight {
+    /// Takes a slot when fewer than `limit` are taken (0 meaning no limit),
+    /// otherwise returns how many are.
+    fn try_claim(&self, limit: usize) -> Result<(), usize> {
+        let mut active = self.active.lock().unwrap();
+        if limit == 0 || *active < limit {
+            *active += 1;
+            Ok(())
+        } else {
+            Err(*active)
+        }
+    }
+
+    /// Cancels the token the requests queued so far wait on and puts a fresh
+    /// one in its place for later requests. Returns how many were queued.
+    fn clear(&self) -> usize {
+        let queued = *self.queued.lock().unwrap();
+        std::mem::take(&mut *self.cleared.lock().unwrap()).cancel();
+        queued
+    }
+
+    /// Gives a slot back and wakes the requests waiting for one.
+    fn release(&self) {
+        *self.active.lock().unwrap() -= 1;
+        self.released.notify_waiters();
+    }
+}
+
+/// Takes a request out of the queue count however it leaves it.
+struct QueueEntry<'a>(&'a InFlight);
+
+impl Drop for QueueEntry<'_> {
+    fn drop(&mut self) {
+        *self.0.queued.lock().unwrap() -= 1;
+    }
+}
+
+/// Waits until fewer than `max_concurrent_requests` requests are in flight
+/// and claims a slot. Requests over the limit queue here; none are refused,
+/// but `clear_queue` turns away the ones waiting.
+pub async fn request_slot(app: &AppHandle) -> Result<RequestSlot, String> {
+    let in_flight = app.state::<InFlight>();
+    let cleared = in_flight.cleared.lock().unwrap().clone();
+    let mut entry = None;
+    loop {
+        // Registered before checking, so a release in between isn't missed
+        let released = in_flight.released.notified();
+        let limit = settings::current(app).max_concurrent_requests;
+        match in_flight.try_claim(limit) {
+            Ok(()) => return Ok(RequestSlot { app: app.clone() }),
+            Err(active) if entry.is_none() => {
+                *in_flight.queued.lock().unwrap() += 1;
+                entry = Some(QueueEntry(&in_flight));
+                let _ = app.emit("request-queued", RequestQueued { in_flight: active, limit });
+            }
+            Err(_) => {}
+        }
+        tokio::select! {
+            _ = cleared.cancelled() => return Err("Request cancelled".to_string()),
+            _ = released => {}
+        }
+    }
+}
+
+/// Turns away every request waiting for a slot and returns how many there were.
+pub fn clear_queue(app: &AppHandle) -> usize {
+    app.state::<InFlight>().clear()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_bucket(per_minute: u32) -> Bucket {
+        let mut bucket = Bucket { tokens: 0.0, per_minute: 0 };
+        bucket.refill(per_minute, Duration::ZERO);
+        bucket
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_spreads_requests_out() {
+        let mut bucket = full_bucket(60);
+        assert_eq!(bucket.capacity(), 15.0);
+        for _ in 0..15 {
+            assert!(bucket.wait_for(1.0).is_zero());
+            bucket.take(1.0);
+        }
+        // One per second at 60 a minute
+        assert_eq!(bucket.wait_for(1.0), Duration::from_secs(1));
+        bucket.refill(60, Duration::from_secs(1));
+        assert!(bucket.wait_for(1.0).is_zero());
+    }
+
+    #[test]
+    fn bucket_refill_stops_at_capacity() {
+        let mut bucket = full_bucket(60);
+        bucket.take(10.0);
+        bucket.refill(60, Duration::from_secs(3600));
+        assert_eq!(bucket.tokens, bucket.capacity());
+    }
+
+    #[test]
+    fn bucket_lets_an_oversized_request_through_once_full() {
+        let mut bucket = full_bucket(1000);
+        assert!(bucket.wait_for(5000.0).is_zero());
+        bucket.take(5000.0);
+        assert_eq!(bucket.tokens, 0.0);
+    }
+
+    #[test]
+    fn bucket_without_a_limit_never_waits() {
+        let mut bucket = full_bucket(0);
+        bucket.take(1_000_000.0);
+        assert!(bucket.wait_for(1_000_000.0).is_zero());
+    }
+
+    #[test]
+    fn bucket_starts_full_when_the_limit_changes() {
+        let mut bucket = full_bucket(60);
+        bucket.take(15.0);
+        bucket.refill(120, Duration::ZERO);
+        assert_eq!(bucket.tokens, 30.0);
+    }
+
+    #[test]
+    fn in_flight_slots_stop_at_the_limit() {
+        let in_flight = InFlight::default();
+        assert_eq!(in_flight.try_claim(2), Ok(()));
+        assert_eq!(in_flight.try_claim(2), Ok(()));
+        assert_eq!(in_flight.try_claim(2), Err(2));
+        // A lower limit set while requests run only holds back new ones
+        assert_eq!(in_flight.try_claim(1), Err(2));
+
+        in_flight.release();
+        assert_eq!(in_flight.try_claim(2), Ok(()));
+        assert_eq!(in_flight.try_claim(0), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn releasing_a_slot_wakes_waiting_requests() {
+        let in_flight = InFlight::default();
+        in_flight.try_claim(1).unwrap();
+        let released = in_flight.released.notified();
+        in_flight.release();
+        tokio::time::timeout(Duration::from_secs(1), released).await.unwrap();
+        assert_eq!(in_flight.try_claim(1), Ok(()));
+    }
+
+    #[test]
+    fn queue_entries_count_themselves_out() {
+        let in_flight = InFlight::default();
+        *in_flight.queued.lock().unwrap() += 1;
+        drop(QueueEntry(&in_flight));
+        assert_eq!(*in_flight.queued.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn clearing_the_queue_turns_away_only_waiting_requests() {
+        let in_flight = InFlight::default();
+        let waiting = in_flight.cleared.lock().unwrap().clone();
+        let _first = QueueEntry(&in_flight);
+        *in_flight.queued.lock().unwrap() += 1;
+        let _second = QueueEntry(&in_flight);
+        *in_flight.queued.lock().unwrap() += 1;
+
+        assert_eq!(in_flight.clear(), 2);
+        assert!(waiting.is_cancelled());
+        // Requests arriving afterwards queue as usual
+        assert!(!in_flight.cleared.lock().unwrap().is_cancelled());
+    }
+}
@@ -0,0 +1,223 @@
+//! Starting over without hunting down KIWI's files. Only the app's own
+//! state is touched; audio it wrote to the user's folders is left alone.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tauri::{command, Manager};
+
+use crate::playback::Player;
+use crate::settings::{self, Settings, SettingsState};
+use crate::{cache, capabilities, endpoint, history, presets, secrets, servers, voices, AppHandle};
+
+/// App cache folders holding voice previews and other samples.
+const SAMPLE_DIRS: [&str; 4] = ["voice-preview", "voice-comparison", "pronunciation", "ipa"];
+
+#[derive(Debug, Serialize)]
+pub struct ResetSummary {
+    history_entries: usize,
+    presets: usize,
+    cached_audio_files: usize,
+    sample_files: usize,
+}
+
+/// Keyring entries `settings` refers to: one per profile, then the key used
+/// without a profile when one was stored.
+fn stored_keys(settings: &Settings) -> Vec<Option<&str>> {
+    let profiles = settings.profiles.keys().map(|name| Some(name.as_str()));
+    let default_key = settings.api_key_in_keyring.then_some(None);
+    profiles.chain(default_key).collect()
+}
+
+/// Undoes what the old settings had switched on outside the settings file:
+/// the shortcut, the local endpoint, the playback device and the keyring
+/// entries nothing refers to any more.
+fn release(app: &AppHandle, old: &Settings) {
+    #[cfg(desktop)]
+    if let Err(e) = crate::hotkey::unregister_hotkey(app.clone()) {
+        println!("{}", e);
+    }
+    endpoint::shut_down(app);
+    app.state::<Player>().set_device(None);
+
+    for profile in stored_keys(old) {
+        if let Err(e) = secrets::delete(profile) {
+            println!("{}", e);
+        }
+    }
+    if let Err(e) = secrets::reload(app) {
+        println!("{}", e);
+    }
+}
+
+/// `release`, and drops what was learned from the servers the old settings
+/// pointed at.
+fn forget(app: &AppHandle, old: &Settings) {
+    release(app, old);
+    capabilities::invalidate(app);
+    voices::invalidate(app);
+    servers::reset_health(app);
+}
+
+/// Replaces the settings file with the defaults and applies them, forgetting
+/// saved profiles and their API keys too.
+#[command]
+pub fn reset_settings(app: AppHandle) -> Result<Settings, String> {
+    let old = settings::current(&app);
+    let settings = settings::update(&app, |s| *s = Settings::default())?;
+    forget(&app, &old);
+    Ok(settings)
+}
+
+fn count_files(dir: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => count_files(&entry.path()),
+            Ok(kind) if kind.is_file() => 1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Removes the `SAMPLE_DIRS` under `cache_dir` and returns how many files
+/// they held; missing ones count as empty.
+fn remove_sample_dirs(cache_dir: &Path) -> Result<usize, String> {
+    let mut removed = 0;
+    for name in SAMPLE_DIRS {
+        let dir = cache_dir.join(name);
+        let files = count_files(&dir);
+        match std::fs::remove_dir_all(&dir) {
+            Ok(()) => removed += files,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove {}: {}", dir.display(), e)),
+        }
+    }
+    Ok(removed)
+}
+
+/// The app folders holding the files `reset_all` empties.
+struct AppDirs {
+    config: PathBuf,
+    data: PathBuf,
+    cache: PathBuf,
+}
+
+fn app_dirs(app: &AppHandle) -> Result<AppDirs, String> {
+    let path = app.path();
+    Ok(AppDirs {
+        config: path.app_config_dir().map_err(|e| format!("Cannot determine config directory: {}", e))?,
+        data: path.app_data_dir().map_err(|e| format!("Cannot determine data directory: {}", e))?,
+        cache: path.app_cache_dir().map_err(|e| format!("Cannot determine cache directory: {}", e))?,
+    })
+}
+
+/// Writes the default settings, empties the history and presets files and
+/// removes the cached audio and voice samples, all under `dirs`.
+fn reset_files(dirs: &AppDirs) -> Result<ResetSummary, String> {
+    settings::reset_in(&dirs.config)?;
+    Ok(ResetSummary {
+        history_entries: history::clear_in(&dirs.data)?,
+        presets: presets::clear_in(&dirs.config)?,
+        cached_audio_files: cache::clear_in(&dirs.cache)?,
+        sample_files: remove_sample_dirs(&dirs.cache)?,
+    })
+}
+
+/// `reset_settings`, and also empties the history, presets, audio cache and
+/// voice samples. Does nothing unless `confirm` is set, since none of it can
+/// be undone.
+#[command]
+pub fn reset_all(app: AppHandle, confirm: bool) -> Result<ResetSummary, String> {
+    if !confirm {
+        return Err("Resetting erases settings, history, presets and caches; confirm to go ahead".to_string());
+    }
+    let old = settings::current(&app);
+    let summary = reset_files(&app_dirs(&app)?)?;
+    *app.state::<SettingsState>().0.lock().unwrap() = Settings::default();
+    app.state::<history::HistoryState>().0.lock().unwrap().clear();
+    forget(&app, &old);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_keys_covers_profiles_and_the_default_key() {
+        let mut settings = Settings::default();
+        assert!(stored_keys(&settings).is_empty());
+
+        settings.profiles.insert("Production".to_string(), Default::default());
+        settings.profiles.insert("Local".to_string(), Default::default());
+        settings.api_key_in_keyring = true;
+        let mut keys = stored_keys(&settings);
+        keys.sort();
+        assert_eq!(keys, vec![None, Some("Local"), Some("Production")]);
+    }
+
+    #[test]
+    fn remove_sample_dirs_counts_nested_files() {
+        let cache = tempfile::tempdir().unwrap();
+        let previews = cache.path().join("voice-preview");
+        std::fs::create_dir_all(previews.join("en-US")).unwrap();
+        std::fs::write(previews.join("a.mp3"), b"a").unwrap();
+        std::fs::write(previews.join("en-US").join("b.mp3"), b"b").unwrap();
+        std::fs::create_dir_all(cache.path().join("ipa")).unwrap();
+        std::fs::write(cache.path().join("ipa").join("c.mp3"), b"c").unwrap();
+        let kept = cache.path().join("audio");
+        std::fs::create_dir_all(&kept).unwrap();
+        std::fs::write(kept.join("d.mp3"), b"d").unwrap();
+
+        assert_eq!(remove_sample_dirs(cache.path()), Ok(3));
+        assert!(!previews.exists() && !cache.path().join("ipa").exists());
+        assert_eq!(count_files(cache.path()), 1);
+        assert_eq!(remove_sample_dirs(cache.path()), Ok(0));
+    }
+
+    #[test]
+    fn reset_files_restores_defaults_and_empties_the_rest() {
+        let root = tempfile::tempdir().unwrap();
+        let dirs = AppDirs {
+            config: root.path().join("config"),
+            data: root.path().join("data"),
+            cache: root.path().join("cache"),
+        };
+        let altered = Settings {
+            default_voice: "en-GB-Chirp3-HD-Kore".to_string(),
+            audio_cache_max_mb: 20,
+            ..Settings::default()
+        };
+        std::fs::create_dir_all(&dirs.config).unwrap();
+        std::fs::write(dirs.config.join("settings.json"), serde_json::to_string(&altered).unwrap()).unwrap();
+        std::fs::write(dirs.config.join("presets.json"), r#"{"Calm": {"speaking_rate": 0.9}}"#).unwrap();
+        let entry = history::HistoryEntry::new("Hi", "en-US-Chirp3-HD-Charon", "MP3", "en-US", "/tmp/hi.mp3", None);
+        std::fs::create_dir_all(&dirs.data).unwrap();
+        std::fs::write(dirs.data.join("history.json"), serde_json::to_string(&[entry.clone(), entry]).unwrap()).unwrap();
+        let audio_cache = dirs.cache.join("audio-cache");
+        std::fs::create_dir_all(&audio_cache).unwrap();
+        std::fs::write(audio_cache.join("a.mp3"), b"a").unwrap();
+        std::fs::write(audio_cache.join("b.wav"), b"b").unwrap();
+        std::fs::create_dir_all(dirs.cache.join("voice-preview")).unwrap();
+        std::fs::write(dirs.cache.join("voice-preview").join("c.mp3"), b"c").unwrap();
+
+        let summary = reset_files(&dirs).unwrap();
+        assert_eq!(
+            (summary.history_entries, summary.presets, summary.cached_audio_files, summary.sample_files),
+            (2, 1, 2, 1)
+        );
+        let read = |path: PathBuf| serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(read(dirs.config.join("settings.json")), serde_json::to_value(Settings::default()).unwrap());
+        assert_eq!(read(dirs.config.join("presets.json")), serde_json::json!({}));
+        assert_eq!(read(dirs.data.join("history.json")), serde_json::json!([]));
+        assert_eq!(count_files(&dirs.cache), 0);
+
+        // Nothing left to reset the second time
+        let again = reset_files(&dirs).unwrap();
+        assert_eq!((again.history_entries, again.presets, again.cached_audio_files, again.sample_files), (0, 0, 0, 0));
+    }
+}
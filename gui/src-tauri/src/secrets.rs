@@ -0,0 +1,173 @@
+//! The API key sent to the servers as a bearer token, kept in the OS secret
+//! store (Keychain, Credential Manager or Secret Service) rather than the
+//! settings file. Each backend profile has its own key; the one set while
+//! no profile is active is used otherwise. Where no keyring is available,
+//! e.g. on headless Linux, a key only lasts until the app closes.
+
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{command, Manager};
+
+use crate::{settings, AppHandle};
+
+const KEYRING_SERVICE: &str = "com.kiwi.app";
+
+/// Keyring account of the key used while no profile is active.
+const DEFAULT_ACCOUNT: &str = "default";
+
+/// Key in use, read from the keyring on startup and when switching profiles
+/// rather than on every request.
+#[derive(Default)]
+pub struct ActiveApiKey(Mutex<Option<String>>);
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyStatus {
+    /// Whether the key went to the keyring and will be there next time.
+    persisted: bool,
+    warning: Option<String>,
+}
+
+pub fn active_api_key(app: &AppHandle) -> Option<String> {
+    app.state::<ActiveApiKey>().0.lock().unwrap().clone()
+}
+
+fn set_active(app: &AppHandle, api_key: Option<String>) {
+    *app.state::<ActiveApiKey>().0.lock().unwrap() = api_key;
+}
+
+fn account(profile: Option<&str>) -> String {
+    match profile {
+        Some(name) => format!("profile:{}", name),
+        None => DEFAULT_ACCOUNT.to_string(),
+    }
+}
+
+fn describe(profile: Option<&str>) -> String {
+    match profile {
+        Some(name) => format!("the API key of profile '{}'", name),
+        None => "the API key".to_string(),
+    }
+}
+
+fn entry(profile: Option<&str>) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, &account(profile)).map_err(|e| format!("Cannot open the system keyring: {}", e))
+}
+
+pub fn read(profile: Option<&str>) -> Result<Option<String>, String> {
+    match entry(profile)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Cannot read {} from the keyring: {}", describe(profile), e)),
+    }
+}
+
+pub fn delete(profile: Option<&str>) -> Result<(), String> {
+    match entry(profile)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Cannot remove {} from the keyring: {}", describe(profile), e)),
+    }
+}
+
+/// Stores `key`, or removes the stored one when it is empty.
+pub fn store(profile: Option<&str>, key: &str) -> Result<(), String> {
+    if key.trim().is_empty() {
+        return delete(profile);
+    }
+    entry(profile)?
+        .set_password(key.trim())
+        .map_err(|e| format!("Cannot store {} in the keyring: {}", describe(profile), e))
+}
+
+/// Reads the key that belongs with the current settings into memory.
+pub fn reload(app: &AppHandle) -> Result<(), String> {
+    let settings = settings::current(app);
+    let api_key = match settings.active_profile.as_deref() {
+        Some(name) => read(Some(name))?,
+        None if settings.api_key_in_keyring => read(None)?,
+        None => None,
+    };
+    set_active(app, api_key);
+    Ok(())
+}
+
+/// Moves a plaintext `api_key` left in the settings file into the keyring
+/// and scrubs it from the file, then loads the key in use. Without a
+/// keyring the plaintext key stays where it is, with a warning, so it isn't
+/// lost.
+pub fn restore(app: &AppHandle) {
+    if let Some(api_key) = settings::current(app).api_key {
+        let migrated = store(None, &api_key).and_then(|_| {
+            settings::update(app, |s| {
+                s.api_key = None;
+                s.api_key_in_keyring = !api_key.trim().is_empty();
+            })
+        });
+        if let Err(e) = migrated {
+            println!("Leaving the API key in the settings file as plain text: {}", e);
+            if settings::current(app).active_profile.is_none() {
+                set_active(app, Some(api_key));
+            }
+            return;
+        }
+    }
+    if let Err(e) = reload(app) {
+        println!("{}; requests go out without an API key", e);
+    }
+}
+
+/// Sets the API key of the active profile, or the one used without a
+/// profile, and starts sending it. An empty key removes it. When no keyring
+/// is available the key is still used, but only until the app closes.
+#[command]
+pub fn set_api_key(app: AppHandle, key: String) -> Result<ApiKeyStatus, String> {
+    let settings = settings::current(&app);
+    let profile = settings.active_profile.as_deref();
+    let key = key.trim().to_string();
+    let status = match store(profile, &key) {
+        Ok(()) => ApiKeyStatus {
+            persisted: true,
+            warning: None,
+        },
+        Err(e) => ApiKeyStatus {
+            persisted: false,
+            warning: Some(format!("{}; the key is only used until KIWI closes", e)),
+        },
+    };
+    if profile.is_none() && status.persisted {
+        settings::update(&app, |s| {
+            s.api_key_in_keyring = !key.is_empty();
+            s.api_key = None;
+        })?;
+    }
+    set_active(&app, Some(key).filter(|key| !key.is_empty()));
+    Ok(status)
+}
+
+/// The key currently sent to the servers, if any.
+#[command]
+pub fn get_api_key(app: AppHandle) -> Option<String> {
+    active_api_key(&app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_profile_has_its_own_account() {
+        assert_eq!(account(None), "default");
+        assert_eq!(account(Some("Production")), "profile:Production");
+        assert_eq!(describe(None), "the API key");
+        assert_eq!(describe(Some("Production")), "the API key of profile 'Production'");
+    }
+
+    #[test]
+    fn an_empty_key_removes_the_stored_one() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        assert_eq!(store(None, "  "), Ok(()));
+        assert_eq!(store(Some("Production"), ""), Ok(()));
+        assert_eq!(read(Some("Production")), Ok(None));
+        assert_eq!(delete(None), Ok(()));
+    }
+}
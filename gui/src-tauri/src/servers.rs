@@ -0,0 +1,264 @@
+//! Spreads synthesis requests over the API servers listed in settings.
+//! Each request starts at the next server in round-robin order, skips any
+//! that fail a health check and moves on to the next one when a request
+//! can't get through.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{command, Manager};
+
+use crate::{capabilities, client, settings, voices, AppHandle, API_BASE_URL};
+
+/// How long a health check result is trusted before asking again.
+const HEALTH_TTL: Duration = Duration::from_secs(30);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often the keep-alive loop looks at `keep_alive_secs` while it is off.
+const KEEP_ALIVE_IDLE_CHECK: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct PoolState {
+    next: usize,
+    /// Last health check result and when it was taken, per server URL.
+    health: HashMap<String, (bool, Instant)>,
+}
+
+#[derive(Default)]
+pub struct ServerPool(Mutex<PoolState>);
+
+#[derive(Debug, Serialize)]
+pub struct ServerStatus {
+    url: String,
+    /// `None` until the server has been checked.
+    healthy: Option<bool>,
+}
+
+/// The configured servers, or the built-in local server when none are.
+pub fn configured(app: &AppHandle) -> Vec<String> {
+    let servers = settings::current(app).servers;
+    if servers.is_empty() {
+        vec![API_BASE_URL.to_string()]
+    } else {
+        servers
+    }
+}
+
+/// Orders `servers` for one request: rotated to start at `start`, with the
+/// ones `is_down` reports moved to the back so they are only tried last.
+pub fn order(servers: &[String], start: usize, is_down: impl Fn(&str) -> bool) -> Vec<String> {
+    if servers.is_empty() {
+        return Vec::new();
+    }
+    let start = start % servers.len();
+    let rotated = servers[start..].iter().chain(&servers[..start]).cloned();
+    let (up, down): (Vec<String>, Vec<String>) = rotated.partition(|url| !is_down(url));
+    up.into_iter().chain(down).collect()
+}
+
+fn cached_health(app: &AppHandle, url: &str) -> Option<bool> {
+    let pool = app.state::<ServerPool>();
+    let state = pool.0.lock().unwrap();
+    state
+        .health
+        .get(url)
+        .filter(|(_, checked)| checked.elapsed() < HEALTH_TTL)
+        .map(|(healthy, _)| *healthy)
+}
+
+fn set_health(app: &AppHandle, url: &str, healthy: bool) {
+    let pool = app.state::<ServerPool>();
+    pool.0.lock().unwrap().health.insert(url.to_string(), (healthy, Instant::now()));
+}
+
+/// Records a server that just failed a request so the next ones avoid it.
+pub fn mark_unhealthy(app: &AppHandle, url: &str) {
+    set_health(app, url, false);
+}
+
+/// Forgets every health check, e.g. after switching to another backend.
+pub fn reset_health(app: &AppHandle) {
+    app.state::<ServerPool>().0.lock().unwrap().health.clear();
+}
+
+async fn is_healthy(app: &AppHandle, client: &reqwest::Client, url: &str) -> bool {
+    if let Some(healthy) = cached_health(app, url) {
+        return healthy;
+    }
+    let healthy = client
+        .get(format!("{}/", url))
+        .timeout(HEALTH_TIMEOUT)
+        .send()
+        .await
+        .is_ok_and(|response| response.status().is_success());
+    set_health(app, url, healthy);
+    healthy
+}
+
+/// Servers to try for the next request, healthy ones first. Servers failing
+/// their health check stay at the end so a request still has somewhere to
+/// go when every check fails.
+pub async fn candidates(app: &AppHandle, client: &reqwest::Client) -> Vec<String> {
+    let start = {
+        let pool = app.state::<ServerPool>();
+        let mut state = pool.0.lock().unwrap();
+        state.next = state.next.wrapping_add(1);
+        state.next - 1
+    };
+    ranked(app, client, start).await
+}
+
+/// `configured` servers in `order` from `start`, after checking the health
+/// of each when there is more than one.
+async fn ranked(app: &AppHandle, client: &reqwest::Client, start: usize) -> Vec<String> {
+    let servers = configured(app);
+    let mut down = Vec::new();
+    if servers.len() > 1 {
+        for url in &servers {
+            if !is_healthy(app, client, url).await {
+                down.push(url.clone());
+            }
+        }
+    }
+    order(&servers, start, |url| down.iter().any(|d| d == url))
+}
+
+/// The server to use for requests that aren't load balanced, like voice
+/// lists, capabilities and streams: the first healthy one in settings order.
+/// It doesn't take a turn in the round robin, so these requests don't shift
+/// which server the next conversion starts at.
+pub async fn primary(app: &AppHandle, client: &reqwest::Client) -> String {
+    ranked(app, client, 0)
+        .await
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| API_BASE_URL.to_string())
+}
+
+/// Sends each configured server a health request through the shared client,
+/// leaving an open connection in its pool for the next conversion. Servers
+/// that don't answer are only noted as unhealthy.
+async fn warm(app: &AppHandle) {
+    if settings::current(app).offline_mode {
+        return;
+    }
+    let client = client::build(app);
+    for url in configured(app) {
+        let healthy = client
+            .get(format!("{}/", url))
+            .timeout(HEALTH_TIMEOUT)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success());
+        set_health(app, &url, healthy);
+    }
+}
+
+/// Keeps warming the servers every `keep_alive_secs` for as long as the app
+/// runs, picking up changes to the setting as it goes. reqwest drops idle
+/// connections after 90 seconds, so longer intervals only save the odd
+/// handshake.
+pub fn start_keep_alive(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let interval = settings::current(&app).keep_alive_secs;
+            if interval == 0 {
+                tokio::time::sleep(KEEP_ALIVE_IDLE_CHECK).await;
+                continue;
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            if settings::current(&app).keep_alive_secs > 0 {
+                warm(&app).await;
+            }
+        }
+    });
+}
+
+/// Opens connections to the API servers ahead of the first conversion.
+/// Does nothing visible when they can't be reached.
+#[command]
+pub async fn warm_connection(app: AppHandle) {
+    warm(&app).await;
+}
+
+pub fn normalize_url(url: &str) -> Result<String, String> {
+    let url = url.trim().trim_end_matches('/');
+    if !(url.starts_with("http://") || url.starts_with("https://")) || url.len() <= "https://".len() {
+        return Err(format!("Invalid server URL '{}'; it must start with http:// or https://", url));
+    }
+    Ok(url.to_string())
+}
+
+#[command]
+pub fn list_servers(app: AppHandle) -> Vec<ServerStatus> {
+    configured(&app)
+        .into_iter()
+        .map(|url| ServerStatus {
+            healthy: cached_health(&app, &url),
+            url,
+        })
+        .collect()
+}
+
+/// Adds a server to the pool. The first addition keeps the built-in local
+/// server in the list so configuring a second one doesn't drop it.
+#[command]
+pub fn add_server(app: AppHandle, url: String) -> Result<Vec<String>, String> {
+    let url = normalize_url(&url)?;
+    let settings = settings::update(&app, |s| {
+        if s.servers.is_empty() {
+            s.servers.push(API_BASE_URL.to_string());
+        }
+        if !s.servers.contains(&url) {
+            s.servers.push(url);
+        }
+    })?;
+    capabilities::invalidate(&app);
+    voices::invalidate(&app);
+    Ok(settings.servers)
+}
+
+/// Removing every server falls back to the built-in local one.
+#[command]
+pub fn remove_server(app: AppHandle, url: String) -> Result<Vec<String>, String> {
+    let url = url.trim().trim_end_matches('/').to_string();
+    if !configured(&app).contains(&url) {
+        return Err(format!("Server {} is not configured", url));
+    }
+    let settings = settings::update(&app, |s| s.servers.retain(|server| *server != url))?;
+    capabilities::invalidate(&app);
+    voices::invalidate(&app);
+    Ok(settings.servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn order_rotates_from_start() {
+        let servers = urls(&["a", "b", "c"]);
+        assert_eq!(order(&servers, 0, |_| false), urls(&["a", "b", "c"]));
+        assert_eq!(order(&servers, 1, |_| false), urls(&["b", "c", "a"]));
+        assert_eq!(order(&servers, 5, |_| false), urls(&["c", "a", "b"]));
+    }
+
+    #[test]
+    fn order_moves_down_servers_to_the_back() {
+        let servers = urls(&["a", "b", "c"]);
+        assert_eq!(order(&servers, 0, |url| url == "a"), urls(&["b", "c", "a"]));
+        assert_eq!(order(&servers, 1, |url| url != "a"), urls(&["a", "b", "c"]));
+        assert_eq!(order(&servers, 2, |_| true), urls(&["c", "a", "b"]));
+    }
+
+    #[test]
+    fn order_of_no_servers_is_empty() {
+        assert!(order(&[], 3, |_| false).is_empty());
+    }
+}
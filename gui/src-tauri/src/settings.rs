@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager, State};
+
+use crate::announce::QuietHours;
+use crate::client;
+use crate::profiles::Profile;
+use crate::AppHandle;
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// User preferences persisted as JSON in the app config directory. Every field
+/// has a default so settings files from older versions keep loading.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Voice used when a feature has no explicit voice, such as the clipboard hotkey.
+    pub default_voice: String,
+    pub default_language: String,
+    /// Refuse a conversion whose language doesn't match the voice instead of
+    /// switching to the voice's language.
+    pub strict_language: bool,
+    /// Accelerator for reading the clipboard aloud, e.g. `CommandOrControl+Shift+K`.
+    /// `None` keeps the global shortcut feature disabled.
+    pub global_hotkey: Option<String>,
+    /// Id of the playback device chosen in `set_output_device`; `None` follows the system default.
+    pub output_device: Option<String>,
+    /// Synthesize with the OS speech engine and never contact the API server.
+    pub offline_mode: bool,
+    /// How often to ask the server about an asynchronous synthesis job.
+    pub status_poll_interval_ms: u64,
+    /// When the requested voice no longer exists, retry once with the closest
+    /// available one instead of failing.
+    pub auto_substitute_voice: bool,
+    /// Client-side limits on synthesis requests; 0 turns a limit off.
+    pub requests_per_minute: u32,
+    pub characters_per_minute: u32,
+    /// Billable characters left in this month's quota, as last entered by the
+    /// user, for `estimate_batch` to warn against; `None` skips the check.
+    pub character_budget_remaining: Option<u64>,
+    /// How often a request the API answers with 429 is retried; 0 reports it straight away.
+    pub rate_limit_retries: u32,
+    /// Synthesis requests allowed in flight at once; more wait their turn. 0 means no limit.
+    pub max_concurrent_requests: usize,
+    /// API server base URLs to balance requests over; empty means the built-in local server.
+    pub servers: Vec<String>,
+    /// `User-Agent` sent to the API servers; `None` sends reqwest's default.
+    pub user_agent: Option<String>,
+    /// Extra headers sent with every request to the API servers, e.g. for a gateway.
+    pub extra_headers: HashMap<String, String>,
+    /// Proxy for requests to the API servers, e.g. `http://proxy:3128`;
+    /// `None` uses the system proxy settings.
+    pub proxy: Option<String>,
+    /// Limits on connecting to an API server and on a whole request,
+    /// download included; 0 turns a limit off.
+    pub connect_timeout_secs: u64,
+    pub request_timeout_secs: u64,
+    /// How often to ping the primary server so its connection stays open
+    /// between conversions; 0 turns the pings off.
+    pub keep_alive_secs: u64,
+    /// Saved backend profiles by name; see `switch_profile`.
+    pub profiles: HashMap<String, Profile>,
+    /// Profile the connection settings were last switched to, if any.
+    pub active_profile: Option<String>,
+    /// Whether `set_api_key` stored a key for use without a profile in the
+    /// OS keyring. The key itself is never written here.
+    pub api_key_in_keyring: bool,
+    /// Plaintext key from older versions, moved to the keyring on startup.
+    /// It only stays when no keyring is available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Longest text `convert_text_to_speech` accepts; 0 disables the check.
+    pub max_text_chars: usize,
+    /// Finished conversions `list_jobs` keeps reporting; the oldest are
+    /// forgotten first.
+    pub max_finished_jobs: usize,
+    /// JSON file replacing the bundled fallback voices: lists of voices,
+    /// shaped like the server's, keyed by language code.
+    pub fallback_voices_path: Option<String>,
+    /// Folder a bare file name given to `convert_text_to_speech` is saved in.
+    pub default_output_dir: Option<String>,
+    /// Accept text without letters or digits, such as a lone emoji some
+    /// voices read by name, instead of refusing it as nothing to speak.
+    pub allow_symbol_only_text: bool,
+    /// How many chunks of one long conversion are synthesized and downloaded at once.
+    pub chunk_download_concurrency: usize,
+    /// Buffer downloads are written to disk through, in bytes, and the least
+    /// they advance between progress events; kept within 4 KiB to 16 MiB.
+    pub download_chunk_bytes: usize,
+    /// Check that downloaded audio has a valid header and, for MP3 and M4A, a
+    /// first frame that decodes, failing the conversion otherwise. Off saves
+    /// a little time.
+    pub verify_audio: bool,
+    /// Port of the local `/speak` endpoint; `None` keeps it off.
+    pub local_endpoint_port: Option<u16>,
+    /// Size limit of the synthesized-audio cache; 0 turns the cache off.
+    pub audio_cache_max_mb: u64,
+    /// Speak status changes such as a finished conversion aloud.
+    pub announce_status: bool,
+    /// API voice for announcements when there is no local speech engine;
+    /// `None` uses `default_voice`.
+    pub announcement_voice: Option<String>,
+    /// Daily window in which status announcements stay silent and finished
+    /// files aren't revealed; `None` has none.
+    pub quiet_hours: Option<QuietHours>,
+    /// Show each finished file in the file manager, or a batch's last one,
+    /// outside quiet hours.
+    pub auto_reveal_on_complete: bool,
+    /// Voice preview phrases by language code, e.g. `"ja-JP"` or just `"ja"`,
+    /// replacing the built-in ones.
+    pub preview_phrases: HashMap<String, String>,
+    /// Sentence `test_pronunciation` says a word in, with `{word}` marking its place.
+    pub pronunciation_carrier: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            default_voice: "en-US-Chirp3-HD-Charon".to_string(),
+            default_language: "en-US".to_string(),
+            strict_language: false,
+            global_hotkey: None,
+            output_device: None,
+            offline_mode: false,
+            status_poll_interval_ms: 1000,
+            auto_substitute_voice: false,
+            requests_per_minute: 200,
+            characters_per_minute: 200_000,
+            character_budget_remaining: None,
+            rate_limit_retries: 0,
+            max_concurrent_requests: 3,
+            servers: Vec::new(),
+            user_agent: None,
+            extra_headers: HashMap::new(),
+            proxy: None,
+            connect_timeout_secs: 0,
+            request_timeout_secs: 0,
+            keep_alive_secs: 0,
+            profiles: HashMap::new(),
+            active_profile: None,
+            api_key_in_keyring: false,
+            api_key: None,
+            max_text_chars: 1_000_000,
+            max_finished_jobs: 50,
+            fallback_voices_path: None,
+            default_output_dir: None,
+            allow_symbol_only_text: false,
+            chunk_download_concurrency: 4,
+            download_chunk_bytes: 64 * 1024,
+            verify_audio: true,
+            local_endpoint_port: None,
+            audio_cache_max_mb: 500,
+            announce_status: false,
+            announcement_voice: None,
+            quiet_hours: None,
+            auto_reveal_on_complete: false,
+            preview_phrases: HashMap::new(),
+            pronunciation_carrier: "The word is {word}.".to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SettingsState(pub Mutex<Settings>);
+
+fn settings_path(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join(SETTINGS_FILE))
+        .map_err(|e| format!("Cannot determine config directory: {}", e))
+}
+
+/// Reads the settings file, falling back to defaults when it is missing or unreadable.
+pub fn load(app: &AppHandle) -> Settings {
+    let Ok(path) = settings_path(app) else {
+        return Settings::default();
+    };
+
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            println!("Ignoring invalid settings file {}: {}", path.display(), e);
+            Settings::default()
+        }),
+        Err(_) => Settings::default(),
+    }
+}
+
+pub fn save(app: &AppHandle, settings: &Settings) -> Result<(), String> {
+    write(&settings_path(app)?, settings)
+}
+
+/// Replaces the settings file in `config_dir` with the defaults.
+pub fn reset_in(config_dir: &Path) -> Result<Settings, String> {
+    let settings = Settings::default();
+    write(&config_dir.join(SETTINGS_FILE), &settings)?;
+    Ok(settings)
+}
+
+fn write(path: &Path, settings: &Settings) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create config directory: {}", e))?;
+    }
+    let json = serde_json::to_string_pretty(settings).map_err(|e| format!("Failed to encode settings: {}", e))?;
+    std::fs::write(path, json).map_err(|e| format!("Failed to save settings: {}", e))
+}
+
+/// Returns a snapshot of the current settings.
+pub fn current(app: &AppHandle) -> Settings {
+    app.state::<SettingsState>().0.lock().unwrap().clone()
+}
+
+/// Applies `change` to the in-memory settings and persists the result.
+pub fn update(app: &AppHandle, change: impl FnOnce(&mut Settings)) -> Result<Settings, String> {
+    let state = app.state::<SettingsState>();
+    let mut settings = state.0.lock().unwrap();
+    change(&mut settings);
+    save(app, &settings)?;
+    Ok(settings.clone())
+}
+
+#[command]
+pub fn get_settings(state: State<'_, SettingsState>) -> Settings {
+    state.0.lock().unwrap().clone()
+}
+
+/// Replaces the settings. The hotkey is left alone because it only takes
+/// effect through `register_hotkey`/`unregister_hotkey`, and a leftover
+/// plaintext API key because only `set_api_key` replaces it.
+#[command]
+pub fn update_settings(app: AppHandle, settings: Settings) -> Result<Settings, String> {
+    client::validate(&settings)?;
+    if let Some(quiet_hours) = &settings.quiet_hours {
+        quiet_hours.validate()?;
+    }
+    update(&app, |current| {
+        let global_hotkey = current.global_hotkey.take();
+        let api_key = current.api_key.take();
+        *current = settings;
+        current.global_hotkey = global_hotkey;
+        current.api_key = api_key;
+    })
+}
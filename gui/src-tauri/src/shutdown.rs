@@ -0,0 +1,136 @@
+//! Winding down when the app quits: running conversions and streams are
+//! cancelled, the local endpoint stops, half-downloaded `.part` files are
+//! removed and settings and history are written out one last time. Closing
+//! the window while work is running first asks the frontend to confirm.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{command, Emitter, Manager, RunEvent, Window, WindowEvent};
+
+use crate::{endpoint, history, jobs, settings, stream, AppHandle};
+
+/// Longest wait for cancelled work to wind down, so a hung request can't
+/// keep the app from quitting.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+pub struct Shutdown {
+    /// Temporary files being written that shouldn't outlive the app.
+    temp_files: Mutex<HashSet<PathBuf>>,
+    /// The user agreed to close the window despite running work.
+    confirmed: AtomicBool,
+    done: AtomicBool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CloseRequested {
+    running_jobs: usize,
+}
+
+/// Registers a temporary file to delete if the app quits before it is renamed
+/// into place.
+pub fn track_temp_file(app: &AppHandle, path: &Path) {
+    app.state::<Shutdown>().temp_files.lock().unwrap().insert(path.to_path_buf());
+}
+
+pub fn untrack_temp_file(app: &AppHandle, path: &Path) {
+    app.state::<Shutdown>().temp_files.lock().unwrap().remove(path);
+}
+
+/// Deletes the given temporary files, skipping ones already gone, and
+/// returns how many were removed.
+pub fn remove_temp_files(paths: impl IntoIterator<Item = PathBuf>) -> usize {
+    paths
+        .into_iter()
+        .filter(|path| match std::fs::remove_file(path) {
+            Ok(()) => true,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => {
+                println!("Failed to remove {}: {}", path.display(), e);
+                false
+            }
+        })
+        .count()
+}
+
+fn running_jobs(app: &AppHandle) -> usize {
+    app.state::<jobs::JobRegistry>().len() + app.state::<stream::StreamRegistry>().len()
+}
+
+/// Cancels running work, waits up to `SHUTDOWN_TIMEOUT` for it to stop and
+/// cleans up after it. Runs once however many exit paths reach it.
+fn shut_down(app: &AppHandle) {
+    let state = app.state::<Shutdown>();
+    if state.done.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    app.state::<jobs::JobRegistry>().cancel_all();
+    app.state::<stream::StreamRegistry>().cancel_all();
+    endpoint::shut_down(app);
+
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while running_jobs(app) > 0 && Instant::now() < deadline {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    let temp_files: Vec<PathBuf> = state.temp_files.lock().unwrap().drain().collect();
+    remove_temp_files(temp_files);
+
+    if let Err(e) = settings::save(app, &settings::current(app)) {
+        println!("{}", e);
+    }
+    if let Err(e) = history::flush(app) {
+        println!("{}", e);
+    }
+}
+
+/// Holds a window open while work is running and asks the frontend, via
+/// `close-requested`, whether to close anyway with `confirm_exit`.
+pub fn on_window_event(window: &Window<crate::Runtime>, event: &WindowEvent) {
+    if let WindowEvent::CloseRequested { api, .. } = event {
+        let app = window.app_handle();
+        let running = running_jobs(app);
+        if running > 0 && !app.state::<Shutdown>().confirmed.load(Ordering::SeqCst) {
+            api.prevent_close();
+            let _ = app.emit("close-requested", CloseRequested { running_jobs: running });
+        }
+    }
+}
+
+pub fn on_run_event(app: &AppHandle, event: RunEvent) {
+    if let RunEvent::ExitRequested { .. } | RunEvent::Exit = event {
+        shut_down(app);
+    }
+}
+
+/// Quits even though work is running, after the user confirmed it.
+#[command]
+pub fn confirm_exit(app: AppHandle) {
+    app.state::<Shutdown>().confirmed.store(true, Ordering::SeqCst);
+    shut_down(&app);
+    app.exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_temp_files_counts_only_what_it_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let part = dir.path().join("speech.mp3.part");
+        std::fs::write(&part, b"half").unwrap();
+        let gone = dir.path().join("done.mp3.part");
+
+        assert_eq!(remove_temp_files([part.clone(), gone]), 1);
+        assert!(!part.exists());
+        assert_eq!(remove_temp_files([part]), 0);
+    }
+}
@@ -0,0 +1,690 @@
+//! Building blocks for an SSML editor: inserting tags into a document while
+//! keeping it well-formed. Positions are UTF-16 offsets, as the frontend's
+//! text fields report them, and must fall in text content inside the root
+//! element, not within a tag or entity. Also synthesizes IPA transcriptions
+//! through `<phoneme>` on servers that accept SSML, and turns the inline
+//! markup described at `markup_to_ssml` into SSML. The bundled Python server
+//! doesn't accept SSML, as Chirp 3 HD voices don't, so all of these need a
+//! server whose capabilities say it does.
+
+use std::ops::Range;
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager};
+
+use crate::capabilities::Capabilities;
+use crate::{audio, capabilities, client, paths, settings, synthesize_bytes, text, AppHandle, TTSRequest};
+
+/// Longest break SSML engines honour.
+const MAX_BREAK_MS: u64 = 10_000;
+
+const EMPHASIS_LEVELS: [&str; 4] = ["strong", "moderate", "reduced", "none"];
+
+const MAX_IPA_CHARS: usize = 500;
+
+/// Added to errors about servers without SSML, since the bundled server is
+/// one of them: Chirp 3 HD voices only read plain text.
+pub const NEEDS_SSML_SERVER: &str = "KIWI's bundled server reads only plain text, so connect a server that supports SSML";
+
+/// How the text of a conversion is read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputType {
+    /// Plain text, read as written.
+    #[default]
+    Text,
+    /// A complete SSML document.
+    Ssml,
+    /// Plain text with the inline markup of `markup_to_ssml`.
+    Markup,
+}
+
+/// Elements and attributes assumed when a server accepts SSML without
+/// listing what it supports: the subset Google Cloud Text-to-Speech reads.
+const CORE_SSML: [(&str, &[&str]); 14] = [
+    ("speak", &[]),
+    ("p", &[]),
+    ("s", &[]),
+    ("break", &["time", "strength"]),
+    ("say-as", &["interpret-as", "format", "detail", "language"]),
+    ("sub", &["alias"]),
+    ("emphasis", &["level"]),
+    ("prosody", &["rate", "pitch", "volume"]),
+    ("phoneme", &["alphabet", "ph"]),
+    ("audio", &["src", "clipBegin", "clipEnd", "speed", "repeatCount", "repeatDur", "soundLevel"]),
+    ("mark", &["name"]),
+    ("voice", &["name", "gender", "variant", "language"]),
+    ("lang", &[]),
+    ("desc", &[]),
+];
+
+/// Markup of a parsed document: the byte range of every tag, entity, comment
+/// and so on, with the element depth right after it.
+struct Markup {
+    spans: Vec<(Range<usize>, usize)>,
+}
+
+impl Markup {
+    /// Parses `ssml`, failing unless it is well-formed with a single root element.
+    fn parse(ssml: &str) -> Result<Markup, String> {
+        let mut reader = Reader::from_str(ssml);
+        let mut spans = Vec::new();
+        let mut depth: usize = 0;
+        let mut roots = 0;
+
+        loop {
+            let start = reader.buffer_position() as usize;
+            let event = reader
+                .read_event()
+                .map_err(|e| format!("Invalid SSML at byte {}: {}", reader.error_position(), e))?;
+            let end = reader.buffer_position() as usize;
+            match event {
+                Event::Eof => break,
+                Event::Text(_) => {
+                    if depth == 0 && !ssml[start..end].trim().is_empty() {
+                        return Err("Invalid SSML: text outside the <speak> element".to_string());
+                    }
+                    continue;
+                }
+                Event::Start(_) | Event::Empty(_) if depth == 0 => roots += 1,
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+            if let Event::Start(_) = event {
+                depth += 1;
+            }
+            spans.push((start..end, depth));
+        }
+
+        if depth != 0 {
+            return Err("Invalid SSML: an element is not closed".to_string());
+        }
+        if roots != 1 {
+            return Err("Invalid SSML: the document needs exactly one root element".to_string());
+        }
+        Ok(Markup { spans })
+    }
+
+    /// Element depth at `offset`, or an error when it isn't a place text
+    /// can go: inside markup, or outside the root element.
+    fn depth_at(&self, offset: usize) -> Result<usize, String> {
+        let mut depth = 0;
+        for (span, after) in &self.spans {
+            if span.start < offset && offset < span.end {
+                return Err("The position is inside a tag or entity".to_string());
+            }
+            if span.end <= offset {
+                depth = *after;
+            }
+        }
+        if depth == 0 {
+            return Err("The position is outside the <speak> element".to_string());
+        }
+        Ok(depth)
+    }
+
+    /// Lowest depth reached by markup between `start` and `end`.
+    fn min_depth_between(&self, start: usize, end: usize) -> Option<usize> {
+        self.spans
+            .iter()
+            .filter(|(span, _)| span.start >= start && span.end <= end)
+            .map(|(_, after)| *after)
+            .min()
+    }
+}
+
+/// Converts a UTF-16 offset into a byte offset of `text`.
+fn byte_offset(text: &str, utf16: usize) -> Result<usize, String> {
+    let mut units = 0;
+    for (byte, c) in text.char_indices() {
+        if units == utf16 {
+            return Ok(byte);
+        }
+        units += c.len_utf16();
+        if units > utf16 {
+            return Err(format!("Position {} splits a character", utf16));
+        }
+    }
+    if units == utf16 {
+        Ok(text.len())
+    } else {
+        Err(format!("Position {} is past the end of the document", utf16))
+    }
+}
+
+/// Confirms an edited document is still well-formed before handing it back.
+fn checked(ssml: String) -> Result<String, String> {
+    Markup::parse(&ssml)?;
+    Ok(ssml)
+}
+
+/// Inserts `<break time="…ms"/>` at `position`.
+#[command]
+pub fn insert_break(ssml: String, position: usize, duration_ms: u64) -> Result<String, String> {
+    if duration_ms == 0 || duration_ms > MAX_BREAK_MS {
+        return Err(format!("Break length must be between 1 and {} ms", MAX_BREAK_MS));
+    }
+    let markup = Markup::parse(&ssml)?;
+    let at = byte_offset(&ssml, position)?;
+    markup.depth_at(at)?;
+
+    let mut edited = ssml;
+    edited.insert_str(at, &format!("<break time=\"{}ms\"/>", duration_ms));
+    checked(edited)
+}
+
+/// Wraps `start..end` in `<emphasis level="…">`. The range must not cut
+/// through an element; it may contain whole ones.
+#[command]
+pub fn wrap_emphasis(ssml: String, start: usize, end: usize, level: String) -> Result<String, String> {
+    let level = level.trim().to_ascii_lowercase();
+    if !EMPHASIS_LEVELS.contains(&level.as_str()) {
+        return Err(format!("Unknown emphasis level '{}'; use {}", level, EMPHASIS_LEVELS.join(", ")));
+    }
+    if start >= end {
+        return Err("The emphasis range is empty".to_string());
+    }
+
+    let markup = Markup::parse(&ssml)?;
+    let (from, to) = (byte_offset(&ssml, start)?, byte_offset(&ssml, end)?);
+    let depth = markup.depth_at(from)?;
+    if markup.depth_at(to)? != depth || markup.min_depth_between(from, to).is_some_and(|min| min < depth) {
+        return Err("The range starts and ends in different elements".to_string());
+    }
+
+    let mut edited = ssml;
+    edited.insert_str(to, "</emphasis>");
+    edited.insert_str(from, &format!("<emphasis level=\"{}\">", level));
+    checked(edited)
+}
+
+/// Parses a `[pause …]` marker at the start of `text` into its length in
+/// milliseconds and the marker's length in bytes.
+fn pause_marker(text: &str) -> Option<(u64, usize)> {
+    let end = text.find(']')?;
+    let inner = text[1..end].trim().to_ascii_lowercase();
+    let spec = inner.strip_prefix("pause").filter(|spec| spec.starts_with(char::is_whitespace))?;
+    let (number, scale) = match spec.trim().strip_suffix("ms") {
+        Some(number) => (number, 1.0),
+        None => (spec.trim().strip_suffix('s')?, 1000.0),
+    };
+    let value = number.trim().parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.0)?;
+    Some((((value * scale).round() as u64).min(MAX_BREAK_MS), end + 1))
+}
+
+/// Byte offset in `text` of the next run of exactly `run` asterisks that
+/// follows something other than whitespace, skipping escaped characters.
+fn closing_run(text: &str, run: usize) -> Option<usize> {
+    let mut at = 0;
+    while let Some(c) = text[at..].chars().next() {
+        match c {
+            '\\' => at += 1 + text[at + 1..].chars().next().map_or(0, char::len_utf8),
+            '*' => {
+                let length = text[at..].len() - text[at..].trim_start_matches('*').len();
+                if length == run && text[..at].chars().last().is_some_and(|c| !c.is_whitespace()) {
+                    return Some(at);
+                }
+                at += length;
+            }
+            _ => at += c.len_utf8(),
+        }
+    }
+    None
+}
+
+/// Renders markup inside emphasis opened by runs of the lengths in `open`,
+/// which can't be opened again until they close.
+fn render_markup(text: &str, open: &[usize]) -> String {
+    let mut ssml = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' {
+            if let Some(escaped @ ('*' | '[' | '\\')) = rest[1..].chars().next() {
+                ssml.push_str(&quick_xml::escape::escape(escaped.to_string()));
+                rest = &rest[2..];
+                continue;
+            }
+        }
+        if c == '[' {
+            if let Some((ms, length)) = pause_marker(rest) {
+                ssml.push_str(&format!("<break time=\"{}ms\"/>", ms));
+                rest = &rest[length..];
+                continue;
+            }
+        }
+        if c == '*' {
+            let run = rest.len() - rest.trim_start_matches('*').len();
+            let (markers, after) = rest.split_at(run);
+            let opens = run <= 2 && !open.contains(&run) && after.starts_with(|c: char| !c.is_whitespace());
+            if let Some(end) = closing_run(after, run).filter(|_| opens) {
+                let level = if run == 2 { "strong" } else { "moderate" };
+                let inner = render_markup(&after[..end], &[open, &[run]].concat());
+                ssml.push_str(&format!("<emphasis level=\"{}\">{}</emphasis>", level, inner));
+                rest = &after[end + run..];
+            } else {
+                ssml.push_str(markers);
+                rest = after;
+            }
+            continue;
+        }
+        ssml.push_str(&quick_xml::escape::escape(&rest[..c.len_utf8()]));
+        rest = &rest[c.len_utf8()..];
+    }
+    ssml
+}
+
+/// Converts plain text with inline markup into an SSML document:
+///
+/// - `*words*` are emphasized and `**words**` strongly emphasized. The
+///   opening asterisks must be followed and the closing ones preceded by
+///   something other than whitespace, so `2 * 3 * 4` stays as it is. The
+///   two kinds nest inside each other but not inside themselves.
+/// - `[pause 500ms]` or `[pause 1.5s]` inserts a break, capped at 10 seconds.
+/// - `\*`, `\[` and `\\` stand for the character itself.
+///
+/// Anything else, including asterisks and brackets that don't form markup,
+/// is kept as text, with XML's special characters escaped.
+pub fn markup_to_ssml(text: &str) -> String {
+    format!("<speak>{}</speak>", render_markup(text, &[]))
+}
+
+/// Whether `c` can appear in an IPA transcription: the IPA letters and
+/// modifiers, the Latin and Greek letters IPA borrows, combining diacritics,
+/// tone letters, and the separators for syllables, feet and intonation groups.
+fn is_ipa_symbol(c: char) -> bool {
+    matches!(c,
+        'a'..='z'
+        | ' ' | '.' | '|'
+        | 'æ' | 'ç' | 'ð' | 'ø' | 'ħ' | 'ŋ' | 'œ' | 'β' | 'θ' | 'χ' | 'ⱱ'
+        | 'ǀ'..='ǃ'
+        | '\u{0250}'..='\u{02FF}' // IPA extensions, modifiers such as ˈ ˌ ː and tone letters
+        | '\u{0300}'..='\u{036F}' // combining diacritics
+        | '\u{1D00}'..='\u{1DBF}' // phonetic extensions such as ᵻ
+        | '‖' | '‿' | '↗' | '↘' | 'ꜛ' | 'ꜜ')
+}
+
+/// Checks `ipa` and wraps it in a `<phoneme>` document. ASCII stand-ins
+/// such as `'` for stress or `:` for length are refused with the symbol to
+/// use instead, since voices would read them literally.
+fn phoneme_ssml(ipa: &str) -> Result<String, String> {
+    let ipa = ipa.trim().trim_matches('/').trim_matches(['[', ']']).trim();
+    if ipa.is_empty() {
+        return Err("The IPA transcription is empty".to_string());
+    }
+    if ipa.chars().count() > MAX_IPA_CHARS {
+        return Err(format!("IPA transcriptions are limited to {} characters", MAX_IPA_CHARS));
+    }
+    if let Some((position, symbol)) = ipa.chars().enumerate().find(|(_, c)| !is_ipa_symbol(*c)) {
+        let hint = match symbol {
+            '\'' => "; use ˈ for primary stress",
+            ',' => "; use ˌ for secondary stress",
+            ':' => "; use ː for length",
+            'A'..='Z' => "; IPA letters are lowercase",
+            _ => "",
+        };
+        return Err(format!("'{}' at position {} is not an IPA symbol{}", symbol, position + 1, hint));
+    }
+    Ok(format!("<speak><phoneme alphabet=\"ipa\" ph=\"{}\">{}</phoneme></speak>", ipa, ipa))
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpaSample {
+    /// The document that was sent.
+    ssml: String,
+    output_path: String,
+    duration_seconds: Option<f64>,
+}
+
+/// Speaks an IPA transcription exactly, such as `/ˈkiːwiː/`, into the app's
+/// cache folder. Needs a server that accepts SSML; whether the voice honours
+/// `<phoneme>` is up to the voice, and a refusal comes back as the error.
+#[command]
+pub async fn synthesize_ipa(
+    app: AppHandle,
+    ipa: String,
+    voice: String,
+    language: Option<String>,
+    format: Option<String>,
+) -> Result<IpaSample, String> {
+    let ssml = phoneme_ssml(&ipa)?;
+    let format = audio::normalize_format(format.as_deref().unwrap_or("MP3"))?;
+    if settings::current(&app).offline_mode {
+        return Err("IPA input needs the API server; the local speech engine reads only plain text".to_string());
+    }
+    let capabilities = capabilities::get(&app).await;
+    if !capabilities.ssml {
+        return Err(format!("The server does not accept SSML, which IPA input is sent as; {}", NEEDS_SSML_SERVER));
+    }
+    if !capabilities.supports_format(format) {
+        return Err(format!("The server does not offer {} output", format));
+    }
+
+    let language = language
+        .or_else(|| text::language_from_voice(&voice))
+        .unwrap_or(settings::current(&app).default_language);
+    let request = TTSRequest {
+        text: ssml.clone(),
+        voice,
+        format: format.to_string(),
+        language,
+        ssml: true,
+        ..Default::default()
+    };
+    let bytes = synthesize_bytes(&app, &client::build(&app), &request).await?;
+
+    let dir = app
+        .path()
+        .app_cache_dir()
+        .map(|dir| dir.join("ipa"))
+        .map_err(|e| format!("Cannot determine cache directory: {}", e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let name = format!("{}-{}", request.voice, ipa.trim());
+    // Not `with_extension`: `.` separates syllables in IPA
+    let path = dir.join(format!("{}.{}", paths::sanitize_filename(&name), audio::file_extension(format)));
+    paths::write_audio(&path, &bytes)?;
+
+    Ok(IpaSample {
+        ssml,
+        output_path: path.to_string_lossy().to_string(),
+        duration_seconds: audio::duration_secs(&bytes),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct SsmlProblem {
+    /// 1-based, with columns counted in characters.
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SsmlValidation {
+    valid: bool,
+    /// Whether the server accepts SSML at all; when it doesn't, that is
+    /// also the first problem.
+    ssml_supported: bool,
+    problems: Vec<SsmlProblem>,
+}
+
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// Whether `name` is allowed on `element`. Namespace declarations and
+/// `xml:` attributes are allowed everywhere.
+fn allows_attribute(capabilities: &Capabilities, element: &str, name: &str) -> bool {
+    if name == "xmlns" || name.starts_with("xmlns:") || name.starts_with("xml:") {
+        return true;
+    }
+    match capabilities.ssml_elements.get(element) {
+        Some(attributes) => attributes.iter().any(|a| a == name),
+        None => CORE_SSML
+            .iter()
+            .any(|(core, attributes)| *core == element && attributes.contains(&name)),
+    }
+}
+
+fn allows_element(capabilities: &Capabilities, element: &str) -> bool {
+    if capabilities.ssml_elements.is_empty() {
+        CORE_SSML.iter().any(|(core, _)| *core == element)
+    } else {
+        capabilities.ssml_elements.contains_key(element)
+    }
+}
+
+/// Problems that would make the server reject `ssml`: malformed XML, a
+/// root other than `<speak>`, and elements or attributes outside what
+/// `capabilities` supports.
+fn ssml_problems(ssml: &str, capabilities: &Capabilities) -> Vec<SsmlProblem> {
+    let mut problems = Vec::new();
+    let mut problem = |offset: usize, message: String| {
+        let (line, column) = line_column(ssml, offset);
+        problems.push(SsmlProblem { line, column, message });
+    };
+    if !capabilities.ssml {
+        problem(0, format!("The server does not accept SSML; {}", NEEDS_SSML_SERVER));
+    }
+
+    let mut reader = Reader::from_str(ssml);
+    let mut depth = 0usize;
+    let mut saw_root = false;
+    loop {
+        let start = reader.buffer_position() as usize;
+        let (element, is_start) = match reader.read_event() {
+            Ok(Event::Eof) => break,
+            Ok(Event::Start(e)) => (e.into_owned(), true),
+            Ok(Event::Empty(e)) => (e.into_owned(), false),
+            Ok(Event::End(_)) => {
+                depth = depth.saturating_sub(1);
+                continue;
+            }
+            Ok(Event::Text(_)) => {
+                if depth == 0 && !ssml[start..reader.buffer_position() as usize].trim().is_empty() {
+                    problem(start, "Text outside the <speak> element".to_string());
+                }
+                continue;
+            }
+            Ok(_) => continue,
+            Err(e) => {
+                problem(reader.error_position() as usize, format!("Malformed XML: {}", e));
+                return problems;
+            }
+        };
+
+        let name = element.name().0.to_string();
+        if depth == 0 {
+            if saw_root {
+                problem(start, "The document needs exactly one root element".to_string());
+            } else if name != "speak" {
+                problem(start, format!("The root element must be <speak>, not <{}>", name));
+            }
+            saw_root = true;
+        }
+        if !allows_element(capabilities, &name) {
+            problem(start, format!("<{}> is not supported by the server", name));
+        } else {
+            for attribute in element.attributes().flatten() {
+                let key = attribute.key.0.to_string();
+                if !allows_attribute(capabilities, &name, &key) {
+                    problem(start, format!("<{}> does not support the '{}' attribute", name, key));
+                }
+            }
+        }
+        if is_start {
+            depth += 1;
+        }
+    }
+    if depth > 0 {
+        problem(ssml.len(), "An element is not closed".to_string());
+    }
+    if !saw_root {
+        problem(0, "The document has no <speak> element".to_string());
+    }
+    problems
+}
+
+/// Checks `ssml` against what the server supports before any credits are
+/// spent on it.
+#[command]
+pub async fn validate_ssml(app: AppHandle, ssml: String) -> SsmlValidation {
+    let capabilities = capabilities::get(&app).await;
+    let problems = ssml_problems(&ssml, &capabilities);
+    SsmlValidation {
+        valid: problems.is_empty(),
+        ssml_supported: capabilities.ssml,
+        problems,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speak(inner: &str) -> String {
+        format!("<speak>{}</speak>", inner)
+    }
+
+    #[test]
+    fn byte_offset_counts_utf16_units() {
+        let text = "a😀b";
+        assert_eq!(byte_offset(text, 0), Ok(0));
+        assert_eq!(byte_offset(text, 3), Ok(5));
+        assert_eq!(byte_offset(text, 4), Ok(6));
+        assert!(byte_offset(text, 2).unwrap_err().contains("splits a character"));
+        assert!(byte_offset(text, 5).unwrap_err().contains("past the end"));
+    }
+
+    #[test]
+    fn insert_break_lands_at_the_position() {
+        let ssml = speak("Hello world");
+        assert_eq!(insert_break(ssml.clone(), 12, 500), Ok(speak("Hello<break time=\"500ms\"/> world")));
+        assert!(insert_break(ssml.clone(), 12, 0).is_err());
+        assert!(insert_break(ssml.clone(), 12, MAX_BREAK_MS + 1).is_err());
+        // Inside the opening tag
+        assert!(insert_break(ssml, 3, 500).is_err());
+    }
+
+    #[test]
+    fn wrap_emphasis_keeps_documents_well_formed() {
+        let ssml = speak("Hello <s>big</s> world");
+        assert_eq!(
+            wrap_emphasis(ssml.clone(), 7, 29, "Strong".to_string()),
+            Ok(speak("<emphasis level=\"strong\">Hello <s>big</s> world</emphasis>"))
+        );
+        assert_eq!(
+            wrap_emphasis(ssml.clone(), 7, 10, "loud".to_string()).unwrap_err(),
+            "Unknown emphasis level 'loud'; use strong, moderate, reduced, none"
+        );
+        assert!(wrap_emphasis(ssml.clone(), 10, 10, "strong".to_string()).is_err());
+        // From outside the <s> element into it
+        assert!(wrap_emphasis(ssml, 7, 18, "strong".to_string()).is_err());
+    }
+
+    #[test]
+    fn phoneme_ssml_wraps_the_transcription() {
+        let expected = "<speak><phoneme alphabet=\"ipa\" ph=\"ˈkiːwiː\">ˈkiːwiː</phoneme></speak>";
+        assert_eq!(phoneme_ssml(" /ˈkiːwiː/ "), Ok(expected.to_string()));
+        assert_eq!(phoneme_ssml("[ˈkiːwiː]"), Ok(expected.to_string()));
+        // Syllable breaks, tie bars and combining diacritics are all IPA
+        assert!(phoneme_ssml("ˈtʃɪ.kən t\u{361}ʃ a\u{303}").is_ok());
+    }
+
+    #[test]
+    fn phoneme_ssml_points_at_ascii_stand_ins() {
+        assert_eq!(
+            phoneme_ssml("'kiwi"),
+            Err("''' at position 1 is not an IPA symbol; use ˈ for primary stress".to_string())
+        );
+        assert_eq!(phoneme_ssml("ki:wi"), Err("':' at position 3 is not an IPA symbol; use ː for length".to_string()));
+        assert_eq!(phoneme_ssml("Kiwi"), Err("'K' at position 1 is not an IPA symbol; IPA letters are lowercase".to_string()));
+        assert_eq!(phoneme_ssml("ki<wi"), Err("'<' at position 3 is not an IPA symbol".to_string()));
+    }
+
+    #[test]
+    fn phoneme_ssml_refuses_empty_and_long_input() {
+        assert_eq!(phoneme_ssml(" // "), Err("The IPA transcription is empty".to_string()));
+        assert!(phoneme_ssml(&"a".repeat(MAX_IPA_CHARS)).is_ok());
+        assert_eq!(
+            phoneme_ssml(&"a".repeat(MAX_IPA_CHARS + 1)),
+            Err(format!("IPA transcriptions are limited to {} characters", MAX_IPA_CHARS))
+        );
+    }
+
+    fn accepts_ssml() -> Capabilities {
+        Capabilities {
+            ssml: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn messages(ssml: &str, capabilities: &Capabilities) -> Vec<String> {
+        ssml_problems(ssml, capabilities).into_iter().map(|problem| problem.message).collect()
+    }
+
+    #[test]
+    fn ssml_problems_accepts_core_documents() {
+        let ssml = "<speak xml:lang=\"en-US\"><p><s>Hi</s><break time=\"300ms\"/>\
+                    <prosody rate=\"slow\">there</prosody></p></speak>";
+        assert!(messages(ssml, &accepts_ssml()).is_empty());
+        assert_eq!(
+            messages(ssml, &Capabilities::default()),
+            vec![format!("The server does not accept SSML; {}", NEEDS_SSML_SERVER)]
+        );
+    }
+
+    #[test]
+    fn ssml_problems_locates_unsupported_markup() {
+        let ssml = "<speak>\n  <blink>Hi</blink>\n  <break pause=\"1s\"/>\n</speak>";
+        let problems = ssml_problems(ssml, &accepts_ssml());
+        let found: Vec<(usize, usize, &str)> =
+            problems.iter().map(|p| (p.line, p.column, p.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, 3, "<blink> is not supported by the server"),
+                (3, 3, "<break> does not support the 'pause' attribute"),
+            ]
+        );
+    }
+
+    #[test]
+    fn ssml_problems_follows_the_server_list() {
+        let mut capabilities = accepts_ssml();
+        capabilities.ssml_elements = [("speak".to_string(), vec![]), ("mark".to_string(), vec!["name".to_string()])]
+            .into_iter()
+            .collect();
+        assert!(messages("<speak><mark name=\"a\"/>Hi</speak>", &capabilities).is_empty());
+        assert_eq!(
+            messages("<speak><p>Hi</p></speak>", &capabilities),
+            vec!["<p> is not supported by the server".to_string()]
+        );
+    }
+
+    #[test]
+    fn ssml_problems_checks_the_document_shape() {
+        let capabilities = accepts_ssml();
+        assert_eq!(
+            messages("<p>Hi</p>", &capabilities),
+            vec!["The root element must be <speak>, not <p>".to_string()]
+        );
+        assert_eq!(
+            messages("Hi", &capabilities),
+            vec!["Text outside the <speak> element".to_string(), "The document has no <speak> element".to_string()]
+        );
+        assert_eq!(
+            messages("<speak>Hi</speak><speak/>", &capabilities),
+            vec!["The document needs exactly one root element".to_string()]
+        );
+        assert_eq!(messages("<speak>Hi", &capabilities), vec!["An element is not closed".to_string()]);
+        let malformed = messages("<speak>Hi</p></speak>", &capabilities);
+        assert_eq!(malformed.len(), 1);
+        assert!(malformed[0].starts_with("Malformed XML"), "{:?}", malformed);
+    }
+
+    #[test]
+    fn markup_to_ssml_emphasizes_and_pauses() {
+        assert_eq!(
+            markup_to_ssml("A *mild* and **strong** word[pause 500ms]done[pause 1.5s]"),
+            speak("A <emphasis level=\"moderate\">mild</emphasis> and <emphasis level=\"strong\">strong</emphasis> word\
+                   <break time=\"500ms\"/>done<break time=\"1500ms\"/>")
+        );
+        assert_eq!(
+            markup_to_ssml("**very *nested* words**"),
+            speak("<emphasis level=\"strong\">very <emphasis level=\"moderate\">nested</emphasis> words</emphasis>")
+        );
+        assert_eq!(markup_to_ssml("[pause 30s]"), speak("<break time=\"10000ms\"/>"));
+    }
+
+    #[test]
+    fn markup_to_ssml_keeps_everything_else_as_text() {
+        assert_eq!(markup_to_ssml("2 * 3 * 4"), speak("2 * 3 * 4"));
+        assert_eq!(markup_to_ssml("\\*literal\\* \\[pause 1s]"), speak("*literal* [pause 1s]"));
+        assert_eq!(markup_to_ssml("[note] [pause soon] ***x***"), speak("[note] [pause soon] ***x***"));
+        assert_eq!(markup_to_ssml("Tom & <Jerry>"), speak("Tom &amp; &lt;Jerry&gt;"));
+    }
+}
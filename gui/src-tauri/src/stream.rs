@@ -0,0 +1,286 @@
+//! Low-latency synthesis over the server's `/synthesize/stream` WebSocket.
+//!
+//! The client sends one JSON text message: the usual `TTSRequest` fields plus
+//! `resume_from`, the sequence number of the first chunk it still needs (0 on
+//! the first attempt, higher after a reconnect). The server answers with
+//! binary messages, each a consecutive piece of the encoded audio file in the
+//! requested format, followed by a text message `{"done": true}` or
+//! `{"error": "..."}`.
+//!
+//! Every piece is forwarded to the frontend as an `audio-chunk` event:
+//!
+//! ```json
+//! { "stream_id": "abc", "sequence": 0, "data": "<base64>", "final": false }
+//! ```
+//!
+//! `data` is standard base64 (with padding) of the raw bytes. Concatenating
+//! the decoded `data` of all chunks in `sequence` order yields a complete MP3
+//! or WAV file, so a MediaSource/SourceBuffer fed in order can start playing
+//! after the first chunk. The last event has `final: true` and may carry an
+//! empty `data`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use tauri::{command, Emitter, State};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_util::sync::CancellationToken;
+
+use crate::{capabilities, client, ratelimit, servers, synthesize_bytes, AppHandle, TTSRequest};
+
+const MAX_RECONNECTS: u32 = 3;
+
+/// Cancellation handles for streams that are still running, keyed by stream id.
+#[derive(Default)]
+pub struct StreamRegistry(Mutex<HashMap<String, CancellationToken>>);
+
+impl StreamRegistry {
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Cancels every running stream and returns how many there were.
+    pub fn cancel_all(&self) -> usize {
+        let streams = self.0.lock().unwrap();
+        for cancel in streams.values() {
+            cancel.cancel();
+        }
+        streams.len()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct AudioChunk {
+    stream_id: String,
+    sequence: u64,
+    data: String,
+    #[serde(rename = "final")]
+    is_final: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamSummary {
+    stream_id: String,
+    chunks: u64,
+    bytes: usize,
+    /// False when the server has no streaming endpoint and the HTTP path was used.
+    streamed: bool,
+    cancelled: bool,
+}
+
+#[derive(Serialize)]
+struct StreamRequest<'a> {
+    #[serde(flatten)]
+    request: &'a TTSRequest,
+    resume_from: u64,
+}
+
+enum StreamEnd {
+    Done,
+    Cancelled,
+}
+
+enum StreamError {
+    /// The handshake was rejected, so the server does not offer streaming.
+    Unsupported,
+    /// The connection dropped and may succeed if retried.
+    Transient(String),
+    /// The server reported an error; retrying will not help.
+    Fatal(String),
+}
+
+struct StreamState {
+    app: AppHandle,
+    stream_id: String,
+    sequence: u64,
+    bytes: usize,
+}
+
+impl StreamState {
+    fn emit(&mut self, data: &[u8], is_final: bool) {
+        let _ = self.app.emit(
+            "audio-chunk",
+            AudioChunk {
+                stream_id: self.stream_id.clone(),
+                sequence: self.sequence,
+                data: base64::engine::general_purpose::STANDARD.encode(data),
+                is_final,
+            },
+        );
+        self.sequence += 1;
+        self.bytes += data.len();
+    }
+}
+
+fn stream_url(server: &str) -> String {
+    let base = server
+        .replacen("https://", "wss://", 1)
+        .replacen("http://", "ws://", 1);
+    format!("{}/synthesize/stream", base)
+}
+
+async fn run_stream(
+    state: &mut StreamState,
+    request: &TTSRequest,
+    cancel: &CancellationToken,
+) -> Result<StreamEnd, StreamError> {
+    tokio::select! {
+        _ = cancel.cancelled() => return Ok(StreamEnd::Cancelled),
+        _ = ratelimit::acquire(&state.app, request.text.chars().count()) => {}
+    }
+
+    let server = servers::primary(&state.app, &client::build(&state.app)).await;
+    let mut socket_request = stream_url(&server)
+        .into_client_request()
+        .map_err(|e| StreamError::Fatal(format!("Invalid stream URL: {}", e)))?;
+    socket_request.headers_mut().extend(client::headers(&state.app));
+    let connect = tokio_tungstenite::connect_async(socket_request);
+    let (mut socket, _) = tokio::select! {
+        _ = cancel.cancelled() => return Ok(StreamEnd::Cancelled),
+        result = connect => result.map_err(|e| match e {
+            tungstenite::Error::Http(_) => StreamError::Unsupported,
+            e => StreamError::Transient(e.to_string()),
+        })?,
+    };
+
+    let body = serde_json::to_string(&StreamRequest { request, resume_from: state.sequence })
+        .map_err(|e| StreamError::Fatal(format!("Failed to encode stream request: {}", e)))?;
+    socket
+        .send(Message::Text(body.into()))
+        .await
+        .map_err(|e| StreamError::Transient(e.to_string()))?;
+
+    loop {
+        let message = tokio::select! {
+            _ = cancel.cancelled() => {
+                let _ = socket.close(None).await;
+                return Ok(StreamEnd::Cancelled);
+            }
+            message = socket.next() => message,
+        };
+
+        match message {
+            Some(Ok(Message::Binary(data))) => state.emit(&data, false),
+            Some(Ok(Message::Text(text))) => {
+                let status: serde_json::Value = serde_json::from_str(&text)
+                    .map_err(|e| StreamError::Fatal(format!("Invalid stream status message: {}", e)))?;
+                if let Some(error) = status.get("error").and_then(|e| e.as_str()) {
+                    return Err(StreamError::Fatal(format!("API error: {}", error)));
+                }
+                if status.get("done").and_then(|d| d.as_bool()) == Some(true) {
+                    let _ = socket.close(None).await;
+                    return Ok(StreamEnd::Done);
+                }
+            }
+            Some(Ok(Message::Close(_))) | None => {
+                return Err(StreamError::Transient("connection closed before the stream finished".to_string()));
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(StreamError::Transient(e.to_string())),
+        }
+    }
+}
+
+/// Streams synthesized audio to the frontend as `audio-chunk` events while it
+/// is generated. Falls back to a single chunk from the HTTP path when the
+/// server has no streaming endpoint.
+#[command]
+pub async fn synthesize_stream(
+    app: AppHandle,
+    registry: State<'_, StreamRegistry>,
+    stream_id: String,
+    text: String,
+    voice: String,
+    format: String,
+    language: String,
+) -> Result<StreamSummary, String> {
+    let cancel = CancellationToken::new();
+    {
+        let mut streams = registry.0.lock().unwrap();
+        if streams.contains_key(&stream_id) {
+            return Err(format!("Stream '{}' is already running", stream_id));
+        }
+        streams.insert(stream_id.clone(), cancel.clone());
+    }
+
+    let request = TTSRequest { text, voice, format, language, ..Default::default() };
+    let mut state = StreamState { app, stream_id: stream_id.clone(), sequence: 0, bytes: 0 };
+    let result = stream_with_fallback(&mut state, &request, &cancel).await;
+
+    registry.0.lock().unwrap().remove(&stream_id);
+
+    let (streamed, end) = result?;
+    Ok(StreamSummary {
+        stream_id,
+        chunks: state.sequence,
+        bytes: state.bytes,
+        streamed,
+        cancelled: matches!(end, StreamEnd::Cancelled),
+    })
+}
+
+async fn stream_with_fallback(
+    state: &mut StreamState,
+    request: &TTSRequest,
+    cancel: &CancellationToken,
+) -> Result<(bool, StreamEnd), String> {
+    let mut reconnects = 0;
+    let streaming = capabilities::get(&state.app).await.streaming;
+    // A server that says it can't stream gets the single request straight away
+    if streaming {
+        loop {
+            match run_stream(state, request, cancel).await {
+                Ok(StreamEnd::Done) => {
+                    state.emit(&[], true);
+                    return Ok((true, StreamEnd::Done));
+                }
+                Ok(StreamEnd::Cancelled) => return Ok((true, StreamEnd::Cancelled)),
+                Err(StreamError::Unsupported) if state.sequence == 0 => break,
+                Err(StreamError::Unsupported) => {
+                    return Err("Server stopped accepting the stream after it had started".to_string());
+                }
+                Err(StreamError::Fatal(e)) => return Err(e),
+                Err(StreamError::Transient(e)) => {
+                    // Nothing was received yet, so the server probably isn't a streaming one at all
+                    if state.sequence == 0 && reconnects == 0 {
+                        break;
+                    }
+                    if reconnects >= MAX_RECONNECTS {
+                        return Err(format!("Stream dropped after {} reconnect attempts: {}", reconnects, e));
+                    }
+                    reconnects += 1;
+                    let backoff = Duration::from_millis(250 * 2u64.pow(reconnects - 1));
+                    tokio::select! {
+                        _ = cancel.cancelled() => return Ok((true, StreamEnd::Cancelled)),
+                        _ = tokio::time::sleep(backoff) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    let client = client::build(&state.app);
+    let bytes = tokio::select! {
+        _ = cancel.cancelled() => return Ok((false, StreamEnd::Cancelled)),
+        bytes = synthesize_bytes(&state.app, &client, request) => bytes?,
+    };
+    state.emit(&bytes, true);
+    Ok((false, StreamEnd::Done))
+}
+
+/// Stops a running stream. Returns false if no stream with that id is active.
+#[command]
+pub fn cancel_stream(registry: State<'_, StreamRegistry>, stream_id: String) -> bool {
+    match registry.0.lock().unwrap().get(&stream_id) {
+        Some(cancel) => {
+            cancel.cancel();
+            true
+        }
+        None => false,
+    }
+}
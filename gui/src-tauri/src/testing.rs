@@ -0,0 +1,265 @@
+//! Test support: the app on Tauri's mock runtime, with its folders in a
+//! temporary directory, and a local stand-in for the API server that
+//! answers with canned responses and records what it was sent.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tauri::utils::config::AppDirectoriesOverride;
+use tauri::Manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::settings::{Settings, SettingsState};
+use crate::{history, AppHandle, Runtime};
+
+/// The app with every piece of state `run` registers, `settings` in place
+/// of the saved ones and an empty history. Settings, history and caches
+/// are written under `dir()`.
+pub struct TestApp {
+    app: tauri::App<Runtime>,
+    dir: tempfile::TempDir,
+}
+
+impl TestApp {
+    pub fn new(settings: Settings) -> TestApp {
+        let dir = tempfile::tempdir().unwrap();
+        let mut context = tauri::test::mock_context(tauri::test::noop_assets());
+        context.config_mut().identifier = "com.kiwi.test".to_string();
+        context.config_mut().app.app_directories_override = Some(AppDirectoriesOverride::Root(dir.path().to_path_buf()));
+        let app = crate::with_state(tauri::test::mock_builder()).build(context).unwrap();
+        app.manage(SettingsState(Mutex::new(settings)));
+        app.manage(history::HistoryState(Mutex::new(Vec::new())));
+        TestApp { app, dir }
+    }
+
+    /// The app talking only to `server`.
+    pub fn with_server(server: &MockServer) -> TestApp {
+        TestApp::new(Settings {
+            servers: vec![server.url.clone()],
+            ..Settings::default()
+        })
+    }
+
+    pub fn handle(&self) -> &AppHandle {
+        self.app.handle()
+    }
+
+    pub fn dir(&self) -> &std::path::Path {
+        self.dir.path()
+    }
+}
+
+/// An HTTP response from `MockServer`.
+pub struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    delay: Duration,
+}
+
+impl Reply {
+    pub fn json(status: u16, body: serde_json::Value) -> Reply {
+        Reply::bytes(status, "application/json", body.to_string().into_bytes())
+    }
+
+    pub fn bytes(status: u16, content_type: &str, body: Vec<u8>) -> Reply {
+        Reply {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn not_found() -> Reply {
+        Reply::json(404, serde_json::json!({ "detail": "Not Found" }))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// This reply, sent `delay` after the request arrives.
+    pub fn after(self, delay: Duration) -> Reply {
+        Reply { delay, ..self }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = reqwest::StatusCode::from_u16(self.status).ok().and_then(|s| s.canonical_reason()).unwrap_or("");
+        let mut head = format!("HTTP/1.1 {} {}\r\nContent-Length: {}\r\n", self.status, reason, self.body.len());
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        [head.into_bytes(), self.body.clone()].concat()
+    }
+}
+
+/// A request `MockServer` received.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub path: String,
+    /// As sent, with the names lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Received {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers.iter().find(|(sent, _)| *sent == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or_default()
+    }
+}
+
+type Respond = dyn Fn(&Received) -> Reply + Send + Sync;
+
+#[derive(Default)]
+struct Log {
+    requests: Mutex<Vec<Received>>,
+    connections: AtomicUsize,
+}
+
+/// A local HTTP/1.1 server answering each request with `respond`. It keeps
+/// connections open between requests, like a real server would.
+pub struct MockServer {
+    pub url: String,
+    log: Arc<Log>,
+}
+
+impl MockServer {
+    pub async fn start(respond: impl Fn(&Received) -> Reply + Send + Sync + 'static) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let log = Arc::new(Log::default());
+        let respond: Arc<Respond> = Arc::new(respond);
+        let server_log = log.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                server_log.connections.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(serve_connection(socket, respond.clone(), server_log.clone()));
+            }
+        });
+        MockServer { url, log }
+    }
+
+    pub fn requests(&self) -> Vec<Received> {
+        self.log.requests.lock().unwrap().clone()
+    }
+
+    /// TCP connections clients opened to the server.
+    pub fn connections(&self) -> usize {
+        self.log.connections.load(Ordering::SeqCst)
+    }
+
+    /// Requests to `path`, ignoring any query.
+    pub fn requests_to(&self, path: &str) -> Vec<Received> {
+        self.requests().into_iter().filter(|r| r.path.split('?').next() == Some(path)).collect()
+    }
+}
+
+async fn serve_connection(mut socket: TcpStream, respond: Arc<Respond>, log: Arc<Log>) {
+    let mut buffer = Vec::new();
+    loop {
+        let Some(request) = read_request(&mut socket, &mut buffer).await else {
+            return;
+        };
+        log.requests.lock().unwrap().push(request.clone());
+        let reply = respond(&request);
+        tokio::time::sleep(reply.delay).await;
+        if socket.write_all(&reply.to_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads one request from `socket`, keeping any bytes past it in `buffer`.
+/// `None` once the client closes the connection.
+async fn read_request(socket: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<Received> {
+    let mut chunk = [0; 8192];
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let read = socket.read(&mut chunk).await.ok().filter(|&n| n > 0)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let path = lines.next()?.split(' ').nth(1)?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let body_start = head_end + 4;
+    while buffer.len() < body_start + length {
+        let read = socket.read(&mut chunk).await.ok().filter(|&n| n > 0)?;
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = buffer[body_start..body_start + length].to_vec();
+    buffer.drain(..body_start + length);
+    Some(Received { path, headers, body })
+}
+
+/// A `/synthesize` answer pointing at `/download/<file_id>`, the way the
+/// bundled server replies.
+pub fn synthesized(file_id: &str) -> Reply {
+    Reply::json(
+        200,
+        serde_json::json!({
+            "success": true,
+            "output_path": format!("/tmp/kiwi_tts/{}.wav", file_id),
+            "file_size": "0.1 MB",
+            "processing_time": "0.10s",
+            "download_url": format!("/download/{}", file_id),
+        }),
+    )
+}
+
+/// Answers like the bundled server whose every synthesis produces `audio`:
+/// `/synthesize` names a download, `/download/...` sends `audio` and
+/// anything else, such as `/capabilities`, is not found.
+pub fn tts_reply(request: &Received, audio: &[u8]) -> Reply {
+    if request.path == "/synthesize" {
+        synthesized("audio")
+    } else if request.path.starts_with("/download/") {
+        Reply::bytes(200, "audio/wav", audio.to_vec())
+    } else {
+        Reply::not_found()
+    }
+}
+
+/// `ms` milliseconds of a quiet tone as 8 kHz mono WAV.
+pub fn tone_wav(ms: usize) -> Vec<u8> {
+    crate::audio::Wav {
+        sample_rate: 8000,
+        channels: 1,
+        bits_per_sample: 16,
+        data: (0..ms * 8).flat_map(|i| (((i as f64 * 0.3).sin() * 4000.0) as i16).to_le_bytes()).collect(),
+    }
+    .to_bytes()
+}
+
+/// Collects the payloads of `event` as the app emits them.
+pub fn listen(app: &AppHandle, event: &str) -> Arc<Mutex<Vec<serde_json::Value>>> {
+    use tauri::Listener;
+
+    let payloads = Arc::new(Mutex::new(Vec::new()));
+    let collected = payloads.clone();
+    app.listen(event.to_string(), move |event| {
+        collected.lock().unwrap().push(serde_json::from_str(event.payload()).unwrap_or_default());
+    });
+    payloads
+}
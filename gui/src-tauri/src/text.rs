@@ -0,0 +1,587 @@
+use std::path::Path;
+
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_8};
+use serde::Serialize;
+use tauri::command;
+use unicode_script::{Script, UnicodeScript};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{capabilities, AppHandle};
+
+/// Maximum request size accepted by Chirp 3 HD voices, in bytes of UTF-8 text.
+pub const MAX_REQUEST_BYTES: usize = 5000;
+
+/// Splits `text` into chunks of at most `max_bytes` bytes, preferring sentence
+/// boundaries, then whitespace, and only cutting mid-word as a last resort.
+pub fn split_text(text: &str, max_bytes: usize) -> Vec<String> {
+    let max_bytes = max_bytes.max(4);
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for sentence in sentences(text) {
+        let sentence = sentence.trim();
+        if sentence.is_empty() {
+            continue;
+        }
+
+        let needed = if current.is_empty() { sentence.len() } else { current.len() + 1 + sentence.len() };
+        if needed <= max_bytes {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(sentence);
+            continue;
+        }
+
+        if !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+        }
+
+        if sentence.len() <= max_bytes {
+            current.push_str(sentence);
+        } else {
+            // A single sentence is too long, so fall back to packing words
+            for word in sentence.split_whitespace() {
+                let needed = if current.is_empty() { word.len() } else { current.len() + 1 + word.len() };
+                if needed <= max_bytes {
+                    if !current.is_empty() {
+                        current.push(' ');
+                    }
+                    current.push_str(word);
+                    continue;
+                }
+
+                if !current.is_empty() {
+                    chunks.push(std::mem::take(&mut current));
+                }
+
+                let mut rest = word;
+                while rest.len() > max_bytes {
+                    let mut cut = max_bytes;
+                    while !rest.is_char_boundary(cut) {
+                        cut -= 1;
+                    }
+                    chunks.push(rest[..cut].to_string());
+                    rest = &rest[cut..];
+                }
+                current.push_str(rest);
+            }
+        }
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Iterates over sentences, keeping the terminating punctuation attached.
+/// Line breaks are treated as sentence boundaries as well.
+fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut chars = rest.char_indices().peekable();
+        let mut end = rest.len();
+        while let Some((i, c)) = chars.next() {
+            let boundary = match c {
+                '\n' => true,
+                '.' | '!' | '?' | '。' | '！' | '？' => {
+                    matches!(chars.peek(), None | Some((_, ' ' | '\t' | '\r' | '\n')))
+                }
+                _ => false,
+            };
+            if boundary {
+                end = i + c.len_utf8();
+                break;
+            }
+        }
+
+        let (sentence, remaining) = rest.split_at(end);
+        rest = remaining;
+        Some(sentence)
+    })
+}
+
+/// Extracts the language code from a voice name such as `en-US-Chirp3-HD-Charon`.
+pub fn language_from_voice(voice: &str) -> Option<String> {
+    let mut parts = voice.splitn(3, '-');
+    let language = parts.next()?;
+    let region = parts.next()?;
+
+    let valid_language = (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_lowercase());
+    let valid_region = (region.len() == 2 && region.chars().all(|c| c.is_ascii_uppercase()))
+        || (region.len() == 3 && region.chars().all(|c| c.is_ascii_digit()));
+
+    if valid_language && valid_region {
+        Some(format!("{}-{}", language, region))
+    } else {
+        None
+    }
+}
+
+/// The language to synthesize `voice` in when `language` was asked for.
+/// A voice only speaks the language in its name, so a conflicting language
+/// is corrected to it, or refused when `strict`. Voices without a language
+/// in their name, like offline ones, keep `language`.
+pub fn reconcile_language(voice: &str, language: &str, strict: bool) -> Result<String, String> {
+    let Some(spoken) = language_from_voice(voice) else {
+        return Ok(language.to_string());
+    };
+    if spoken.eq_ignore_ascii_case(language) {
+        return Ok(spoken);
+    }
+    if strict {
+        return Err(format!("Voice '{}' speaks {}, not {}", voice, spoken, language));
+    }
+    println!("Using {} instead of {} to match voice '{}'", spoken, language, voice);
+    Ok(spoken)
+}
+
+/// Average narration pace used for duration estimates.
+const WORDS_PER_MINUTE: f64 = 150.0;
+
+/// Characters shown from each end of a chunk in `preview_chunks`.
+const PREVIEW_CHARS: usize = 60;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkInfo {
+    index: usize,
+    bytes: usize,
+    characters: usize,
+    /// The chunk's first and last `PREVIEW_CHARS` characters, where awkward
+    /// breaks would show; both hold the whole chunk when it is short.
+    starts_with: String,
+    ends_with: String,
+}
+
+/// Shows how `split_text` would divide `text` into requests, without
+/// synthesizing or contacting the server. `max_bytes` defaults to the
+/// server's limit from the last capabilities check.
+#[command]
+pub fn preview_chunks(app: AppHandle, text: &str, max_bytes: Option<usize>) -> Result<Vec<ChunkInfo>, String> {
+    let max_bytes = max_bytes.unwrap_or_else(|| capabilities::cached(&app).max_request_bytes);
+    if max_bytes == 0 {
+        return Err("The chunk size must be at least 1 byte".to_string());
+    }
+
+    Ok(split_text(text, max_bytes)
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let characters = chunk.chars().count();
+            ChunkInfo {
+                index,
+                bytes: chunk.len(),
+                characters,
+                starts_with: chunk.chars().take(PREVIEW_CHARS).collect(),
+                ends_with: chunk.chars().skip(characters.saturating_sub(PREVIEW_CHARS)).collect(),
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TextStats {
+    characters: usize,
+    characters_no_spaces: usize,
+    words: usize,
+    sentences: usize,
+    estimated_seconds: f64,
+}
+
+/// Counts user-perceived characters (grapheme clusters), words and sentences,
+/// and estimates the spoken duration. Purely local, so it is cheap to call on
+/// every keystroke.
+#[command]
+pub fn text_stats(text: &str) -> TextStats {
+    let mut characters = 0;
+    let mut characters_no_spaces = 0;
+    for grapheme in text.graphemes(true) {
+        characters += 1;
+        if !grapheme.chars().all(char::is_whitespace) {
+            characters_no_spaces += 1;
+        }
+    }
+
+    let words = word_count(text);
+    let sentences = text.unicode_sentences().filter(|s| !s.trim().is_empty()).count();
+
+    TextStats {
+        characters,
+        characters_no_spaces,
+        words,
+        sentences,
+        estimated_seconds: spoken_seconds(words),
+    }
+}
+
+/// Estimated narration length of `words` words, rounded to a tenth of a second.
+pub fn spoken_seconds(words: usize) -> f64 {
+    (words as f64 * 60.0 / WORDS_PER_MINUTE * 10.0).round() / 10.0
+}
+
+/// Characters per word of typical prose, counting the space after it.
+const CHARS_PER_WORD: f64 = 6.0;
+
+/// Estimated narration length of `chars` characters of prose.
+pub fn spoken_seconds_for_chars(chars: usize) -> f64 {
+    spoken_seconds((chars as f64 / CHARS_PER_WORD).round() as usize)
+}
+
+pub fn word_count(text: &str) -> usize {
+    text.unicode_words().count()
+}
+
+/// Whether `text` has anything a voice would read out: at least one letter
+/// or digit. Punctuation, symbols and emoji alone come back as silence or
+/// an error from the server.
+pub fn has_speakable_content(text: &str) -> bool {
+    text.chars().any(char::is_alphanumeric)
+}
+
+/// Below this many letters detection is mostly guesswork.
+const MIN_DETECTION_LETTERS: usize = 12;
+
+/// Guesses the language of `text` and returns it as a TTS locale such as
+/// `fr-FR`. Returns `None` for short, ambiguous or unsupported text instead of
+/// guessing, so the UI only prompts the user when it is confident.
+#[command]
+pub fn detect_language(text: &str) -> Option<String> {
+    if text.chars().filter(|c| c.is_alphabetic()).count() < MIN_DETECTION_LETTERS {
+        return None;
+    }
+
+    let info = whatlang::detect(text)?;
+    if !info.is_reliable() {
+        return None;
+    }
+
+    use whatlang::Lang;
+    let locale = match info.lang() {
+        Lang::Eng => "en-US",
+        Lang::Fra => "fr-FR",
+        Lang::Deu => "de-DE",
+        Lang::Spa => "es-ES",
+        Lang::Ita => "it-IT",
+        Lang::Por => "pt-BR",
+        Lang::Nld => "nl-NL",
+        Lang::Rus => "ru-RU",
+        Lang::Ukr => "uk-UA",
+        Lang::Pol => "pl-PL",
+        Lang::Ces => "cs-CZ",
+        Lang::Tur => "tr-TR",
+        Lang::Swe => "sv-SE",
+        Lang::Dan => "da-DK",
+        Lang::Fin => "fi-FI",
+        Lang::Nob => "nb-NO",
+        Lang::Ell => "el-GR",
+        Lang::Hun => "hu-HU",
+        Lang::Ron => "ro-RO",
+        Lang::Ara => "ar-XA",
+        Lang::Heb => "he-IL",
+        Lang::Hin => "hi-IN",
+        Lang::Ben => "bn-IN",
+        Lang::Tam => "ta-IN",
+        Lang::Tel => "te-IN",
+        Lang::Mar => "mr-IN",
+        Lang::Guj => "gu-IN",
+        Lang::Kan => "kn-IN",
+        Lang::Mal => "ml-IN",
+        Lang::Urd => "ur-IN",
+        Lang::Tha => "th-TH",
+        Lang::Vie => "vi-VN",
+        Lang::Ind => "id-ID",
+        Lang::Jpn => "ja-JP",
+        Lang::Kor => "ko-KR",
+        Lang::Cmn => "cmn-CN",
+        _ => return None,
+    };
+
+    Some(locale.to_string())
+}
+
+/// Share of the letters below which a script doesn't make text count as
+/// mixed, so a brand name or two in Japanese text raises no warning.
+const MIN_MIXED_SHARE: f64 = 0.05;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptShare {
+    script: String,
+    characters: usize,
+    /// Fraction of all characters that belong to some script.
+    proportion: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptAnalysis {
+    /// Most used first. Digits, punctuation and spaces belong to no script
+    /// and are left out.
+    scripts: Vec<ScriptShare>,
+    mixed: bool,
+    warning: Option<String>,
+}
+
+/// Scripts one language writes together, such as Han and Hiragana in
+/// Japanese, share a group so they don't count as a mix.
+fn writing_system(script: Script) -> Script {
+    match script {
+        Script::Hiragana | Script::Katakana | Script::Hangul | Script::Bopomofo => Script::Han,
+        other => other,
+    }
+}
+
+/// Finds the scripts in `text` and how much of it each makes up, to warn
+/// before a single-language voice reads text in more than one.
+pub fn analyze_scripts(text: &str) -> ScriptAnalysis {
+    let mut counts: Vec<(Script, usize)> = Vec::new();
+    for script in text.chars().map(|c| c.script()) {
+        if matches!(script, Script::Common | Script::Inherited | Script::Unknown) {
+            continue;
+        }
+        match counts.iter_mut().find(|(known, _)| *known == script) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((script, 1)),
+        }
+    }
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    let share = |count: usize| count as f64 / total.max(1) as f64;
+
+    // Scripts big enough to matter, one per writing system
+    let mut significant: Vec<Script> = Vec::new();
+    for &(script, count) in &counts {
+        let system = writing_system(script);
+        if share(count) >= MIN_MIXED_SHARE && !significant.iter().any(|s| writing_system(*s) == system) {
+            significant.push(script);
+        }
+    }
+
+    let mixed = significant.len() > 1;
+    let warning = mixed.then(|| {
+        let names: Vec<&str> = significant.iter().map(|s| s.full_name()).collect();
+        let (last, rest) = names.split_last().unwrap_or((&"", &[]));
+        format!("This text mixes {} and {}; consider splitting it", rest.join(", "), last)
+    });
+
+    ScriptAnalysis {
+        scripts: counts
+            .into_iter()
+            .map(|(script, characters)| ScriptShare {
+                script: script.full_name().to_string(),
+                characters,
+                proportion: share(characters),
+            })
+            .collect(),
+        mixed,
+        warning,
+    }
+}
+
+#[command]
+pub fn get_script_analysis(text: &str) -> ScriptAnalysis {
+    analyze_scripts(text)
+}
+
+/// Reads a text file whatever its encoding, returning the text and the name
+/// of the encoding it was read as. `encoding` is a label to use instead of
+/// guessing, e.g. `windows-1252`; otherwise a byte order mark decides, then
+/// valid UTF-8, then a statistical guess. A leading byte order mark is dropped.
+pub fn read_text_file(path: &Path, encoding: Option<&str>) -> Result<(String, &'static str), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let encoding = match encoding.map(str::trim).filter(|label| !label.is_empty()) {
+        Some(label) => Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("Unknown text encoding '{}'", label))?,
+        None => match Encoding::for_bom(&bytes) {
+            Some((encoding, _)) => encoding,
+            None if std::str::from_utf8(&bytes).is_ok() => UTF_8,
+            None => {
+                let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+                detector.feed(&bytes, true);
+                detector.guess(None, Utf8Detection::Deny)
+            }
+        },
+    };
+    let (text, malformed) = encoding.decode_with_bom_removal(&bytes);
+    if malformed {
+        return Err(format!("{} is not valid {} text", path.display(), encoding.name()));
+    }
+    Ok((text.into_owned(), encoding.name()))
+}
+
+/// The readable text of a Markdown document: headings, paragraphs and list
+/// items on lines of their own, without the markup, links reduced to their
+/// text and images to their alt text. HTML is dropped.
+pub fn strip_markdown(markdown: &str) -> String {
+    use pulldown_cmark::{Event, Parser, TagEnd};
+
+    let mut text = String::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::CodeBlock) => {
+                text.truncate(text.trim_end().len());
+                text.push_str("\n\n");
+            }
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_stats_counts_graphemes_words_and_sentences() {
+        let stats = text_stats("Hello world. How are you?");
+        assert_eq!(stats.characters, 25);
+        assert_eq!(stats.characters_no_spaces, 21);
+        assert_eq!(stats.words, 5);
+        assert_eq!(stats.sentences, 2);
+        assert_eq!(stats.estimated_seconds, 2.0);
+    }
+
+    #[test]
+    fn text_stats_counts_combined_characters_once() {
+        // "e" plus a combining acute accent, and a family emoji made of several code points
+        let stats = text_stats("e\u{301} \u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}");
+        assert_eq!(stats.characters, 3);
+        assert_eq!(stats.characters_no_spaces, 2);
+    }
+
+    #[test]
+    fn text_stats_of_blank_text_is_zero() {
+        let stats = text_stats(" \n\t ");
+        assert_eq!(stats.characters_no_spaces, 0);
+        assert_eq!(stats.words, 0);
+        assert_eq!(stats.sentences, 0);
+        assert_eq!(stats.estimated_seconds, 0.0);
+    }
+
+    #[test]
+    fn detect_language_maps_to_a_locale() {
+        let french = "Bonjour tout le monde, je suis très heureux de vous voir aujourd'hui dans cette belle ville.";
+        assert_eq!(detect_language(french).as_deref(), Some("fr-FR"));
+        let english = "It was the best of times, it was the worst of times, it was the age of wisdom, \
+                       it was the age of foolishness, it was the epoch of belief.";
+        assert_eq!(detect_language(english).as_deref(), Some("en-US"));
+    }
+
+    #[test]
+    fn detect_language_refuses_short_text() {
+        assert_eq!(detect_language("Bonjour"), None);
+        assert_eq!(detect_language("12345 67890 !!!"), None);
+    }
+
+    #[test]
+    fn spoken_seconds_follows_the_narration_pace() {
+        assert_eq!(spoken_seconds(150), 60.0);
+        assert_eq!(spoken_seconds(1), 0.4);
+    }
+
+    #[test]
+    fn analyze_scripts_counts_letters_only() {
+        let analysis = analyze_scripts("Hello, 123!");
+        assert!(!analysis.mixed && analysis.warning.is_none());
+        assert_eq!(analysis.scripts.len(), 1);
+        assert_eq!(analysis.scripts[0].script, "Latin");
+        assert_eq!(analysis.scripts[0].characters, 5);
+        assert_eq!(analysis.scripts[0].proportion, 1.0);
+
+        assert!(analyze_scripts("123 ...").scripts.is_empty());
+    }
+
+    #[test]
+    fn analyze_scripts_warns_about_real_mixes_only() {
+        let mixed = analyze_scripts("Hello there Привет друг");
+        assert!(mixed.mixed);
+        assert_eq!(mixed.warning.as_deref(), Some("This text mixes Latin and Cyrillic; consider splitting it"));
+
+        // Kanji and kana are one writing system
+        assert!(!analyze_scripts("今日はいい天気ですね").mixed);
+        // A brand name in a long Japanese sentence is under the threshold
+        let japanese = format!("{}KIWI", "今日はとてもいい天気ですね".repeat(10));
+        assert!(!analyze_scripts(&japanese).mixed);
+    }
+
+    #[test]
+    fn reconcile_language_follows_the_voice() {
+        let voice = "fr-FR-Chirp3-HD-Leda";
+        assert_eq!(reconcile_language(voice, "fr-fr", false), Ok("fr-FR".to_string()));
+        assert_eq!(reconcile_language(voice, "en-US", false), Ok("fr-FR".to_string()));
+        assert_eq!(
+            reconcile_language(voice, "en-US", true),
+            Err("Voice 'fr-FR-Chirp3-HD-Leda' speaks fr-FR, not en-US".to_string())
+        );
+        // Nothing to go on in names like offline voices have
+        assert_eq!(reconcile_language("Samantha", "en-GB", true), Ok("en-GB".to_string()));
+    }
+
+    #[test]
+    fn has_speakable_content_needs_a_letter_or_digit() {
+        assert!(has_speakable_content("Hi!"));
+        assert!(has_speakable_content("42"));
+        assert!(has_speakable_content("日本"));
+        assert!(!has_speakable_content(""));
+        assert!(!has_speakable_content("?!... -- *"));
+        assert!(!has_speakable_content("🎉👍"));
+    }
+
+    #[test]
+    fn spoken_seconds_for_chars_assumes_six_characters_a_word() {
+        assert_eq!(spoken_seconds_for_chars(900), 60.0);
+        assert_eq!(spoken_seconds_for_chars(0), 0.0);
+        assert_eq!(spoken_seconds_for_chars(9), spoken_seconds(2));
+    }
+
+    fn read_bytes(bytes: &[u8], encoding: Option<&str>) -> Result<(String, &'static str), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.txt");
+        std::fs::write(&path, bytes).unwrap();
+        read_text_file(&path, encoding)
+    }
+
+    #[test]
+    fn read_text_file_prefers_utf8_and_byte_order_marks() {
+        assert_eq!(read_bytes("Crème brûlée".as_bytes(), None), Ok(("Crème brûlée".to_string(), "UTF-8")));
+        assert_eq!(read_bytes("\u{feff}Hi".as_bytes(), None), Ok(("Hi".to_string(), "UTF-8")));
+        let utf16: Vec<u8> = [0xff, 0xfe].into_iter().chain("Hi".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+        assert_eq!(read_bytes(&utf16, None), Ok(("Hi".to_string(), "UTF-16LE")));
+    }
+
+    #[test]
+    fn read_text_file_guesses_legacy_encodings() {
+        let latin = b"Le caf\xe9 est tr\xe8s bon, merci beaucoup pour le d\xe9jeuner.";
+        let (text, encoding) = read_bytes(latin, None).unwrap();
+        assert_eq!(text, "Le café est très bon, merci beaucoup pour le déjeuner.");
+        assert_eq!(encoding, "windows-1252");
+    }
+
+    #[test]
+    fn read_text_file_uses_the_named_encoding() {
+        assert_eq!(read_bytes(b"caf\xe9", Some(" latin1 ")), Ok(("café".to_string(), "windows-1252")));
+        assert_eq!(read_bytes(b"Hi", Some("klingon")), Err("Unknown text encoding 'klingon'".to_string()));
+        let err = read_bytes(b"caf\xe9", Some("utf-8")).unwrap_err();
+        assert!(err.ends_with("is not valid UTF-8 text"), "{}", err);
+    }
+
+    #[test]
+    fn strip_markdown_keeps_the_readable_text() {
+        let markdown = "# Title\n\nSome *emphasis* and a [link](https://example.com)\nover two lines.\n\n\
+                        - first\n- second\n\n![A kiwi](kiwi.png) with `code`.\n\n<div>html</div>\n";
+        assert_eq!(
+            strip_markdown(markdown),
+            "Title\n\nSome emphasis and a link over two lines.\n\nfirst\n\nsecond\n\nA kiwi with code."
+        );
+        assert_eq!(strip_markdown("   "), "");
+    }
+}
@@ -0,0 +1,96 @@
+//! Everything a bug report needs to say about what was running.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::{client, servers, AppHandle};
+
+const SERVER_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    app_version: String,
+    /// Short hash of the commit the app was built from, or `unknown`.
+    git_hash: String,
+    /// RFC 3339, in UTC. Set when the build script last ran, which is when
+    /// the commit changed, so rebuilds of one commit share it.
+    build_timestamp: String,
+    os: String,
+    arch: String,
+    server_url: String,
+    /// `None` when the server can't be reached or doesn't say.
+    server_version: Option<String>,
+}
+
+/// The API root, `GET /`, which names the server's version.
+#[derive(Deserialize)]
+struct ServerRoot {
+    version: Option<String>,
+}
+
+async fn server_version(client: &reqwest::Client, url: &str) -> Option<String> {
+    let response = client.get(format!("{}/", url)).timeout(SERVER_TIMEOUT).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    response.json::<ServerRoot>().await.ok()?.version
+}
+
+/// Seconds since the Unix epoch as RFC 3339, or `unknown`.
+fn rfc3339(secs: &str) -> String {
+    secs.parse()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map_or_else(|| "unknown".to_string(), |built| built.to_rfc3339())
+}
+
+#[command]
+pub async fn get_version_info(app: AppHandle) -> VersionInfo {
+    let client = client::build(&app);
+    let server_url = servers::primary(&app, &client).await;
+    VersionInfo {
+        app_version: app.package_info().version.to_string(),
+        git_hash: env!("KIWI_GIT_HASH").to_string(),
+        build_timestamp: rfc3339(env!("KIWI_BUILD_TIMESTAMP")),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        server_version: server_version(&client, &server_url).await,
+        server_url,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{MockServer, Reply};
+
+    /// A server answering every request with `body`.
+    async fn root(status: u16, body: serde_json::Value) -> MockServer {
+        MockServer::start(move |_| Reply::json(status, body.clone())).await
+    }
+
+    #[test]
+    fn rfc3339_formats_epoch_seconds() {
+        assert_eq!(rfc3339("1700000000"), "2023-11-14T22:13:20+00:00");
+        assert_eq!(rfc3339(""), "unknown");
+        assert!(env!("KIWI_BUILD_TIMESTAMP").parse::<i64>().is_ok());
+        assert!(!env!("KIWI_GIT_HASH").is_empty());
+    }
+
+    #[tokio::test]
+    async fn server_version_reads_the_api_root() {
+        let client = reqwest::Client::new();
+        let server = root(200, json!({ "message": "KIWI API", "version": "1.4.0" })).await;
+        assert_eq!(server_version(&client, &server.url).await.as_deref(), Some("1.4.0"));
+        assert_eq!(server.requests()[0].path, "/");
+
+        let server = root(200, json!({ "message": "KIWI API" })).await;
+        assert_eq!(server_version(&client, &server.url).await, None);
+        let server = root(500, json!({})).await;
+        assert_eq!(server_version(&client, &server.url).await, None);
+    }
+}
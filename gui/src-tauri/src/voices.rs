@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tauri::{command, Manager};
+
+use crate::{
+    audio, cache, client, fetch_voices_with_source, paths, settings, synthesize_long, text, AppHandle, TTSRequest, Voice,
+};
+
+/// How long a server's voice list is reused before asking again.
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Built-in preview phrases, one per language KIWI offers.
+const PREVIEW_PHRASES: [(&str, &str); 12] = [
+    ("en-US", "Hello! This is how I sound when reading your text aloud."),
+    ("en-GB", "Hello! This is how I sound when reading your text aloud."),
+    ("es-ES", "¡Hola! Así es como sueno cuando leo tu texto en voz alta."),
+    ("fr-FR", "Bonjour ! Voici ma voix lorsque je lis votre texte à voix haute."),
+    ("de-DE", "Hallo! So klinge ich, wenn ich Ihren Text vorlese."),
+    ("it-IT", "Ciao! Ecco come suono quando leggo il tuo testo ad alta voce."),
+    ("ja-JP", "こんにちは。これは、あなたの文章を読み上げるときの私の声です。"),
+    ("ko-KR", "안녕하세요. 제가 텍스트를 소리 내어 읽을 때의 목소리입니다."),
+    ("pt-BR", "Olá! É assim que eu soo ao ler o seu texto em voz alta."),
+    ("zh-CN", "你好！这就是我朗读你的文字时的声音。"),
+    ("hi-IN", "नमस्ते! आपका पाठ पढ़ते समय मेरी आवाज़ ऐसी लगती है।"),
+    ("ar-XA", "مرحبًا! هكذا يبدو صوتي عندما أقرأ نصك بصوت عالٍ."),
+];
+
+const DEFAULT_PREVIEW_LANGUAGE: &str = "en-US";
+
+/// Voices offered when the API server can't be asked for its list, keyed by
+/// language code, each shaped like an entry of the server's own lists. Only
+/// English Chirp 3 HD voices are bundled.
+const BUNDLED_FALLBACK_VOICES: &str = include_str!("fallback_voices.json");
+
+type FallbackVoices = HashMap<String, Vec<Voice>>;
+
+/// Voice lists from the server, keyed by lowercased language code.
+#[derive(Default)]
+pub struct VoiceCache(Mutex<HashMap<String, (Vec<Voice>, Instant)>>);
+
+pub fn cached(app: &AppHandle, language_code: &str) -> Option<Vec<Voice>> {
+    let cache = app.state::<VoiceCache>();
+    let voices = cache.0.lock().unwrap();
+    voices
+        .get(&language_code.to_ascii_lowercase())
+        .filter(|(_, fetched)| fetched.elapsed() < CACHE_TTL)
+        .map(|(voices, _)| voices.clone())
+}
+
+pub fn store(app: &AppHandle, language_code: &str, voices: &[Voice]) {
+    let cache = app.state::<VoiceCache>();
+    cache
+        .0
+        .lock()
+        .unwrap()
+        .insert(language_code.to_ascii_lowercase(), (voices.to_vec(), Instant::now()));
+}
+
+/// Forgets every cached list, e.g. after the server list changes.
+pub fn invalidate(app: &AppHandle) {
+    app.state::<VoiceCache>().0.lock().unwrap().clear();
+}
+
+fn parse_fallback(json: &str) -> Result<FallbackVoices, String> {
+    serde_json::from_str(json).map_err(|e| format!("Invalid fallback voices: {}", e))
+}
+
+fn bundled_fallback() -> FallbackVoices {
+    parse_fallback(BUNDLED_FALLBACK_VOICES).unwrap_or_default()
+}
+
+fn fallback_for(voices: FallbackVoices, language_code: &str) -> Vec<Voice> {
+    voices
+        .into_iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(language_code))
+        .map(|(_, voices)| voices)
+        .unwrap_or_default()
+}
+
+/// Fallback voices for `language_code`, from the file at
+/// `fallback_voices_path` or else the bundled ones; empty when there are
+/// none for the language. An unreadable file is logged and skipped.
+pub fn fallback(app: &AppHandle, language_code: &str) -> Vec<Voice> {
+    fallback_in(settings::current(app).fallback_voices_path.as_deref(), language_code)
+}
+
+fn fallback_in(path: Option<&str>, language_code: &str) -> Vec<Voice> {
+    let voices = match path {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read fallback voices {}: {}", path, e))
+            .and_then(|json| parse_fallback(&json))
+            .unwrap_or_else(|e| {
+                println!("{}; using the bundled ones", e);
+                bundled_fallback()
+            }),
+        None => bundled_fallback(),
+    };
+    fallback_for(voices, language_code)
+}
+
+/// Whether a synthesis error means the voice itself doesn't exist, as opposed
+/// to a network, quota or text problem. Google reports these as
+/// "Voice '...' does not exist".
+pub fn is_voice_missing(error: &str) -> bool {
+    let error = error.to_ascii_lowercase();
+    error.contains("voice")
+        && ["does not exist", "not found", "not available", "unknown voice", "invalid voice"]
+            .iter()
+            .any(|phrase| error.contains(phrase))
+}
+
+/// The model family in a voice name, e.g. `Chirp3-HD` in
+/// `en-US-Chirp3-HD-Charon` or `Wavenet` in `en-US-Wavenet-D`.
+pub fn voice_tier(name: &str) -> Option<String> {
+    let parts: Vec<&str> = name.split('-').collect();
+    (parts.len() > 3).then(|| parts[2..parts.len() - 1].join("-"))
+}
+
+/// Gender of a voice that is no longer listed, known only for the voices
+/// KIWI ships as fallbacks.
+fn known_gender(name: &str) -> Option<String> {
+    let language = text::language_from_voice(name)?;
+    fallback_for(bundled_fallback(), &language)
+        .into_iter()
+        .find(|voice| voice.name == name)
+        .map(|voice| voice.ssml_gender)
+}
+
+/// Picks the available voice most like `missing`: same tier and gender if
+/// possible, then same tier, then same gender. Ties go to the first voice
+/// in name order so the choice is stable.
+pub fn closest_voice(missing: &str, available: &[Voice]) -> Option<Voice> {
+    let tier = voice_tier(missing);
+    let gender = known_gender(missing);
+
+    available
+        .iter()
+        .filter(|voice| voice.name != missing)
+        .max_by_key(|voice| {
+            let same_tier = tier.is_some() && voice_tier(&voice.name) == tier;
+            let same_gender = gender.as_deref() == Some(voice.ssml_gender.as_str());
+            // Reversed so that among equal scores the first name wins
+            (same_tier as u8 * 2 + same_gender as u8, std::cmp::Reverse(voice.name.clone()))
+        })
+        .cloned()
+}
+
+#[derive(Debug, Serialize)]
+pub struct VoiceCheck {
+    exists: bool,
+    /// The server couldn't be reached, so `exists` only reflects the voices
+    /// KIWI ships with.
+    fallback: bool,
+}
+
+/// Whether `voice` is offered for `language`. An absent voice is not an
+/// error, so stale favorites can simply be shown as unavailable.
+#[command]
+pub async fn voice_exists(app: AppHandle, voice: String, language: String) -> Result<VoiceCheck, String> {
+    let list = fetch_voices_with_source(&app, &language).await;
+    Ok(VoiceCheck {
+        exists: list.voices.iter().any(|v| v.name == voice.trim()),
+        fallback: list.fallback_reason.is_some(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct VoiceSample {
+    voice: String,
+    success: bool,
+    output_path: Option<String>,
+    duration_seconds: Option<f64>,
+    /// True when the audio came from the audio cache.
+    cached: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComparisonResult {
+    a: VoiceSample,
+    b: VoiceSample,
+}
+
+/// Renders `text` with `voice` into `dir`, from the audio cache when possible.
+async fn voice_sample(app: &AppHandle, dir: &std::path::Path, text: &str, voice: String, language: &str) -> VoiceSample {
+    let path = dir.join(format!("{}.mp3", paths::sanitize_filename(&voice)));
+    let path_str = path.to_string_lossy().to_string();
+    let request = TTSRequest {
+        text: text.to_string(),
+        voice: voice.clone(),
+        format: "MP3".to_string(),
+        language: language.to_string(),
+        ..Default::default()
+    };
+
+    let cached = cache::restore(app, &request, &path_str).is_some();
+    let rendered = if cached {
+        std::fs::read(&path).map_err(|e| format!("Failed to read audio file: {}", e))
+    } else {
+        match synthesize_long(app, &client::build(app), &request, None).await {
+            Ok(bytes) => paths::write_audio(&path, &bytes).map(|_| {
+                cache::store(app, &request, &path_str);
+                bytes
+            }),
+            Err(e) => Err(e),
+        }
+    };
+
+    VoiceSample::new(voice, path_str, cached, rendered)
+}
+
+impl VoiceSample {
+    /// Describes the outcome of rendering `voice` to `path`.
+    fn new(voice: String, path: String, cached: bool, rendered: Result<Vec<u8>, String>) -> Self {
+        match rendered {
+            Ok(bytes) => VoiceSample {
+                voice,
+                success: true,
+                output_path: Some(path),
+                duration_seconds: audio::duration_secs(&bytes),
+                cached,
+                error: None,
+            },
+            Err(e) => VoiceSample {
+                voice,
+                success: false,
+                output_path: None,
+                duration_seconds: None,
+                cached: false,
+                error: Some(e),
+            },
+        }
+    }
+}
+
+/// Rejects comparisons with nothing to say or only one voice.
+fn check_comparison(text: &str, voice_a: &str, voice_b: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("Text cannot be empty".to_string());
+    }
+    if voice_a == voice_b {
+        return Err("Choose two different voices to compare".to_string());
+    }
+    Ok(())
+}
+
+/// Synthesizes the same text with two voices at once for A/B listening.
+/// One voice failing doesn't fail the comparison; its sample carries the
+/// error instead.
+#[command]
+pub async fn compare_voices(
+    app: AppHandle,
+    text: String,
+    voice_a: String,
+    voice_b: String,
+    language: String,
+) -> Result<ComparisonResult, String> {
+    check_comparison(&text, &voice_a, &voice_b)?;
+
+    let dir = app
+        .path()
+        .app_cache_dir()
+        .map(|dir| dir.join("voice-comparison"))
+        .map_err(|e| format!("Cannot determine cache directory: {}", e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let (a, b) = tokio::join!(
+        voice_sample(&app, &dir, &text, voice_a, &language),
+        voice_sample(&app, &dir, &text, voice_b, &language),
+    );
+    Ok(ComparisonResult { a, b })
+}
+
+/// Phrase in `language` from `phrases`, matching the exact code first and
+/// then just the language part, so `es-MX` uses the `es-ES` phrase.
+fn phrase_for<'a>(phrases: impl Iterator<Item = (&'a str, &'a str)> + Clone, language: &str) -> Option<&'a str> {
+    let base = language.split('-').next().unwrap_or(language);
+    let exact = phrases.clone().find(|(code, _)| code.eq_ignore_ascii_case(language));
+    let same_base = || {
+        phrases
+            .clone()
+            .find(|(code, _)| code.split('-').next().unwrap_or(code).eq_ignore_ascii_case(base))
+    };
+    exact.or_else(same_base).map(|(_, phrase)| phrase)
+}
+
+/// The preview phrase for `language`: the user's own from `overrides` if
+/// there is one, else the built-in one, else the English one.
+pub fn preview_phrase(language: &str, overrides: &HashMap<String, String>) -> String {
+    let custom = overrides
+        .iter()
+        .filter(|(_, phrase)| !phrase.trim().is_empty())
+        .map(|(code, phrase)| (code.as_str(), phrase.as_str()));
+    phrase_for(custom, language)
+        .or_else(|| phrase_for(PREVIEW_PHRASES.into_iter(), language))
+        .or_else(|| phrase_for(PREVIEW_PHRASES.into_iter(), DEFAULT_PREVIEW_LANGUAGE))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Renders a short sample of `voice` for the frontend to play: `text` when
+/// given, otherwise the preview phrase for the voice's language.
+#[command]
+pub async fn preview_voice(app: AppHandle, voice: String, text: Option<String>) -> Result<VoiceSample, String> {
+    let settings = settings::current(&app);
+    let language = text::language_from_voice(&voice).unwrap_or(settings.default_language);
+    let text = text
+        .filter(|text| !text.trim().is_empty())
+        .unwrap_or_else(|| preview_phrase(&language, &settings.preview_phrases));
+
+    let dir = app
+        .path()
+        .app_cache_dir()
+        .map(|dir| dir.join("voice-preview"))
+        .map_err(|e| format!("Cannot determine cache directory: {}", e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    Ok(voice_sample(&app, &dir, &text, voice, &language).await)
+}
+
+/// Longest text `test_pronunciation` takes; it is meant for a word or two.
+const MAX_PRONUNCIATION_CHARS: usize = 100;
+
+/// The text `test_pronunciation` sends: `word` alone, or `word` inside the
+/// carrier sentence, which sounds more like running speech.
+fn pronunciation_text(word: &str, carrier: Option<&str>) -> Result<String, String> {
+    let word = word.trim();
+    if word.is_empty() {
+        return Err("Enter a word to test".to_string());
+    }
+    if word.chars().count() > MAX_PRONUNCIATION_CHARS {
+        return Err(format!("Pronunciation tests are limited to {} characters", MAX_PRONUNCIATION_CHARS));
+    }
+    match carrier {
+        None => Ok(word.to_string()),
+        Some(carrier) if carrier.contains("{word}") => Ok(carrier.replace("{word}", word)),
+        Some(carrier) => Err(format!("The carrier sentence '{}' has no {{word}} placeholder", carrier)),
+    }
+}
+
+/// Renders how `voice` currently says `word`, for checking it before adding
+/// a lexicon entry. With `in_sentence` the word is spoken inside the
+/// `pronunciation_carrier` setting. Renderings go through the audio cache,
+/// so testing the same word with the same voice again is instant.
+#[command]
+pub async fn test_pronunciation(
+    app: AppHandle,
+    word: String,
+    voice: String,
+    language: Option<String>,
+    in_sentence: Option<bool>,
+) -> Result<VoiceSample, String> {
+    let settings = settings::current(&app);
+    let carrier = in_sentence.unwrap_or(false).then_some(settings.pronunciation_carrier.as_str());
+    let text = pronunciation_text(&word, carrier)?;
+    let language = language
+        .or_else(|| text::language_from_voice(&voice))
+        .unwrap_or(settings.default_language);
+
+    let dir = app
+        .path()
+        .app_cache_dir()
+        .map(|dir| dir.join("pronunciation").join(paths::sanitize_filename(word.trim())))
+        .map_err(|e| format!("Cannot determine cache directory: {}", e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    Ok(voice_sample(&app, &dir, &text, voice, &language).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(name: &str, gender: &str) -> Voice {
+        Voice {
+            name: name.to_string(),
+            language_code: text::language_from_voice(name).unwrap_or_default(),
+            ssml_gender: gender.to_string(),
+            display_name: None,
+            natural_sample_rate_hz: None,
+            natural_channels: None,
+            supported_formats: Vec::new(),
+            model_tier: None,
+            speaking_styles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn is_voice_missing_recognizes_only_voice_errors() {
+        assert!(is_voice_missing("API error: Voice 'en-US-Chirp3-HD-Foo' does not exist."));
+        assert!(is_voice_missing("Unknown voice en-US-X"));
+        assert!(!is_voice_missing("Quota exceeded for requests per minute"));
+        assert!(!is_voice_missing("File not found"));
+    }
+
+    #[test]
+    fn voice_tier_is_the_middle_of_the_name() {
+        assert_eq!(voice_tier("en-US-Chirp3-HD-Charon").as_deref(), Some("Chirp3-HD"));
+        assert_eq!(voice_tier("en-US-Wavenet-D").as_deref(), Some("Wavenet"));
+        assert_eq!(voice_tier("Samantha"), None);
+    }
+
+    #[test]
+    fn closest_voice_prefers_tier_then_gender() {
+        // Kore is a bundled female Chirp 3 HD voice
+        let available = [
+            voice("en-US-Wavenet-F", "FEMALE"),
+            voice("en-US-Chirp3-HD-Zephyr", "MALE"),
+            voice("en-US-Chirp3-HD-Leda", "FEMALE"),
+            voice("en-US-Chirp3-HD-Aoede", "FEMALE"),
+        ];
+        let closest = closest_voice("en-US-Chirp3-HD-Kore", &available).unwrap();
+        assert_eq!(closest.name, "en-US-Chirp3-HD-Aoede");
+
+        let other_tiers = [voice("en-US-Wavenet-D", "MALE"), voice("en-US-Wavenet-F", "FEMALE")];
+        assert_eq!(closest_voice("en-US-Chirp3-HD-Kore", &other_tiers).unwrap().name, "en-US-Wavenet-F");
+    }
+
+    #[test]
+    fn closest_voice_never_picks_the_missing_voice() {
+        let available = [voice("en-US-Chirp3-HD-Kore", "FEMALE")];
+        assert!(closest_voice("en-US-Chirp3-HD-Kore", &available).is_none());
+        assert!(closest_voice("en-US-Chirp3-HD-Kore", &[]).is_none());
+    }
+
+    #[test]
+    fn comparisons_need_text_and_two_voices() {
+        assert!(check_comparison("Hello", "en-US-Chirp3-HD-Kore", "en-US-Chirp3-HD-Puck").is_ok());
+        assert_eq!(check_comparison("  ", "a", "b"), Err("Text cannot be empty".to_string()));
+        assert_eq!(check_comparison("Hello", "a", "a"), Err("Choose two different voices to compare".to_string()));
+    }
+
+    #[test]
+    fn voice_samples_carry_either_audio_or_the_error() {
+        let wav = audio::Wav {
+            sample_rate: 8000,
+            channels: 1,
+            bits_per_sample: 16,
+            data: vec![0; 8000],
+        }
+        .to_bytes();
+        let sample = VoiceSample::new("Kore".to_string(), "/tmp/Kore.mp3".to_string(), true, Ok(wav));
+        assert!(sample.success && sample.cached);
+        assert_eq!(sample.output_path.as_deref(), Some("/tmp/Kore.mp3"));
+        assert_eq!(sample.duration_seconds, Some(0.5));
+
+        let failed = VoiceSample::new("Puck".to_string(), "/tmp/Puck.mp3".to_string(), true, Err("offline".to_string()));
+        assert!(!failed.success && !failed.cached);
+        assert_eq!(failed.output_path, None);
+        assert_eq!(failed.error.as_deref(), Some("offline"));
+    }
+
+    #[test]
+    fn preview_phrase_matches_the_language_then_its_base() {
+        let none = HashMap::new();
+        assert!(preview_phrase("fr-FR", &none).starts_with("Bonjour"));
+        assert!(preview_phrase("es-MX", &none).starts_with("¡Hola"));
+        assert!(preview_phrase("DE-de", &none).starts_with("Hallo"));
+        assert_eq!(preview_phrase("sw-KE", &none), preview_phrase(DEFAULT_PREVIEW_LANGUAGE, &none));
+    }
+
+    #[test]
+    fn preview_phrase_prefers_non_blank_overrides() {
+        let overrides = HashMap::from([
+            ("fr".to_string(), "Salut !".to_string()),
+            ("de-DE".to_string(), "   ".to_string()),
+        ]);
+        assert_eq!(preview_phrase("fr-CA", &overrides), "Salut !");
+        assert!(preview_phrase("de-DE", &overrides).starts_with("Hallo"));
+    }
+
+    #[test]
+    fn pronunciation_text_places_the_word() {
+        assert_eq!(pronunciation_text("  quinoa ", None), Ok("quinoa".to_string()));
+        assert_eq!(
+            pronunciation_text("quinoa", Some("Say {word} again.")),
+            Ok("Say quinoa again.".to_string())
+        );
+        assert_eq!(
+            pronunciation_text("quinoa", Some("Say it again.")),
+            Err("The carrier sentence 'Say it again.' has no {word} placeholder".to_string())
+        );
+    }
+
+    #[test]
+    fn pronunciation_text_wants_a_short_word() {
+        assert_eq!(pronunciation_text(" ", None), Err("Enter a word to test".to_string()));
+        assert!(pronunciation_text(&"a".repeat(MAX_PRONUNCIATION_CHARS), None).is_ok());
+        assert!(pronunciation_text(&"a".repeat(MAX_PRONUNCIATION_CHARS + 1), None).is_err());
+    }
+
+    fn names(voices: &[Voice]) -> Vec<&str> {
+        voices.iter().map(|voice| voice.name.as_str()).collect()
+    }
+
+    #[test]
+    fn fallback_is_the_requested_language() {
+        for language in ["en-US", "en-GB", "en-au"] {
+            let voices = fallback_in(None, language);
+            assert!(!voices.is_empty());
+            assert!(voices.iter().all(|voice| voice.language_code.eq_ignore_ascii_case(language)));
+        }
+        assert!(fallback_in(None, "de-DE").is_empty());
+    }
+
+    #[test]
+    fn fallback_file_replaces_the_bundled_voices() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("voices.json");
+        let json = serde_json::json!({ "de-DE": [voice("de-DE-Wavenet-A", "FEMALE")] });
+        std::fs::write(&path, json.to_string()).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        assert_eq!(names(&fallback_in(Some(&path), "de-DE")), ["de-DE-Wavenet-A"]);
+        assert!(fallback_in(Some(&path), "en-US").is_empty());
+    }
+
+    #[test]
+    fn unreadable_fallback_file_uses_the_bundled_voices() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("voices.json");
+        std::fs::write(&path, "not json").unwrap();
+        let bundled = fallback_in(None, "en-US");
+
+        assert_eq!(names(&fallback_in(Some(&path.to_string_lossy()), "en-US")), names(&bundled));
+        assert_eq!(names(&fallback_in(Some("/no/such/voices.json"), "en-US")), names(&bundled));
+    }
+}
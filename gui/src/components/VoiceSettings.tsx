@@ -74,9 +74,15 @@ export function VoiceSettings({
     setIsLoadingVoices(true);
     try {
       // Call Tauri command to get voices
-      const voiceList = await invoke<Voice[]>('get_available_voices', { 
+      const { voices: voiceList, fallback_reason } = await invoke<{
+        voices: Voice[];
+        fallback_reason: string | null;
+      }>('get_available_voices', { 
         languageCode: language 
       });
+      if (fallback_reason) {
+        console.warn('Using built-in voices:', fallback_reason);
+      }
       
       setVoices(voiceList);
       if (!voice && voiceList.length > 0) {
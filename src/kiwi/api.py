@@ -16,18 +16,23 @@ from fastapi.middleware.cors import CORSMiddleware
 from pydantic import BaseModel
 import uvicorn
 
-from .tts import TTSClient, TTSConfig
+from .tts import TTSClient, TTSConfig, EFFECTS_PROFILES
 from .parsers import ParserFactory
 from .utils import (
     AudioFormat,
+    AUDIO_EXTENSIONS,
     validate_input_file,
     generate_output_path,
     KiwiError,
+    QuotaExceededError,
     TTSAuthenticationError,
     FileValidationError,
     TextTooLongError,
 )
 
+# Google doesn't say when quota frees up; per-minute quotas reset within this
+QUOTA_RETRY_AFTER_SECONDS = 60
+
 
 # Pydantic models for API requests/responses
 class Voice(BaseModel):
@@ -36,6 +41,7 @@ class Voice(BaseModel):
     language_code: str
     ssml_gender: str
     display_name: Optional[str] = None
+    supported_formats: List[str] = []
 
 
 class VoicesResponse(BaseModel):
@@ -50,6 +56,18 @@ class TTSRequest(BaseModel):
     voice: str
     format: str = "MP3"
     language: str = "en-US"
+    sample_rate: Optional[int] = None
+    speaking_rate: Optional[float] = None
+    pitch: Optional[float] = None
+    volume_gain_db: Optional[float] = None
+    # Google picks its own Opus bitrate, so a requested one is refused
+    # rather than silently ignored.
+    bitrate_kbps: Optional[int] = None
+    # Device profile to tune the audio for, one of EFFECTS_PROFILES
+    effects_profile_id: Optional[str] = None
+    # The text is an SSML document. Chirp 3 HD voices only read plain text,
+    # so this server refuses it rather than reading the tags aloud.
+    ssml: bool = False
 
 
 class TTSResponse(BaseModel):
@@ -108,11 +126,27 @@ async def root():
         "endpoints": {
             "voices": "/voices/{language_code}",
             "synthesize": "/synthesize",
-            "download": "/download/{file_id}"
+            "download": "/download/{file_id}",
+            "capabilities": "/capabilities"
         }
     }
 
 
+@app.get("/capabilities")
+async def capabilities():
+    """Describe what this server supports so clients don't have to assume."""
+    return {
+        "tiers": ["Chirp3-HD"],
+        "formats": [audio_format.name for audio_format in AudioFormat],
+        "max_request_bytes": 5000,
+        "streaming": False,
+        # Chirp 3 HD voices don't accept SSML; SSML, inline markup and IPA
+        # input need a server that advertises it
+        "ssml": False,
+        "effects_profiles": EFFECTS_PROFILES
+    }
+
+
 @app.get("/voices/{language_code}", response_model=VoicesResponse)
 async def get_voices(language_code: str = "en-US"):
     """
@@ -140,7 +174,8 @@ async def get_voices(language_code: str = "en-US"):
                 name=voice_name,
                 language_code=language_code,
                 ssml_gender=gender,
-                display_name=f"{display_name} (HD)"
+                display_name=f"{display_name} (HD)",
+                supported_formats=[audio_format.name for audio_format in AudioFormat]
             ))
         
         return VoicesResponse(
@@ -172,7 +207,24 @@ async def synthesize_text(request: TTSRequest):
         except KeyError:
             raise HTTPException(
                 status_code=400, 
-                detail=f"Invalid audio format: {request.format}. Use MP3 or LINEAR16"
+                detail=f"Invalid audio format: {request.format}. Use MP3, LINEAR16 or OGG_OPUS"
+            )
+        if request.ssml:
+            raise HTTPException(
+                status_code=400,
+                detail="SSML is not supported: Chirp 3 HD voices only read plain text. "
+                       "Send plain text, or use a server that lists ssml in /capabilities"
+            )
+        if request.bitrate_kbps is not None:
+            raise HTTPException(
+                status_code=400,
+                detail="Setting a bitrate is not supported: the voices pick their own Opus bitrate"
+            )
+        if request.effects_profile_id is not None and request.effects_profile_id not in EFFECTS_PROFILES:
+            raise HTTPException(
+                status_code=400,
+                detail=f"Invalid effects profile: {request.effects_profile_id}. "
+                       f"Use {', '.join(EFFECTS_PROFILES)}"
             )
         
         # Create TTS configuration
@@ -181,6 +233,16 @@ async def synthesize_text(request: TTSRequest):
             language_code=request.language,
             audio_encoding=audio_format
         )
+        if request.sample_rate:
+            config.sample_rate = request.sample_rate
+        if request.speaking_rate is not None:
+            config.speaking_rate = request.speaking_rate
+        if request.pitch is not None:
+            config.pitch = request.pitch
+        if request.volume_gain_db is not None:
+            config.volume_gain_db = request.volume_gain_db
+        if request.effects_profile_id is not None:
+            config.effects_profile_id = request.effects_profile_id
         
         # Get TTS client
         tts_client = get_tts_client(config)
@@ -191,7 +253,7 @@ async def synthesize_text(request: TTSRequest):
         
         # Create unique filename
         timestamp = int(time.time() * 1000)
-        extension = "mp3" if audio_format == AudioFormat.MP3 else "wav"
+        extension = AUDIO_EXTENSIONS[audio_format]
         output_path = temp_dir / f"tts_{timestamp}.{extension}"
         
         # Synthesize speech
@@ -230,6 +292,12 @@ async def synthesize_text(request: TTSRequest):
             
     except TTSAuthenticationError as e:
         raise HTTPException(status_code=401, detail=str(e))
+    except QuotaExceededError as e:
+        raise HTTPException(
+            status_code=429,
+            detail=str(e),
+            headers={"Retry-After": str(QUOTA_RETRY_AFTER_SECONDS)}
+        )
     except TextTooLongError as e:
         raise HTTPException(status_code=400, detail=str(e))
     except Exception as e:
@@ -327,7 +395,8 @@ async def download_file(file_id: str):
         raise HTTPException(status_code=404, detail="File no longer available")
     
     # Determine media type
-    media_type = "audio/mpeg" if file_path.suffix == ".mp3" else "audio/wav"
+    media_types = {".mp3": "audio/mpeg", ".opus": "audio/ogg"}
+    media_type = media_types.get(file_path.suffix, "audio/wav")
     
     return FileResponse(
         path=file_path,
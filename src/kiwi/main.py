@@ -20,6 +20,7 @@ from .utils import (
     TTSAuthenticationError,
     FileValidationError,
     TextTooLongError,
+    QuotaExceededError,
 )
 
 
@@ -194,6 +195,14 @@ def cli(
         click.echo("4. Ensure your project has billing enabled")
         raise click.Abort()
     
+    except QuotaExceededError as e:
+        click.echo(f"❌ Quota exceeded: {e}", err=True)
+        click.echo(
+            "💡 Tip: Wait a minute and try again, or check your quota in the Google Cloud console.",
+            err=True
+        )
+        raise click.Abort()
+    
     except KiwiError as e:
         click.echo(f"❌ Error: {e}", err=True)
         raise click.Abort()
@@ -13,9 +13,11 @@ from dataclasses import dataclass
 from dotenv import load_dotenv
 from google.cloud import texttospeech
 from google.auth.exceptions import DefaultCredentialsError
+from google.api_core.exceptions import ResourceExhausted
 
 from .utils import (
     AudioFormat,
+    QuotaExceededError,
     TTSAuthenticationError,
     validate_text_length,
     ensure_directory_exists,
@@ -31,6 +33,14 @@ CHIRP_3_HD_VOICES = [
     "Vindemiatrix", "Zephyr", "Zubenelgenubi"
 ]
 
+# Device profiles Google Cloud TTS can tune the audio for
+EFFECTS_PROFILES = [
+    "wearable-class-device", "handset-class-device", "headphone-class-device",
+    "small-bluetooth-speaker-class-device", "medium-bluetooth-speaker-class-device",
+    "large-home-entertainment-class-device", "large-automotive-class-device",
+    "telephony-class-application"
+]
+
 
 @dataclass
 class TTSConfig:
@@ -39,6 +49,11 @@ class TTSConfig:
     language_code: str = "en-US"
     audio_encoding: AudioFormat = AudioFormat.MP3
     sample_rate: int = 24000
+    speaking_rate: float = 1.0
+    pitch: float = 0.0
+    volume_gain_db: float = 0.0
+    # One of EFFECTS_PROFILES, or None for untuned audio
+    effects_profile_id: Optional[str] = None
 
 
 @dataclass
@@ -125,6 +140,10 @@ class TTSClient:
             
         Returns:
             ProcessingResult: Result of the synthesis operation.
+            
+        Raises:
+            QuotaExceededError: If Google Cloud TTS rejects the request for
+                quota reasons, so callers can wait before trying again.
         """
         start_time = time.time()
         
@@ -151,7 +170,14 @@ class TTSClient:
             )
             audio_config = texttospeech.AudioConfig(
                 audio_encoding=audio_encoding,
-                sample_rate_hertz=self.config.sample_rate
+                sample_rate_hertz=self.config.sample_rate,
+                speaking_rate=self.config.speaking_rate,
+                pitch=self.config.pitch,
+                volume_gain_db=self.config.volume_gain_db,
+                effects_profile_id=(
+                    [self.config.effects_profile_id]
+                    if self.config.effects_profile_id else []
+                )
             )
             
             # Perform synthesis with retry logic
@@ -171,6 +197,8 @@ class TTSClient:
                 duration_seconds=duration
             )
             
+        except QuotaExceededError:
+            raise
         except Exception as e:
             duration = time.time() - start_time
             return ProcessingResult(
@@ -217,6 +245,9 @@ class TTSClient:
                 # Use async-friendly approach
                 response = self.client.synthesize_speech(request=request)
                 return response
+            except ResourceExhausted as e:
+                # Retrying straight away only burns more quota
+                raise QuotaExceededError(str(e)) from e
             except Exception as e:
                 last_exception = e
                 
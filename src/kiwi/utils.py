@@ -13,6 +13,15 @@ class AudioFormat(Enum):
     """Supported audio formats for TTS output."""
     MP3 = "MP3"
     LINEAR16 = "LINEAR16"
+    OGG_OPUS = "OGG_OPUS"
+
+
+# File extension written for each audio format
+AUDIO_EXTENSIONS = {
+    AudioFormat.MP3: "mp3",
+    AudioFormat.LINEAR16: "wav",
+    AudioFormat.OGG_OPUS: "opus",
+}
 
 
 class SupportedFileType(Enum):
@@ -36,6 +45,11 @@ class TTSAuthenticationError(KiwiError):
     pass
 
 
+class QuotaExceededError(KiwiError):
+    """Exception raised when Google Cloud TTS rejects a request for quota reasons."""
+    pass
+
+
 class TextTooLongError(KiwiError):
     """Exception raised when text exceeds the 5,000 byte limit for Chirp 3 HD."""
     pass
@@ -113,7 +127,7 @@ def generate_output_path(input_path: Path, output_dir: Optional[Path] = None,
     
     # Generate output filename based on input filename
     base_name = input_path.stem
-    extension = "mp3" if audio_format == AudioFormat.MP3 else "wav"
+    extension = AUDIO_EXTENSIONS[audio_format]
     output_filename = f"{base_name}_tts.{extension}"
     
     return output_dir / output_filename